@@ -0,0 +1,90 @@
+//! The decisions and the serialization of the events, in memory. See `README.md`.
+use car_rental::{
+    domain::{
+        default_tenant, CustomerRegistration, CustomerRentalStatus, DomainEvent, RentEvent,
+        VehicleAvailability, VehicleType,
+    },
+    fixtures,
+    pii::EncryptedJson,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use disintegrate::{
+    serde::{json::Json, Deserializer, Serializer},
+    Decision, Event,
+};
+
+/// Folds the availability of the cars over 100 vehicles rented and returned 5,000 times.
+fn fold_availability(c: &mut Criterion) {
+    let events: Vec<RentEvent> = fixtures::fleet(VehicleType::Car, 100)
+        .chain(fixtures::rentals(VehicleType::Car, 100, 100, 5_000))
+        .map(|event| event.try_into().unwrap())
+        .collect();
+    let mut group = c.benchmark_group("fold_availability");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    group.bench_function("10k_events", |b| {
+        b.iter_batched(
+            || events.clone(),
+            |events| {
+                fixtures::fold(
+                    VehicleAvailability::new(default_tenant(), VehicleType::Car),
+                    events,
+                )
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// Starts a rental on fleets of growing size, all of their vehicles available.
+fn start_rent(c: &mut Criterion) {
+    let mut group = c.benchmark_group("start_rent_process");
+    for vehicles in [10, 1_000, 100_000] {
+        let history: Vec<DomainEvent> = fixtures::customers(1)
+            .chain(fixtures::fleet(VehicleType::Car, vehicles))
+            .collect();
+        let state = (
+            fixtures::fold(
+                CustomerRegistration::new(default_tenant(), fixtures::customer(0)),
+                history.clone(),
+            ),
+            fixtures::fold(
+                CustomerRentalStatus::new(default_tenant(), fixtures::customer(0)),
+                history.clone(),
+            ),
+            fixtures::fold(
+                VehicleAvailability::new(default_tenant(), VehicleType::Car),
+                history,
+            ),
+        );
+        let command = fixtures::start_rent(0, VehicleType::Car);
+        group.bench_with_input(BenchmarkId::from_parameter(vehicles), &state, |b, state| {
+            b.iter(|| command.process(state).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Serializes then deserializes a registration and a return, in plain JSON and sealed.
+fn serde_round_trip(c: &mut Criterion) {
+    let events: Vec<DomainEvent> = fixtures::customers(1)
+        .chain(fixtures::rentals(VehicleType::Car, 1, 1, 1).skip(1))
+        .collect();
+    let mut group = c.benchmark_group("serde_round_trip");
+    for event in &events {
+        let json = Json::<DomainEvent>::default();
+        group.bench_with_input(BenchmarkId::new("json", event.name()), event, |b, event| {
+            b.iter(|| json.deserialize(json.serialize(event.clone())).unwrap())
+        });
+        let sealed = EncryptedJson::default();
+        group.bench_with_input(
+            BenchmarkId::new("sealed", event.name()),
+            event,
+            |b, event| b.iter(|| sealed.deserialize(sealed.serialize(event.clone())).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fold_availability, start_rent, serde_round_trip);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS vehicle (
+    vehicle_id TEXT PRIMARY KEY,
+    vehicle_type TEXT
+);
+
+CREATE TABLE IF NOT EXISTS customer (
+    customer_id TEXT PRIMARY KEY,
+    first_name TEXT,
+    last_name TEXT
+);
+
+CREATE TABLE IF NOT EXISTS rent (
+    customer_id TEXT,
+    vehicle_id TEXT,
+    start_date timestamptz,
+    end_date timestamptz NULL,
+    PRIMARY KEY(customer_id, vehicle_id)
+);
@@ -0,0 +1,24 @@
+-- The rent id is the id of the `VehicleRented` event that started the rental. Rentals
+-- keyed by (customer_id, vehicle_id) get it from the recorded start event, or from the
+-- first matching `VehicleRented` event.
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'rent' AND column_name = 'rent_id'
+    ) THEN
+        ALTER TABLE rent ADD COLUMN IF NOT EXISTS start_event_id BIGINT;
+        ALTER TABLE rent ADD COLUMN rent_id BIGINT;
+        UPDATE rent SET rent_id = coalesce(start_event_id, (
+            SELECT min(e.event_id) FROM event e
+            WHERE e.event_type = 'VehicleRented'
+            AND e.customer_id = rent.customer_id
+            AND e.vehicle_id = rent.vehicle_id
+        ));
+        ALTER TABLE rent DROP CONSTRAINT rent_pkey;
+        ALTER TABLE rent ADD PRIMARY KEY (rent_id);
+        ALTER TABLE rent DROP COLUMN start_event_id;
+    END IF;
+END $$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_rent_open_vehicle ON rent(vehicle_id) WHERE end_date IS NULL;
@@ -0,0 +1,16 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm WITH SCHEMA public;
+
+CREATE INDEX IF NOT EXISTS idx_customer_search ON customer
+    USING gin ((first_name || ' ' || last_name || ' ' || customer_id) gin_trgm_ops);
+
+-- Emails are indexed both whole and split into words, so that partial emails match too.
+ALTER TABLE vehicle ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', vehicle_id || ' ' || coalesce(vehicle_type, ''))) STORED;
+ALTER TABLE customer ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', customer_id || ' ' || translate(customer_id, '@.', '  ') || ' ' || coalesce(first_name, '') || ' ' || coalesce(last_name, ''))) STORED;
+ALTER TABLE rent ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', customer_id || ' ' || translate(customer_id, '@.', '  ') || ' ' || vehicle_id)) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_vehicle_search ON vehicle USING gin (search);
+CREATE INDEX IF NOT EXISTS idx_customer_fulltext ON customer USING gin (search);
+CREATE INDEX IF NOT EXISTS idx_rent_search ON rent USING gin (search);
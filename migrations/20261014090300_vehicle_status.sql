@@ -0,0 +1,16 @@
+-- Vehicles tracked before the status column existed are marked rented when they have an
+-- open rental.
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'vehicle' AND column_name = 'status'
+    ) THEN
+        ALTER TABLE vehicle ADD COLUMN status TEXT NOT NULL DEFAULT 'available';
+        ALTER TABLE vehicle ADD COLUMN last_event_id BIGINT NOT NULL DEFAULT 0;
+        UPDATE vehicle SET status = 'rented', last_event_id = rent.rent_id
+        FROM rent WHERE rent.vehicle_id = vehicle.vehicle_id AND rent.end_date IS NULL;
+    END IF;
+END $$;
+
+CREATE INDEX IF NOT EXISTS idx_vehicle_status ON vehicle(status);
@@ -0,0 +1,12 @@
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'vehicle' AND column_name = 'current_renter_email'
+    ) THEN
+        ALTER TABLE vehicle ADD COLUMN current_renter_email TEXT;
+        ALTER TABLE vehicle ADD COLUMN rented_since timestamptz;
+        UPDATE vehicle SET current_renter_email = rent.customer_id, rented_since = rent.start_date
+        FROM rent WHERE rent.vehicle_id = vehicle.vehicle_id AND rent.end_date IS NULL;
+    END IF;
+END $$;
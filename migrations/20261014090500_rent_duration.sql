@@ -0,0 +1,11 @@
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'rent' AND column_name = 'duration_minutes'
+    ) THEN
+        ALTER TABLE rent ADD COLUMN duration_minutes BIGINT;
+        UPDATE rent SET duration_minutes = floor(extract(epoch FROM end_date - start_date) / 60)
+        WHERE end_date >= start_date;
+    END IF;
+END $$;
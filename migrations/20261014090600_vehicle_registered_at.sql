@@ -0,0 +1,16 @@
+-- Vehicles tracked before the registration date was recorded take it from their
+-- `VehicleAdded` event.
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'vehicle' AND column_name = 'registered_at'
+    ) THEN
+        ALTER TABLE vehicle ADD COLUMN registered_at timestamptz;
+        UPDATE vehicle SET registered_at = coalesce((
+            SELECT min(e.inserted_at) FROM event e
+            WHERE e.event_type = 'VehicleAdded' AND e.vehicle_id = vehicle.vehicle_id
+        ), now());
+        ALTER TABLE vehicle ALTER COLUMN registered_at SET NOT NULL;
+    END IF;
+END $$;
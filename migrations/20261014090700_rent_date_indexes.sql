@@ -0,0 +1,4 @@
+CREATE INDEX IF NOT EXISTS idx_rent_start_date ON rent(start_date);
+CREATE INDEX IF NOT EXISTS idx_rent_end_date ON rent(end_date);
+-- Supports the overdue report, which only looks at open rentals.
+CREATE INDEX IF NOT EXISTS idx_rent_open_start_date ON rent(start_date) WHERE end_date IS NULL;
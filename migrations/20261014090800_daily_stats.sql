@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS daily_stats (
+    day DATE PRIMARY KEY,
+    rentals_started BIGINT NOT NULL DEFAULT 0,
+    rentals_ended BIGINT NOT NULL DEFAULT 0,
+    unique_customers BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS daily_stats_vehicle_type (
+    day DATE,
+    vehicle_type TEXT,
+    rentals_started BIGINT NOT NULL DEFAULT 0,
+    rentals_ended BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, vehicle_type)
+);
+
+CREATE TABLE IF NOT EXISTS daily_stats_customer (
+    day DATE,
+    customer_id TEXT,
+    PRIMARY KEY (day, customer_id)
+);
+
+CREATE TABLE IF NOT EXISTS daily_stats_event (event_id BIGINT PRIMARY KEY);
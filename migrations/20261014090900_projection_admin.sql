@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS projection_rebuild (
+    listener_id TEXT PRIMARY KEY,
+    target_event_id BIGINT NOT NULL,
+    requested_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS projection_failure (
+    listener_id TEXT,
+    event_id BIGINT,
+    attempts INT NOT NULL,
+    PRIMARY KEY (listener_id, event_id)
+);
+
+CREATE TABLE IF NOT EXISTS projection_dead_letter (
+    id BIGSERIAL PRIMARY KEY,
+    listener_id TEXT NOT NULL,
+    event_id BIGINT NOT NULL,
+    event_type TEXT NOT NULL,
+    payload jsonb,
+    error TEXT NOT NULL,
+    recorded_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (listener_id, event_id)
+);
@@ -0,0 +1,35 @@
+-- Vehicle types are restricted to the ones known by the application. Adding a type takes
+-- an `ALTER TYPE vehicle_type ADD VALUE` migration along with the new `VehicleType` variant.
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM pg_type
+        WHERE typname = 'vehicle_type' AND typnamespace = current_schema()::regnamespace
+    ) THEN
+        CREATE TYPE vehicle_type AS ENUM ('car', 'pick_up', 'van', 'truck');
+    END IF;
+END $$;
+
+-- Casting an enum to text is only stable, as labels can be renamed, while generated columns
+-- need immutable expressions. Renaming a label requires recomputing the search column.
+CREATE OR REPLACE FUNCTION vehicle_type_label(vehicle_type) RETURNS text
+    LANGUAGE sql IMMUTABLE AS $$ SELECT $1::text $$;
+
+DO $$
+BEGIN
+    IF (
+        SELECT data_type FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'vehicle' AND column_name = 'vehicle_type'
+    ) = 'text' THEN
+        ALTER TABLE vehicle DROP COLUMN search;
+        ALTER TABLE vehicle
+            ALTER COLUMN vehicle_type TYPE vehicle_type USING vehicle_type::vehicle_type,
+            ALTER COLUMN vehicle_type SET NOT NULL;
+        ALTER TABLE vehicle ADD COLUMN search tsvector
+            GENERATED ALWAYS AS (to_tsvector('simple', vehicle_id || ' ' || vehicle_type_label(vehicle_type))) STORED;
+        CREATE INDEX idx_vehicle_search ON vehicle USING gin (search);
+
+        ALTER TABLE daily_stats_vehicle_type
+            ALTER COLUMN vehicle_type TYPE vehicle_type USING vehicle_type::vehicle_type;
+    END IF;
+END $$;
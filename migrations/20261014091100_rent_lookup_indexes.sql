@@ -0,0 +1,9 @@
+-- Open rentals are looked up through the partial indexes on `end_date IS NULL`, so the end
+-- date index only needs the closed ones. Range conditions on `end_date` imply it is not null
+-- and still match this index.
+DROP INDEX IF EXISTS idx_rent_end_date;
+CREATE INDEX IF NOT EXISTS idx_rent_closed_end_date ON rent(end_date) WHERE end_date IS NOT NULL;
+
+-- Rental history of a customer or of a vehicle, most recent first.
+CREATE INDEX IF NOT EXISTS idx_rent_customer_start_date ON rent(customer_id, start_date DESC);
+CREATE INDEX IF NOT EXISTS idx_rent_vehicle_start_date ON rent(vehicle_id, start_date DESC);
@@ -0,0 +1,28 @@
+-- The read model lives in a schema of its own, whose migrations start from scratch. When it
+-- is empty while the listeners have already made progress, e.g. next to a read model left by
+-- an earlier version in `public`, the listeners start over to fill it from the event store.
+-- Projections are idempotent, so the events replayed meanwhile by other read models sharing
+-- the listener checkpoints have no effect on them.
+DO $$
+DECLARE
+    listeners TEXT[] := ARRAY[
+        'drive_me_crazy_customers',
+        'drive_me_crazy_vehicles',
+        'drive_me_crazy_rentals',
+        'drive_me_crazy_daily_stats'
+    ];
+BEGIN
+    IF to_regclass('public.event_listener') IS NOT NULL
+        AND NOT EXISTS (SELECT 1 FROM customer)
+        AND NOT EXISTS (SELECT 1 FROM vehicle)
+        AND NOT EXISTS (SELECT 1 FROM daily_stats_event)
+    THEN
+        INSERT INTO projection_rebuild (listener_id, target_event_id)
+            SELECT id, last_processed_event_id FROM public.event_listener
+            WHERE id = ANY(listeners) AND last_processed_event_id > 0
+            ON CONFLICT (listener_id) DO UPDATE
+                SET target_event_id = excluded.target_event_id, requested_at = now();
+        UPDATE public.event_listener SET last_processed_event_id = 0, updated_at = now()
+            WHERE id = ANY(listeners);
+    END IF;
+END $$;
@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS webhook (
+    webhook_id BIGSERIAL PRIMARY KEY,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    event_types TEXT[] NOT NULL,
+    -- Last event when registered, the webhook only being notified of the later ones.
+    after_event_id BIGINT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS webhook_delivery_attempt (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id BIGINT NOT NULL REFERENCES webhook ON DELETE CASCADE,
+    event_id BIGINT NOT NULL,
+    attempt INT NOT NULL,
+    status_code INT,
+    error TEXT,
+    delivered BOOLEAN NOT NULL,
+    attempted_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS webhook_delivery_attempt_event_idx
+    ON webhook_delivery_attempt (webhook_id, event_id);
+
+CREATE TABLE IF NOT EXISTS webhook_dead_letter (
+    id BIGSERIAL PRIMARY KEY,
+    webhook_id BIGINT NOT NULL REFERENCES webhook ON DELETE CASCADE,
+    event_id BIGINT NOT NULL,
+    event_type TEXT NOT NULL,
+    payload jsonb NOT NULL,
+    attempts INT NOT NULL,
+    error TEXT NOT NULL,
+    recorded_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (webhook_id, event_id)
+);
@@ -0,0 +1,10 @@
+-- Calls to the admin routes, rejected ones included.
+CREATE TABLE IF NOT EXISTS admin_call (
+    id BIGSERIAL PRIMARY KEY,
+    -- Name of the API key or subject of the token, when the call came with one.
+    actor TEXT,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    status SMALLINT NOT NULL,
+    called_at timestamptz NOT NULL DEFAULT now()
+);
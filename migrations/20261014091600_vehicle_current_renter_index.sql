@@ -0,0 +1,3 @@
+-- Rental status of a customer, looked up by the desk before handing over the keys.
+CREATE INDEX IF NOT EXISTS idx_vehicle_current_renter ON vehicle(current_renter_email)
+    WHERE current_renter_email IS NOT NULL;
@@ -0,0 +1,6 @@
+-- Seats and transmission are optional, and unknown for the vehicles registered before they
+-- were recorded: those keep them NULL, and no filter on them matches them.
+ALTER TABLE vehicle ADD COLUMN IF NOT EXISTS seats SMALLINT;
+ALTER TABLE vehicle ADD COLUMN IF NOT EXISTS transmission TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_vehicle_seats ON vehicle(seats);
@@ -0,0 +1,18 @@
+-- Commands executed by the application, rejected ones included.
+CREATE TABLE IF NOT EXISTS command_audit (
+    id BIGSERIAL PRIMARY KEY,
+    command TEXT NOT NULL,
+    -- The command as sent, its personal data redacted.
+    payload JSONB NOT NULL,
+    -- Name of the API key or subject of the token, when the request came with one.
+    actor TEXT,
+    -- Request id of the request that sent the command.
+    correlation_id TEXT,
+    -- success, domain_error or infrastructure_error.
+    outcome TEXT NOT NULL,
+    error_code TEXT,
+    latency_ms DOUBLE PRECISION NOT NULL,
+    executed_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS command_audit_command_idx ON command_audit (command, id);
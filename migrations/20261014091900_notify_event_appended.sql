@@ -0,0 +1,13 @@
+-- Notifies the `event_appended` channel once events are appended, waking the listeners when
+-- `LISTENER_DELIVERY=notify`. The trigger belongs to the event store, shared by the read models
+-- of every schema, so it's created again rather than once per schema.
+CREATE OR REPLACE FUNCTION public.notify_event_appended() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('event_appended', '');
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS event_appended ON public.event;
+CREATE TRIGGER event_appended AFTER INSERT ON public.event
+    FOR EACH STATEMENT EXECUTE FUNCTION public.notify_event_appended();
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS vehicle_stats (
+    vehicle_id TEXT PRIMARY KEY,
+    rentals BIGINT NOT NULL DEFAULT 0,
+    rented_minutes BIGINT NOT NULL DEFAULT 0,
+    last_rented_at TIMESTAMPTZ,
+    -- Start of the rental under way, for the returns recorded without it.
+    rented_since TIMESTAMPTZ,
+    last_event_id BIGINT NOT NULL DEFAULT 0
+);
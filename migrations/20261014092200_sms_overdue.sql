@@ -0,0 +1,10 @@
+ALTER TABLE customer ADD COLUMN IF NOT EXISTS phone TEXT;
+
+-- The SMS sent about the overdue rentals, at most one per rental and day.
+CREATE TABLE IF NOT EXISTS sms_log (
+    rent_id BIGINT NOT NULL,
+    day DATE NOT NULL,
+    recipient TEXT NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (rent_id, day)
+);
@@ -0,0 +1,40 @@
+-- The fleets of several rental companies, the tenants, share the deployment. The rows
+-- projected before them belong to the default tenant. The events recorded before them are
+-- left as they are: their payloads read as the default tenant's, and so do their empty
+-- `tenant_id` columns to the decisions.
+
+ALTER TABLE customer ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE customer DROP CONSTRAINT IF EXISTS customer_pkey;
+ALTER TABLE customer ADD PRIMARY KEY (tenant_id, customer_id);
+
+ALTER TABLE vehicle ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE vehicle DROP CONSTRAINT IF EXISTS vehicle_pkey;
+ALTER TABLE vehicle ADD PRIMARY KEY (tenant_id, vehicle_id);
+DROP INDEX IF EXISTS idx_vehicle_current_renter;
+CREATE INDEX idx_vehicle_current_renter ON vehicle(tenant_id, current_renter_email)
+    WHERE current_renter_email IS NOT NULL;
+
+-- Rentals keep the id of the event that started them, unique across the tenants.
+ALTER TABLE rent ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+DROP INDEX IF EXISTS idx_rent_open_vehicle;
+CREATE UNIQUE INDEX idx_rent_open_vehicle ON rent(tenant_id, vehicle_id) WHERE end_date IS NULL;
+DROP INDEX IF EXISTS idx_rent_open_start_date;
+CREATE INDEX idx_rent_open_start_date ON rent(tenant_id, start_date) WHERE end_date IS NULL;
+DROP INDEX IF EXISTS idx_rent_customer_start_date;
+CREATE INDEX idx_rent_customer_start_date ON rent(tenant_id, customer_id, start_date DESC);
+DROP INDEX IF EXISTS idx_rent_vehicle_start_date;
+CREATE INDEX idx_rent_vehicle_start_date ON rent(tenant_id, vehicle_id, start_date DESC);
+
+ALTER TABLE vehicle_stats ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE vehicle_stats DROP CONSTRAINT IF EXISTS vehicle_stats_pkey;
+ALTER TABLE vehicle_stats ADD PRIMARY KEY (tenant_id, vehicle_id);
+
+ALTER TABLE daily_stats ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE daily_stats DROP CONSTRAINT IF EXISTS daily_stats_pkey;
+ALTER TABLE daily_stats ADD PRIMARY KEY (tenant_id, day);
+ALTER TABLE daily_stats_vehicle_type ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE daily_stats_vehicle_type DROP CONSTRAINT IF EXISTS daily_stats_vehicle_type_pkey;
+ALTER TABLE daily_stats_vehicle_type ADD PRIMARY KEY (tenant_id, day, vehicle_type);
+ALTER TABLE daily_stats_customer ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE daily_stats_customer DROP CONSTRAINT IF EXISTS daily_stats_customer_pkey;
+ALTER TABLE daily_stats_customer ADD PRIMARY KEY (tenant_id, day, customer_id);
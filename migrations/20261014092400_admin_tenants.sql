@@ -0,0 +1,13 @@
+-- The admin routes only show a tenant what belongs to it. The rows recorded before belong to
+-- the default tenant.
+
+ALTER TABLE command_audit ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+CREATE INDEX IF NOT EXISTS command_audit_tenant_idx ON command_audit (tenant_id, id);
+
+ALTER TABLE admin_call ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+CREATE INDEX IF NOT EXISTS admin_call_tenant_idx ON admin_call (tenant_id, id);
+
+-- A webhook is only notified of the events of its tenant.
+ALTER TABLE webhook ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+
+ALTER TABLE projection_dead_letter ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
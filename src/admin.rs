@@ -0,0 +1,1352 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    web,
+};
+use async_stream::try_stream;
+use chrono::{DateTime, Utc};
+use disintegrate::{
+    serde::Deserializer, BoxDynError, Event, EventListener, EventStore, PersistedEvent,
+    StateMutate, StatePart, StateSnapshotter,
+};
+use disintegrate_postgres::PgSnapshotter;
+use futures_util::{Stream, TryStreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth,
+    config::ListenerConfig,
+    daily_stats::DailyStatsProjection,
+    dead_letter,
+    domain::{
+        CustomerActivity, CustomerRegistration, CustomerRentalStatus, DomainEvent, RentEvent,
+        TenantId, VehicleAvailability, VehicleRegistration, DEFAULT_TENANT,
+    },
+    filters::{AuditParams, SnapshotTarget},
+    pagination::PageParams,
+    pii::{self, EncryptedJson},
+    read_model::{
+        queries::ReadModelRepository, CustomerProjection, RentalProjection, VehicleProjection,
+    },
+    tenant::Tenant,
+    vehicle_stats::VehicleStatsProjection,
+};
+
+struct Projection {
+    listener_id: &'static str,
+    /// Tables owned by the projection, emptied when it is rebuilt.
+    tables: &'static [&'static str],
+    /// Events the listener follows.
+    event_types: &'static [&'static str],
+}
+
+const PROJECTIONS: &[Projection] = &[
+    Projection {
+        listener_id: CustomerProjection::ID,
+        tables: CustomerProjection::TABLES,
+        event_types: CustomerActivity::SCHEMA.types,
+    },
+    Projection {
+        listener_id: VehicleProjection::ID,
+        tables: VehicleProjection::TABLES,
+        event_types: RentEvent::SCHEMA.types,
+    },
+    Projection {
+        listener_id: RentalProjection::ID,
+        tables: RentalProjection::TABLES,
+        event_types: RentEvent::SCHEMA.types,
+    },
+    Projection {
+        listener_id: DailyStatsProjection::ID,
+        tables: DailyStatsProjection::TABLES,
+        event_types: RentEvent::SCHEMA.types,
+    },
+    Projection {
+        listener_id: VehicleStatsProjection::ID,
+        tables: VehicleStatsProjection::TABLES,
+        event_types: RentEvent::SCHEMA.types,
+    },
+];
+
+/// The listeners of the projections that can be rebuilt.
+pub fn rebuildable() -> impl Iterator<Item = &'static str> {
+    PROJECTIONS.iter().map(|projection| projection.listener_id)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rebuild {
+    pub listener_id: String,
+    /// The rebuild is over once the listener has processed this event again.
+    pub target_event_id: i64,
+}
+
+/// Empties the tables of the projection and sets its listener back to the start of the
+/// stream, so that it replays every event. Returns `None` for unknown listeners.
+///
+/// The listener checkpoint is locked first: the reset waits for the batch being processed,
+/// and the listener skips its turns until the reset is committed.
+pub async fn rebuild(pool: &PgPool, listener_id: &str) -> Result<Option<Rebuild>, sqlx::Error> {
+    let Some(Projection {
+        listener_id,
+        tables,
+        ..
+    }) = PROJECTIONS.iter().find(|p| p.listener_id == listener_id)
+    else {
+        return Ok(None);
+    };
+    let mut tx = pool.begin().await?;
+    let checkpoint: Option<i64> = sqlx::query_scalar(
+        "SELECT last_processed_event_id FROM event_listener WHERE id = $1 FOR UPDATE",
+    )
+    .bind(listener_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let target_event_id = checkpoint.unwrap_or(0);
+    // Qualified, so that nothing outside the read model schema is ever emptied.
+    let schema: String = sqlx::query_scalar("SELECT current_schema()")
+        .fetch_one(&mut *tx)
+        .await?;
+    let tables: Vec<String> = tables.iter().map(|t| format!("{schema}.{t}")).collect();
+    sqlx::query(&format!("TRUNCATE {}", tables.join(", ")))
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "UPDATE event_listener SET last_processed_event_id = 0, updated_at = now() WHERE id = $1",
+    )
+    .bind(listener_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"INSERT INTO projection_rebuild (listener_id, target_event_id) VALUES($1, $2)
+            ON CONFLICT (listener_id) DO UPDATE SET target_event_id = $2, requested_at = now()"#,
+    )
+    .bind(listener_id)
+    .bind(target_event_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    dead_letter::clear_failures(pool, listener_id).await?;
+    tracing::info!(listener_id, target_event_id, "projection rebuild started");
+    Ok(Some(Rebuild {
+        listener_id: listener_id.to_string(),
+        target_event_id,
+    }))
+}
+
+/// How far a listener is behind the event store.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectionLag {
+    pub listener_id: &'static str,
+    pub last_processed_event_id: i64,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub latest_event_id: i64,
+    /// Events of the kinds the listener follows that it has not processed yet.
+    pub pending_events: i64,
+    /// Age of the oldest pending event, zero when the listener is caught up.
+    pub lag_seconds: f64,
+}
+
+/// The lag of a projection, along with how its listener polls, or whether it's paused.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectionStatus {
+    #[serde(flatten)]
+    pub lag: ProjectionLag,
+    pub poll_interval_ms: u64,
+    pub batch_size: usize,
+    pub paused: bool,
+}
+
+impl ProjectionStatus {
+    pub fn new(lag: ProjectionLag, config: &ListenerConfig) -> Self {
+        let polling = config.polling(lag.listener_id);
+        let paused = config.paused.contains(lag.listener_id);
+        Self {
+            lag,
+            poll_interval_ms: polling.interval.as_millis() as u64,
+            batch_size: polling.batch_size,
+            paused,
+        }
+    }
+}
+
+impl ReadModelRepository {
+    /// Reports the checkpoint and the lag of every listener.
+    ///
+    /// Listeners that haven't started yet are reported at checkpoint zero, even before the first
+    /// one created the `event_listener` table.
+    pub async fn projection_lags(&self) -> Result<Vec<ProjectionLag>, sqlx::Error> {
+        let (latest_event_id, listening): (i64, bool) = sqlx::query_as(
+            "SELECT coalesce(max(event_id), 0), to_regclass('event_listener') IS NOT NULL FROM event",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let mut lags = Vec::with_capacity(PROJECTIONS.len());
+        for projection in PROJECTIONS {
+            let checkpoint: Option<(i64, Option<DateTime<Utc>>)> = if listening {
+                sqlx::query_as(
+                    "SELECT last_processed_event_id, updated_at::timestamptz FROM event_listener WHERE id = $1",
+                )
+                .bind(projection.listener_id)
+                .fetch_optional(&self.pool)
+                .await?
+            } else {
+                None
+            };
+            let (last_processed_event_id, updated_at) = checkpoint.unwrap_or_default();
+            let (pending_events, lag_seconds): (i64, f64) = sqlx::query_as(
+                r#"SELECT count(*), coalesce(extract(epoch FROM now() - min(inserted_at)::timestamptz), 0)::float8
+                    FROM event WHERE event_id > $1 AND event_type = ANY($2)"#,
+            )
+            .bind(last_processed_event_id)
+            .bind(projection.event_types)
+            .fetch_one(&self.pool)
+            .await?;
+            lags.push(ProjectionLag {
+                listener_id: projection.listener_id,
+                last_processed_event_id,
+                updated_at,
+                latest_event_id,
+                pending_events,
+                lag_seconds,
+            });
+        }
+        Ok(lags)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RetryOutcome {
+    Reprocessed,
+    Failed(String),
+}
+
+/// Hands the event of a dead letter to its listener again, going through the same handling
+/// as events coming from the stream. Returns `None` when the tenant has no such dead letter.
+///
+/// An event failing again is set aside anew.
+pub async fn retry_dead_letter(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+    id: i64,
+) -> Result<Option<RetryOutcome>, sqlx::Error> {
+    let Some(letter) = dead_letter::find_dead_letter(pool, tenant_id, id).await? else {
+        return Ok(None);
+    };
+    if !PROJECTIONS
+        .iter()
+        .any(|p| p.listener_id == letter.listener_id)
+    {
+        return Ok(None);
+    }
+    let payload: Vec<u8> = sqlx::query_scalar("SELECT payload FROM event WHERE event_id = $1")
+        .bind(letter.event_id)
+        .fetch_one(pool)
+        .await?;
+    let event: DomainEvent = EncryptedJson::default()
+        .deserialize(payload)
+        .map_err(|e| sqlx::Error::Decode(e.into()))?;
+
+    dead_letter::remove(pool, id).await?;
+    let event_id = letter.event_id;
+    let result = match letter.listener_id.as_str() {
+        CustomerProjection::ID => {
+            handle(CustomerProjection::new(pool.clone()), event_id, event).await
+        }
+        VehicleProjection::ID => {
+            handle(VehicleProjection::new(pool.clone()), event_id, event).await
+        }
+        RentalProjection::ID => handle(RentalProjection::new(pool.clone()), event_id, event).await,
+        DailyStatsProjection::ID => {
+            handle(DailyStatsProjection::new(pool.clone()), event_id, event).await
+        }
+        VehicleStatsProjection::ID => {
+            handle(VehicleStatsProjection::new(pool.clone()), event_id, event).await
+        }
+        listener_id => unreachable!("{listener_id} is not a known listener"),
+    };
+    if let Err(err) = result {
+        dead_letter::record_dead_letter(
+            pool,
+            &letter.listener_id,
+            event_id,
+            &letter.event_type,
+            &err,
+        )
+        .await?;
+        return Ok(Some(RetryOutcome::Failed(err.to_string())));
+    }
+    Ok(Some(
+        match dead_letter::dead_letter_error(pool, &letter.listener_id, event_id).await? {
+            Some(error) => RetryOutcome::Failed(error),
+            None => RetryOutcome::Reprocessed,
+        },
+    ))
+}
+
+async fn handle<E>(
+    listener: impl EventListener<E, Error = sqlx::Error>,
+    event_id: i64,
+    event: DomainEvent,
+) -> Result<(), sqlx::Error>
+where
+    E: Event + Clone + TryFrom<DomainEvent>,
+    E::Error: std::error::Error + Send + Sync + 'static,
+{
+    let event = E::try_from(event).map_err(|e| sqlx::Error::Decode(e.into()))?;
+    listener.handle(PersistedEvent::new(event_id, event)).await
+}
+
+/// An event as persisted in the event store.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub event_id: i64,
+    pub event_type: &'static str,
+    pub identifiers: BTreeMap<String, String>,
+    pub payload: serde_json::Value,
+    pub inserted_at: DateTime<Utc>,
+}
+
+/// Reads the events matching the params from the event store, the newest first, streaming
+/// the page as its rows are fetched.
+///
+/// The page is picked in SQL, on the identifier columns the store indexes the events by, so
+/// only its rows are read, however long the audit log. Only the events of the tenant are read,
+/// those recorded before the tenants belonging to the default one.
+pub fn audit_events(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+    params: &AuditParams,
+) -> impl Stream<Item = Result<AuditEvent, sqlx::Error>> + 'static {
+    let (pool, tenant_id) = (pool.clone(), tenant_id.clone());
+    let customer_id = params
+        .customer_id
+        .as_ref()
+        .map(|customer_id| pii::token(customer_id));
+    let vehicle_id = params.vehicle_id.clone();
+    let event_types = params.event_types.map(<[&str]>::to_vec);
+    let cursor = params.cursor.map_or(i64::MAX, |cursor| cursor.0);
+    let (from, to, limit) = (params.from, params.to, params.limit);
+    try_stream! {
+        let mut page = sqlx::query_as::<_, (i64, Vec<u8>, DateTime<Utc>)>(
+            r#"SELECT event_id, payload, inserted_at::timestamptz FROM event
+                WHERE event_id < $1
+                    AND ($2::text IS NULL OR customer_id = $2)
+                    AND ($3::text IS NULL OR vehicle_id = $3)
+                    AND ($4::text[] IS NULL OR event_type = ANY($4))
+                    AND ($5::timestamptz IS NULL OR inserted_at::timestamptz >= $5)
+                    AND ($6::timestamptz IS NULL OR inserted_at::timestamptz < $6)
+                    AND (tenant_id = $8 OR ($8 = $9 AND coalesce(tenant_id, '') = ''))
+                ORDER BY event_id DESC LIMIT $7"#,
+        )
+        .bind(cursor)
+        .bind(customer_id)
+        .bind(vehicle_id)
+        .bind(event_types)
+        .bind(from)
+        .bind(to)
+        .bind(limit)
+        .bind(tenant_id)
+        .bind(DEFAULT_TENANT)
+        .fetch(&pool);
+        while let Some((event_id, payload, inserted_at)) = page.try_next().await? {
+            let event: DomainEvent = EncryptedJson::default()
+                .deserialize(payload)
+                .map_err(|e| sqlx::Error::Decode(e.into()))?;
+            yield AuditEvent {
+                event_id,
+                event_type: event.name(),
+                identifiers: event
+                    .domain_identifiers()
+                    .iter()
+                    .map(|(ident, value)| (ident.to_string(), value.to_string()))
+                    .collect(),
+                payload: serde_json::to_value(&event).unwrap_or_default(),
+                inserted_at,
+            };
+        }
+    }
+}
+
+/// The snapshot stored for a state query compared with the state folded from its events.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotInspection {
+    pub query: &'static str,
+    pub snapshot: Option<VersionedState>,
+    /// The state folded from every event, ignoring the snapshot.
+    pub live: VersionedState,
+    /// Whether the snapshot, brought up to date with the events that followed it, differs
+    /// from the live state.
+    pub diverged: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct VersionedState {
+    /// Id of the last event folded into the state.
+    pub version: i64,
+    pub state: serde_json::Value,
+}
+
+/// Loads the snapshot the decisions of `tenant_id` would start from and folds the live state
+/// next to it.
+pub async fn inspect_snapshot<ES>(
+    snapshotter: &PgSnapshotter,
+    event_store: &ES,
+    tenant_id: TenantId,
+    target: SnapshotTarget,
+) -> Result<SnapshotInspection, BoxDynError>
+where
+    ES: EventStore<DomainEvent, Error = disintegrate_postgres::Error> + Sync,
+{
+    match target {
+        SnapshotTarget::CustomerRegistration(customer_id) => {
+            inspect(
+                snapshotter,
+                event_store,
+                CustomerRegistration::new(tenant_id, customer_id),
+            )
+            .await
+        }
+        SnapshotTarget::CustomerRentalStatus(customer_id) => {
+            inspect(
+                snapshotter,
+                event_store,
+                CustomerRentalStatus::new(tenant_id, customer_id),
+            )
+            .await
+        }
+        SnapshotTarget::VehicleRegistration(vehicle_id) => {
+            inspect(
+                snapshotter,
+                event_store,
+                VehicleRegistration::new(tenant_id, vehicle_id),
+            )
+            .await
+        }
+        SnapshotTarget::VehicleAvailability(vehicle_type) => {
+            inspect(
+                snapshotter,
+                event_store,
+                VehicleAvailability::new(tenant_id, vehicle_type),
+            )
+            .await
+        }
+    }
+}
+
+async fn inspect<S, ES>(
+    snapshotter: &PgSnapshotter,
+    event_store: &ES,
+    initial: S,
+) -> Result<SnapshotInspection, BoxDynError>
+where
+    S: StateMutate + Serialize + DeserializeOwned + 'static,
+    S::Event: TryFrom<DomainEvent>,
+    <S::Event as TryFrom<DomainEvent>>::Error: std::error::Error + Send + Sync + 'static,
+    ES: EventStore<DomainEvent, Error = disintegrate_postgres::Error> + Sync,
+{
+    let stored = snapshotter
+        .load_snapshot(StatePart::new(0, initial.clone()))
+        .await;
+    // Snapshots are only taken after some events, so a state at version zero is the initial one.
+    let snapshot = if stored.version() > 0 {
+        Some(versioned(&stored)?)
+    } else {
+        None
+    };
+    let refreshed = versioned(&fold(event_store, stored).await?)?;
+    let live = versioned(&fold(event_store, StatePart::new(0, initial)).await?)?;
+    Ok(SnapshotInspection {
+        query: S::NAME,
+        diverged: snapshot.is_some() && refreshed != live,
+        snapshot,
+        live,
+    })
+}
+
+async fn fold<S, ES>(event_store: &ES, mut part: StatePart<S>) -> Result<StatePart<S>, BoxDynError>
+where
+    S: StateMutate + 'static,
+    S::Event: TryFrom<DomainEvent>,
+    <S::Event as TryFrom<DomainEvent>>::Error: std::error::Error + Send + Sync + 'static,
+    ES: EventStore<DomainEvent, Error = disintegrate_postgres::Error>,
+{
+    let query = part.query_part();
+    let mut events = event_store.stream(&query);
+    while let Some(event) = events.try_next().await? {
+        part.mutate_part::<S::Event>(event);
+    }
+    Ok(part)
+}
+
+fn versioned<S: StateMutate + Serialize>(
+    part: &StatePart<S>,
+) -> Result<VersionedState, BoxDynError> {
+    Ok(VersionedState {
+        version: part.version(),
+        state: canonical(serde_json::to_value(&**part)?),
+    })
+}
+
+/// Sets come out of states in any order, so arrays are sorted to compare them.
+fn canonical(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Array(items) => {
+            let mut items: Vec<_> = items.into_iter().map(canonical).collect();
+            items.sort_by_key(|item| item.to_string());
+            serde_json::Value::Array(items)
+        }
+        serde_json::Value::Object(fields) => serde_json::Value::Object(
+            fields
+                .into_iter()
+                .map(|(field, value)| (field, canonical(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// The snapshots removed by a pruning, by why.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrunedSnapshots {
+    /// Those whose last event is older than the max age.
+    pub stale: u64,
+    /// Those of a vehicle decommissioned in the read model.
+    pub decommissioned: u64,
+}
+
+/// Removes the snapshots whose last event was appended more than `max_age` ago, and those of
+/// the vehicles decommissioned, whose states are never decided again.
+///
+/// Safe while the decisions go on: a decision missing its snapshot folds its state from the
+/// events, snapshotting it again.
+pub async fn prune_snapshots(
+    pool: &PgPool,
+    max_age: Duration,
+) -> Result<PrunedSnapshots, sqlx::Error> {
+    let stale = sqlx::query(
+        r#"DELETE FROM public.snapshot s
+            USING public.event e
+            WHERE e.event_id = s.version
+            AND e.inserted_at < (now() AT TIME ZONE current_setting('TimeZone')) - make_interval(secs => $1)"#,
+    )
+    .bind(max_age.as_secs_f64())
+    .execute(pool)
+    .await?
+    .rows_affected();
+    // The query of a snapshot is its key, e.g. `(VehicleAdded)&tenant_id=default&vehicle_id=AA111AA`.
+    let decommissioned = sqlx::query(
+        r#"DELETE FROM public.snapshot s
+            USING vehicle v
+            WHERE v.status = 'decommissioned'
+            AND 'vehicle_id=' || v.vehicle_id = ANY(regexp_split_to_array(s.query, '[&|()]'))
+            AND 'tenant_id=' || v.tenant_id = ANY(regexp_split_to_array(s.query, '[&|()]'))"#,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    let pruned = PrunedSnapshots {
+        stale,
+        decommissioned,
+    };
+    tracing::info!(?max_age, stale, decommissioned, "pruned the snapshots");
+    Ok(pruned)
+}
+
+/// How reads are served while a projection is being rebuilt, set by `READ_MODEL_REBUILD_MODE`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RebuildReadMode {
+    /// Serve the partially rebuilt data, flagged with the `X-Read-Model-Stale` header.
+    #[default]
+    Stale,
+    /// Answer with 503 Service Unavailable.
+    Unavailable,
+}
+
+impl FromStr for RebuildReadMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stale" => Ok(RebuildReadMode::Stale),
+            "unavailable" => Ok(RebuildReadMode::Unavailable),
+            _ => Err(format!(
+                "READ_MODEL_REBUILD_MODE: must be one of stale, unavailable, got {s}"
+            )),
+        }
+    }
+}
+
+/// A call to an admin route: who made it, what it asked for and when.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminCall {
+    pub id: i64,
+    pub actor: Option<String>,
+    pub method: String,
+    /// Along with the query string.
+    pub path: String,
+    pub status: i16,
+    pub called_at: DateTime<Utc>,
+}
+
+/// Middleware of the admin scope recording every call in `admin_call` once answered, the
+/// rejected ones included.
+///
+/// Failing to record a call is only logged, the response being sent all the same.
+pub fn audit<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let pool = req.app_data::<web::Data<PgPool>>().cloned();
+    let actor = auth::actor(&req);
+    let Tenant(tenant_id) = Tenant::of(req.request());
+    let method = req.method().to_string();
+    let path = req
+        .uri()
+        .path_and_query()
+        .map_or_else(|| req.path().to_string(), ToString::to_string);
+    let response = srv.call(req);
+    async move {
+        let response = response.await?;
+        if let Some(pool) = pool {
+            let status = response.status().as_u16() as i16;
+            let recorded =
+                record_call(&pool, &tenant_id, actor.as_deref(), &method, &path, status).await;
+            if let Err(err) = recorded {
+                tracing::warn!(error = %err, %method, %path, "failed to record an admin call");
+            }
+        }
+        Ok(response)
+    }
+}
+
+async fn record_call(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+    actor: Option<&str>,
+    method: &str,
+    path: &str,
+    status: i16,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO admin_call (tenant_id, actor, method, path, status) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(tenant_id)
+    .bind(actor)
+    .bind(method)
+    .bind(path)
+    .bind(status)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// A page of the admin calls of the tenant, the latest first, along with their total.
+pub async fn admin_calls(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+    page: PageParams,
+) -> Result<(Vec<AdminCall>, i64), sqlx::Error> {
+    let calls = sqlx::query_as(
+        r#"SELECT id, actor, method, path, status, called_at FROM admin_call
+            WHERE tenant_id = $1 ORDER BY id DESC LIMIT $2 OFFSET $3"#,
+    )
+    .bind(tenant_id)
+    .bind(page.limit)
+    .bind(page.offset)
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar("SELECT count(*) FROM admin_call WHERE tenant_id = $1")
+        .bind(tenant_id)
+        .fetch_one(pool)
+        .await?;
+    Ok((calls, total))
+}
+
+/// Tells whether a projection is being rebuilt, refreshed in the background so that
+/// requests don't have to query it.
+#[derive(Debug, Clone, Default)]
+pub struct RebuildStatus(Arc<AtomicBool>);
+
+impl RebuildStatus {
+    pub fn in_progress(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn started(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Polls the listener checkpoints until the process exits.
+    pub async fn watch(self, pool: PgPool) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            match rebuild_in_progress(&pool).await {
+                Ok(in_progress) => self.0.store(in_progress, Ordering::Relaxed),
+                Err(err) => tracing::warn!(error = %err, "failed to check projection rebuilds"),
+            }
+        }
+    }
+}
+
+/// Whether a projection hasn't handled again every event it had handled before its rebuild.
+pub async fn rebuild_in_progress(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM projection_rebuild r
+            JOIN event_listener l ON l.id = r.listener_id
+            WHERE l.last_processed_event_id < r.target_event_id
+        )"#,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        application::{Application, DecisionMaker},
+        domain::{default_tenant, VehicleType},
+        health::Readiness,
+        live::LiveUpdates,
+        pagination::Cursor,
+        shutdown::Shutdown,
+        test_support,
+    };
+    use disintegrate_postgres::PgEventStore;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    async fn audit_page(pool: &PgPool, params: &AuditParams) -> Vec<AuditEvent> {
+        audit_events(pool, &default_tenant(), params)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_audit_the_events_of_a_customer(_: PgPoolOptions, options: PgConnectOptions) {
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            EncryptedJson::default(),
+        )
+        .await
+        .unwrap();
+        let rented = |customer_id: &str| DomainEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: customer_id.into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: Utc::now(),
+        };
+        let events = vec![
+            DomainEvent::VehicleAdded {
+                tenant_id: default_tenant(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Car,
+                seats: None,
+                transmission: None,
+            },
+            rented("mario@example.com"),
+            DomainEvent::VehicleReturned {
+                tenant_id: default_tenant(),
+                customer_id: "mario@example.com".into(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Car,
+                start_date: None,
+                returned_date: Utc::now(),
+            },
+            rented("luigi@example.com"),
+        ];
+        event_store
+            .append(events, disintegrate::query!(DomainEvent), 0)
+            .await
+            .unwrap();
+
+        let params = AuditParams::new(
+            Some("mario@example.com"),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let page = audit_page(&pool, &params).await;
+        let types: Vec<&str> = page.iter().map(|event| event.event_type).collect();
+        assert_eq!(types, ["VehicleReturned", "VehicleRented"]);
+        assert!(page.iter().all(
+            |event| event.identifiers["customer_id"] == crate::pii::token("mario@example.com")
+        ));
+
+        let params = AuditParams::new(
+            Some("mario@example.com"),
+            None,
+            None,
+            None,
+            None,
+            Some(1),
+            None,
+        )
+        .unwrap();
+        let page = audit_page(&pool, &params).await;
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].event_type, "VehicleReturned");
+        let params = AuditParams {
+            cursor: Some(Cursor(page[0].event_id)),
+            ..params
+        };
+        let page = audit_page(&pool, &params).await;
+        assert_eq!(page[0].event_type, "VehicleRented");
+
+        let params = AuditParams::new(
+            None,
+            Some("AA111AA"),
+            Some("VehicleRented"),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let page = audit_page(&pool, &params).await;
+        let customers: Vec<&str> = page
+            .iter()
+            .map(|event| event.identifiers["customer_id"].as_str())
+            .collect();
+        assert_eq!(
+            customers,
+            [
+                crate::pii::token("luigi@example.com"),
+                crate::pii::token("mario@example.com")
+            ]
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_let_the_lag_grow_while_the_listener_stands_still(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            EncryptedJson::default(),
+        )
+        .await
+        .unwrap();
+        let rented = |customer_id: &str| DomainEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: customer_id.into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: Utc::now(),
+        };
+        let added = event_store
+            .append(
+                vec![DomainEvent::VehicleAdded {
+                    tenant_id: default_tenant(),
+                    vehicle_id: "AA111AA".to_string(),
+                    vehicle_type: VehicleType::Car,
+                    seats: None,
+                    transmission: None,
+                }],
+                disintegrate::query!(DomainEvent),
+                0,
+            )
+            .await
+            .unwrap();
+        let checkpoint = added[0].id();
+        let repository = ReadModelRepository::new(pool.clone());
+        // The checkpoints are missing until the listener starts.
+        let find_lag = || async {
+            repository
+                .projection_lags()
+                .await
+                .ok()?
+                .into_iter()
+                .find(|lag| lag.listener_id == RentalProjection::ID)
+        };
+        let lag = || async { find_lag().await.unwrap() };
+        // The listener processes the vehicle, then stands still.
+        let shutdown = Shutdown::default();
+        let listening = tokio::spawn(crate::event_listener(
+            pool,
+            event_store.clone(),
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig {
+                poll_interval: std::time::Duration::from_millis(20),
+                ..ListenerConfig::default()
+            },
+            crate::reporting::noop(),
+            shutdown.requested(),
+        ));
+        while find_lag().await.map(|lag| lag.last_processed_event_id) != Some(checkpoint) {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        shutdown.trigger();
+        listening.await.unwrap().unwrap();
+
+        let caught_up = lag().await;
+        assert_eq!(caught_up.last_processed_event_id, checkpoint);
+        assert_eq!(caught_up.latest_event_id, checkpoint);
+        assert_eq!((caught_up.pending_events, caught_up.lag_seconds), (0, 0.0));
+        assert!(caught_up.updated_at.is_some());
+
+        let appended = event_store
+            .append(
+                vec![rented("mario@example.com")],
+                disintegrate::query!(DomainEvent),
+                checkpoint,
+            )
+            .await
+            .unwrap();
+        let behind = lag().await;
+        assert_eq!(behind.last_processed_event_id, checkpoint);
+        assert_eq!(behind.latest_event_id, appended[0].id());
+        assert_eq!(behind.pending_events, 1);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let appended = event_store
+            .append(
+                vec![rented("luigi@example.com")],
+                disintegrate::query!(DomainEvent),
+                appended[0].id(),
+            )
+            .await
+            .unwrap();
+        let further_behind = lag().await;
+        assert_eq!(further_behind.latest_event_id, appended[0].id());
+        assert_eq!(further_behind.pending_events, 2);
+        assert!(further_behind.lag_seconds >= behind.lag_seconds + 0.05);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_correct_a_corrupted_row_by_rebuilding_its_projection(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            EncryptedJson::default(),
+        )
+        .await
+        .unwrap();
+        let added = |vehicle_id: &str| DomainEvent::VehicleAdded {
+            tenant_id: default_tenant(),
+            vehicle_id: vehicle_id.to_string(),
+            vehicle_type: VehicleType::Van,
+            seats: None,
+            transmission: None,
+        };
+        let appended = event_store
+            .append(
+                vec![
+                    added("AA111AA"),
+                    added("BB222BB"),
+                    DomainEvent::VehicleRented {
+                        tenant_id: default_tenant(),
+                        customer_id: "mario@example.com".into(),
+                        vehicle_id: "AA111AA".to_string(),
+                        vehicle_type: VehicleType::Van,
+                        start_date: Utc::now(),
+                    },
+                ],
+                disintegrate::query!(DomainEvent),
+                0,
+            )
+            .await
+            .unwrap();
+        let last = appended[2].id();
+        let repository = ReadModelRepository::new(pool.clone());
+        let caught_up = || async {
+            tokio::time::timeout(std::time::Duration::from_secs(10), async {
+                loop {
+                    let lags = repository.projection_lags().await.unwrap_or_default();
+                    let checkpoint = lags
+                        .iter()
+                        .find(|lag| lag.listener_id == VehicleProjection::ID)
+                        .map(|lag| lag.last_processed_event_id);
+                    if checkpoint == Some(last) {
+                        break;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                }
+            })
+            .await
+            .expect("the vehicles didn't catch up");
+        };
+        let vehicles = || async {
+            sqlx::query_as::<_, (String, String, String, Option<String>)>(
+                r#"SELECT vehicle_id, vehicle_type::text, status, current_renter_email
+                    FROM vehicle ORDER BY vehicle_id"#,
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+        };
+        let shutdown = Shutdown::default();
+        let listening = tokio::spawn(crate::event_listener(
+            pool.clone(),
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig {
+                poll_interval: std::time::Duration::from_millis(20),
+                ..ListenerConfig::default()
+            },
+            crate::reporting::noop(),
+            shutdown.requested(),
+        ));
+        caught_up().await;
+        let projected = vehicles().await;
+        assert_eq!(projected.len(), 2);
+
+        // A bug of the projection leaves a vehicle wrong and loses the other.
+        sqlx::query(
+            r#"UPDATE vehicle SET vehicle_type = 'truck', status = 'available',
+                current_renter_email = NULL WHERE vehicle_id = 'AA111AA'"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query("DELETE FROM vehicle WHERE vehicle_id = 'BB222BB'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_ne!(vehicles().await, projected);
+        let started = rebuild(&pool, VehicleProjection::ID)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(started.target_event_id, last);
+        caught_up().await;
+        shutdown.trigger();
+        listening.await.unwrap().unwrap();
+
+        assert_eq!(vehicles().await, projected);
+        assert!(rebuild(&pool, "unknown").await.unwrap().is_none());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_set_a_poisoned_event_aside_then_retry_it_once_fixed(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            EncryptedJson::default(),
+        )
+        .await
+        .unwrap();
+        let added = |vehicle_id: &str| DomainEvent::VehicleAdded {
+            tenant_id: default_tenant(),
+            vehicle_id: vehicle_id.to_string(),
+            vehicle_type: VehicleType::Car,
+            seats: None,
+            transmission: None,
+        };
+        let rented = |customer_id: &str, vehicle_id: &str| DomainEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: customer_id.into(),
+            vehicle_id: vehicle_id.to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: Utc::now(),
+        };
+        let appended = event_store
+            .append(
+                vec![
+                    added("AA111AA"),
+                    added("BB222BB"),
+                    rented("luigi@example.com", "BB222BB"),
+                    rented("mario@example.com", "AA111AA"),
+                ],
+                disintegrate::query!(DomainEvent),
+                0,
+            )
+            .await
+            .unwrap();
+        let (poisoned, last) = (appended[2].id(), appended[3].id());
+        // Corrupt data the rental of BB222BB can never be written against.
+        sqlx::query("ALTER TABLE rent ADD CONSTRAINT corrupt CHECK (vehicle_id <> 'BB222BB')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let repository = ReadModelRepository::new(pool.clone());
+        let rented_vehicles = || async {
+            sqlx::query_scalar::<_, String>("SELECT vehicle_id FROM rent ORDER BY vehicle_id")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+        };
+
+        let shutdown = Shutdown::default();
+        let listening = tokio::spawn(crate::event_listener(
+            pool.clone(),
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig {
+                poll_interval: std::time::Duration::from_millis(20),
+                ..ListenerConfig::default()
+            },
+            crate::reporting::noop(),
+            shutdown.requested(),
+        ));
+        let checkpoint = || async {
+            repository
+                .projection_lags()
+                .await
+                .ok()?
+                .into_iter()
+                .find(|lag| lag.listener_id == RentalProjection::ID)
+                .map(|lag| lag.last_processed_event_id)
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            while checkpoint().await != Some(last) {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the listener got stuck on the poisoned event");
+        shutdown.trigger();
+        listening.await.unwrap().unwrap();
+
+        assert_eq!(rented_vehicles().await, ["AA111AA"]);
+        let letters = repository.dead_letters().await.unwrap();
+        assert_eq!(letters.len(), 1, "{letters:?}");
+        let letter = &letters[0];
+        assert_eq!(
+            (letter.listener_id.as_str(), letter.event_id),
+            (RentalProjection::ID, poisoned)
+        );
+        assert!(letter.error.contains("corrupt"), "{}", letter.error);
+        // Retried before the fix, it is set aside anew.
+        assert!(matches!(
+            retry_dead_letter(&pool, &default_tenant(), letter.id).await.unwrap(),
+            Some(RetryOutcome::Failed(error)) if error.contains("corrupt")
+        ));
+
+        sqlx::query("ALTER TABLE rent DROP CONSTRAINT corrupt")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let letter = repository.dead_letters().await.unwrap().remove(0);
+        assert_eq!(
+            retry_dead_letter(&pool, &default_tenant(), letter.id)
+                .await
+                .unwrap(),
+            Some(RetryOutcome::Reprocessed)
+        );
+        assert_eq!(rented_vehicles().await, ["AA111AA", "BB222BB"]);
+        assert!(repository.dead_letters().await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_find_the_snapshot_consistent_with_the_events(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        test_support::read_model(options.clone()).await;
+        let pool = PgPool::connect_with(options).await.unwrap();
+        let event_store = PgEventStore::new(pool.clone(), EncryptedJson::default())
+            .await
+            .unwrap();
+        let snapshotter = PgSnapshotter::new(pool.clone(), 10).await.unwrap();
+        let application = Application::new(
+            DecisionMaker::snapshotted(event_store.clone(), snapshotter.clone()),
+            event_store.clone(),
+        );
+        for i in 0..11 {
+            let command = serde_json::json!({ "vehicleId": format!("V{i}"), "vehicleType": "Car" });
+            application
+                .register_vehicle(serde_json::from_value(command).unwrap())
+                .await
+                .unwrap();
+        }
+        let command = serde_json::json!({
+            "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+        });
+        application
+            .register_customer(serde_json::from_value(command).unwrap())
+            .await
+            .unwrap();
+        // Folds the 11 vehicles added, more than the threshold, into a snapshot.
+        let command =
+            serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Car" });
+        application
+            .start_rent(serde_json::from_value(command).unwrap())
+            .await
+            .unwrap();
+
+        let target = SnapshotTarget::VehicleAvailability(VehicleType::Car);
+        let inspection = inspect_snapshot(&snapshotter, &event_store, default_tenant(), target)
+            .await
+            .unwrap();
+        let snapshot = inspection.snapshot.unwrap();
+        assert_eq!(
+            snapshot.state["available_vehicles"]
+                .as_array()
+                .unwrap()
+                .len(),
+            11
+        );
+        assert_eq!(
+            inspection.live.state["available_vehicles"]
+                .as_array()
+                .unwrap()
+                .len(),
+            10
+        );
+        assert!(inspection.live.version > snapshot.version);
+        assert!(!inspection.diverged);
+
+        sqlx::query(
+            r#"UPDATE snapshot SET payload = jsonb_set(payload::jsonb, '{available_vehicles}', '[]')::text
+                WHERE name = 'VehicleAvailability'"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let target = SnapshotTarget::VehicleAvailability(VehicleType::Car);
+        let inspection = inspect_snapshot(&snapshotter, &event_store, default_tenant(), target)
+            .await
+            .unwrap();
+        assert!(inspection.diverged);
+
+        let target = SnapshotTarget::CustomerRegistration("mario@example.com".into());
+        let inspection = inspect_snapshot(&snapshotter, &event_store, default_tenant(), target)
+            .await
+            .unwrap();
+        assert!(inspection.snapshot.is_none());
+        assert_eq!(inspection.live.state["registered"], true);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_decide_again_once_the_snapshots_are_pruned(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let read_model = test_support::read_model(options.clone()).await;
+        let pool = PgPool::connect_with(options).await.unwrap();
+        let event_store = PgEventStore::new(pool.clone(), EncryptedJson::default())
+            .await
+            .unwrap();
+        let snapshotter = PgSnapshotter::new(pool.clone(), 10).await.unwrap();
+        let application = Application::new(
+            DecisionMaker::snapshotted(event_store.clone(), snapshotter),
+            event_store.clone(),
+        );
+        for i in 0..11 {
+            let command = serde_json::json!({ "vehicleId": format!("V{i}"), "vehicleType": "Car" });
+            application
+                .register_vehicle(serde_json::from_value(command).unwrap())
+                .await
+                .unwrap();
+        }
+        for (customer_id, first_name) in [
+            ("mario@example.com", "Mario"),
+            ("luigi@example.com", "Luigi"),
+        ] {
+            let command = serde_json::json!({
+                "customerId": customer_id, "firstName": first_name, "lastName": "Rossi"
+            });
+            application
+                .register_customer(serde_json::from_value(command).unwrap())
+                .await
+                .unwrap();
+        }
+        let rent = |customer_id: &str| {
+            serde_json::from_value(
+                serde_json::json!({ "customerId": customer_id, "vehicleType": "Car" }),
+            )
+            .unwrap()
+        };
+        application
+            .start_rent(rent("mario@example.com"))
+            .await
+            .unwrap();
+        let snapshots = || async {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT version FROM snapshot WHERE name = 'VehicleAvailability'",
+            )
+            .fetch_optional(&pool)
+            .await
+            .unwrap()
+        };
+        let snapshotted = snapshots().await.unwrap();
+
+        // The snapshots of a vehicle decommissioned and of one in service.
+        sqlx::query(
+            "INSERT INTO vehicle (vehicle_id, vehicle_type, status, registered_at) VALUES ('V3', 'car', 'decommissioned', now())",
+        )
+        .execute(&read_model)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO snapshot (id, name, query, payload, version)
+                VALUES (gen_random_uuid(), 'VehicleRegistration', '(VehicleAdded)&tenant_id=default&vehicle_id=V3', '{}', 3),
+                    (gen_random_uuid(), 'VehicleRegistration', '(VehicleAdded)&tenant_id=default&vehicle_id=V30', '{}', 4)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let pruned = prune_snapshots(&read_model, Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_eq!(
+            pruned,
+            PrunedSnapshots {
+                stale: 0,
+                decommissioned: 1
+            }
+        );
+        assert_eq!(snapshots().await, Some(snapshotted));
+
+        let left: i64 = sqlx::query_scalar("SELECT count(*) FROM snapshot")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let pruned = prune_snapshots(&read_model, Duration::ZERO).await.unwrap();
+        assert_eq!(pruned.stale, left as u64);
+        assert_eq!(snapshots().await, None);
+
+        application
+            .start_rent(rent("luigi@example.com"))
+            .await
+            .unwrap();
+        assert!(snapshots().await.unwrap() > snapshotted);
+    }
+
+    #[test]
+    fn it_should_parse_the_rebuild_read_mode() {
+        assert_eq!("unavailable".parse(), Ok(RebuildReadMode::Unavailable));
+        assert!("offline".parse::<RebuildReadMode>().is_err());
+    }
+}
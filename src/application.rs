@@ -1,43 +1,849 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use disintegrate::{decision::Error, serde::json::Json};
-use disintegrate_postgres::{PgDecisionMaker, WithPgSnapshot};
+use actix_web::web::Data;
+use async_stream::try_stream;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use disintegrate::{
+    decision::Error,
+    ident, query,
+    stream_query::{and, or, origin, StreamFilter},
+    BoxDynError, Decision, DecisionStateStore, Event, EventSourcedDecisionStateStore, EventStore,
+    IntoState, IntoStatePart, MultiState, NoSnapshot, PersistedEvent, StreamQuery, WithSnapshot,
+};
+use disintegrate_postgres::{PgEventStore, PgSnapshotter, WithPgSnapshot};
+use futures_util::{stream::BoxStream, TryStreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::PgPool;
 
+use crate::{
+    backoff::{self, Backoff},
+    command_audit::{self, Redact},
+    domain::{
+        self, DomainEvent, Email, EndRent, PlateNumber, RegisterCustomer, RegisterVehicle,
+        StartRent, TenantId, TenantScoped, VehicleType, DEFAULT_TENANT,
+    },
+    errors::is_conflict,
+    memory::MemoryEventStore,
+    metrics::Metrics,
+    pii::EncryptedJson,
+    read_model::rental_duration_minutes,
+    reporting::{self, ErrorContext, ErrorReporter},
+};
 
-use crate::domain::{DomainEvent, EndRent, RegisterCustomer, RegisterVehicle, StartRent};
+pub type DomainEventStore = PgEventStore<DomainEvent, EncryptedJson>;
 
-pub type DecisionMaker = PgDecisionMaker<DomainEvent, Json<DomainEvent>, WithPgSnapshot>;
-pub type ApplicationError = Error<crate::domain::Error>;
-pub type ApplicationResult = Result<(), ApplicationError>;
+/// The event store as the decisions read it: the events recorded before the tenants, their
+/// `tenant_id` column empty, belong to the default tenant, as their payloads say.
+///
+/// Postgres matches no empty column against the tenant of a query, so the queries of the
+/// default tenant are run without it and their events filtered once read.
+#[derive(Clone)]
+pub struct TenantEventStore(DomainEventStore);
+
+impl TenantEventStore {
+    pub fn new(event_store: DomainEventStore) -> Self {
+        Self(event_store)
+    }
+}
+
+#[async_trait]
+impl EventStore<DomainEvent> for TenantEventStore {
+    type Error = disintegrate_postgres::Error;
+
+    fn stream<'a, QE>(
+        &'a self,
+        query: &'a StreamQuery<QE>,
+    ) -> BoxStream<'a, Result<PersistedEvent<QE>, Self::Error>>
+    where
+        QE: TryFrom<DomainEvent> + Event + 'static + Clone + Send + Sync,
+        <QE as TryFrom<DomainEvent>>::Error: std::error::Error + 'static + Send + Sync,
+    {
+        let Some(filter) = without_default_tenant(query.filter()) else {
+            return self.0.stream(query);
+        };
+        let widened = disintegrate::query::<QE>(Some(filter));
+        Box::pin(try_stream! {
+            let mut events = self.0.stream(&widened);
+            while let Some(event) = events.try_next().await? {
+                if matches(query.filter(), &event) {
+                    yield event;
+                }
+            }
+        })
+    }
+
+    async fn append<QE>(
+        &self,
+        events: Vec<DomainEvent>,
+        query: StreamQuery<QE>,
+        last_event_id: i64,
+    ) -> Result<Vec<PersistedEvent<DomainEvent>>, Self::Error>
+    where
+        QE: Event + 'static + Clone + Send + Sync,
+    {
+        // The events appended since the query all have a tenant.
+        self.0.append(events, query, last_event_id).await
+    }
+}
+
+/// The filter matching any tenant where it matches the default one, `None` when it names no
+/// tenant.
+fn without_default_tenant(filter: &StreamFilter) -> Option<StreamFilter> {
+    match filter {
+        StreamFilter::Eq { ident, value }
+            if *ident == ident!(#tenant_id) && value.to_string() == DEFAULT_TENANT =>
+        {
+            Some(origin(0))
+        }
+        StreamFilter::And { l, r } | StreamFilter::Or { l, r } => {
+            let (widened_l, widened_r) = (without_default_tenant(l), without_default_tenant(r));
+            if widened_l.is_none() && widened_r.is_none() {
+                return None;
+            }
+            let l = widened_l.unwrap_or_else(|| (**l).clone());
+            let r = widened_r.unwrap_or_else(|| (**r).clone());
+            Some(match filter {
+                StreamFilter::And { .. } => and(l, r),
+                _ => or(l, r),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Whether the event matches the filter, an identifier missing from the event matching any
+/// value, as in `PgEventStore`.
+pub(crate) fn matches<E: Event>(filter: &StreamFilter, event: &PersistedEvent<E>) -> bool {
+    match filter {
+        StreamFilter::Events { names } => names.contains(&event.name()),
+        StreamFilter::ExcludeEvents { names } => !names.contains(&event.name()),
+        StreamFilter::Eq { ident, value } => event
+            .domain_identifiers()
+            .get(ident)
+            .is_none_or(|identifier| identifier == value),
+        StreamFilter::And { l, r } => matches(l, event) && matches(r, event),
+        StreamFilter::Or { l, r } => matches(l, event) || matches(r, event),
+        StreamFilter::Origin { id } => event.id() > *id,
+    }
+}
+pub type ApplicationResult<T = ()> = Result<T, ApplicationError>;
+
+/// Why a command failed: the errors of its decision, or the decision abandoned for taking too
+/// long.
+#[derive(Debug, thiserror::Error)]
+pub enum ApplicationError {
+    #[error("event store error: {0}")]
+    EventStore(#[source] BoxDynError),
+    #[error("state store error: {0}")]
+    StateStore(#[source] BoxDynError),
+    #[error("domain error: {0}")]
+    Domain(#[source] domain::Error),
+    /// The decision took longer than the timeout of the application, and was made in full or
+    /// not at all, see `Application::with_decision_timeout`.
+    #[error("the decision took longer than {0:?}")]
+    Timeout(Duration),
+}
+
+impl From<Error<domain::Error>> for ApplicationError {
+    fn from(error: Error<domain::Error>) -> Self {
+        match error {
+            Error::EventStore(error) => ApplicationError::EventStore(error),
+            Error::StateStore(error) => ApplicationError::StateStore(error),
+            Error::Domain(error) => ApplicationError::Domain(error),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RentStarted {
+    /// Rentals are identified by the event that started them.
+    pub rent_id: i64,
+    pub vehicle_id: PlateNumber,
+    pub vehicle_type: VehicleType,
+    pub start_date: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RentEnded {
+    pub vehicle_id: PlateNumber,
+    pub vehicle_type: VehicleType,
+    pub start_date: Option<DateTime<Utc>>,
+    pub returned_date: DateTime<Utc>,
+    pub duration_minutes: Option<i64>,
+    /// Set when the vehicle was back already, the rental being the one returned last.
+    pub already_returned: bool,
+    /// The return event, the version of the customer after it.
+    #[serde(skip)]
+    pub event_id: i64,
+}
 
 #[derive(Clone)]
 pub struct Application {
     decision_maker: DecisionMaker,
+    event_store: Events,
+    conflict_retries: u32,
+    conflict_backoff: Duration,
+    decision_timeout: Option<Duration>,
+    command_audit: Option<PgPool>,
+    metrics: Option<Metrics>,
+    reporter: Arc<dyn ErrorReporter>,
 }
 
 impl Application {
-    pub fn new(decision_maker: DecisionMaker) -> Self {
-        Self { decision_maker }
+    pub fn new(decision_maker: impl Into<DecisionMaker>, event_store: DomainEventStore) -> Self {
+        Self::deciding_on(
+            decision_maker.into(),
+            Events::Postgres(TenantEventStore::new(event_store)),
+        )
+    }
+
+    /// An application deciding on events kept in memory, as with `EVENT_STORE=memory`, and in
+    /// the tests needing no database.
+    pub fn in_memory(event_store: MemoryEventStore) -> Self {
+        let state_store = EventSourcedDecisionStateStore::new(event_store.clone(), NoSnapshot);
+        Self::deciding_on(
+            DecisionMaker::InMemory(disintegrate::DecisionMaker::new(state_store)),
+            Events::Memory(event_store),
+        )
+    }
+
+    fn deciding_on(decision_maker: DecisionMaker, event_store: Events) -> Self {
+        Self {
+            decision_maker,
+            event_store,
+            conflict_retries: 0,
+            conflict_backoff: Duration::ZERO,
+            decision_timeout: None,
+            command_audit: None,
+            metrics: None,
+            reporter: reporting::noop(),
+        }
+    }
+
+    /// Makes a decision that lost the race against another one again, on the state the other
+    /// one left, up to `retries` times before reporting the conflict.
+    pub fn with_conflict_retries(mut self, retries: u32) -> Self {
+        self.conflict_retries = retries;
+        self
+    }
+
+    /// Waits up to `backoff` before making a decision again, doubled at each retry, so that
+    /// the decisions racing each other don't all retry at once.
+    pub fn with_conflict_backoff(mut self, backoff: Duration) -> Self {
+        self.conflict_backoff = backoff;
+        self
     }
-    pub async fn register_vehicle(&self, command: RegisterVehicle) -> ApplicationResult {
-        self.decision_maker.make(command).await?;
 
-        Ok(())
+    /// Gives up on the decisions, their retries included, that take longer than `timeout`.
+    ///
+    /// Only the wait is abandoned: the events of a decision are appended in a transaction, so
+    /// a decision timing out was either made in full or not at all.
+    pub fn with_decision_timeout(mut self, timeout: Duration) -> Self {
+        self.decision_timeout = Some(timeout);
+        self
     }
 
-    pub async fn register_customer(&self, command: RegisterCustomer) -> ApplicationResult {
-        self.decision_maker.make(command).await?;
-        Ok(())
+    /// Records every command executed in the `command_audit` table of `pool`, rejected ones
+    /// included, see `command_audit::record`.
+    pub fn with_command_audit(mut self, pool: PgPool) -> Self {
+        self.command_audit = Some(pool);
+        self
     }
 
-    pub async fn start_rent(&self, command: StartRent) -> ApplicationResult {
-        self.decision_maker.make(command).await?;
+    /// Counts the commands executed by their result and records how long they took.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Reports the infrastructure errors of the commands to `reporter`, but for the conflicts
+    /// past the retries, which the clients are told to retry.
+    pub fn with_error_reporter(mut self, reporter: Arc<dyn ErrorReporter>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    /// Makes the decision, failing with `ApplicationError::Timeout` when it takes too long.
+    async fn deciding<T, F, R>(&self, command: &str, make: F) -> ApplicationResult<T>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = ApplicationResult<T>>,
+    {
+        let Some(timeout) = self.decision_timeout else {
+            return self.retrying(command, make).await;
+        };
+        match tokio::time::timeout(timeout, self.retrying(command, make)).await {
+            Ok(result) => result,
+            Err(_) => {
+                tracing::warn!(?timeout, "the decision timed out");
+                Err(ApplicationError::Timeout(timeout))
+            }
+        }
+    }
+
+    /// Makes the decision until it doesn't conflict, or the retries run out; domain errors are
+    /// never retried. Each attempt reads the state of the decision afresh.
+    ///
+    /// The attempts made are recorded as `attempts` on the span of the command, and logged
+    /// when there were retries, and with metrics observed by command.
+    async fn retrying<T, F, R>(&self, command: &str, mut make: F) -> ApplicationResult<T>
+    where
+        F: FnMut() -> R,
+        R: Future<Output = ApplicationResult<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = make().await;
+            match result {
+                Err(err) if attempt < self.conflict_retries && is_conflict(&err) => {
+                    attempt += 1;
+                    let delay =
+                        Backoff::new(self.conflict_backoff).jittered(attempt, backoff::random());
+                    tracing::debug!(
+                        attempt,
+                        ?delay,
+                        "making the decision again after a conflict"
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => {
+                    let attempts = attempt + 1;
+                    tracing::Span::current().record("attempts", attempts);
+                    if let Some(metrics) = &self.metrics {
+                        metrics.attempts(command, attempts);
+                    }
+                    if attempts > 1 {
+                        tracing::info!(attempts, "decision made again after conflicts");
+                    }
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Makes the decision of a command, retrying it on conflicts and giving up on it past the
+    /// timeout, and tells its outcome.
+    ///
+    /// With a command audit, the command is recorded along with how it ended, and with metrics
+    /// it's counted by result: an `EndRent` of a vehicle back already counts as
+    /// `ALREADY_RETURNED`, the decision having persisted nothing.
+    pub async fn execute<D, DS>(&self, command: D) -> ApplicationResult<Outcome<D>>
+    where
+        D: Command,
+        D::StateQuery: Serialize + DeserializeOwned + IntoStatePart<D::StateQuery, Target = DS>,
+        DS: Send
+            + Sync
+            + Serialize
+            + DeserializeOwned
+            + IntoState<D::StateQuery>
+            + MultiState<DomainEvent>
+            + 'static,
+        StateStore: DecisionStateStore<DS, DomainEvent>,
+    {
+        let started = Instant::now();
+        let result = self
+            .deciding(D::NAME, || self.decision_maker.make(command.clone()))
+            .await
+            .and_then(|events| {
+                let ids: Vec<_> = events.iter().map(|event| event.id().to_string()).collect();
+                tracing::Span::current().record("event_ids", ids.join(","));
+                D::outcome(events)
+            });
+        let elapsed = started.elapsed();
+        if let Err(err) = &result {
+            if !matches!(err, ApplicationError::Domain(_)) && !is_conflict(err) {
+                let context = ErrorContext {
+                    command: Some(D::NAME),
+                    ..ErrorContext::current()
+                };
+                self.reporter.report(err, &context);
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.command(D::NAME, &result, elapsed);
+        }
+        if let Some(pool) = &self.command_audit {
+            command_audit::record(
+                pool,
+                D::NAME,
+                command.tenant_id(),
+                command.redacted(),
+                &result,
+                elapsed,
+            );
+        }
+        result
+    }
+
+    #[tracing::instrument(skip_all, fields(command = "RegisterVehicle", vehicle_id = %command.vehicle_id, attempts = tracing::field::Empty, event_ids = tracing::field::Empty))]
+    pub async fn register_vehicle(
+        &self,
+        command: RegisterVehicle,
+    ) -> ApplicationResult<PlateNumber> {
+        self.execute(command).await
+    }
+
+    #[tracing::instrument(skip_all, fields(command = "RegisterCustomer", customer_id = %RedactedEmail(&command.customer_id), attempts = tracing::field::Empty, event_ids = tracing::field::Empty))]
+    pub async fn register_customer(&self, command: RegisterCustomer) -> ApplicationResult<Email> {
+        self.execute(command).await
+    }
+
+    #[tracing::instrument(skip_all, fields(command = "StartRent", customer_id = %RedactedEmail(&command.customer_id), vehicle_type = %command.vehicle_type, attempts = tracing::field::Empty, event_ids = tracing::field::Empty))]
+    pub async fn start_rent(&self, command: StartRent) -> ApplicationResult<RentStarted> {
+        self.execute(command).await
+    }
+
+    /// Ends the rental in progress or, when the vehicle was returned less than
+    /// `EndRent::REPEAT_WINDOW` ago, reports that return again.
+    #[tracing::instrument(skip_all, fields(command = "EndRent", customer_id = %RedactedEmail(&command.customer_id), attempts = tracing::field::Empty, event_ids = tracing::field::Empty))]
+    pub async fn end_rent(&self, command: EndRent) -> ApplicationResult<RentEnded> {
+        let (tenant_id, customer_id) = (command.tenant_id.clone(), command.customer_id.clone());
+        match self.execute(command).await {
+            Err(ApplicationError::Domain(domain::Error::AlreadyReturned)) => {
+                let events = self
+                    .last_return(tenant_id, customer_id)
+                    .await?
+                    .into_iter()
+                    .collect();
+                rent_ended(events, true)
+            }
+            result => result,
+        }
+    }
 
-        Ok(())
+    /// Version of the customer of the tenant, the id of its last event, or `None` when it has
+    /// none.
+    ///
+    /// It's read from the event store, so that it is as up to date as the decisions.
+    pub async fn customer_version(
+        &self,
+        tenant_id: &TenantId,
+        customer_id: &Email,
+    ) -> ApplicationResult<Option<i64>> {
+        let (tenant_id, customer_id) = (tenant_id.clone(), customer_id.clone());
+        let query = query!(
+            DomainEvent,
+            (tenant_id == tenant_id) and (customer_id == customer_id)
+        );
+        self.event_store
+            .stream(&query)
+            .try_fold(None, |_, event| std::future::ready(Ok(Some(event.id()))))
+            .await
+            .map_err(|e| ApplicationError::EventStore(e.into()))
     }
 
-    pub async fn end_rent(&self, command: EndRent) -> ApplicationResult {
-        self.decision_maker.make(command).await?;
+    /// Reads the last return of the customer from the event store, so that it is as up to date
+    /// as the decision.
+    async fn last_return(
+        &self,
+        tenant_id: TenantId,
+        customer_id: Email,
+    ) -> ApplicationResult<Option<PersistedEvent<DomainEvent>>> {
+        let query = query!(
+            DomainEvent,
+            (tenant_id == tenant_id) and (customer_id == customer_id)
+        );
+        self.event_store
+            .stream(&query)
+            .try_filter(|event| {
+                std::future::ready(matches!(**event, DomainEvent::VehicleReturned { .. }))
+            })
+            .try_fold(None, |_, event| std::future::ready(Ok(Some(event))))
+            .await
+            .map_err(|e| ApplicationError::EventStore(e.into()))
+    }
+}
+
+/// The commands the handlers send, implemented by `Application` and, in the handler tests, by
+/// a mock needing no database.
+#[async_trait]
+pub trait CommandService: Send + Sync {
+    async fn register_vehicle(&self, command: RegisterVehicle) -> ApplicationResult<PlateNumber>;
+
+    async fn register_customer(&self, command: RegisterCustomer) -> ApplicationResult<Email>;
+
+    async fn start_rent(&self, command: StartRent) -> ApplicationResult<RentStarted>;
+
+    async fn end_rent(&self, command: EndRent) -> ApplicationResult<RentEnded>;
+
+    async fn customer_version(
+        &self,
+        tenant_id: &TenantId,
+        customer_id: &Email,
+    ) -> ApplicationResult<Option<i64>>;
+}
+
+#[async_trait]
+impl CommandService for Application {
+    async fn register_vehicle(&self, command: RegisterVehicle) -> ApplicationResult<PlateNumber> {
+        Application::register_vehicle(self, command).await
+    }
+
+    async fn register_customer(&self, command: RegisterCustomer) -> ApplicationResult<Email> {
+        Application::register_customer(self, command).await
+    }
+
+    async fn start_rent(&self, command: StartRent) -> ApplicationResult<RentStarted> {
+        Application::start_rent(self, command).await
+    }
+
+    async fn end_rent(&self, command: EndRent) -> ApplicationResult<RentEnded> {
+        Application::end_rent(self, command).await
+    }
+
+    async fn customer_version(
+        &self,
+        tenant_id: &TenantId,
+        customer_id: &Email,
+    ) -> ApplicationResult<Option<i64>> {
+        Application::customer_version(self, tenant_id, customer_id).await
+    }
+}
+
+/// The service as the handlers take it, `Data<dyn CommandService>`.
+pub fn command_service(service: impl CommandService + 'static) -> Data<dyn CommandService> {
+    Data::from(Arc::new(service) as Arc<dyn CommandService>)
+}
+
+/// The state store of the decision maker.
+type StateStore = EventSourcedDecisionStateStore<TenantEventStore, WithPgSnapshot>;
+
+/// Makes the decisions on the states snapshotted every `SNAPSHOT_EVERY` events, or folded from
+/// the events alone when `SNAPSHOTS_ENABLED=false`, the decisions being the same either way.
+#[derive(Clone)]
+pub enum DecisionMaker {
+    Snapshotted(disintegrate::DecisionMaker<StateStore>),
+    Unsnapshotted(
+        disintegrate::DecisionMaker<EventSourcedDecisionStateStore<TenantEventStore, NoSnapshot>>,
+    ),
+    InMemory(
+        disintegrate::DecisionMaker<EventSourcedDecisionStateStore<MemoryEventStore, NoSnapshot>>,
+    ),
+}
+
+impl DecisionMaker {
+    /// Decides on the states the snapshotter keeps.
+    pub fn snapshotted(event_store: DomainEventStore, snapshotter: PgSnapshotter) -> Self {
+        DecisionMaker::Snapshotted(disintegrate::DecisionMaker::new(
+            EventSourcedDecisionStateStore::new(
+                TenantEventStore::new(event_store),
+                WithSnapshot::new(snapshotter),
+            ),
+        ))
+    }
+
+    /// Decides on the states folded from the events alone.
+    pub fn unsnapshotted(event_store: DomainEventStore) -> Self {
+        DecisionMaker::Unsnapshotted(disintegrate::DecisionMaker::new(
+            EventSourcedDecisionStateStore::new(TenantEventStore::new(event_store), NoSnapshot),
+        ))
+    }
+
+    async fn make<D, DS>(&self, decision: D) -> ApplicationResult<Vec<PersistedEvent<DomainEvent>>>
+    where
+        D: Command,
+        D::StateQuery: Serialize + DeserializeOwned + IntoStatePart<D::StateQuery, Target = DS>,
+        DS: Send
+            + Sync
+            + Serialize
+            + DeserializeOwned
+            + IntoState<D::StateQuery>
+            + MultiState<DomainEvent>
+            + 'static,
+        StateStore: DecisionStateStore<DS, DomainEvent>,
+    {
+        let made = match self {
+            DecisionMaker::Snapshotted(decision_maker) => decision_maker.make(decision).await,
+            DecisionMaker::Unsnapshotted(decision_maker) => decision_maker.make(decision).await,
+            DecisionMaker::InMemory(decision_maker) => decision_maker.make(decision).await,
+        };
+        made.map_err(ApplicationError::from)
+    }
+}
+
+/// The event store the decisions are made on, read for what they don't tell.
+#[derive(Clone)]
+enum Events {
+    Postgres(TenantEventStore),
+    Memory(MemoryEventStore),
+}
+
+impl Events {
+    fn stream<'a>(
+        &'a self,
+        query: &'a StreamQuery<DomainEvent>,
+    ) -> BoxStream<'a, Result<PersistedEvent<DomainEvent>, disintegrate_postgres::Error>> {
+        match self {
+            Events::Postgres(event_store) => event_store.stream(query),
+            Events::Memory(event_store) => event_store.stream(query),
+        }
+    }
+}
+
+/// A decision sent as a command, telling its outcome out of the events it persisted.
+pub trait Command:
+    Decision<Event = DomainEvent, Error = domain::Error> + Clone + Redact + TenantScoped
+{
+    /// The name the command is audited and measured under.
+    const NAME: &'static str;
+
+    type Outcome;
+
+    fn outcome(events: Vec<PersistedEvent<DomainEvent>>) -> ApplicationResult<Self::Outcome>;
+}
+
+pub type Outcome<D> = <D as Command>::Outcome;
+
+/// Names of the commands, as audited.
+pub const COMMANDS: [&str; 4] = [
+    RegisterVehicle::NAME,
+    RegisterCustomer::NAME,
+    StartRent::NAME,
+    EndRent::NAME,
+];
+
+impl Command for RegisterVehicle {
+    const NAME: &'static str = "RegisterVehicle";
+
+    type Outcome = PlateNumber;
+
+    fn outcome(events: Vec<PersistedEvent<DomainEvent>>) -> ApplicationResult<PlateNumber> {
+        outcome(events, |_, event| match event {
+            DomainEvent::VehicleAdded { vehicle_id, .. } => Some(vehicle_id),
+            _ => None,
+        })
+    }
+}
+
+impl Command for RegisterCustomer {
+    const NAME: &'static str = "RegisterCustomer";
+
+    type Outcome = Email;
+
+    fn outcome(events: Vec<PersistedEvent<DomainEvent>>) -> ApplicationResult<Email> {
+        outcome(events, |_, event| match event {
+            DomainEvent::CustomerRegistered { customer_id, .. } => Some(customer_id),
+            _ => None,
+        })
+    }
+}
+
+impl Command for StartRent {
+    const NAME: &'static str = "StartRent";
+
+    type Outcome = RentStarted;
+
+    fn outcome(events: Vec<PersistedEvent<DomainEvent>>) -> ApplicationResult<RentStarted> {
+        outcome(events, |event_id, event| match event {
+            DomainEvent::VehicleRented {
+                vehicle_id,
+                vehicle_type,
+                start_date,
+                ..
+            } => Some(RentStarted {
+                rent_id: event_id,
+                vehicle_id,
+                vehicle_type,
+                start_date,
+            }),
+            _ => None,
+        })
+    }
+}
+
+impl Command for EndRent {
+    const NAME: &'static str = "EndRent";
+
+    type Outcome = RentEnded;
+
+    fn outcome(events: Vec<PersistedEvent<DomainEvent>>) -> ApplicationResult<RentEnded> {
+        rent_ended(events, false)
+    }
+}
+
+fn rent_ended(
+    events: Vec<PersistedEvent<DomainEvent>>,
+    already_returned: bool,
+) -> ApplicationResult<RentEnded> {
+    outcome(events, |event_id, event| match event {
+        DomainEvent::VehicleReturned {
+            vehicle_id,
+            vehicle_type,
+            start_date,
+            returned_date,
+            ..
+        } => Some(RentEnded {
+            vehicle_id,
+            vehicle_type,
+            start_date,
+            returned_date,
+            duration_minutes: start_date
+                .and_then(|start_date| rental_duration_minutes(start_date, returned_date)),
+            already_returned,
+            event_id,
+        }),
+        _ => None,
+    })
+}
+
+/// Picks the outcome of a decision out of the events it persisted.
+fn outcome<T>(
+    events: Vec<PersistedEvent<DomainEvent>>,
+    pick: impl Fn(i64, DomainEvent) -> Option<T>,
+) -> ApplicationResult<T> {
+    events
+        .into_iter()
+        .find_map(|event| pick(event.id(), event.into_inner()))
+        .ok_or_else(|| {
+            ApplicationError::StateStore("the decision did not persist the expected event".into())
+        })
+}
+
+/// Customer ids are emails, so only their first character and domain make it to the logs.
+pub(crate) struct RedactedEmail<'a>(pub(crate) &'a str);
+
+impl std::fmt::Display for RedactedEmail<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (local, domain) = match self.0.split_once('@') {
+            Some((local, domain)) => (local, format!("@{domain}")),
+            None => (self.0, String::new()),
+        };
+        let first = local.chars().next().map(String::from).unwrap_or_default();
+        write!(f, "{first}***{domain}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{domain::default_tenant, test_support};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    #[actix_web::test]
+    async fn it_should_tell_the_conflicts_of_the_memory_store_as_the_ones_of_postgres() {
+        let event_store = MemoryEventStore::default();
+        let app = Application::in_memory(event_store.clone());
+        let mario = || RegisterCustomer {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: None,
+        };
+        app.register_customer(mario()).await.unwrap();
+        assert_eq!(
+            app.customer_version(&default_tenant(), &"mario@example.com".into())
+                .await
+                .unwrap(),
+            Some(1)
+        );
+
+        let customer_id = Email::from("mario@example.com");
+        let query = query!(DomainEvent, customer_id == customer_id);
+        let stale = event_store
+            .append(
+                vec![DomainEvent::CustomerRegistered {
+                    tenant_id: default_tenant(),
+                    customer_id: customer_id.clone(),
+                    first_name: "Mario".to_string(),
+                    last_name: "Rossi".to_string(),
+                    phone: None,
+                }],
+                query,
+                0,
+            )
+            .await
+            .unwrap_err();
+        assert!(is_conflict(&ApplicationError::EventStore(Box::new(stale))));
+        assert!(matches!(
+            app.register_customer(mario()).await,
+            Err(ApplicationError::Domain(
+                domain::Error::AlreadyRegisteredCustomer
+            ))
+        ));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_read_the_events_without_a_tenant_as_the_ones_of_the_default_tenant(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        test_support::read_model(options.clone()).await;
+        let pool = PgPool::connect_with(options).await.unwrap();
+        let event_store = DomainEventStore::new(pool.clone(), EncryptedJson::default())
+            .await
+            .unwrap();
+        let app = Application::new(
+            DecisionMaker::unsnapshotted(event_store.clone()),
+            event_store,
+        );
+        let mario = |tenant_id: &str| RegisterCustomer {
+            tenant_id: tenant_id.to_string(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: None,
+        };
+        app.register_customer(mario(DEFAULT_TENANT)).await.unwrap();
+        // As recorded before the tenants.
+        for table in ["event", "event_sequence"] {
+            sqlx::query(&format!("UPDATE public.{table} SET tenant_id = NULL"))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        assert!(matches!(
+            app.register_customer(mario(DEFAULT_TENANT)).await,
+            Err(ApplicationError::Domain(
+                domain::Error::AlreadyRegisteredCustomer
+            ))
+        ));
+        assert_eq!(
+            app.customer_version(&default_tenant(), &"mario@example.com".into())
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        app.register_customer(mario("acme")).await.unwrap();
+        assert_eq!(
+            app.customer_version(&"acme".to_string(), &"mario@example.com".into())
+                .await
+                .unwrap(),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn it_should_double_the_ceiling_of_the_conflict_delay_at_each_retry() {
+        let backoff = Backoff::new(Duration::from_millis(10));
+        assert_eq!(backoff.jittered(1, 1.0), backoff.initial);
+        assert_eq!(backoff.jittered(3, 1.0), backoff.initial * 4);
+        assert_eq!(backoff.jittered(3, 0.5), backoff.initial * 2);
+        assert_eq!(backoff.jittered(2, 0.0), Duration::ZERO);
+        assert_eq!(
+            Backoff::new(Duration::ZERO).jittered(5, 1.0),
+            Duration::ZERO
+        );
+        assert!((0..100)
+            .map(|_| backoff::random())
+            .all(|jitter| (0.0..1.0).contains(&jitter)));
+    }
 
-        Ok(())
+    #[test]
+    fn it_should_redact_customer_ids() {
+        assert_eq!(
+            RedactedEmail("mario.rossi@example.com").to_string(),
+            "m***@example.com"
+        );
+        assert_eq!(RedactedEmail("mario").to_string(), "m***");
+        assert_eq!(RedactedEmail("").to_string(), "***");
     }
 }
@@ -0,0 +1,552 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Arc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{HeaderName, AUTHORIZATION, WWW_AUTHENTICATE},
+        Method,
+    },
+    FromRequest, HttpMessage, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    domain::TenantId,
+    errors::{ErrorBody, ErrorCode},
+    tenant::Tenant,
+    tokens::{self, Caller, TokenKeys},
+};
+
+pub const API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Who sent the request: the name of the API key it came with, or the subject of its token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal(pub String);
+
+/// Keys accepted by the API, known by the SHA-256 of their value only.
+///
+/// Commands require a key of `API_KEYS`, given in `Authorization: Bearer` or `X-Api-Key`;
+/// reads only require one when `READ_API_KEYS` is set, either kind being accepted then. Both
+/// list `name:sha256` entries, comma separated, or `name:sha256:tenant` for the keys of a
+/// tenant other than the default one. The probes are never authenticated.
+///
+/// With `ADMIN_API_KEYS`, the admin routes only accept those keys instead, whatever the method;
+/// without it nor `JWT_SECRET`, they accept no one.
+///
+/// With `JWT_SECRET`, a valid bearer token is accepted in place of any key, what it allows being
+/// checked by the handlers.
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys(Arc<ApiKeyHashes>);
+
+#[derive(Debug, Default)]
+struct ApiKeyHashes {
+    commands: HashMap<String, KeyOwner>,
+    reads: Option<HashMap<String, KeyOwner>>,
+    admin: Option<HashMap<String, KeyOwner>>,
+    tokens: Option<TokenKeys>,
+}
+
+/// Who a key was issued to, by the hash of the key.
+#[derive(Debug, Clone)]
+struct KeyOwner {
+    name: String,
+    tenant: Option<TenantId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Open,
+    Commands,
+    Reads,
+    Admin,
+}
+
+/// Marks the requests authenticated with a key of `ADMIN_API_KEYS`.
+#[derive(Debug, Clone, Copy)]
+struct AdminKey;
+
+impl ApiKeys {
+    pub fn new(
+        commands: Option<&str>,
+        reads: Option<&str>,
+        admin: Option<&str>,
+        tokens: Option<TokenKeys>,
+    ) -> Result<Self, String> {
+        Ok(Self(Arc::new(ApiKeyHashes {
+            commands: commands
+                .map(|keys| parse_keys("API_KEYS", keys))
+                .transpose()?
+                .unwrap_or_default(),
+            reads: reads
+                .map(|keys| parse_keys("READ_API_KEYS", keys))
+                .transpose()?,
+            admin: admin
+                .map(|keys| parse_keys("ADMIN_API_KEYS", keys))
+                .transpose()?,
+            tokens,
+        })))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.commands.is_empty()
+            && self.0.reads.is_none()
+            && self.0.admin.is_none()
+            && self.0.tokens.is_none()
+    }
+
+    /// Whether anyone can be admitted to the admin routes, by key or by token.
+    pub fn admits_admins(&self) -> bool {
+        self.0.admin.is_some() || self.0.tokens.is_some()
+    }
+
+    pub fn tokens(&self) -> Option<&TokenKeys> {
+        self.0.tokens.as_ref()
+    }
+
+    fn access(&self, req: &ServiceRequest) -> Access {
+        if matches!(req.path(), "/healthz" | "/readyz") {
+            Access::Open
+        } else if is_admin(req.path()) && (self.0.admin.is_some() || self.0.tokens.is_none()) {
+            Access::Admin
+        } else if req.method() == Method::GET || req.method() == Method::HEAD {
+            match self.0.reads {
+                Some(_) => Access::Reads,
+                None => Access::Open,
+            }
+        } else if self.0.commands.is_empty() && self.0.tokens.is_none() {
+            Access::Open
+        } else {
+            Access::Commands
+        }
+    }
+
+    fn owner(&self, access: Access, key: &str) -> Option<&KeyOwner> {
+        let hash = hash(key);
+        let reads = || self.0.reads.as_ref().and_then(|reads| reads.get(&hash));
+        match access {
+            Access::Open => None,
+            Access::Commands => self.0.commands.get(&hash),
+            Access::Reads => self.0.commands.get(&hash).or_else(reads),
+            Access::Admin => self.0.admin.as_ref().and_then(|admin| admin.get(&hash)),
+        }
+    }
+
+    /// The tenant of the key or token of an open request, which reads within it when given
+    /// one, just as it would once authenticated.
+    fn open_tenant(&self, req: &ServiceRequest) -> Option<Tenant> {
+        if let Some((tokens, token)) = self
+            .0
+            .tokens
+            .as_ref()
+            .zip(tokens::bearer_token(req.headers()))
+        {
+            return tokens.decode(token).ok()?.tenant.map(Tenant);
+        }
+        let key = presented_key(req)?;
+        self.owner(Access::Reads, key)?.tenant.clone().map(Tenant)
+    }
+}
+
+fn parse_keys(var: &str, keys: &str) -> Result<HashMap<String, KeyOwner>, String> {
+    keys.split(',')
+        .map(|entry| {
+            let invalid = || format!("{var}: `{entry}` is not a `name:sha256[:tenant]` entry");
+            let mut parts = entry.trim().splitn(3, ':');
+            let (Some(name), Some(hash)) = (parts.next(), parts.next()) else {
+                return Err(invalid());
+            };
+            let tenant = parts.next();
+            if name.is_empty()
+                || hash.len() != 64
+                || !hash.chars().all(|c| c.is_ascii_hexdigit())
+                || tenant.is_some_and(|tenant| tenant.is_empty() || tenant.contains(':'))
+            {
+                return Err(invalid());
+            }
+            let owner = KeyOwner {
+                name: name.to_string(),
+                tenant: tenant.map(str::to_string),
+            };
+            Ok((hash.to_ascii_lowercase(), owner))
+        })
+        .collect()
+}
+
+fn hash(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The key of the request, from `Authorization: Bearer` or else `X-Api-Key`.
+fn presented_key(req: &ServiceRequest) -> Option<&str> {
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let api_key = || {
+        req.headers()
+            .get(API_KEY)
+            .and_then(|value| value.to_str().ok())
+    };
+    bearer.or_else(api_key).map(str::trim)
+}
+
+/// Middleware answering `401 Unauthorized` to the requests without a key and
+/// `403 Forbidden` to those with an unknown one. The principal of the others is stored in the
+/// request extensions and recorded on the request span as the actor.
+impl<S> Transform<S, ServiceRequest> for ApiKeys
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Transform = ApiKeyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyMiddleware {
+            service: Rc::new(service),
+            keys: self.clone(),
+        }))
+    }
+}
+
+pub struct ApiKeyMiddleware<S> {
+    service: Rc<S>,
+    keys: ApiKeys,
+}
+
+impl<S> Service<ServiceRequest> for ApiKeyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let access = self.keys.access(&req);
+        if access == Access::Open {
+            if let Some(tenant) = self.keys.open_tenant(&req) {
+                req.extensions_mut().insert(tenant);
+            }
+            return Box::pin(self.service.call(req));
+        }
+        let token = self
+            .keys
+            .0
+            .tokens
+            .as_ref()
+            .zip(tokens::bearer_token(req.headers()));
+        if let Some((tokens, token)) = token {
+            return match tokens.decode(token) {
+                Ok(claims) => {
+                    let principal = Principal(claims.sub);
+                    tracing::Span::current().record("actor", principal.0.as_str());
+                    req.extensions_mut().insert(principal);
+                    if let Some(tenant) = claims.tenant {
+                        req.extensions_mut().insert(Tenant(tenant));
+                    }
+                    Box::pin(self.service.call(req))
+                }
+                Err(err) => {
+                    let response = actix_web::ResponseError::error_response(&err);
+                    Box::pin(ready(Ok(req.into_response(response))))
+                }
+            };
+        }
+        let owner = match presented_key(&req) {
+            None => {
+                let response = HttpResponse::Unauthorized()
+                    .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                    .json(ErrorBody::new(
+                        ErrorCode::Unauthenticated,
+                        "an API key is required".to_string(),
+                    ));
+                return Box::pin(ready(Ok(req.into_response(response))));
+            }
+            Some(key) => self.keys.owner(access, key).cloned(),
+        };
+        let Some(owner) = owner else {
+            let response = HttpResponse::Forbidden().json(ErrorBody::new(
+                ErrorCode::Forbidden,
+                "the API key is not allowed".to_string(),
+            ));
+            return Box::pin(ready(Ok(req.into_response(response))));
+        };
+        tracing::Span::current().record("actor", owner.name.as_str());
+        req.extensions_mut().insert(Principal(owner.name));
+        if let Some(tenant) = owner.tenant {
+            req.extensions_mut().insert(Tenant(tenant));
+        }
+        if access == Access::Admin {
+            req.extensions_mut().insert(AdminKey);
+        }
+        Box::pin(self.service.call(req))
+    }
+}
+
+/// Who sent the request, as audited: its principal or, the reads being possibly open, the
+/// subject of its token.
+pub fn actor(req: &ServiceRequest) -> Option<String> {
+    match req.extensions().get::<Principal>() {
+        Some(principal) => Some(principal.0.clone()),
+        None => Caller::extract(req.request())
+            .into_inner()
+            .ok()
+            .and_then(|caller| caller.subject().map(str::to_string)),
+    }
+}
+
+/// Whether the path is an admin one, in any version of the API.
+pub fn is_admin(path: &str) -> bool {
+    path.strip_prefix(crate::http::API_V1)
+        .unwrap_or(path)
+        .starts_with("/admin/")
+}
+
+/// Middleware of the admin scope, answering `403 Forbidden` to the callers that aren't admins:
+/// requests come with a key of `ADMIN_API_KEYS`, or once tokens are validated, an admin token.
+/// Without either, every caller is turned away.
+///
+/// It only adds to `ApiKeys`, which authenticated the request beforehand.
+pub fn require_admin<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let admitted = match req.extensions().get::<AdminKey>() {
+        Some(AdminKey) => Ok(()),
+        None => Caller::extract(req.request())
+            .into_inner()
+            .and_then(|caller| caller.admin()),
+    };
+    match admitted {
+        Ok(()) => {
+            let response = srv.call(req);
+            Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+        }
+        Err(err) => {
+            let response = actix_web::ResponseError::error_response(&err);
+            Box::pin(ready(Ok(req.into_response(response).map_into_right_body())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    fn entry(name: &str, key: &str) -> String {
+        format!("{name}:{}", hash(key))
+    }
+
+    async fn principal(req: actix_web::HttpRequest) -> HttpResponse {
+        match req.extensions().get::<Principal>() {
+            Some(principal) => HttpResponse::Ok().body(principal.0.clone()),
+            None => HttpResponse::Ok().finish(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn it_should_require_a_known_key_for_the_commands() {
+        let keys = ApiKeys::new(Some(&entry("desk", "desk-secret")), None, None, None).unwrap();
+        let service = test::init_service(
+            App::new()
+                .wrap(keys)
+                .route("/rent/start", web::post().to(principal))
+                .route("/vehicles", web::get().to(principal))
+                .route("/healthz", web::get().to(principal)),
+        )
+        .await;
+        let start = || test::TestRequest::post().uri("/rent/start");
+
+        let response = test::call_service(&service, start().to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers().get(WWW_AUTHENTICATE).unwrap(), "Bearer");
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "UNAUTHENTICATED");
+
+        let request = start().insert_header((API_KEY, "guess")).to_request();
+        let response = test::call_service(&service, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "FORBIDDEN");
+
+        for request in [
+            start().insert_header((API_KEY, "desk-secret")),
+            start().insert_header((AUTHORIZATION, "Bearer desk-secret")),
+        ] {
+            let response = test::call_service(&service, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(test::read_body(response).await, "desk");
+        }
+
+        for uri in ["/vehicles", "/healthz"] {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            let response = test::call_service(&service, request).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn it_should_require_a_key_for_the_reads_when_configured() {
+        let keys = ApiKeys::new(
+            Some(&entry("desk", "desk-secret")),
+            Some(&entry("dashboard", "dashboard-secret")),
+            None,
+            None,
+        )
+        .unwrap();
+        let service = test::init_service(
+            App::new()
+                .wrap(keys)
+                .route("/rent/start", web::post().to(principal))
+                .route("/vehicles", web::get().to(principal))
+                .route("/healthz", web::get().to(principal)),
+        )
+        .await;
+        let get = |uri: &str, key: Option<&str>| {
+            let request = test::TestRequest::get().uri(uri);
+            match key {
+                Some(key) => request.insert_header((API_KEY, key)),
+                None => request,
+            }
+            .to_request()
+        };
+
+        let response = test::call_service(&service, get("/vehicles", None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        for (key, principal) in [("dashboard-secret", "dashboard"), ("desk-secret", "desk")] {
+            let response = test::call_service(&service, get("/vehicles", Some(key))).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(test::read_body(response).await, principal);
+        }
+        let response = test::call_service(&service, get("/healthz", None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::post()
+            .uri("/rent/start")
+            .insert_header((API_KEY, "dashboard-secret"))
+            .to_request();
+        let response = test::call_service(&service, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn it_should_only_admit_the_admin_keys_to_the_admin_routes() {
+        let keys = ApiKeys::new(
+            Some(&entry("desk", "desk-secret")),
+            None,
+            Some(&entry("ops", "ops-secret")),
+            None,
+        )
+        .unwrap();
+        let service = test::init_service(
+            App::new()
+                .wrap(keys)
+                .route("/rent/start", web::post().to(principal))
+                .service(
+                    web::scope("/admin")
+                        .wrap_fn(require_admin)
+                        .route("/dead-letters", web::get().to(principal)),
+                ),
+        )
+        .await;
+        let request = |method: Method, uri: &str, key: &str| {
+            test::TestRequest::default()
+                .method(method)
+                .uri(uri)
+                .insert_header((API_KEY, key))
+                .to_request()
+        };
+
+        let response = test::call_service(
+            &service,
+            request(Method::GET, "/admin/dead-letters", "ops-secret"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response).await, "ops");
+        let response = test::call_service(
+            &service,
+            request(Method::GET, "/admin/dead-letters", "desk-secret"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response =
+            test::call_service(&service, request(Method::POST, "/rent/start", "ops-secret")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn it_should_close_the_admin_routes_without_admin_credentials() {
+        let keys = ApiKeys::new(Some(&entry("desk", "desk-secret")), None, None, None).unwrap();
+        for keys in [keys, ApiKeys::default()] {
+            let service = test::init_service(
+                App::new().wrap(keys).service(
+                    web::scope("/admin")
+                        .wrap_fn(require_admin)
+                        .route("/dead-letters", web::get().to(principal)),
+                ),
+            )
+            .await;
+            let request = || test::TestRequest::get().uri("/admin/dead-letters");
+
+            let response = test::call_service(&service, request().to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let request = request().insert_header((API_KEY, "desk-secret"));
+            let response = test::call_service(&service, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        // Behind no key middleware at all, as in the tests of the routes.
+        let service = test::init_service(
+            App::new().service(
+                web::scope("/admin")
+                    .wrap_fn(require_admin)
+                    .route("/dead-letters", web::get().to(principal)),
+            ),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/admin/dead-letters");
+        let response = test::call_service(&service, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[std::prelude::v1::test]
+    fn it_should_tell_admin_paths_in_any_version() {
+        assert!(is_admin("/admin/projections"));
+        assert!(is_admin("/api/v1/admin/projections"));
+        assert!(!is_admin("/api/v1/vehicles"));
+    }
+
+    #[std::prelude::v1::test]
+    fn it_should_only_accept_hashed_keys() {
+        assert!(ApiKeys::new(Some(&entry("desk", "secret")), None, None, None).is_ok());
+        assert!(ApiKeys::new(Some("desk:secret"), None, None, None).is_err());
+        let tenant = format!("{}:north", entry("desk", "secret"));
+        assert!(ApiKeys::new(Some(&tenant), None, None, None).is_ok());
+        assert!(ApiKeys::new(Some(&format!("{tenant}:south")), None, None, None).is_err());
+        assert!(ApiKeys::new(None, None, Some(&format!(":{}", hash("secret"))), None).is_err());
+        assert!(ApiKeys::new(None, None, None, None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// Waits between attempts: `initial` before the first retry, twice as long before each next
+/// one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration) -> Self {
+        Self { initial }
+    }
+
+    /// Delay before the `retry`th retry, the first being 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+
+    /// A share of the delay before the `retry`th retry, `jitter` picking which, the full
+    /// jitter spreading the instances retrying together apart.
+    pub fn jittered(&self, retry: u32, jitter: f64) -> Duration {
+        self.delay(retry).mul_f64(jitter.clamp(0.0, 1.0))
+    }
+}
+
+/// A number between 0 and 1, drawn from the random bits of a v4 UUID.
+pub fn random() -> f64 {
+    (uuid::Uuid::new_v4().as_u64_pair().0 >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Attempts `attempt` until it succeeds, it fails for good as `retryable` tells, or retrying
+/// would take longer than `max_wait`, waiting as `backoff` tells, jittered, in between.
+///
+/// Each failed attempt is logged, as failing at `what`.
+pub async fn retry<T, E, F, R>(
+    what: &str,
+    backoff: Backoff,
+    max_wait: Duration,
+    retryable: impl Fn(&E) -> bool,
+    mut attempt: F,
+) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> R,
+    R: Future<Output = Result<T, E>>,
+{
+    let started = Instant::now();
+    let mut retry = 0;
+    loop {
+        let err = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        retry += 1;
+        let delay = backoff.jittered(retry, random());
+        if !retryable(&err) || started.elapsed() + delay > max_wait {
+            return Err(err);
+        }
+        tracing::warn!(error = %err, attempt = retry, ?delay, "failed to {what}, retrying");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_should_give_up_past_the_max_wait() {
+        let backoff = Backoff::new(Duration::from_millis(5));
+        let mut attempts = 0;
+        let result: Result<(), String> = retry(
+            "count",
+            backoff,
+            Duration::from_millis(100),
+            |_| true,
+            || {
+                attempts += 1;
+                std::future::ready(Err("refused".to_string()))
+            },
+        )
+        .await;
+        assert_eq!(result, Err("refused".to_string()));
+        assert!(attempts > 1, "{attempts}");
+
+        let mut attempts = 0;
+        let result: Result<(), String> = retry(
+            "count",
+            backoff,
+            Duration::from_secs(60),
+            |err| err != "denied",
+            || {
+                attempts += 1;
+                std::future::ready(Err("denied".to_string()))
+            },
+        )
+        .await;
+        assert_eq!(result, Err("denied".to_string()));
+        assert_eq!(attempts, 1);
+    }
+}
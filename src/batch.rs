@@ -0,0 +1,159 @@
+use actix_web::{body::to_bytes, http::StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    application::CommandService,
+    domain::{EndRent, RegisterCustomer, RegisterVehicle, StartRent, TenantId, TenantScoped},
+    errors::CarRentalResponseError,
+    tokens::Caller,
+    validation::{self, Validate, ValidationErrors},
+};
+
+/// Commands a batch holds at most, rejected with a `413` beyond.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// A command of a batch, e.g. `{"type": "registerVehicle", "payload": {...}}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "camelCase")]
+pub enum BatchCommand {
+    RegisterVehicle(RegisterVehicle),
+    RegisterCustomer(RegisterCustomer),
+    StartRent(StartRent),
+    EndRent(EndRent),
+}
+
+impl Validate for BatchCommand {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        match self {
+            BatchCommand::RegisterVehicle(command) => command.validate(),
+            BatchCommand::RegisterCustomer(command) => command.validate(),
+            BatchCommand::StartRent(command) => command.validate(),
+            BatchCommand::EndRent(command) => command.validate(),
+        }
+    }
+}
+
+/// Outcome of a command of the batch, with the status and body its own endpoint would have
+/// answered with on failure.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemResult {
+    pub index: usize,
+    pub status: u16,
+    pub applied: bool,
+    /// The vehicle or customer registered, the rental started or the vehicle returned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchOutcome {
+    pub atomic: bool,
+    /// Commands applied; in an atomic batch, those before the first failure.
+    pub applied: usize,
+    pub results: Vec<ItemResult>,
+}
+
+/// Executes the commands one after the other, within the fleet of `tenant_id`.
+///
+/// A failure doesn't stop the others, unless the batch is `atomic`: the commands after the
+/// first failure are then skipped, with a `424 Failed Dependency`. The commands applied
+/// before it stay applied, as events can't be taken back.
+pub async fn execute(
+    app: &dyn CommandService,
+    caller: &Caller,
+    tenant_id: &TenantId,
+    items: Vec<serde_json::Value>,
+    atomic: bool,
+) -> BatchOutcome {
+    let mut results = Vec::with_capacity(items.len());
+    let mut failed = false;
+    for (index, item) in items.into_iter().enumerate() {
+        let result = if failed && atomic {
+            ItemResult {
+                index,
+                status: StatusCode::FAILED_DEPENDENCY.as_u16(),
+                applied: false,
+                id: None,
+                error: None,
+            }
+        } else {
+            match execute_one(app, caller, tenant_id, item).await {
+                Ok((status, id)) => ItemResult {
+                    index,
+                    status: status.as_u16(),
+                    applied: true,
+                    id: Some(id),
+                    error: None,
+                },
+                Err(err) => {
+                    failed = true;
+                    let (status, body) = rendered(err).await;
+                    ItemResult {
+                        index,
+                        status: status.as_u16(),
+                        applied: false,
+                        id: None,
+                        error: body,
+                    }
+                }
+            }
+        };
+        results.push(result);
+    }
+    BatchOutcome {
+        atomic,
+        applied: results.iter().filter(|result| result.applied).count(),
+        results,
+    }
+}
+
+async fn execute_one(
+    app: &dyn CommandService,
+    caller: &Caller,
+    tenant_id: &TenantId,
+    item: serde_json::Value,
+) -> actix_web::Result<(StatusCode, serde_json::Value)> {
+    let tenant_id = tenant_id.clone();
+    let decided = match validation::parse(item)? {
+        BatchCommand::RegisterVehicle(command) => {
+            caller.register()?;
+            app.register_vehicle(command.for_tenant(tenant_id))
+                .await
+                .map(|vehicle_id| (StatusCode::CREATED, vehicle_id.into()))
+        }
+        BatchCommand::RegisterCustomer(command) => {
+            caller.register()?;
+            app.register_customer(command.for_tenant(tenant_id))
+                .await
+                .map(|customer_id| (StatusCode::CREATED, customer_id.into_string().into()))
+        }
+        BatchCommand::StartRent(command) => {
+            caller.act_for(&command.customer_id)?;
+            app.start_rent(command.for_tenant(tenant_id))
+                .await
+                .map(|started| (StatusCode::CREATED, started.rent_id.into()))
+        }
+        BatchCommand::EndRent(command) => {
+            caller.act_for(&command.customer_id)?;
+            app.end_rent(command.for_tenant(tenant_id))
+                .await
+                .map(|ended| (StatusCode::OK, ended.vehicle_id.into()))
+        }
+    };
+    Ok(decided.map_err(CarRentalResponseError::from)?)
+}
+
+/// The status and JSON body the error is rendered with.
+async fn rendered(err: actix_web::Error) -> (StatusCode, Option<serde_json::Value>) {
+    let response = err.error_response();
+    let status = response.status();
+    let body = to_bytes(response.into_body())
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice(&body).ok());
+    (status, body)
+}
@@ -0,0 +1,9 @@
+use car_rental::loadgen::{self, LoadConfig};
+use clap::Parser;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let report = loadgen::run(&LoadConfig::parse()).await?;
+    print!("{report}");
+    Ok(())
+}
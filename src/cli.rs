@@ -0,0 +1,1024 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use disintegrate::{serde::Deserializer, Event, EventStore as _};
+use disintegrate_postgres::{PgEventStore, PgSnapshotter};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    admin::{self, PrunedSnapshots, Rebuild},
+    application::{Application, DecisionMaker},
+    backoff::{self, Backoff},
+    config::{AppConfig, DatabaseConfig},
+    domain::DomainEvent,
+    health::Readiness,
+    live::LiveUpdates,
+    pii::{self, EncryptedJson},
+    reporting,
+    seed::{self, Seeded},
+    self_check::{self, Missing, SelfCheck},
+    shutdown::Shutdown,
+    EventStore,
+};
+
+/// The car rental service, serving the API unless told otherwise.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Seeds the demo data before serving.
+    #[arg(long)]
+    pub seed: bool,
+    #[command(flatten)]
+    pub overrides: Overrides,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serves the API and runs the projections.
+    Serve,
+    /// Sets the event store up and migrates the read model, then exits.
+    Migrate,
+    /// Registers the demo data sized by the `SEED_*` variables, then exits.
+    Seed,
+    /// Rebuilds a projection from the first event, exiting once it has caught up.
+    Replay {
+        /// The id of its listener, e.g. `vehicle_projection`.
+        #[arg(long)]
+        listener: String,
+    },
+    /// Writes the events to a file, one JSON object per line.
+    ExportEvents {
+        #[arg(long)]
+        out: PathBuf,
+        /// Only the events past this id, e.g. the last one of a previous export.
+        #[arg(long)]
+        after: Option<i64>,
+        /// Only the events of this type, e.g. `VehicleRented`.
+        #[arg(long = "type")]
+        event_type: Option<String>,
+    },
+    /// Removes the snapshots of the states not decided for long, and of the vehicles
+    /// decommissioned, then exits.
+    PruneSnapshots {
+        /// Overrides `SNAPSHOT_MAX_AGE_DAYS`.
+        #[arg(long)]
+        max_age_days: Option<u64>,
+    },
+    /// Appends the events of a file written by `export-events` to an empty event store.
+    ImportEvents {
+        #[arg(long = "in")]
+        input: PathBuf,
+        /// Only checks that every event can be imported, appending none.
+        #[arg(long)]
+        dry_run: bool,
+        /// Appends the events even when the event store already holds some.
+        #[arg(long)]
+        force_append: bool,
+    },
+    /// Seals the events recorded in plaintext, and those sealed under the keys derived from
+    /// `PII_ENCRYPTION_KEY` when `PII_KEY_DIR` is set, then exits: a migration rewriting the
+    /// events in place, run once before serving.
+    SealEvents,
+    /// Shreds the key of a customer in `PII_KEY_DIR`, erasing their personal data from every
+    /// event, then exits.
+    ShredCustomer {
+        #[arg(long)]
+        email: String,
+        /// The tenant of the customer, `default` unless given.
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+}
+
+/// Settings taking precedence over the environment and the config files.
+#[derive(Args)]
+pub struct Overrides {
+    /// Overrides `RUN_MODE`: `api`, `listener` or `all`.
+    #[arg(long, global = true)]
+    pub mode: Option<String>,
+    /// Overrides `HTTP_PORT`.
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// Overrides `DATABASE_URL`.
+    #[arg(long, global = true)]
+    pub database_url: Option<String>,
+    /// Overrides `LISTENER_POLL_INTERVAL_MS`.
+    #[arg(long, global = true)]
+    pub poll_interval_ms: Option<u64>,
+}
+
+impl Overrides {
+    /// The variables they set.
+    pub fn variables(&self) -> Vec<(&'static str, String)> {
+        let mut variables = Vec::new();
+        if let Some(mode) = &self.mode {
+            variables.push(("RUN_MODE", mode.clone()));
+        }
+        if let Some(port) = self.port {
+            variables.push(("HTTP_PORT", port.to_string()));
+        }
+        if let Some(url) = &self.database_url {
+            variables.push(("DATABASE_URL", url.clone()));
+        }
+        if let Some(millis) = self.poll_interval_ms {
+            variables.push(("LISTENER_POLL_INTERVAL_MS", millis.to_string()));
+        }
+        variables
+    }
+}
+
+/// The event store, set up, and the read model, migrated: what every subcommand starts with.
+pub struct Stores {
+    pub event_store: EventStore,
+    /// The same snapshots as the decision maker, for inspection.
+    pub snapshotter: PgSnapshotter,
+    pub read_model: PgPool,
+    pub listener: ListenerStores,
+    /// Every pool, by name, for their metrics.
+    pub pools: Vec<(&'static str, PgPool)>,
+    pub check: SelfCheck,
+}
+
+/// The stores the event listener goes through, on pools of its own when
+/// `DATABASE_LISTENER_MAX_CONNECTIONS` is set, the others' otherwise.
+#[derive(Clone)]
+pub struct ListenerStores {
+    pub event_store: EventStore,
+    pub read_model: PgPool,
+}
+
+impl Stores {
+    /// Connects to the stores, setting them up when `migrate` is set and checking that they
+    /// were set up otherwise, failing with what is missing.
+    ///
+    /// The database is retried while it refuses the connections, for at most
+    /// `connect_max_wait`.
+    pub async fn connect(config: &AppConfig, migrate: bool) -> anyhow::Result<Self> {
+        let database = &config.database;
+        let unreachable = |source| Missing::Database {
+            target: self_check::target(&database.options),
+            source,
+        };
+        let backoff = Backoff::new(DatabaseConfig::CONNECT_BACKOFF);
+        let pool = backoff::retry(
+            "connect to the database",
+            backoff,
+            database.connect_max_wait,
+            transient,
+            || {
+                database
+                    .pool_options()
+                    .connect_with(database.connect_options())
+            },
+        )
+        .await
+        .map_err(unreachable)?;
+        let mut check = SelfCheck {
+            database: true,
+            ..SelfCheck::default()
+        };
+        if !migrate {
+            self_check::event_store_tables(&pool).await?;
+        }
+        let serde = EncryptedJson::default();
+        let set_up = |err: &disintegrate_postgres::Error| match err {
+            disintegrate_postgres::Error::Database(err) => transient(err),
+            _ => false,
+        };
+        let snapshotter = backoff::retry(
+            "set the snapshots up",
+            backoff,
+            database.connect_max_wait,
+            set_up,
+            || PgSnapshotter::new(pool.clone(), config.snapshot_every),
+        )
+        .await?;
+        let event_store = backoff::retry(
+            "set the event store up",
+            backoff,
+            database.connect_max_wait,
+            set_up,
+            || PgEventStore::new(pool.clone(), serde.clone()),
+        )
+        .await?;
+        self_check::event_store(&event_store).await?;
+        check.event_store = true;
+        self_check::sealed(&pool, &serde, migrate).await?;
+        check.sealed = true;
+
+        // The read model migrations backfill from the event store, so they run after its setup.
+        let read_model = config
+            .read_model_schema
+            .connect(database.pool_options(), database.connect_options())
+            .await
+            .map_err(unreachable)?;
+        if migrate {
+            sqlx::migrate!().run(&read_model).await?;
+        }
+        self_check::migrations(&read_model, &config.read_model_schema).await?;
+        check.migrations = true;
+        self_check::checkpoints(&pool, migrate).await?;
+        check.checkpoints = true;
+        let mut pools = vec![("event_store", pool), ("read_model", read_model.clone())];
+
+        let listener = if database.listener_max_connections.is_some() {
+            let pool = database
+                .listener_pool_options()
+                .connect_with(database.connect_options())
+                .await?;
+            let listener_read_model = config
+                .read_model_schema
+                .connect(database.listener_pool_options(), database.connect_options())
+                .await?;
+            pools.push(("listener_event_store", pool.clone()));
+            pools.push(("listener_read_model", listener_read_model.clone()));
+            ListenerStores {
+                event_store: PgEventStore::new(pool, serde).await?,
+                read_model: listener_read_model,
+            }
+        } else {
+            ListenerStores {
+                event_store: event_store.clone(),
+                read_model: read_model.clone(),
+            }
+        };
+        Ok(Self {
+            event_store,
+            snapshotter,
+            read_model,
+            listener,
+            pools,
+            check,
+        })
+    }
+}
+
+/// Whether `err` may pass once the database is up: refused, timed out, or the server still
+/// starting or full.
+fn transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(err) => matches!(err.code().as_deref(), Some("57P03" | "53300")),
+        _ => false,
+    }
+}
+
+/// The application deciding the commands as configured, auditing them in the read model.
+pub async fn application(config: &AppConfig, stores: &Stores) -> anyhow::Result<Application> {
+    let decision_maker = if config.snapshots_enabled {
+        DecisionMaker::snapshotted(stores.event_store.clone(), stores.snapshotter.clone())
+    } else {
+        tracing::warn!("snapshots disabled, the decisions fold every event of their state");
+        DecisionMaker::unsnapshotted(stores.event_store.clone())
+    };
+    Ok(Application::new(decision_maker, stores.event_store.clone())
+        .with_conflict_retries(config.decisions.conflict_retries)
+        .with_conflict_backoff(config.decisions.conflict_backoff)
+        .with_decision_timeout(config.decisions.timeout)
+        .with_command_audit(stores.read_model.clone()))
+}
+
+/// Seeds the demo data through the decisions, the projections catching up once serving.
+pub async fn seed(config: &AppConfig, stores: &Stores) -> anyhow::Result<Seeded> {
+    let seeded = seed::run(&application(config, stores).await?, config.seed).await?;
+    tracing::info!(
+        applied = seeded.applied,
+        skipped = seeded.skipped,
+        "seeded the demo data"
+    );
+    Ok(seeded)
+}
+
+/// Seals the events as `pii::seal_events` does, under the keys of the customers.
+pub async fn seal_events(stores: &Stores) -> anyhow::Result<u64> {
+    let sealed = pii::seal_events(&stores.read_model, &EncryptedJson::default()).await?;
+    tracing::info!(sealed, "sealed the events");
+    Ok(sealed)
+}
+
+/// Shreds the key of the customer `email` of `tenant_id` in `PII_KEY_DIR`.
+pub fn shred_customer(config: &AppConfig, tenant_id: &str, email: &str) -> anyhow::Result<()> {
+    let Some(dir) = &config.pii_key_dir else {
+        anyhow::bail!("PII_KEY_DIR: must be set to shred a key, the derived ones can't be");
+    };
+    pii::KeyStore::open(dir)?.shred(tenant_id, email)?;
+    tracing::info!("shredded the key of the customer");
+    Ok(())
+}
+
+/// Removes the snapshots older than `max_age_days`, `SNAPSHOT_MAX_AGE_DAYS` unless given, and
+/// those of the vehicles decommissioned.
+pub async fn prune_snapshots(
+    config: &AppConfig,
+    stores: &Stores,
+    max_age_days: Option<u64>,
+) -> anyhow::Result<PrunedSnapshots> {
+    let max_age = max_age_days.map_or(config.snapshot_max_age, |days| {
+        Duration::from_secs(days * 24 * 60 * 60)
+    });
+    Ok(admin::prune_snapshots(&stores.read_model, max_age).await?)
+}
+
+/// Empties the projection of `listener_id` and runs the listeners until it has handled again
+/// every event it had handled, along with the events the others hadn't handled yet.
+pub async fn replay(
+    config: &AppConfig,
+    stores: &Stores,
+    listener_id: &str,
+) -> anyhow::Result<Rebuild> {
+    let Some(rebuild) = admin::rebuild(&stores.read_model, listener_id).await? else {
+        let known: Vec<_> = admin::rebuildable().collect();
+        anyhow::bail!(
+            "unknown listener {listener_id}, expected one of {}",
+            known.join(", ")
+        );
+    };
+    let pool = stores.read_model.clone();
+    let interval = config.listener.poll_interval;
+    let caught_up = async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            match admin::rebuild_in_progress(&pool).await {
+                Ok(false) => break,
+                Ok(true) => {}
+                Err(err) => tracing::warn!(error = %err, "failed to check the replay"),
+            }
+        }
+    };
+    crate::event_listener(
+        stores.listener.read_model.clone(),
+        stores.listener.event_store.clone(),
+        Readiness::default(),
+        LiveUpdates::new(Shutdown::default()),
+        config.listener.clone(),
+        reporting::noop(),
+        caught_up,
+    )
+    .await?;
+    tracing::info!(
+        listener_id,
+        target_event_id = rebuild.target_event_id,
+        "projection replayed"
+    );
+    Ok(rebuild)
+}
+
+/// The version of the envelope of the exported events, raised whenever a field of theirs
+/// changes meaning or goes away; fields may be added without raising it.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// How many events are exported between two flushes of the file.
+const EXPORT_FLUSH_EVERY: usize = 1000;
+
+/// An event as exported, one per line, e.g.
+/// `{"version":1,"eventId":7,"eventType":"VehicleRented","insertedAt":"2026-10-14T09:30:00Z",
+/// "identifiers":{"customer_id":"5f0c...",...},"payload":{"VehicleRented":{...}}}`.
+///
+/// The payload is the `DomainEvent` read back from the event store, its personal fields
+/// opened; the identifiers are those the event is queried by, the customers by the token of
+/// their email, and `insertedAt` is when it was appended.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedEvent<'a> {
+    version: u32,
+    event_id: i64,
+    event_type: &'static str,
+    inserted_at: DateTime<Utc>,
+    identifiers: BTreeMap<String, String>,
+    payload: &'a DomainEvent,
+}
+
+/// Writes the events past `after`, of `event_type` when given, to `out` as NDJSON, the oldest
+/// first, returning how many.
+///
+/// The events are read from the table of the event store as they're written, flushing the
+/// file every `EXPORT_FLUSH_EVERY` events, so that no store is too large to be exported.
+pub async fn export_events(
+    stores: &Stores,
+    out: &Path,
+    after: Option<i64>,
+    event_type: Option<&str>,
+) -> anyhow::Result<usize> {
+    if let Some(event_type) = event_type {
+        if !DomainEvent::SCHEMA.types.contains(&event_type) {
+            anyhow::bail!(
+                "--type: must be one of {}",
+                DomainEvent::SCHEMA.types.join(", ")
+            );
+        }
+    }
+    let mut file = BufWriter::new(File::create(out)?);
+    let mut rows = sqlx::query_as::<_, (i64, Vec<u8>, DateTime<Utc>)>(
+        r#"SELECT event_id, payload, inserted_at AT TIME ZONE current_setting('TimeZone')
+            FROM public.event
+            WHERE event_id > $1 AND ($2::text IS NULL OR event_type = $2)
+            ORDER BY event_id"#,
+    )
+    .bind(after.unwrap_or(0))
+    .bind(event_type)
+    .fetch(&stores.read_model);
+    let serde = EncryptedJson::default();
+    let mut exported = 0;
+    while let Some((event_id, payload, inserted_at)) = rows.try_next().await? {
+        let event = serde.deserialize(payload)?;
+        let exported_event = ExportedEvent {
+            version: EXPORT_VERSION,
+            event_id,
+            event_type: event.name(),
+            inserted_at,
+            identifiers: event
+                .domain_identifiers()
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            payload: &event,
+        };
+        serde_json::to_writer(&mut file, &exported_event)?;
+        file.write_all(b"\n")?;
+        exported += 1;
+        if exported % EXPORT_FLUSH_EVERY == 0 {
+            file.flush()?;
+            tracing::info!(exported, event_id, "exporting the events");
+        }
+    }
+    file.flush()?;
+    tracing::info!(exported, out = %out.display(), "exported the events");
+    Ok(exported)
+}
+
+/// How many events are appended at a time by an import.
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// How many of the lines that can't be imported are told.
+const IMPORT_FAILURES_TOLD: usize = 20;
+
+/// An event as imported, the fields added to later versions of the envelope being ignored.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportedEvent {
+    version: u32,
+    event_type: String,
+    inserted_at: DateTime<Utc>,
+    payload: DomainEvent,
+}
+
+/// The event of a line of an export, read as the current `DomainEvent`, its fields added since
+/// taking their default.
+fn imported_event(line: &str) -> Result<ImportedEvent, String> {
+    let imported: ImportedEvent = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    if imported.version > EXPORT_VERSION {
+        return Err(format!(
+            "version {} is newer than {EXPORT_VERSION}, the one this build reads",
+            imported.version
+        ));
+    }
+    if imported.event_type != imported.payload.name() {
+        return Err(format!(
+            "eventType {} doesn't match the payload, a {}",
+            imported.event_type,
+            imported.payload.name()
+        ));
+    }
+    Ok(imported)
+}
+
+/// The events of `input`, each along with its line number, the blank lines left out.
+fn imported_events(
+    input: &Path,
+) -> anyhow::Result<impl Iterator<Item = std::io::Result<(usize, Result<ImportedEvent, String>)>>> {
+    let lines = BufReader::new(File::open(input)?).lines();
+    Ok(lines.enumerate().filter_map(|(index, line)| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(Ok((index + 1, imported_event(&line)))),
+        Err(err) => Some(Err(err)),
+    }))
+}
+
+/// Appends the events of `input`, as written by `export_events`, to the event store, returning
+/// how many; the projections then handle them as any other event.
+///
+/// Every line is checked before any event is appended, failing with the lines that can't be
+/// imported; a `dry_run` stops there. The event store must be empty unless `force_append` is
+/// set, and the events get the ids that follow its last one, in the order of the file, keeping
+/// when they were appended first.
+pub async fn import_events(
+    stores: &Stores,
+    input: &Path,
+    dry_run: bool,
+    force_append: bool,
+) -> anyhow::Result<usize> {
+    let mut events = 0;
+    let mut failures = Vec::new();
+    for line in imported_events(input)? {
+        match line? {
+            (_, Ok(_)) => events += 1,
+            (number, Err(err)) => failures.push(format!("line {number}: {err}")),
+        }
+    }
+    if !failures.is_empty() {
+        let more = failures.len().saturating_sub(IMPORT_FAILURES_TOLD);
+        failures.truncate(IMPORT_FAILURES_TOLD);
+        if more > 0 {
+            failures.push(format!("and {more} more"));
+        }
+        anyhow::bail!(
+            "{} can't be imported:\n{}",
+            input.display(),
+            failures.join("\n")
+        );
+    }
+    if dry_run {
+        tracing::info!(events, input = %input.display(), "checked the events to import");
+        return Ok(0);
+    }
+
+    let mut version: i64 =
+        sqlx::query_scalar("SELECT coalesce(max(event_id), 0) FROM public.event")
+            .fetch_one(&stores.read_model)
+            .await?;
+    if version > 0 && !force_append {
+        anyhow::bail!(
+            "the event store already holds events, up to {version}; import into an empty one, \
+             or pass --force-append"
+        );
+    }
+    let mut imported = 0;
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut inserted_at = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut lines = imported_events(input)?.peekable();
+    while let Some(line) = lines.next() {
+        let (_, event) = line?;
+        let event = event.map_err(anyhow::Error::msg)?;
+        batch.push(event.payload);
+        inserted_at.push(event.inserted_at);
+        if batch.len() < IMPORT_BATCH_SIZE && lines.peek().is_some() {
+            continue;
+        }
+        // The events past `version` are the only ones checked for a conflict, so that an
+        // append of the import never scans the events before it.
+        let appended = stores
+            .event_store
+            .append(
+                std::mem::take(&mut batch),
+                disintegrate::query::<DomainEvent>(None),
+                version,
+            )
+            .await?;
+        let ids: Vec<i64> = appended.iter().map(|event| event.id()).collect();
+        sqlx::query(
+            r#"UPDATE public.event SET inserted_at = imported.inserted_at AT TIME ZONE current_setting('TimeZone')
+                FROM unnest($1::bigint[], $2::timestamptz[]) AS imported(event_id, inserted_at)
+                WHERE event.event_id = imported.event_id"#,
+        )
+        .bind(&ids)
+        .bind(std::mem::take(&mut inserted_at))
+        .execute(&stores.read_model)
+        .await?;
+        imported += appended.len();
+        version = ids.last().copied().unwrap_or(version);
+        tracing::info!(imported, events, "importing the events");
+    }
+    tracing::info!(imported, input = %input.display(), "imported the events");
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::DatabaseConfig,
+        daily_stats::DailyStatsProjection,
+        domain::{default_tenant, VehicleType, DEFAULT_TENANT},
+        read_model::{CustomerProjection, RentalProjection, VehicleProjection},
+        seed::SeedConfig,
+        vehicle_stats::VehicleStatsProjection,
+    };
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    fn config(options: PgConnectOptions) -> AppConfig {
+        AppConfig {
+            database: DatabaseConfig {
+                options,
+                max_connections: 2,
+                ..AppConfig::default().database
+            },
+            read_model_schema: "test_read_model".parse().unwrap(),
+            seed: SeedConfig {
+                customers: 3,
+                vehicles: 4,
+                rentals: 2,
+                rng: 7,
+            },
+            ..AppConfig::default()
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_migrate_the_read_model_again_without_changes(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let config = config(options);
+        Stores::connect(&config, true).await.unwrap();
+        let stores = Stores::connect(&config, true).await.unwrap();
+        let schema: String = sqlx::query_scalar(
+            "SELECT table_schema::text FROM information_schema.tables WHERE table_name = 'vehicle'",
+        )
+        .fetch_one(&stores.read_model)
+        .await
+        .unwrap();
+        assert_eq!(schema, "test_read_model");
+    }
+
+    #[tokio::test]
+    async fn it_should_tell_which_database_is_unreachable() {
+        let mut config = config(PgConnectOptions::new().host("127.0.0.1").port(1));
+        config.database.acquire_timeout = std::time::Duration::from_millis(500);
+        config.database.connect_max_wait = std::time::Duration::ZERO;
+        let error = Stores::connect(&config, true).await.err().unwrap();
+        assert!(
+            error
+                .to_string()
+                .starts_with("the database postgres on 127.0.0.1:1 is unreachable: "),
+            "{error}"
+        );
+        assert!(error
+            .to_string()
+            .ends_with("; check DATABASE_URL, or the PG* variables"));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_wait_for_the_database_to_accept_the_connections(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        // A port refusing the connections, then proxying them to the database after a while.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let database = format!("{}:{}", options.get_host(), options.get_port());
+        let proxy = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+                .await
+                .unwrap();
+            loop {
+                let (mut inbound, _) = listener.accept().await.unwrap();
+                let database = database.clone();
+                tokio::spawn(async move {
+                    let mut outbound = tokio::net::TcpStream::connect(database).await.unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                });
+            }
+        });
+        let mut config = config(options.host("127.0.0.1").port(port));
+        config.database.acquire_timeout = std::time::Duration::from_millis(200);
+        config.database.connect_max_wait = std::time::Duration::from_secs(20);
+        let stores = Stores::connect(&config, true).await.unwrap();
+        assert!(stores.check.passed());
+        proxy.abort();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_tell_what_an_empty_database_is_missing(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let config = config(options.clone());
+        let error = Stores::connect(&config, false).await.err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the event store tables are missing: event, event_sequence, snapshot; run \
+             `car-rental migrate`, or start with DATABASE_AUTO_MIGRATE=true"
+        );
+
+        let pool = PgPool::connect_with(options).await.unwrap();
+        PgSnapshotter::new(pool.clone(), 10).await.unwrap();
+        PgEventStore::new(pool, EncryptedJson::default())
+            .await
+            .unwrap();
+        let error = Stores::connect(&config, false).await.err().unwrap();
+        let pending = sqlx::migrate!()
+            .iter()
+            .map(|migration| format!("{}_{}", migration.version, migration.description))
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "the migrations of the read model test_read_model are not applied: {pending}; \
+                 run `car-rental migrate`, or start with DATABASE_AUTO_MIGRATE=true"
+            )
+        );
+
+        Stores::connect(&config, true).await.unwrap();
+        let stores = Stores::connect(&config, false).await.unwrap();
+        assert!(stores.check.passed());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_seal_the_events_recorded_in_plaintext_before_starting(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let config = config(options);
+        let stores = Stores::connect(&config, true).await.unwrap();
+        let registered = DomainEvent::CustomerRegistered {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: None,
+        };
+        stores
+            .event_store
+            .append(vec![registered], disintegrate::query!(DomainEvent), 0)
+            .await
+            .unwrap();
+        // Indexed by the email itself, as before sealing.
+        sqlx::query("UPDATE public.event SET customer_id = 'mario@example.com'")
+            .execute(&stores.read_model)
+            .await
+            .unwrap();
+
+        let error = Stores::connect(&config, false).await.err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "events of public.event were recorded in plaintext, before sealing; run \
+             `car-rental seal-events`, or start with DATABASE_AUTO_MIGRATE=true"
+        );
+        let stores = Stores::connect(&config, true).await.unwrap();
+        assert!(stores.check.passed());
+        let customer_id: String = sqlx::query_scalar("SELECT customer_id FROM public.event")
+            .fetch_one(&stores.read_model)
+            .await
+            .unwrap();
+        assert_eq!(customer_id, pii::token("mario@example.com"));
+        assert!(Stores::connect(&config, false).await.is_ok());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_seed_and_export_the_events(_: PgPoolOptions, options: PgConnectOptions) {
+        let config = config(options);
+        let stores = Stores::connect(&config, true).await.unwrap();
+        let seeded = seed(&config, &stores).await.unwrap();
+        assert!(seeded.applied >= 7, "{seeded:?}");
+
+        let again = seed(&config, &stores).await.unwrap();
+        assert!(again.skipped >= 7, "{again:?}");
+
+        let out = std::env::temp_dir().join(format!("car-rental-{}-events", std::process::id()));
+        let read = |out: &Path| -> Vec<serde_json::Value> {
+            std::fs::read_to_string(out)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        let exported = export_events(&stores, &out, None, None).await.unwrap();
+        let lines = read(&out);
+        assert_eq!(lines.len(), exported);
+        assert!(lines.len() >= seeded.applied + again.applied);
+        assert_eq!(lines[0]["version"], EXPORT_VERSION);
+        assert_eq!(lines[0]["eventType"], "CustomerRegistered");
+        assert!(lines[0]["identifiers"]["customer_id"].is_string());
+        assert!(lines[0]["insertedAt"].is_string());
+        assert!(lines
+            .windows(2)
+            .all(|pair| pair[0]["eventId"].as_i64() < pair[1]["eventId"].as_i64()));
+        for line in &lines {
+            let event: DomainEvent = serde_json::from_value(line["payload"].clone()).unwrap();
+            assert_eq!(line["eventType"], event.name());
+        }
+
+        let after = lines[2]["eventId"].as_i64().unwrap();
+        let exported = export_events(&stores, &out, Some(after), Some("VehicleAdded"))
+            .await
+            .unwrap();
+        let vehicles = lines
+            .iter()
+            .filter(|line| line["eventType"] == "VehicleAdded")
+            .filter(|line| line["eventId"].as_i64().unwrap() > after)
+            .count();
+        assert_eq!(exported, vehicles);
+        assert_eq!(read(&out).len(), vehicles);
+
+        let error = export_events(&stores, &out, None, Some("VehicleStolen"))
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().starts_with("--type: must be one of "),
+            "{error}"
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_export_the_events_appended_as_they_were(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let config = config(options);
+        let stores = Stores::connect(&config, true).await.unwrap();
+        let events = vec![
+            DomainEvent::CustomerRegistered {
+                tenant_id: default_tenant(),
+                customer_id: "mario@example.com".into(),
+                first_name: "Mario".to_string(),
+                last_name: "Rossi".to_string(),
+                phone: Some("+39 333 1234567".to_string()),
+            },
+            DomainEvent::VehicleAdded {
+                tenant_id: default_tenant(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Car,
+                seats: Some(5),
+                transmission: None,
+            },
+            DomainEvent::VehicleRented {
+                tenant_id: default_tenant(),
+                customer_id: "mario@example.com".into(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Car,
+                start_date: Utc::now(),
+            },
+        ];
+        stores
+            .event_store
+            .append(events.clone(), disintegrate::query!(DomainEvent), 0)
+            .await
+            .unwrap();
+
+        let out = std::env::temp_dir().join(format!("car-rental-{}-appended", std::process::id()));
+        assert_eq!(
+            export_events(&stores, &out, None, None).await.unwrap(),
+            events.len()
+        );
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let exported: Vec<DomainEvent> = lines
+            .iter()
+            .map(|line| serde_json::from_value(line["payload"].clone()).unwrap())
+            .collect();
+        assert_eq!(exported, events);
+        assert_eq!(
+            lines[2]["identifiers"],
+            serde_json::json!({
+                "customer_id": crate::pii::token("mario@example.com"),
+                "tenant_id": DEFAULT_TENANT,
+                "vehicle_id": "AA111AA",
+                "vehicle_type": "car",
+            })
+        );
+    }
+
+    const PROJECTIONS: [(&str, &[&str]); 5] = [
+        (CustomerProjection::ID, CustomerProjection::TABLES),
+        (VehicleProjection::ID, VehicleProjection::TABLES),
+        (RentalProjection::ID, RentalProjection::TABLES),
+        (DailyStatsProjection::ID, DailyStatsProjection::TABLES),
+        (VehicleStatsProjection::ID, VehicleStatsProjection::TABLES),
+    ];
+
+    /// Runs the listeners until the projections have handled every event, returning the rows
+    /// of their tables.
+    async fn project(config: &AppConfig, stores: &Stores) -> Vec<String> {
+        let pool = stores.read_model.clone();
+        let ids: Vec<&str> = PROJECTIONS.iter().map(|(id, _)| *id).collect();
+        let caught_up = async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let behind: bool = sqlx::query_scalar(
+                    r#"SELECT EXISTS (SELECT 1 FROM unnest($1::text[]) AS projection(id)
+                        LEFT JOIN event_listener l ON l.id = projection.id
+                        WHERE coalesce(l.last_processed_event_id, 0)
+                            < (SELECT coalesce(max(event_id), 0) FROM event))"#,
+                )
+                .bind(&ids)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+                if !behind {
+                    break;
+                }
+            }
+        };
+        crate::event_listener(
+            stores.listener.read_model.clone(),
+            stores.listener.event_store.clone(),
+            Readiness::default(),
+            LiveUpdates::new(Shutdown::default()),
+            config.listener.clone(),
+            reporting::noop(),
+            caught_up,
+        )
+        .await
+        .unwrap();
+        let mut rows = Vec::new();
+        for table in PROJECTIONS.iter().flat_map(|(_, tables)| *tables) {
+            let table_rows: String = sqlx::query_scalar(&format!(
+                "SELECT coalesce(json_agg(t ORDER BY t::text), '[]')::text FROM {table} t"
+            ))
+            .fetch_one(&stores.read_model)
+            .await
+            .unwrap();
+            rows.push(format!("{table}: {table_rows}"));
+        }
+        rows
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_import_the_events_exported_into_an_empty_store(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let config_a = config(options.clone());
+        let stores = Stores::connect(&config_a, true).await.unwrap();
+        seed(&config_a, &stores).await.unwrap();
+        let projected = project(&config_a, &stores).await;
+        let out = std::env::temp_dir().join(format!("car-rental-{}-import", std::process::id()));
+        let exported = export_events(&stores, &out, None, None).await.unwrap();
+
+        let database = format!("{}_imported", options.get_database().unwrap());
+        let admin = PgPool::connect_with(options.clone()).await.unwrap();
+        sqlx::query(&format!(
+            r#"DROP DATABASE IF EXISTS "{database}" WITH (FORCE)"#
+        ))
+        .execute(&admin)
+        .await
+        .unwrap();
+        sqlx::query(&format!(r#"CREATE DATABASE "{database}""#))
+            .execute(&admin)
+            .await
+            .unwrap();
+        let config_b = config(options.database(&database));
+        let imported = Stores::connect(&config_b, true).await.unwrap();
+
+        assert_eq!(
+            import_events(&imported, &out, true, false).await.unwrap(),
+            0
+        );
+        assert_eq!(
+            import_events(&imported, &out, false, false).await.unwrap(),
+            exported
+        );
+        assert_eq!(project(&config_b, &imported).await, projected);
+
+        let error = import_events(&imported, &out, false, false)
+            .await
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("the event store already holds events"),
+            "{error}"
+        );
+
+        let lines = std::fs::read_to_string(&out).unwrap();
+        let first = lines.lines().next().unwrap();
+        std::fs::write(
+            &out,
+            format!(
+                "{first}\n\n{}\n{}\n",
+                first.replace(r#""version":1"#, r#""version":2"#),
+                first.replace("CustomerRegistered", "CustomerBanned")
+            ),
+        )
+        .unwrap();
+        let error = import_events(&imported, &out, true, true)
+            .await
+            .unwrap_err()
+            .to_string();
+        let failures: Vec<&str> = error.lines().skip(1).collect();
+        assert_eq!(failures.len(), 2, "{error}");
+        assert!(
+            failures[0].starts_with("line 3: version 2 is newer than 1"),
+            "{error}"
+        );
+        assert!(
+            failures[1].starts_with("line 4: unknown variant `CustomerBanned`"),
+            "{error}"
+        );
+
+        drop(imported);
+        sqlx::query(&format!(r#"DROP DATABASE "{database}" WITH (FORCE)"#))
+            .execute(&admin)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+use std::{fmt::Display, future::Future, str::FromStr, time::Duration};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+
+use crate::{
+    application::{ApplicationError, ApplicationResult, RedactedEmail},
+    auth,
+    domain::{EndRent, RegisterCustomer, RegisterVehicle, StartRent, TenantId},
+    errors::ErrorCode,
+    filters::CommandAuditFilter,
+    pagination::PageParams,
+    request_id::RequestId,
+};
+
+/// Stands for the personal data left out of the audited payloads.
+const REDACTED: &str = "***";
+
+tokio::task_local! {
+    static ACTOR: Option<String>;
+}
+
+/// The payload of a command as the audit log records it, its personal data redacted.
+pub trait Redact {
+    fn redacted(&self) -> serde_json::Value;
+}
+
+impl Redact for RegisterVehicle {
+    fn redacted(&self) -> serde_json::Value {
+        json!({
+            "vehicleId": self.vehicle_id,
+            "vehicleType": self.vehicle_type,
+            "seats": self.seats,
+            "transmission": self.transmission,
+        })
+    }
+}
+
+impl Redact for RegisterCustomer {
+    fn redacted(&self) -> serde_json::Value {
+        json!({
+            "customerId": RedactedEmail(&self.customer_id).to_string(),
+            "firstName": REDACTED,
+            "lastName": REDACTED,
+            "phone": self.phone.as_ref().map(|_| REDACTED),
+        })
+    }
+}
+
+impl Redact for StartRent {
+    fn redacted(&self) -> serde_json::Value {
+        json!({
+            "customerId": RedactedEmail(&self.customer_id).to_string(),
+            "vehicleType": self.vehicle_type,
+        })
+    }
+}
+
+impl Redact for EndRent {
+    fn redacted(&self) -> serde_json::Value {
+        json!({ "customerId": RedactedEmail(&self.customer_id).to_string() })
+    }
+}
+
+/// How a command ended, the error code telling why when it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandOutcome {
+    Success,
+    /// Rejected by its decision, leaving no event.
+    DomainError,
+    /// The store failing, a conflict past the retries or a timeout.
+    InfrastructureError,
+}
+
+impl CommandOutcome {
+    const ALL: [CommandOutcome; 3] = [
+        CommandOutcome::Success,
+        CommandOutcome::DomainError,
+        CommandOutcome::InfrastructureError,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            CommandOutcome::Success => "success",
+            CommandOutcome::DomainError => "domain_error",
+            CommandOutcome::InfrastructureError => "infrastructure_error",
+        }
+    }
+}
+
+impl Display for CommandOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CommandOutcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|outcome| outcome.as_str() == s)
+            .ok_or_else(|| format!("unknown command outcome: {s}"))
+    }
+}
+
+/// A command executed by the application: who sent it, what it asked for, how it ended and
+/// how long it took.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditedCommand {
+    pub id: i64,
+    pub command: String,
+    pub payload: serde_json::Value,
+    pub actor: Option<String>,
+    /// The request id of the request that sent it.
+    pub correlation_id: Option<String>,
+    /// `success`, `domain_error` or `infrastructure_error`.
+    pub outcome: String,
+    pub error_code: Option<String>,
+    pub latency_ms: f64,
+    pub executed_at: DateTime<Utc>,
+}
+
+/// Writes a row to `command_audit` for the command, in the background: the command neither
+/// waits for it nor fails along with it, failures being only logged.
+///
+/// The actor is the one of the request being handled, and the correlation id its request id.
+pub fn record<T>(
+    pool: &PgPool,
+    command: &'static str,
+    tenant_id: &TenantId,
+    payload: serde_json::Value,
+    result: &ApplicationResult<T>,
+    latency: Duration,
+) {
+    let (outcome, error_code) = match result {
+        Ok(_) => (CommandOutcome::Success, None),
+        Err(ApplicationError::Domain(error)) => {
+            (CommandOutcome::DomainError, Some(ErrorCode::from(error)))
+        }
+        Err(error) => (
+            CommandOutcome::InfrastructureError,
+            Some(ErrorCode::from(error)),
+        ),
+    };
+    let actor = ACTOR.try_with(Clone::clone).ok().flatten();
+    let correlation_id = RequestId::current().map(|id| id.to_string());
+    let (pool, tenant_id) = (pool.clone(), tenant_id.clone());
+    tokio::spawn(async move {
+        let inserted = sqlx::query(
+            r#"INSERT INTO command_audit
+                (command, payload, actor, correlation_id, outcome, error_code, latency_ms, tenant_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+        )
+        .bind(command)
+        .bind(payload)
+        .bind(actor)
+        .bind(correlation_id)
+        .bind(outcome.as_str())
+        .bind(error_code.map(|code| code.to_string()))
+        .bind(latency.as_secs_f64() * 1000.0)
+        .bind(tenant_id)
+        .execute(&pool)
+        .await;
+        if let Err(err) = inserted {
+            tracing::warn!(error = %err, command, "failed to audit a command");
+        }
+    });
+}
+
+/// Middleware making the actor of every request available to the audit of the commands it
+/// sends; it runs after `ApiKeys`, which authenticates it.
+pub fn scope_actor<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let actor = auth::actor(&req);
+    let response = ACTOR.sync_scope(actor.clone(), || srv.call(req));
+    ACTOR.scope(actor, response)
+}
+
+/// A page of the audited commands of the tenant matching the filter, the latest first, along
+/// with their total.
+pub async fn audited_commands(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+    filter: &CommandAuditFilter,
+    page: PageParams,
+) -> Result<(Vec<AuditedCommand>, i64), sqlx::Error> {
+    let mut select = QueryBuilder::new(
+        r#"SELECT id, command, payload, actor, correlation_id, outcome, error_code, latency_ms,
+            executed_at FROM command_audit"#,
+    );
+    push_filter(&mut select, tenant_id, filter);
+    select
+        .push(" ORDER BY id DESC LIMIT ")
+        .push_bind(page.limit)
+        .push(" OFFSET ")
+        .push_bind(page.offset);
+    let commands = select.build_query_as().fetch_all(pool).await?;
+    let mut count = QueryBuilder::new("SELECT count(*) FROM command_audit");
+    push_filter(&mut count, tenant_id, filter);
+    let total = count.build_query_scalar().fetch_one(pool).await?;
+    Ok((commands, total))
+}
+
+fn push_filter(
+    builder: &mut QueryBuilder<Postgres>,
+    tenant_id: &TenantId,
+    filter: &CommandAuditFilter,
+) {
+    builder
+        .push(" WHERE tenant_id = ")
+        .push_bind(tenant_id.clone());
+    if let Some(command) = filter.command {
+        builder.push(" AND command = ").push_bind(command);
+    }
+    if let Some(outcome) = filter.outcome {
+        builder.push(" AND outcome = ").push_bind(outcome.as_str());
+    }
+    if let Some(code) = filter.error_code {
+        builder
+            .push(" AND error_code = ")
+            .push_bind(code.to_string());
+    }
+    if let Some(actor) = &filter.actor {
+        builder.push(" AND actor = ").push_bind(actor.clone());
+    }
+    if let Some(correlation_id) = &filter.correlation_id {
+        builder
+            .push(" AND correlation_id = ")
+            .push_bind(correlation_id.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::{default_tenant, VehicleType};
+
+    #[test]
+    fn it_should_redact_the_personal_data_of_the_payloads() {
+        let command = RegisterCustomer {
+            tenant_id: default_tenant(),
+            customer_id: "mario.rossi@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: Some("+39 02 1234567".to_string()),
+        };
+        assert_eq!(
+            command.redacted(),
+            json!({
+                "customerId": "m***@example.com", "firstName": "***", "lastName": "***",
+                "phone": "***"
+            })
+        );
+        let command = StartRent {
+            tenant_id: default_tenant(),
+            customer_id: "mario.rossi@example.com".into(),
+            vehicle_type: VehicleType::Van,
+        };
+        assert_eq!(
+            command.redacted(),
+            json!({ "customerId": "m***@example.com", "vehicleType": "Van" })
+        );
+    }
+}
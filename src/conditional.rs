@@ -0,0 +1,77 @@
+use actix_web::{
+    error,
+    http::{
+        header::{ETag, EntityTag, IfMatch},
+        StatusCode,
+    },
+    HttpResponse,
+};
+
+use crate::errors::{ErrorBody, ErrorCode};
+
+/// Entity tag of a resource at `version`, the id of the last event applied to it.
+pub fn etag(version: i64) -> ETag {
+    ETag(EntityTag::new_strong(version.to_string()))
+}
+
+/// Checks `If-Match` against the current version of the resource, `None` when it doesn't
+/// exist.
+///
+/// A missing header is read as an empty list, which sets no condition.
+pub fn check(if_match: &IfMatch, version: Option<i64>) -> Result<(), PreconditionFailed> {
+    let matches = match (if_match, version) {
+        (IfMatch::Items(tags), _) if tags.is_empty() => true,
+        (_, None) => false,
+        (IfMatch::Any, Some(_)) => true,
+        (IfMatch::Items(tags), Some(version)) => {
+            let current = etag(version).0;
+            tags.iter().any(|tag| tag.strong_eq(&current))
+        }
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(PreconditionFailed)
+    }
+}
+
+/// `412 Precondition Failed`: the resource changed since the client read it.
+#[derive(Debug)]
+pub struct PreconditionFailed;
+
+impl std::fmt::Display for PreconditionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("the resource changed since it was read")
+    }
+}
+
+impl error::ResponseError for PreconditionFailed {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::PRECONDITION_FAILED
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody::new(
+            ErrorCode::PreconditionFailed,
+            self.to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_should_only_match_the_current_version() {
+        let tags =
+            |tags: &[&str]| IfMatch::Items(tags.iter().map(|tag| tag.parse().unwrap()).collect());
+        assert!(check(&tags(&["\"42\""]), Some(42)).is_ok());
+        assert!(check(&tags(&["\"41\"", "\"42\""]), Some(42)).is_ok());
+        assert!(check(&tags(&["\"41\""]), Some(42)).is_err());
+        assert!(check(&tags(&["W/\"42\""]), Some(42)).is_err());
+        assert!(check(&IfMatch::Any, Some(42)).is_ok());
+        assert!(check(&IfMatch::Any, None).is_err());
+        assert!(check(&tags(&[]), None).is_ok());
+    }
+}
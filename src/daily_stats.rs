@@ -0,0 +1,331 @@
+use crate::{
+    dead_letter,
+    domain::{RentEvent, TenantId, VehicleType},
+    filters::ReportPeriod,
+    read_model::queries::ReadModelRepository,
+    reporting::{self, ErrorReporter},
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use disintegrate::{query, Event, EventListener, PersistedEvent, StreamQuery};
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// Keeps per-day rental counters up to date as rent events arrive.
+///
+/// Counters are incremented rather than recomputed, so every processed event is recorded to
+/// make sure a redelivered one is not counted twice.
+pub struct DailyStatsProjection {
+    query: StreamQuery<RentEvent>,
+    pool: PgPool,
+    reporter: Arc<dyn ErrorReporter>,
+}
+
+impl DailyStatsProjection {
+    pub const ID: &'static str = "drive_me_crazy_daily_stats";
+    pub const TABLES: &'static [&'static str] = &[
+        "daily_stats",
+        "daily_stats_vehicle_type",
+        "daily_stats_customer",
+        "daily_stats_event",
+    ];
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            query: query(None),
+            pool,
+            reporter: reporting::noop(),
+        }
+    }
+
+    /// Reports the events set aside as dead letters to `reporter`.
+    pub fn with_error_reporter(mut self, reporter: Arc<dyn ErrorReporter>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    async fn apply(&self, event_id: i64, event: RentEvent) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let first_delivery = sqlx::query(
+            "INSERT INTO daily_stats_event (event_id) VALUES($1) ON CONFLICT (event_id) DO NOTHING",
+        )
+        .bind(event_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            == 1;
+        if !first_delivery {
+            return Ok(());
+        }
+        match event {
+            RentEvent::VehicleAdded { .. } => {}
+            RentEvent::VehicleRented {
+                tenant_id,
+                customer_id,
+                vehicle_id: _,
+                vehicle_type,
+                start_date,
+            } => {
+                let day = day_of(start_date);
+                let new_customer = sqlx::query(
+                    "INSERT INTO daily_stats_customer (tenant_id, day, customer_id) VALUES($1, $2, $3) ON CONFLICT DO NOTHING",
+                )
+                .bind(&tenant_id)
+                .bind(day)
+                .bind(customer_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+                sqlx::query(
+                    r#"INSERT INTO daily_stats (tenant_id, day, rentals_started, unique_customers) VALUES($1, $2, 1, $3)
+                        ON CONFLICT (tenant_id, day) DO UPDATE SET
+                            rentals_started = daily_stats.rentals_started + 1,
+                            unique_customers = daily_stats.unique_customers + $3"#,
+                )
+                .bind(&tenant_id)
+                .bind(day)
+                .bind(new_customer as i64)
+                .execute(&mut *tx)
+                .await?;
+                increment_vehicle_type(&mut tx, &tenant_id, day, vehicle_type, "rentals_started")
+                    .await?;
+            }
+            RentEvent::VehicleReturned {
+                tenant_id,
+                vehicle_type,
+                returned_date,
+                ..
+            } => {
+                let day = day_of(returned_date);
+                sqlx::query(
+                    r#"INSERT INTO daily_stats (tenant_id, day, rentals_ended) VALUES($1, $2, 1)
+                        ON CONFLICT (tenant_id, day) DO UPDATE SET rentals_ended = daily_stats.rentals_ended + 1"#,
+                )
+                .bind(&tenant_id)
+                .bind(day)
+                .execute(&mut *tx)
+                .await?;
+                increment_vehicle_type(&mut tx, &tenant_id, day, vehicle_type, "rentals_ended")
+                    .await?;
+            }
+        };
+        tx.commit().await
+    }
+}
+
+/// Days are calendar days in UTC.
+fn day_of(date: DateTime<Utc>) -> NaiveDate {
+    date.date_naive()
+}
+
+async fn increment_vehicle_type(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant_id: &TenantId,
+    day: NaiveDate,
+    vehicle_type: VehicleType,
+    counter: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        r#"INSERT INTO daily_stats_vehicle_type (tenant_id, day, vehicle_type, {counter}) VALUES($1, $2, $3, 1)
+            ON CONFLICT (tenant_id, day, vehicle_type) DO UPDATE SET {counter} = daily_stats_vehicle_type.{counter} + 1"#
+    ))
+    .bind(tenant_id)
+    .bind(day)
+    .bind(vehicle_type)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl EventListener<RentEvent> for DailyStatsProjection {
+    type Error = sqlx::Error;
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn query(&self) -> &StreamQuery<RentEvent> {
+        &self.query
+    }
+
+    #[tracing::instrument(skip_all, fields(listener_id = self.id(), event_id = event.id(), event_type = event.name()))]
+    async fn handle(&self, event: PersistedEvent<RentEvent>) -> Result<(), Self::Error> {
+        let (event_id, event_type) = (event.id(), event.name());
+        let result = self.apply(event_id, event.into_inner()).await;
+        dead_letter::settle(
+            &self.pool,
+            &*self.reporter,
+            self.id(),
+            event_id,
+            event_type,
+            result,
+        )
+        .await
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyStats {
+    pub day: NaiveDate,
+    pub rentals_started: i64,
+    pub rentals_ended: i64,
+    pub unique_customers: i64,
+    pub by_vehicle_type: Vec<DailyVehicleTypeStats>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyVehicleTypeStats {
+    pub vehicle_type: VehicleType,
+    pub rentals_started: i64,
+    pub rentals_ended: i64,
+}
+
+impl ReadModelRepository {
+    /// Lists the statistics of the days overlapping the period, in chronological order.
+    ///
+    /// Days without any rental activity are left out.
+    pub async fn daily_stats(&self, period: ReportPeriod) -> Result<Vec<DailyStats>, sqlx::Error> {
+        let (from, to) = (day_of(period.from), day_of(period.to));
+        let days: Vec<(NaiveDate, i64, i64, i64)> = sqlx::query_as(
+            r#"SELECT day, rentals_started, rentals_ended, unique_customers FROM daily_stats
+                WHERE tenant_id = $1 AND day BETWEEN $2 AND $3 ORDER BY day"#,
+        )
+        .bind(&self.tenant_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+        let types: Vec<(NaiveDate, VehicleType, i64, i64)> = sqlx::query_as(
+            r#"SELECT day, vehicle_type, rentals_started, rentals_ended FROM daily_stats_vehicle_type
+                WHERE tenant_id = $1 AND day BETWEEN $2 AND $3 ORDER BY day, vehicle_type"#,
+        )
+        .bind(&self.tenant_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut stats: Vec<DailyStats> = days
+            .into_iter()
+            .map(
+                |(day, rentals_started, rentals_ended, unique_customers)| DailyStats {
+                    day,
+                    rentals_started,
+                    rentals_ended,
+                    unique_customers,
+                    by_vehicle_type: vec![],
+                },
+            )
+            .collect();
+        for (day, vehicle_type, rentals_started, rentals_ended) in types {
+            if let Some(stats) = stats.iter_mut().find(|stats| stats.day == day) {
+                stats.by_vehicle_type.push(DailyVehicleTypeStats {
+                    vehicle_type,
+                    rentals_started,
+                    rentals_ended,
+                });
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{domain::default_tenant, test_support};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_roll_the_rentals_up_by_day_and_vehicle_type(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let projection = DailyStatsProjection::new(pool.clone());
+        let date = |date: &str| date.parse::<DateTime<Utc>>().unwrap();
+        let rented = |customer_id: &str, vehicle_type: VehicleType, start_date: &str| {
+            RentEvent::VehicleRented {
+                tenant_id: default_tenant(),
+                customer_id: customer_id.into(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type,
+                start_date: date(start_date),
+            }
+        };
+        let events = [
+            rented(
+                "mario@example.com",
+                VehicleType::Car,
+                "2024-07-01T09:00:00Z",
+            ),
+            rented(
+                "luigi@example.com",
+                VehicleType::Van,
+                "2024-07-01T10:00:00Z",
+            ),
+            RentEvent::VehicleReturned {
+                tenant_id: default_tenant(),
+                customer_id: "mario@example.com".into(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Car,
+                start_date: Some(date("2024-07-01T09:00:00Z")),
+                returned_date: date("2024-07-01T18:00:00Z"),
+            },
+            rented(
+                "mario@example.com",
+                VehicleType::Van,
+                "2024-07-01T19:00:00Z",
+            ),
+            rented(
+                "mario@example.com",
+                VehicleType::Car,
+                "2024-07-02T09:00:00Z",
+            ),
+        ];
+        for (event_id, event) in (1..).zip(events.clone()) {
+            projection.apply(event_id, event).await.unwrap();
+        }
+        // Delivered again, they are not counted twice.
+        for (event_id, event) in (1..).zip(events) {
+            projection.apply(event_id, event).await.unwrap();
+        }
+
+        let period = ReportPeriod {
+            from: date("2024-07-01T00:00:00Z"),
+            to: date("2024-07-03T00:00:00Z"),
+        };
+        let stats = ReadModelRepository::new(pool)
+            .daily_stats(period)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(stats).unwrap(),
+            serde_json::json!([
+                {
+                    "day": "2024-07-01",
+                    "rentalsStarted": 3,
+                    "rentalsEnded": 1,
+                    "uniqueCustomers": 2,
+                    "byVehicleType": [
+                        { "vehicleType": "Car", "rentalsStarted": 1, "rentalsEnded": 1 },
+                        { "vehicleType": "Van", "rentalsStarted": 2, "rentalsEnded": 0 }
+                    ]
+                },
+                {
+                    "day": "2024-07-02",
+                    "rentalsStarted": 1,
+                    "rentalsEnded": 0,
+                    "uniqueCustomers": 1,
+                    "byVehicleType": [
+                        { "vehicleType": "Car", "rentalsStarted": 1, "rentalsEnded": 0 }
+                    ]
+                }
+            ])
+        );
+    }
+}
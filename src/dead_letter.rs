@@ -0,0 +1,192 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{types::Json, PgPool};
+
+use crate::{
+    domain::{TenantId, DEFAULT_TENANT},
+    read_model::{is_permanent, queries::ReadModelRepository},
+    reporting::{ErrorContext, ErrorReporter},
+};
+
+/// Consecutive failures after which an event is set aside.
+pub const MAX_ATTEMPTS: i32 = 10;
+
+/// Decides what the listener does with the outcome of handling an event.
+///
+/// Failures due to the database being unreachable are retried indefinitely. Any other failure
+/// is retried up to `MAX_ATTEMPTS` times, or not at all when it can never succeed (e.g. a
+/// constraint violation); the event is then recorded as a dead letter and acknowledged, so
+/// that it doesn't block the events that follow, and reported to `reporter`.
+pub async fn settle(
+    pool: &PgPool,
+    reporter: &dyn ErrorReporter,
+    listener_id: &str,
+    event_id: i64,
+    event_type: &str,
+    result: Result<(), sqlx::Error>,
+) -> Result<(), sqlx::Error> {
+    let err = match result {
+        Ok(()) => return Ok(()),
+        Err(err) if is_unavailable(&err) => {
+            tracing::warn!(listener_id, event_id, event_type, error = %err, "database unavailable, the event will be retried");
+            return Err(err);
+        }
+        Err(err) => err,
+    };
+    let attempts = if is_permanent(&err) {
+        MAX_ATTEMPTS
+    } else {
+        record_failure(pool, listener_id, event_id).await?
+    };
+    if attempts < MAX_ATTEMPTS {
+        tracing::warn!(listener_id, event_id, event_type, attempts, error = %err, "failed to project event, it will be retried");
+        return Err(err);
+    }
+    record_dead_letter(pool, listener_id, event_id, event_type, &err).await?;
+    tracing::error!(listener_id, event_id, event_type, attempts, error = %err, "event set aside as a dead letter");
+    let context = ErrorContext {
+        listener_id: Some(listener_id.to_string()),
+        event_id: Some(event_id),
+        ..Default::default()
+    };
+    reporter.report(&err, &context);
+    Ok(())
+}
+
+/// Connection failures say nothing about the event, so they don't count as attempts.
+fn is_unavailable(err: &sqlx::Error) -> bool {
+    matches!(
+        err,
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed
+    )
+}
+
+async fn record_failure(
+    pool: &PgPool,
+    listener_id: &str,
+    event_id: i64,
+) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"INSERT INTO projection_failure (listener_id, event_id, attempts) VALUES($1, $2, 1)
+            ON CONFLICT (listener_id, event_id) DO UPDATE SET attempts = projection_failure.attempts + 1
+            RETURNING attempts"#,
+    )
+    .bind(listener_id)
+    .bind(event_id)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn record_dead_letter(
+    pool: &PgPool,
+    listener_id: &str,
+    event_id: i64,
+    event_type: &str,
+    err: &sqlx::Error,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // The dead letter belongs to the tenant of its event, the default one for the events
+    // recorded before the tenants.
+    sqlx::query(
+        r#"INSERT INTO projection_dead_letter (listener_id, event_id, event_type, payload, error, tenant_id)
+            VALUES($1, $2, $3, (SELECT convert_from(payload, 'UTF8')::jsonb FROM event WHERE event_id = $2), $4,
+                coalesce((SELECT nullif(tenant_id, '') FROM event WHERE event_id = $2), $5))
+            ON CONFLICT (listener_id, event_id) DO UPDATE SET error = $4, recorded_at = now()"#,
+    )
+    .bind(listener_id)
+    .bind(event_id)
+    .bind(event_type)
+    .bind(err.to_string())
+    .bind(DEFAULT_TENANT)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM projection_failure WHERE listener_id = $1 AND event_id = $2")
+        .bind(listener_id)
+        .bind(event_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+/// Forgets the failures counted for the listener, e.g. when it is rebuilt.
+pub async fn clear_failures(pool: &PgPool, listener_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM projection_failure WHERE listener_id = $1")
+        .bind(listener_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub id: i64,
+    pub listener_id: String,
+    pub event_id: i64,
+    pub event_type: String,
+    pub payload: Option<Json<serde_json::Value>>,
+    pub error: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl ReadModelRepository {
+    /// The dead letters of the tenant.
+    pub async fn dead_letters(&self) -> Result<Vec<DeadLetter>, sqlx::Error> {
+        sqlx::query_as(
+            r#"SELECT id, listener_id, event_id, event_type, payload, error, recorded_at
+                FROM projection_dead_letter WHERE tenant_id = $1 ORDER BY id"#,
+        )
+        .bind(&self.tenant_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+pub async fn find_dead_letter(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+    id: i64,
+) -> Result<Option<DeadLetter>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT id, listener_id, event_id, event_type, payload, error, recorded_at
+            FROM projection_dead_letter WHERE id = $1 AND tenant_id = $2"#,
+    )
+    .bind(id)
+    .bind(tenant_id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn remove(pool: &PgPool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM projection_dead_letter WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Returns the error the event is set aside with by the listener, if any.
+pub async fn dead_letter_error(
+    pool: &PgPool,
+    listener_id: &str,
+    event_id: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT error FROM projection_dead_letter WHERE listener_id = $1 AND event_id = $2",
+    )
+    .bind(listener_id)
+    .bind(event_id)
+    .fetch_optional(pool)
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_should_not_count_connection_failures_as_attempts() {
+        assert!(is_unavailable(&sqlx::Error::PoolTimedOut));
+        assert!(!is_unavailable(&sqlx::Error::RowNotFound));
+    }
+}
@@ -0,0 +1,390 @@
+use std::{collections::BTreeSet, future::Future, marker::PhantomData, time::Duration};
+
+use disintegrate::{Event, EventListener, EventStore as _};
+use futures_util::{future::BoxFuture, FutureExt, StreamExt};
+use sqlx::{
+    postgres::{PgListener, PgPoolOptions},
+    PgPool,
+};
+use tokio::sync::watch;
+
+use crate::{
+    config::{Delivery, ListenerConfig, Polling},
+    domain::DomainEvent,
+    self_check, EventStore,
+};
+
+/// Notified by the trigger of the `event` table once events are appended.
+pub const CHANNEL: &str = "event_appended";
+
+/// The listeners of the projections, polling or notified of the events as `ListenerConfig`
+/// tells: with `Delivery::Notify`, each one runs as soon as events are appended, as notified on
+/// `CHANNEL`, at most once per poll interval, and every fallback interval as well for the
+/// notifications missed.
+///
+/// The checkpoints are those of `PgEventListener`, so that it can take over from them.
+pub struct Listeners {
+    pool: PgPool,
+    event_store: EventStore,
+    delivery: Delivery,
+    fallback: Duration,
+    drain_timeout: Duration,
+    paused: BTreeSet<&'static str>,
+    executors: Vec<Box<dyn Execute>>,
+}
+
+impl Listeners {
+    pub fn new(pool: PgPool, event_store: EventStore, config: &ListenerConfig) -> Self {
+        Self {
+            pool,
+            event_store,
+            delivery: config.delivery,
+            fallback: config.fallback_poll_interval,
+            drain_timeout: config.drain_timeout,
+            paused: config.paused.clone(),
+            executors: Vec::new(),
+        }
+    }
+
+    /// Registers `listener`, unless it's paused.
+    pub fn register_listener<QE>(
+        mut self,
+        listener: impl EventListener<QE> + 'static,
+        polling: Polling,
+    ) -> Self
+    where
+        QE: TryFrom<DomainEvent> + Event + Send + Sync + Clone + 'static,
+        <QE as TryFrom<DomainEvent>>::Error: std::error::Error + Send + Sync + 'static,
+    {
+        if self.paused.contains(listener.id()) {
+            tracing::info!(listener_id = listener.id(), "paused");
+            return self;
+        }
+        self.executors.push(Box::new(Executor {
+            event_store: self.event_store.clone(),
+            listener,
+            polling,
+            _events: PhantomData,
+        }));
+        self
+    }
+
+    /// Runs the listeners until one of them fails, or `shutdown` resolves: they then stop
+    /// fetching events, finish the one they're handling and move their checkpoint past it,
+    /// within the drain timeout, after which they're dropped.
+    ///
+    /// Their checkpoints are locked through connections of their own, one per listener, so
+    /// that the projections never wait for a connection held by a checkpoint.
+    pub async fn start(self, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
+        let checkpoints = PgPoolOptions::new()
+            .max_connections(self.executors.len() as u32 + 1)
+            .connect_with((*self.pool.connect_options()).clone())
+            .await?;
+        sqlx::query(self_check::CREATE_CHECKPOINTS)
+            .execute(&checkpoints)
+            .await?;
+        for executor in &self.executors {
+            sqlx::query(
+                "INSERT INTO event_listener (id, last_processed_event_id) VALUES ($1, 0) ON CONFLICT (id) DO NOTHING",
+            )
+            .bind(executor.id())
+            .execute(&checkpoints)
+            .await?;
+        }
+        let notifications = match self.delivery {
+            Delivery::Poll => None,
+            Delivery::Notify => {
+                let mut notifications = PgListener::connect_with(&checkpoints).await?;
+                notifications.listen(CHANNEL).await?;
+                Some(notifications)
+            }
+        };
+        let (appended, _) = watch::channel(());
+        let (stopping, _) = watch::channel(false);
+        let runs = self.executors.iter().map(|executor| {
+            run(
+                executor.as_ref(),
+                &checkpoints,
+                notifications.is_some().then(|| appended.subscribe()),
+                self.fallback,
+                stopping.subscribe(),
+            )
+        });
+        let runs = futures_util::future::try_join_all(runs);
+        tokio::pin!(runs);
+        let woken = async {
+            match notifications {
+                Some(notifications) => wake(notifications, &appended, self.fallback).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = &mut runs => return Ok(result.map(drop)?),
+            () = woken => unreachable!("woken forever"),
+            () = shutdown => {}
+        }
+        stopping.send_replace(true);
+        match tokio::time::timeout(self.drain_timeout, runs).await {
+            Ok(result) => {
+                result?;
+                tracing::info!("drained the event listeners");
+            }
+            Err(_) => tracing::warn!(
+                drain_timeout = ?self.drain_timeout,
+                "dropped the event listeners still handling events"
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Wakes the listeners on every notification, and whenever the connection was lost, the
+/// notifications sent meanwhile being lost as well.
+async fn wake(mut notifications: PgListener, appended: &watch::Sender<()>, fallback: Duration) {
+    loop {
+        match notifications.try_recv().await {
+            Ok(_) => {
+                appended.send_replace(());
+            }
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to listen for the events appended");
+                tokio::time::sleep(fallback).await;
+            }
+        }
+    }
+}
+
+/// Runs `executor` at once, then every poll interval, or, when `appended` is given, whenever
+/// notified or the fallback interval elapsed, resting for its poll interval in between; until
+/// `stopping`.
+async fn run(
+    executor: &dyn Execute,
+    checkpoints: &PgPool,
+    mut appended: Option<watch::Receiver<()>>,
+    fallback: Duration,
+    mut stopping: watch::Receiver<bool>,
+) -> Result<(), sqlx::Error> {
+    let interval = executor.polling().interval;
+    while !*stopping.borrow() {
+        match executor.execute(checkpoints, &stopping).await {
+            // Retried at the next turn, as `PgEventListener` does.
+            Ok(()) | Err(sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut) => {}
+            Err(err) => return Err(err),
+        }
+        tokio::select! {
+            () = next_turn(interval, appended.as_mut(), fallback) => {}
+            _ = stopping.wait_for(|stopping| *stopping) => {}
+        }
+    }
+    Ok(())
+}
+
+async fn next_turn(
+    interval: Duration,
+    appended: Option<&mut watch::Receiver<()>>,
+    fallback: Duration,
+) {
+    tokio::time::sleep(interval).await;
+    let Some(appended) = appended else {
+        return;
+    };
+    tokio::select! {
+        // The sender lives as long as the listeners run.
+        _ = appended.changed() => {}
+        () = tokio::time::sleep(fallback.saturating_sub(interval)) => {}
+    }
+}
+
+trait Execute: Send + Sync {
+    fn id(&self) -> &'static str;
+    fn polling(&self) -> Polling;
+    /// Handles a batch of the events following the checkpoint, moving it past the ones handled,
+    /// the batch ending early once `stopping`.
+    fn execute<'a>(
+        &'a self,
+        checkpoints: &'a PgPool,
+        stopping: &'a watch::Receiver<bool>,
+    ) -> BoxFuture<'a, Result<(), sqlx::Error>>;
+}
+
+struct Executor<L, QE> {
+    event_store: EventStore,
+    listener: L,
+    polling: Polling,
+    _events: PhantomData<fn() -> QE>,
+}
+
+impl<L, QE> Execute for Executor<L, QE>
+where
+    L: EventListener<QE>,
+    QE: TryFrom<DomainEvent> + Event + Send + Sync + Clone + 'static,
+    <QE as TryFrom<DomainEvent>>::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn id(&self) -> &'static str {
+        self.listener.id()
+    }
+
+    fn polling(&self) -> Polling {
+        self.polling
+    }
+
+    fn execute<'a>(
+        &'a self,
+        checkpoints: &'a PgPool,
+        stopping: &'a watch::Receiver<bool>,
+    ) -> BoxFuture<'a, Result<(), sqlx::Error>> {
+        async move {
+            let mut tx = checkpoints.begin().await?;
+            // Skipped while another instance, or a rebuild, holds the checkpoint.
+            let checkpoint: Option<i64> = sqlx::query_scalar(
+                "SELECT last_processed_event_id FROM event_listener WHERE id = $1 FOR UPDATE SKIP LOCKED",
+            )
+            .bind(self.listener.id())
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(mut last_processed_event_id) = checkpoint else {
+                return Ok(());
+            };
+            let query = self
+                .listener
+                .query()
+                .clone()
+                .change_origin(last_processed_event_id);
+            let mut events = self
+                .event_store
+                .stream(&query)
+                .take(self.polling.batch_size);
+            // A failing event is handled again at the next turn, as with `PgEventListener`.
+            while !*stopping.borrow() {
+                let Some(Ok(event)) = events.next().await else {
+                    break;
+                };
+                let event_id = event.id();
+                if self.listener.handle(event).await.is_err() {
+                    break;
+                }
+                last_processed_event_id = event_id;
+            }
+            drop(events);
+            sqlx::query(
+                "UPDATE event_listener SET last_processed_event_id = $1, updated_at = now() WHERE id = $2",
+            )
+            .bind(last_processed_event_id)
+            .bind(self.listener.id())
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use disintegrate::{query, PersistedEvent, StreamQuery};
+    use disintegrate_postgres::PgEventStore;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    use super::*;
+    use crate::{
+        domain::{default_tenant, VehicleType},
+        shutdown::Shutdown,
+    };
+
+    /// Records the events it handles, slowly.
+    #[derive(Clone)]
+    struct Recorder {
+        handled: Arc<Mutex<Vec<i64>>>,
+        query: StreamQuery<DomainEvent>,
+    }
+
+    #[async_trait]
+    impl EventListener<DomainEvent> for Recorder {
+        type Error = std::convert::Infallible;
+
+        fn id(&self) -> &'static str {
+            "recorder"
+        }
+
+        fn query(&self) -> &StreamQuery<DomainEvent> {
+            &self.query
+        }
+
+        async fn handle(&self, event: PersistedEvent<DomainEvent>) -> Result<(), Self::Error> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.handled.lock().unwrap().push(event.id());
+            Ok(())
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_finish_the_event_handled_on_shutdown(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = PgPool::connect_with(options).await.unwrap();
+        let event_store: EventStore = PgEventStore::new(pool.clone(), Default::default())
+            .await
+            .unwrap();
+        // Validated against events that never happen, so that the appends don't conflict.
+        let nobody = disintegrate::query!(DomainEvent, customer_id == "nobody");
+        let mut appended = Vec::new();
+        for i in 0..10 {
+            let added = DomainEvent::VehicleAdded {
+                tenant_id: default_tenant(),
+                vehicle_id: format!("AA{i:03}AA"),
+                vehicle_type: VehicleType::Car,
+                seats: None,
+                transmission: None,
+            };
+            let events = event_store
+                .append(vec![added], nobody.clone(), 0)
+                .await
+                .unwrap();
+            appended.extend(events.iter().map(|event| event.id()));
+        }
+        let recorder = Recorder {
+            handled: Arc::default(),
+            query: query(None),
+        };
+        let config = ListenerConfig {
+            poll_interval: Duration::from_millis(10),
+            ..ListenerConfig::default()
+        };
+        let start = |shutdown: Shutdown| {
+            Listeners::new(pool.clone(), event_store.clone(), &config)
+                .register_listener(recorder.clone(), config.polling("recorder"))
+                .start(shutdown.requested())
+        };
+        let handled = |recorder: &Recorder| recorder.handled.lock().unwrap().clone();
+
+        let shutdown = Shutdown::default();
+        let running = tokio::spawn(start(shutdown.clone()));
+        while handled(&recorder).len() < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        shutdown.trigger();
+        running.await.unwrap().unwrap();
+        let drained = handled(&recorder);
+        assert!(drained.len() < appended.len(), "{drained:?}");
+        let checkpoint: i64 = sqlx::query_scalar(
+            "SELECT last_processed_event_id FROM event_listener WHERE id = 'recorder'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(Some(&checkpoint), drained.last());
+
+        let shutdown = Shutdown::default();
+        let running = tokio::spawn(start(shutdown.clone()));
+        while handled(&recorder).len() < appended.len() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        shutdown.trigger();
+        running.await.unwrap().unwrap();
+        assert_eq!(handled(&recorder), appended);
+    }
+}
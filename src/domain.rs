@@ -1,5 +1,5 @@
 #![allow(clippy::enum_variant_names)]
-use std::{collections::HashSet, fmt::Display};
+use std::{collections::HashSet, fmt::Display, ops::Deref, str::FromStr};
 
 use chrono::{DateTime, Utc};
 use disintegrate::{
@@ -8,24 +8,45 @@ use disintegrate::{
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::pii;
+
 #[derive(Debug, Clone, PartialEq, Eq, Event, Serialize, Deserialize)]
 #[stream(CustomerEvent, [CustomerRegistered])]
+#[stream(CustomerActivity, [CustomerRegistered, VehicleRented, VehicleReturned])]
 #[stream(VehicleEvent, [VehicleAdded])]
 #[stream(RentEvent, [VehicleAdded, VehicleRented, VehicleReturned])]
 pub enum DomainEvent {
     CustomerRegistered {
+        /// Missing from the events recorded before the tenants, which belong to the default one.
+        #[id]
+        #[serde(default = "default_tenant")]
+        tenant_id: TenantId,
         #[id]
         customer_id: Email,
         first_name: String,
         last_name: String,
+        /// Missing from the events recorded before the customers had one, and optional since.
+        #[serde(default)]
+        phone: Option<String>,
     },
     VehicleAdded {
+        #[id]
+        #[serde(default = "default_tenant")]
+        tenant_id: TenantId,
         #[id]
         vehicle_id: PlateNumber,
         #[id]
         vehicle_type: VehicleType,
+        /// Missing from the events recorded before the vehicles had them, and optional since.
+        #[serde(default)]
+        seats: Option<u8>,
+        #[serde(default)]
+        transmission: Option<Transmission>,
     },
     VehicleRented {
+        #[id]
+        #[serde(default = "default_tenant")]
+        tenant_id: TenantId,
         #[id]
         customer_id: Email,
         #[id]
@@ -35,27 +56,47 @@ pub enum DomainEvent {
         start_date: DateTime<Utc>,
     },
     VehicleReturned {
+        #[id]
+        #[serde(default = "default_tenant")]
+        tenant_id: TenantId,
         #[id]
         customer_id: Email,
         #[id]
         vehicle_id: PlateNumber,
         #[id]
         vehicle_type: VehicleType,
+        /// Start of the rental, missing from the events recorded before it was kept.
+        #[serde(default)]
+        start_date: Option<DateTime<Utc>>,
         returned_date: DateTime<Utc>,
     },
 }
 
+impl DomainEvent {
+    pub fn tenant_id(&self) -> &TenantId {
+        match self {
+            DomainEvent::CustomerRegistered { tenant_id, .. }
+            | DomainEvent::VehicleAdded { tenant_id, .. }
+            | DomainEvent::VehicleRented { tenant_id, .. }
+            | DomainEvent::VehicleReturned { tenant_id, .. } => tenant_id,
+        }
+    }
+}
+
 #[derive(Debug, StateQuery, Clone, Serialize, Deserialize)]
 #[state_query(CustomerEvent)]
 pub struct CustomerRegistration {
+    #[id]
+    pub(crate) tenant_id: TenantId,
     #[id]
     pub(crate) customer_id: Email,
     pub(crate) registered: bool,
 }
 
 impl CustomerRegistration {
-    pub fn new(customer_id: String) -> Self {
+    pub fn new(tenant_id: TenantId, customer_id: Email) -> Self {
         Self {
+            tenant_id,
             customer_id,
             registered: false,
         }
@@ -73,14 +114,17 @@ impl StateMutate for CustomerRegistration {
 #[derive(Debug, StateQuery, Clone, Serialize, Deserialize)]
 #[state_query(VehicleEvent)]
 pub struct VehicleRegistration {
+    #[id]
+    pub(crate) tenant_id: TenantId,
     #[id]
     pub(crate) vehicle_id: PlateNumber,
     pub(crate) registered: bool,
 }
 
 impl VehicleRegistration {
-    pub fn new(vehicle_id: PlateNumber) -> Self {
+    pub fn new(tenant_id: TenantId, vehicle_id: PlateNumber) -> Self {
         Self {
+            tenant_id,
             vehicle_id,
             registered: false,
         }
@@ -98,14 +142,17 @@ impl StateMutate for VehicleRegistration {
 #[derive(Debug, StateQuery, Clone, Serialize, Deserialize)]
 #[state_query(RentEvent)]
 pub struct VehicleAvailability {
+    #[id]
+    pub(crate) tenant_id: TenantId,
     #[id]
     pub(crate) vehicle_type: VehicleType,
     pub(crate) available_vehicles: HashSet<PlateNumber>,
 }
 
 impl VehicleAvailability {
-    pub fn new(vehicle_type: VehicleType) -> Self {
+    pub fn new(tenant_id: TenantId, vehicle_type: VehicleType) -> Self {
         Self {
+            tenant_id,
             vehicle_type,
             available_vehicles: HashSet::new(),
         }
@@ -133,18 +180,28 @@ impl StateMutate for VehicleAvailability {
 #[derive(Debug, StateQuery, Clone, Serialize, Deserialize)]
 #[state_query(RentEvent)]
 pub struct CustomerRentalStatus {
+    #[id]
+    pub(crate) tenant_id: TenantId,
     #[id]
     pub(crate) customer_id: Email,
     pub(crate) rented_vehicle_type: Option<VehicleType>,
     pub(crate) rented_vehicle_id: Option<PlateNumber>,
+    #[serde(default)]
+    pub(crate) rented_since: Option<DateTime<Utc>>,
+    /// When the customer last returned a vehicle.
+    #[serde(default)]
+    pub(crate) last_returned: Option<DateTime<Utc>>,
 }
 
 impl CustomerRentalStatus {
-    pub fn new(customer_id: Email) -> Self {
+    pub fn new(tenant_id: TenantId, customer_id: Email) -> Self {
         Self {
+            tenant_id,
             customer_id,
             rented_vehicle_type: None,
             rented_vehicle_id: None,
+            rented_since: None,
+            last_returned: None,
         }
     }
 }
@@ -157,15 +214,19 @@ impl StateMutate for CustomerRentalStatus {
             RentEvent::VehicleRented {
                 vehicle_id,
                 vehicle_type,
+                start_date,
                 ..
             } => {
                 self.rented_vehicle_id = Some(vehicle_id);
                 self.rented_vehicle_type = Some(vehicle_type);
+                self.rented_since = Some(start_date);
             }
 
-            RentEvent::VehicleReturned { .. } => {
+            RentEvent::VehicleReturned { returned_date, .. } => {
                 self.rented_vehicle_id = None;
                 self.rented_vehicle_type = None;
+                self.rented_since = None;
+                self.last_returned = Some(returned_date);
             }
         };
     }
@@ -185,12 +246,119 @@ pub enum Error {
     CustomerNotFound,
     #[error("Rental Not Found")]
     RentalNotFound,
+    /// The vehicle was returned already, less than `EndRent::REPEAT_WINDOW` ago, e.g. by a
+    /// request whose response was lost.
+    #[error("Already Returned")]
+    AlreadyReturned,
 }
 
 pub type PlateNumber = String;
-pub type Email = String;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+/// The email of a customer, which identifies them. The events are indexed and queried by its
+/// token rather than by the email itself, which is sealed in their payload: see `pii`.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct Email(String);
+
+impl Email {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl Deref for Email {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Email {
+    fn from(email: String) -> Self {
+        Self(email)
+    }
+}
+
+impl From<&str> for Email {
+    fn from(email: &str) -> Self {
+        Self(email.to_string())
+    }
+}
+
+impl From<Email> for String {
+    fn from(email: Email) -> Self {
+        email.0
+    }
+}
+
+impl Display for Email {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for Email {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Email {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Email {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+impl PartialEq<Email> for String {
+    fn eq(&self, other: &Email) -> bool {
+        *self == other.0
+    }
+}
+
+impl IntoIdentifierValue for Email {
+    const TYPE: IdentifierType = IdentifierType::String;
+
+    fn into_identifier_value(self) -> IdentifierValue {
+        IdentifierValue::String(pii::token(&self.0))
+    }
+}
+
+impl IntoIdentifierValue for &Email {
+    const TYPE: IdentifierType = IdentifierType::String;
+
+    fn into_identifier_value(self) -> IdentifierValue {
+        IdentifierValue::String(pii::token(&self.0))
+    }
+}
+
+/// The rental company a fleet and its customers belong to, told by the credentials of the
+/// caller rather than by the commands.
+pub type TenantId = String;
+
+/// The tenant of the events recorded before the tenants, and of the callers without one.
+pub const DEFAULT_TENANT: &str = "default";
+
+pub fn default_tenant() -> TenantId {
+    DEFAULT_TENANT.to_string()
+}
+
+/// Stored as the `vehicle_type` Postgres enum, whose labels are the `Display` ones.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "vehicle_type", rename_all = "snake_case")]
 pub enum VehicleType {
     Car,
     PickUp,
@@ -198,6 +366,15 @@ pub enum VehicleType {
     Truck,
 }
 
+impl VehicleType {
+    pub const ALL: [VehicleType; 4] = [
+        VehicleType::Car,
+        VehicleType::PickUp,
+        VehicleType::Van,
+        VehicleType::Truck,
+    ];
+}
+
 impl IntoIdentifierValue for VehicleType {
     const TYPE: disintegrate::IdentifierType = IdentifierType::String;
 
@@ -217,11 +394,85 @@ impl Display for VehicleType {
     }
 }
 
-#[derive(Deserialize, Debug)]
+impl FromStr for VehicleType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "car" => Ok(VehicleType::Car),
+            "pick_up" => Ok(VehicleType::PickUp),
+            "van" => Ok(VehicleType::Van),
+            "truck" => Ok(VehicleType::Truck),
+            _ => Err(format!("unknown vehicle type: {s}")),
+        }
+    }
+}
+
+/// Stored as the `Display` label, in the `transmission` column of the read model.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transmission {
+    Manual,
+    Automatic,
+}
+
+impl Display for Transmission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transmission::Manual => write!(f, "manual"),
+            Transmission::Automatic => write!(f, "automatic"),
+        }
+    }
+}
+
+impl FromStr for Transmission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manual" => Ok(Transmission::Manual),
+            "automatic" => Ok(Transmission::Automatic),
+            _ => Err(format!("unknown transmission: {s}")),
+        }
+    }
+}
+
+/// A command decided within the fleet of a tenant, the one of the caller.
+pub trait TenantScoped {
+    fn for_tenant(self, tenant_id: TenantId) -> Self;
+
+    fn tenant_id(&self) -> &TenantId;
+}
+
+macro_rules! tenant_scoped {
+    ($($command:ty),+) => {
+        $(impl TenantScoped for $command {
+            fn for_tenant(mut self, tenant_id: TenantId) -> Self {
+                self.tenant_id = tenant_id;
+                self
+            }
+
+            fn tenant_id(&self) -> &TenantId {
+                &self.tenant_id
+            }
+        })+
+    };
+}
+
+tenant_scoped!(RegisterVehicle, RegisterCustomer, StartRent, EndRent);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct RegisterVehicle {
-    vehicle_id: PlateNumber,
-    vehicle_type: VehicleType,
+    /// Set from the caller, see `TenantScoped`.
+    #[serde(skip, default = "default_tenant")]
+    pub(crate) tenant_id: TenantId,
+    pub(crate) vehicle_id: PlateNumber,
+    pub(crate) vehicle_type: VehicleType,
+    #[serde(default)]
+    pub(crate) seats: Option<u8>,
+    #[serde(default)]
+    pub(crate) transmission: Option<Transmission>,
 }
 
 impl Decision for RegisterVehicle {
@@ -232,7 +483,7 @@ impl Decision for RegisterVehicle {
     type Error = Error;
 
     fn state_query(&self) -> Self::StateQuery {
-        VehicleRegistration::new(self.vehicle_id.clone())
+        VehicleRegistration::new(self.tenant_id.clone(), self.vehicle_id.clone())
     }
 
     fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
@@ -240,18 +491,26 @@ impl Decision for RegisterVehicle {
             return Err(Error::AlreadyRegisteredVehicle);
         }
         Ok(vec![DomainEvent::VehicleAdded {
+            tenant_id: self.tenant_id.clone(),
             vehicle_id: self.vehicle_id.clone(),
             vehicle_type: self.vehicle_type.clone(),
+            seats: self.seats,
+            transmission: self.transmission,
         }])
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct RegisterCustomer {
-    customer_id: Email,
-    first_name: String,
-    last_name: String,
+    /// Set from the caller, see `TenantScoped`.
+    #[serde(skip, default = "default_tenant")]
+    pub(crate) tenant_id: TenantId,
+    pub(crate) customer_id: Email,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    #[serde(default)]
+    pub(crate) phone: Option<String>,
 }
 
 impl Decision for RegisterCustomer {
@@ -262,7 +521,7 @@ impl Decision for RegisterCustomer {
     type Error = Error;
 
     fn state_query(&self) -> Self::StateQuery {
-        CustomerRegistration::new(self.customer_id.clone())
+        CustomerRegistration::new(self.tenant_id.clone(), self.customer_id.clone())
     }
 
     fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
@@ -270,18 +529,23 @@ impl Decision for RegisterCustomer {
             return Err(Error::AlreadyRegisteredCustomer);
         }
         Ok(vec![DomainEvent::CustomerRegistered {
+            tenant_id: self.tenant_id.clone(),
             customer_id: self.customer_id.clone(),
             first_name: self.first_name.clone(),
             last_name: self.last_name.clone(),
+            phone: self.phone.clone(),
         }])
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct StartRent {
-    customer_id: Email,
-    vehicle_type: VehicleType,
+    /// Set from the caller, see `TenantScoped`.
+    #[serde(skip, default = "default_tenant")]
+    pub(crate) tenant_id: TenantId,
+    pub(crate) customer_id: Email,
+    pub(crate) vehicle_type: VehicleType,
 }
 
 impl Decision for StartRent {
@@ -297,9 +561,9 @@ impl Decision for StartRent {
 
     fn state_query(&self) -> Self::StateQuery {
         (
-            CustomerRegistration::new(self.customer_id.clone()),
-            CustomerRentalStatus::new(self.customer_id.clone()),
-            VehicleAvailability::new(self.vehicle_type.clone()),
+            CustomerRegistration::new(self.tenant_id.clone(), self.customer_id.clone()),
+            CustomerRentalStatus::new(self.tenant_id.clone(), self.customer_id.clone()),
+            VehicleAvailability::new(self.tenant_id.clone(), self.vehicle_type.clone()),
         )
     }
 
@@ -320,6 +584,7 @@ impl Decision for StartRent {
         }
 
         Ok(vec![DomainEvent::VehicleRented {
+            tenant_id: self.tenant_id.clone(),
             customer_id: self.customer_id.to_owned(),
             vehicle_type: self.vehicle_type.to_owned(),
             vehicle_id: vehicle.to_owned(),
@@ -328,10 +593,19 @@ impl Decision for StartRent {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct EndRent {
-    customer_id: Email,
+    /// Set from the caller, see `TenantScoped`.
+    #[serde(skip, default = "default_tenant")]
+    pub(crate) tenant_id: TenantId,
+    pub(crate) customer_id: Email,
+}
+
+impl EndRent {
+    /// How long after a return an `EndRent` is taken for a retry of it, rather than for a
+    /// rental that isn't there.
+    pub const REPEAT_WINDOW: chrono::Duration = chrono::Duration::minutes(15);
 }
 
 impl Decision for EndRent {
@@ -342,17 +616,24 @@ impl Decision for EndRent {
     type Error = Error;
 
     fn state_query(&self) -> Self::StateQuery {
-        CustomerRentalStatus::new(self.customer_id.clone())
+        CustomerRentalStatus::new(self.tenant_id.clone(), self.customer_id.clone())
     }
 
     fn process(&self, state: &Self::StateQuery) -> Result<Vec<Self::Event>, Self::Error> {
         if let Some(rented_vehicle_id) = state.rented_vehicle_id.as_ref() {
             Ok(vec![DomainEvent::VehicleReturned {
+                tenant_id: self.tenant_id.clone(),
                 customer_id: self.customer_id.to_owned(),
                 vehicle_type: state.rented_vehicle_type.as_ref().unwrap().clone(),
+                start_date: state.rented_since,
                 returned_date: Utc::now(),
                 vehicle_id: rented_vehicle_id.to_owned(),
             }])
+        } else if state
+            .last_returned
+            .is_some_and(|returned| Utc::now() - returned < Self::REPEAT_WINDOW)
+        {
+            Err(Error::AlreadyReturned)
         } else {
             Err(Error::RentalNotFound)
         }
@@ -363,18 +644,121 @@ impl Decision for EndRent {
 mod test {
 
     use super::*;
+    use crate::fixtures;
     #[test]
     fn it_should_not_register_customer_twice() {
         disintegrate::TestHarness::given([DomainEvent::CustomerRegistered {
-            customer_id: "customer".to_string(),
+            tenant_id: default_tenant(),
+            customer_id: "customer".into(),
             first_name: "Bob".to_string(),
             last_name: "Solo".to_string(),
+            phone: None,
         }])
         .when(RegisterCustomer {
-            customer_id: "customer".to_string(),
+            tenant_id: default_tenant(),
+            customer_id: "customer".into(),
             first_name: "Bob".to_string(),
             last_name: "Solo".to_string(),
+            phone: None,
         })
         .then_err(Error::AlreadyRegisteredCustomer);
     }
+
+    #[test]
+    fn it_should_tell_a_repeated_return_apart_from_a_missing_rental() {
+        let rented = DomainEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: "customer".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: Utc::now(),
+        };
+        let returned = DomainEvent::VehicleReturned {
+            tenant_id: default_tenant(),
+            customer_id: "customer".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: None,
+            returned_date: Utc::now(),
+        };
+        disintegrate::TestHarness::given([rented, returned])
+            .when(EndRent {
+                tenant_id: default_tenant(),
+                customer_id: "customer".into(),
+            })
+            .then_err(Error::AlreadyReturned);
+        disintegrate::TestHarness::given([])
+            .when(EndRent {
+                tenant_id: default_tenant(),
+                customer_id: "customer".into(),
+            })
+            .then_err(Error::RentalNotFound);
+    }
+
+    #[test]
+    fn it_should_not_find_the_rental_long_after_the_last_return() {
+        let start_date = Utc::now() - chrono::Duration::days(3);
+        disintegrate::TestHarness::given([
+            DomainEvent::VehicleRented {
+                tenant_id: default_tenant(),
+                customer_id: "customer".into(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Car,
+                start_date,
+            },
+            DomainEvent::VehicleReturned {
+                tenant_id: default_tenant(),
+                customer_id: "customer".into(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Car,
+                start_date: Some(start_date),
+                returned_date: Utc::now() - EndRent::REPEAT_WINDOW - chrono::Duration::minutes(1),
+            },
+        ])
+        .when(EndRent {
+            tenant_id: default_tenant(),
+            customer_id: "customer".into(),
+        })
+        .then_err(Error::RentalNotFound);
+    }
+
+    #[test]
+    fn it_should_rent_out_the_fleet_again_once_returned() {
+        let history: Vec<DomainEvent> = fixtures::customers(3)
+            .chain(fixtures::fleet(VehicleType::Car, 5))
+            .chain(fixtures::rentals(VehicleType::Car, 5, 3, 12))
+            .collect();
+        let state = (
+            fixtures::fold(
+                CustomerRegistration::new(default_tenant(), fixtures::customer(0)),
+                history.clone(),
+            ),
+            fixtures::fold(
+                CustomerRentalStatus::new(default_tenant(), fixtures::customer(0)),
+                history.clone(),
+            ),
+            fixtures::fold(
+                VehicleAvailability::new(default_tenant(), VehicleType::Car),
+                history,
+            ),
+        );
+        assert_eq!(state.2.available_vehicles.len(), 5);
+        assert!(state.1.last_returned.is_some());
+
+        let rented = fixtures::start_rent(0, VehicleType::Car)
+            .process(&state)
+            .unwrap();
+        assert!(matches!(
+            &rented[..],
+            [DomainEvent::VehicleRented { vehicle_id, .. }] if state.2.available_vehicles.contains(vehicle_id)
+        ));
+    }
+
+    #[test]
+    fn it_should_parse_vehicle_types_from_their_display_name() {
+        for vehicle_type in VehicleType::ALL {
+            assert_eq!(vehicle_type.to_string().parse(), Ok(vehicle_type));
+        }
+        assert!("bike".parse::<VehicleType>().is_err());
+    }
 }
@@ -0,0 +1,399 @@
+use crate::{
+    config::EmailConfig, domain::CustomerActivity, i18n, read_model::rental_duration_minutes,
+};
+use async_trait::async_trait;
+use std::{fmt::Display, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use disintegrate::{query, Event, EventListener, PersistedEvent, StreamQuery};
+use sqlx::PgPool;
+
+const WELCOME: &str = include_str!("email/welcome.txt");
+const RECEIPT: &str = include_str!("email/receipt.txt");
+
+/// An email to a customer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Sends the emails, through SMTP or, in the tests, to a recording.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: &Message) -> anyhow::Result<()>;
+}
+
+/// The mailer of `config`, `None` unless the emails are enabled.
+pub fn mailer(config: &EmailConfig) -> Result<Option<Arc<dyn Mailer>>, String> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    #[cfg(feature = "email")]
+    return Ok(Some(Arc::new(smtp::SmtpMailer::new(config)?)));
+    #[cfg(not(feature = "email"))]
+    Err("EMAIL_ENABLED: requires the email feature".to_string())
+}
+
+#[cfg(feature = "email")]
+mod smtp {
+    use async_trait::async_trait;
+    use lettre::{
+        message::{header::ContentType, Mailbox},
+        AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+    };
+
+    use super::{Mailer, Message};
+    use crate::config::EmailConfig;
+
+    pub struct SmtpMailer {
+        transport: AsyncSmtpTransport<Tokio1Executor>,
+        from: Mailbox,
+    }
+
+    impl SmtpMailer {
+        pub fn new(config: &EmailConfig) -> Result<Self, String> {
+            let url = config
+                .smtp_url
+                .as_ref()
+                .ok_or("SMTP_URL: must be set when EMAIL_ENABLED is")?;
+            let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url.expose())
+                .map_err(|err| format!("SMTP_URL: {err}"))?
+                .build();
+            let from = config
+                .from
+                .parse()
+                .map_err(|err| format!("EMAIL_FROM: {err}"))?;
+            Ok(Self { transport, from })
+        }
+    }
+
+    #[async_trait]
+    impl Mailer for SmtpMailer {
+        async fn send(&self, message: &Message) -> anyhow::Result<()> {
+            let email = lettre::Message::builder()
+                .from(self.from.clone())
+                .to(message.to.parse()?)
+                .subject(&message.subject)
+                .header(ContentType::TEXT_PLAIN)
+                .body(message.body.clone())?;
+            self.transport.send(email).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Welcomes the customers once registered and sends them the receipt of each rental once the
+/// vehicle is returned.
+///
+/// The emails of an event are recorded in the outbox before being sent, then marked as sent,
+/// so that a redelivered event sends nothing again and a crash drops nothing: an email whose
+/// sending failed, or was cut short, is sent again along with those of the next events, up to
+/// `MAX_ATTEMPTS` times. When the emails are disabled, the events are passed on, so that
+/// enabling them doesn't email about the past.
+pub struct EmailNotifier {
+    query: StreamQuery<CustomerActivity>,
+    pool: PgPool,
+    mailer: Option<Arc<dyn Mailer>>,
+}
+
+impl EmailNotifier {
+    pub const ID: &'static str = "drive_me_crazy_emails";
+    pub const MAX_ATTEMPTS: i32 = 5;
+
+    pub fn new(pool: PgPool, mailer: Option<Arc<dyn Mailer>>) -> Self {
+        Self {
+            query: query(None),
+            pool,
+            mailer,
+        }
+    }
+
+    async fn record(&self, event_id: i64, emails: Vec<(&str, Message)>) -> Result<(), sqlx::Error> {
+        for (template, message) in emails {
+            sqlx::query(
+                r#"INSERT INTO email_outbox (event_id, template, recipient, subject, body)
+                    VALUES($1, $2, $3, $4, $5) ON CONFLICT (event_id, template) DO NOTHING"#,
+            )
+            .bind(event_id)
+            .bind(template)
+            .bind(message.to)
+            .bind(message.subject)
+            .bind(message.body)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the emails of the outbox not sent yet, oldest first, recording why each one that
+    /// failed did.
+    async fn flush(&self, mailer: &dyn Mailer) -> Result<(), sqlx::Error> {
+        let unsent: Vec<(i64, String, String, String, String)> = sqlx::query_as(
+            r#"SELECT event_id, template, recipient, subject, body FROM email_outbox
+                WHERE sent_at IS NULL AND attempts < $1 ORDER BY event_id, template"#,
+        )
+        .bind(Self::MAX_ATTEMPTS)
+        .fetch_all(&self.pool)
+        .await?;
+        for (event_id, template, to, subject, body) in unsent {
+            let message = Message { to, subject, body };
+            let error = match mailer.send(&message).await {
+                Ok(()) => None,
+                Err(err) => {
+                    tracing::warn!(
+                        event_id,
+                        template,
+                        error = format!("{err:#}"),
+                        "failed to send an email"
+                    );
+                    Some(format!("{err:#}"))
+                }
+            };
+            sqlx::query(
+                r#"UPDATE email_outbox SET attempts = attempts + 1, last_error = $3,
+                    sent_at = CASE WHEN $3 IS NULL THEN now() END
+                    WHERE event_id = $1 AND template = $2"#,
+            )
+            .bind(event_id)
+            .bind(&template)
+            .bind(error)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// The emails of the event, along with the template of each one.
+fn emails(event: CustomerActivity) -> Vec<(&'static str, Message)> {
+    match event {
+        CustomerActivity::CustomerRegistered {
+            customer_id,
+            first_name,
+            last_name,
+            ..
+        } => {
+            let body = render(
+                WELCOME,
+                &[
+                    ("first_name", &first_name),
+                    ("last_name", &last_name),
+                    ("customer_id", &customer_id),
+                ],
+            );
+            vec![(
+                "welcome",
+                Message {
+                    to: customer_id.into_string(),
+                    subject: "Welcome to Drive Me Crazy".to_string(),
+                    body,
+                },
+            )]
+        }
+        CustomerActivity::VehicleRented { .. } => vec![],
+        CustomerActivity::VehicleReturned {
+            customer_id,
+            vehicle_id,
+            vehicle_type,
+            start_date,
+            returned_date,
+            ..
+        } => {
+            let duration = start_date
+                .and_then(|start_date| rental_duration_minutes(start_date, returned_date))
+                .map_or("unknown".to_string(), |minutes| {
+                    format!("{minutes} minutes")
+                });
+            let body = render(
+                RECEIPT,
+                &[
+                    ("vehicle_type", &vehicle_type.to_string().replace('_', " ")),
+                    ("vehicle_id", &vehicle_id),
+                    (
+                        "start_date",
+                        &start_date.map_or("unknown".to_string(), day_and_time),
+                    ),
+                    ("returned_date", &day_and_time(returned_date)),
+                    ("duration", &duration),
+                ],
+            );
+            vec![(
+                "receipt",
+                Message {
+                    to: customer_id.into_string(),
+                    subject: format!("Your receipt for the rental of {vehicle_id}"),
+                    body,
+                },
+            )]
+        }
+    }
+}
+
+fn render(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    i18n::fill(template, args).expect("the email templates only name the arguments given")
+}
+
+fn day_and_time(date: DateTime<Utc>) -> String {
+    date.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+#[async_trait]
+impl EventListener<CustomerActivity> for EmailNotifier {
+    type Error = sqlx::Error;
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn query(&self) -> &StreamQuery<CustomerActivity> {
+        &self.query
+    }
+
+    #[tracing::instrument(skip_all, fields(listener_id = self.id(), event_id = event.id(), event_type = event.name()))]
+    async fn handle(&self, event: PersistedEvent<CustomerActivity>) -> Result<(), Self::Error> {
+        let Some(mailer) = &self.mailer else {
+            return Ok(());
+        };
+        let event_id = event.id();
+        self.record(event_id, emails(event.into_inner())).await?;
+        self.flush(mailer.as_ref()).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::{default_tenant, VehicleType},
+        test_support,
+    };
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    /// Records the emails sent, failing while `failing` is set.
+    #[derive(Default)]
+    struct RecordingMailer {
+        sent: Mutex<Vec<Message>>,
+        failing: AtomicBool,
+    }
+
+    #[async_trait]
+    impl Mailer for RecordingMailer {
+        async fn send(&self, message: &Message) -> anyhow::Result<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                anyhow::bail!("connection refused");
+            }
+            self.sent.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    fn registered() -> CustomerActivity {
+        CustomerActivity::CustomerRegistered {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: None,
+        }
+    }
+
+    fn returned() -> CustomerActivity {
+        CustomerActivity::VehicleReturned {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA123BB".to_string(),
+            vehicle_type: VehicleType::PickUp,
+            start_date: Some("2024-07-01T09:00:00Z".parse().unwrap()),
+            returned_date: "2024-07-01T10:30:00Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn it_should_render_the_receipt_of_the_rental() {
+        let [(template, receipt)] = emails(returned()).try_into().unwrap();
+        assert_eq!(template, "receipt");
+        assert_eq!(receipt.to, "mario@example.com");
+        assert_eq!(receipt.subject, "Your receipt for the rental of AA123BB");
+        assert!(receipt.body.contains("pick up AA123BB"), "{}", receipt.body);
+        assert!(receipt.body.contains("Rented on:   2024-07-01 09:00 UTC"));
+        assert!(receipt.body.contains("Duration:    90 minutes"));
+
+        let [(_, welcome)] = emails(registered()).try_into().unwrap();
+        assert!(welcome.body.starts_with("Dear Mario Rossi,"));
+        assert!(!welcome.body.contains('{'));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_send_each_email_once_even_when_the_event_is_redelivered(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let mailer = Arc::new(RecordingMailer::default());
+        let notifier = EmailNotifier::new(pool.clone(), Some(mailer.clone()));
+        let sent = || mailer.sent.lock().unwrap().clone();
+
+        mailer.failing.store(true, Ordering::SeqCst);
+        notifier
+            .handle(PersistedEvent::new(1, registered()))
+            .await
+            .unwrap();
+        assert!(sent().is_empty());
+        let (attempts, error): (i32, Option<String>) =
+            sqlx::query_as("SELECT attempts, last_error FROM email_outbox WHERE event_id = 1")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            (attempts, error.as_deref()),
+            (1, Some("connection refused"))
+        );
+
+        // The welcome that failed goes out along with the receipt.
+        mailer.failing.store(false, Ordering::SeqCst);
+        notifier
+            .handle(PersistedEvent::new(2, returned()))
+            .await
+            .unwrap();
+        let subjects = |sent: Vec<Message>| -> Vec<String> {
+            sent.into_iter().map(|message| message.subject).collect()
+        };
+        assert_eq!(
+            subjects(sent()),
+            [
+                "Welcome to Drive Me Crazy",
+                "Your receipt for the rental of AA123BB"
+            ]
+        );
+
+        for (event_id, event) in [(1, registered()), (2, returned())] {
+            notifier
+                .handle(PersistedEvent::new(event_id, event))
+                .await
+                .unwrap();
+        }
+        assert_eq!(sent().len(), 2);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_pass_the_events_on_while_the_emails_are_disabled(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        EmailNotifier::new(pool.clone(), None)
+            .handle(PersistedEvent::new(1, registered()))
+            .await
+            .unwrap();
+        let recorded: i64 = sqlx::query_scalar("SELECT count(*) FROM email_outbox")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(recorded, 0);
+    }
+}
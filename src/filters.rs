@@ -0,0 +1,788 @@
+use std::future::{ready, Ready};
+
+use actix_web::{error, web::Query, FromRequest, HttpRequest};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use disintegrate::Event;
+use serde::Deserialize;
+
+use crate::{
+    application::COMMANDS,
+    command_audit::CommandOutcome,
+    domain::{DomainEvent, Email, PlateNumber, Transmission, VehicleType},
+    errors::ErrorCode,
+    pagination::{Cursor, DEFAULT_LIMIT, MAX_LIMIT},
+    read_model::VehicleStatus,
+};
+
+/// Rentals still open this long after their start date are considered overdue.
+pub const MAX_RENTAL_DAYS: i32 = 30;
+/// Length of the period covered by reports when `?from=` is not given.
+pub const DEFAULT_REPORT_DAYS: i64 = 30;
+pub const DEFAULT_TOP_CUSTOMERS: i64 = 10;
+pub const MAX_TOP_CUSTOMERS: i64 = 100;
+/// Longest range of days the availability calendar covers at once.
+pub const MAX_CALENDAR_DAYS: i64 = 93;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RentalStatus {
+    Open,
+    Closed,
+    Overdue,
+}
+
+/// Filters accepted by the rental listings through `?from=`, `?to=` and `?status=`.
+///
+/// The date range applies to the start date, or to the end date when listing closed rentals.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RentalFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub status: Option<RentalStatus>,
+}
+
+impl RentalFilter {
+    pub fn new(from: Option<&str>, to: Option<&str>, status: Option<&str>) -> Result<Self, String> {
+        let from = from.map(|from| parse_date("from", from)).transpose()?;
+        let to = to.map(|to| parse_date("to", to)).transpose()?;
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err("from: must not be after to".to_string());
+            }
+        }
+        let status = status
+            .map(|status| match status {
+                "open" => Ok(RentalStatus::Open),
+                "closed" => Ok(RentalStatus::Closed),
+                "overdue" => Ok(RentalStatus::Overdue),
+                _ => Err("status: must be one of open, closed, overdue".to_string()),
+            })
+            .transpose()?;
+        Ok(Self { from, to, status })
+    }
+}
+
+/// Filters accepted by the vehicle listings through `?vehicleType=`, `?status=`, `?minSeats=`
+/// and `?transmission=`, all of them applying when combined.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VehicleFilter {
+    pub vehicle_type: Option<VehicleType>,
+    pub status: Option<VehicleStatus>,
+    pub min_seats: Option<i16>,
+    pub transmission: Option<Transmission>,
+}
+
+impl VehicleFilter {
+    pub fn new(
+        vehicle_type: Option<&str>,
+        status: Option<&str>,
+        min_seats: Option<&str>,
+        transmission: Option<&str>,
+    ) -> Result<Self, String> {
+        let vehicle_type = vehicle_type
+            .map(|vehicle_type| {
+                vehicle_type
+                    .parse()
+                    .map_err(|_| "vehicleType: must be one of car, pick_up, van, truck")
+            })
+            .transpose()?;
+        let status = status
+            .map(|status| {
+                status.parse().map_err(|_| {
+                    "status: must be one of available, rented, maintenance, decommissioned"
+                })
+            })
+            .transpose()?;
+        let min_seats = min_seats
+            .map(|min_seats| match min_seats.parse() {
+                Ok(min_seats) if min_seats >= 1 => Ok(min_seats),
+                _ => Err("minSeats: must be a positive number"),
+            })
+            .transpose()?;
+        let transmission = transmission
+            .map(|transmission| {
+                transmission
+                    .parse()
+                    .map_err(|_| "transmission: must be one of manual, automatic")
+            })
+            .transpose()?;
+        Ok(Self {
+            vehicle_type,
+            status,
+            min_seats,
+            transmission,
+        })
+    }
+}
+
+/// Period covered by a report, requested through `?from=` and `?to=`.
+///
+/// It ends now and spans `DEFAULT_REPORT_DAYS` unless told otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportPeriod {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl ReportPeriod {
+    pub fn new(from: Option<&str>, to: Option<&str>, now: DateTime<Utc>) -> Result<Self, String> {
+        let to = to
+            .map(|to| parse_date("to", to))
+            .transpose()?
+            .unwrap_or(now);
+        let from = from
+            .map(|from| parse_date("from", from))
+            .transpose()?
+            .unwrap_or(to - Duration::days(DEFAULT_REPORT_DAYS));
+        if from >= to {
+            return Err("from: must be before to".to_string());
+        }
+        Ok(Self { from, to })
+    }
+}
+
+#[derive(Deserialize)]
+struct RawReportPeriod {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl FromRequest for ReportPeriod {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let period = Query::<RawReportPeriod>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| {
+                ReportPeriod::new(params.from.as_deref(), params.to.as_deref(), Utc::now())
+            })
+            .map_err(error::ErrorBadRequest);
+        ready(period)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CustomerRanking {
+    Rentals,
+    Days,
+}
+
+/// Ranking requested through `?by=` and `?limit=` on the top customers report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopCustomersParams {
+    pub by: CustomerRanking,
+    pub limit: i64,
+}
+
+impl TopCustomersParams {
+    pub fn new(by: Option<&str>, limit: Option<i64>) -> Result<Self, String> {
+        let by = match by.unwrap_or("rentals") {
+            "rentals" => CustomerRanking::Rentals,
+            "days" => CustomerRanking::Days,
+            _ => return Err("by: must be one of rentals, days".to_string()),
+        };
+        let limit = limit.unwrap_or(DEFAULT_TOP_CUSTOMERS);
+        if !(1..=MAX_TOP_CUSTOMERS).contains(&limit) {
+            return Err(format!("limit: must be between 1 and {MAX_TOP_CUSTOMERS}"));
+        }
+        Ok(Self { by, limit })
+    }
+}
+
+#[derive(Deserialize)]
+struct RawTopCustomersParams {
+    by: Option<String>,
+    limit: Option<i64>,
+}
+
+impl FromRequest for TopCustomersParams {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let params = Query::<RawTopCustomersParams>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| TopCustomersParams::new(params.by.as_deref(), params.limit))
+            .map_err(error::ErrorBadRequest);
+        ready(params)
+    }
+}
+
+/// Days requested through `?vehicleType=`, `?from=` and `?to=` on the availability calendar,
+/// both ends included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalendarRange {
+    pub vehicle_type: VehicleType,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl CalendarRange {
+    pub fn new(
+        vehicle_type: Option<&str>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<Self, String> {
+        let vehicle_type = vehicle_type
+            .ok_or("vehicleType: is required")?
+            .parse()
+            .map_err(|_| "vehicleType: must be one of car, pick_up, van, truck")?;
+        let from = parse_day("from", from)?;
+        let to = parse_day("to", to)?;
+        if from > to {
+            return Err("from: must not be after to".to_string());
+        }
+        if (to - from).num_days() >= MAX_CALENDAR_DAYS {
+            return Err(format!(
+                "to: must be less than {MAX_CALENDAR_DAYS} days after from"
+            ));
+        }
+        Ok(Self {
+            vehicle_type,
+            from,
+            to,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCalendarRange {
+    vehicle_type: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl FromRequest for CalendarRange {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let range = Query::<RawCalendarRange>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| {
+                CalendarRange::new(
+                    params.vehicle_type.as_deref(),
+                    params.from.as_deref(),
+                    params.to.as_deref(),
+                )
+            })
+            .map_err(error::ErrorBadRequest);
+        ready(range)
+    }
+}
+
+/// Events requested from the audit log through `?customerId=`, `?vehicleId=`, `?type=`,
+/// `?from=`, `?to=`, `?limit=` and `?cursor=`.
+///
+/// The cursor is the `nextCursor` of the previous page: the events older than its last one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditParams {
+    pub customer_id: Option<Email>,
+    pub vehicle_id: Option<String>,
+    /// The events of the type, as a one-item slice of the event schema.
+    pub event_types: Option<&'static [&'static str]>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: i64,
+    pub cursor: Option<Cursor>,
+}
+
+impl AuditParams {
+    pub fn new(
+        customer_id: Option<&str>,
+        vehicle_id: Option<&str>,
+        event_type: Option<&str>,
+        from: Option<&str>,
+        to: Option<&str>,
+        limit: Option<i64>,
+        cursor: Option<&str>,
+    ) -> Result<Self, String> {
+        let types = DomainEvent::SCHEMA.types;
+        let event_types = event_type
+            .map(|event_type| {
+                types
+                    .iter()
+                    .position(|t| *t == event_type)
+                    .map(|i| &types[i..=i])
+                    .ok_or(format!("type: must be one of {}", types.join(", ")))
+            })
+            .transpose()?;
+        let from = from.map(|from| parse_date("from", from)).transpose()?;
+        let to = to.map(|to| parse_date("to", to)).transpose()?;
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err("from: must not be after to".to_string());
+            }
+        }
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(format!("limit: must be between 1 and {MAX_LIMIT}"));
+        }
+        let cursor = cursor.map(str::parse).transpose()?;
+        Ok(Self {
+            customer_id: customer_id.map(Email::from),
+            vehicle_id: vehicle_id.map(str::to_string),
+            event_types,
+            from,
+            to,
+            limit,
+            cursor,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawAuditParams {
+    customer_id: Option<String>,
+    vehicle_id: Option<String>,
+    #[serde(rename = "type")]
+    event_type: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+impl FromRequest for AuditParams {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let params = Query::<RawAuditParams>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| {
+                AuditParams::new(
+                    params.customer_id.as_deref(),
+                    params.vehicle_id.as_deref(),
+                    params.event_type.as_deref(),
+                    params.from.as_deref(),
+                    params.to.as_deref(),
+                    params.limit,
+                    params.cursor.as_deref(),
+                )
+            })
+            .map_err(error::ErrorBadRequest);
+        ready(params)
+    }
+}
+
+/// Audited commands requested through `?command=`, `?outcome=`, `?errorCode=`, `?actor=` and
+/// `?correlationId=`, all of them applying when combined.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandAuditFilter {
+    pub command: Option<&'static str>,
+    pub outcome: Option<CommandOutcome>,
+    pub error_code: Option<ErrorCode>,
+    pub actor: Option<String>,
+    pub correlation_id: Option<String>,
+}
+
+impl CommandAuditFilter {
+    pub fn new(
+        command: Option<&str>,
+        outcome: Option<&str>,
+        error_code: Option<&str>,
+        actor: Option<&str>,
+        correlation_id: Option<&str>,
+    ) -> Result<Self, String> {
+        let command = command
+            .map(|command| {
+                COMMANDS
+                    .into_iter()
+                    .find(|name| *name == command)
+                    .ok_or(format!("command: must be one of {}", COMMANDS.join(", ")))
+            })
+            .transpose()?;
+        let outcome = outcome
+            .map(|outcome| {
+                outcome.parse().map_err(|_| {
+                    "outcome: must be one of success, domain_error, infrastructure_error"
+                })
+            })
+            .transpose()?;
+        let error_code = error_code
+            .map(|code| {
+                serde_json::from_value(serde_json::Value::String(code.to_string()))
+                    .map_err(|_| "errorCode: must be an error code, e.g. NO_AVAILABLE_VEHICLES")
+            })
+            .transpose()?;
+        Ok(Self {
+            command,
+            outcome,
+            error_code,
+            actor: actor.map(str::to_string),
+            correlation_id: correlation_id.map(str::to_string),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCommandAuditFilter {
+    command: Option<String>,
+    outcome: Option<String>,
+    error_code: Option<String>,
+    actor: Option<String>,
+    correlation_id: Option<String>,
+}
+
+impl FromRequest for CommandAuditFilter {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let filter = Query::<RawCommandAuditFilter>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| {
+                CommandAuditFilter::new(
+                    params.command.as_deref(),
+                    params.outcome.as_deref(),
+                    params.error_code.as_deref(),
+                    params.actor.as_deref(),
+                    params.correlation_id.as_deref(),
+                )
+            })
+            .map_err(error::ErrorBadRequest);
+        ready(filter)
+    }
+}
+
+/// State query whose snapshot is inspected, requested through `?query=` along with the
+/// identifier it takes: `?customerId=`, `?vehicleId=` or `?vehicleType=`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotTarget {
+    CustomerRegistration(Email),
+    CustomerRentalStatus(Email),
+    VehicleRegistration(PlateNumber),
+    VehicleAvailability(VehicleType),
+}
+
+impl SnapshotTarget {
+    pub fn new(
+        query: Option<&str>,
+        customer_id: Option<&str>,
+        vehicle_id: Option<&str>,
+        vehicle_type: Option<&str>,
+    ) -> Result<Self, String> {
+        let required = |field: &str, value: Option<&str>| {
+            value
+                .map(str::to_string)
+                .ok_or(format!("{field}: is required by the query"))
+        };
+        match query.ok_or("query: is required")? {
+            "CustomerRegistration" => Ok(SnapshotTarget::CustomerRegistration(required(
+                "customerId",
+                customer_id,
+            )?
+            .into())),
+            "CustomerRentalStatus" => Ok(SnapshotTarget::CustomerRentalStatus(required(
+                "customerId",
+                customer_id,
+            )?
+            .into())),
+            "VehicleRegistration" => Ok(SnapshotTarget::VehicleRegistration(required(
+                "vehicleId",
+                vehicle_id,
+            )?)),
+            "VehicleAvailability" => required("vehicleType", vehicle_type)?
+                .parse()
+                .map(SnapshotTarget::VehicleAvailability)
+                .map_err(|_| "vehicleType: must be one of car, pick_up, van, truck".to_string()),
+            _ => Err("query: must be one of CustomerRegistration, CustomerRentalStatus, VehicleRegistration, VehicleAvailability".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSnapshotTarget {
+    query: Option<String>,
+    customer_id: Option<String>,
+    vehicle_id: Option<String>,
+    vehicle_type: Option<String>,
+}
+
+impl FromRequest for SnapshotTarget {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let target = Query::<RawSnapshotTarget>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| {
+                SnapshotTarget::new(
+                    params.query.as_deref(),
+                    params.customer_id.as_deref(),
+                    params.vehicle_id.as_deref(),
+                    params.vehicle_type.as_deref(),
+                )
+            })
+            .map_err(error::ErrorBadRequest);
+        ready(target)
+    }
+}
+
+fn parse_day(field: &str, value: Option<&str>) -> Result<NaiveDate, String> {
+    let value = value.ok_or(format!("{field}: is required"))?;
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("{field}: must be a date such as 2024-07-01"))
+}
+
+fn parse_date(field: &str, value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|_| format!("{field}: must be an RFC 3339 timestamp"))
+}
+
+#[derive(Deserialize)]
+struct RawRentalFilter {
+    from: Option<String>,
+    to: Option<String>,
+    status: Option<String>,
+}
+
+impl FromRequest for RentalFilter {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let filter = Query::<RawRentalFilter>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| {
+                RentalFilter::new(
+                    params.from.as_deref(),
+                    params.to.as_deref(),
+                    params.status.as_deref(),
+                )
+            })
+            .map_err(error::ErrorBadRequest);
+        ready(filter)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawVehicleFilter {
+    vehicle_type: Option<String>,
+    status: Option<String>,
+    min_seats: Option<String>,
+    transmission: Option<String>,
+}
+
+impl FromRequest for VehicleFilter {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let filter = Query::<RawVehicleFilter>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| {
+                VehicleFilter::new(
+                    params.vehicle_type.as_deref(),
+                    params.status.as_deref(),
+                    params.min_seats.as_deref(),
+                    params.transmission.as_deref(),
+                )
+            })
+            .map_err(error::ErrorBadRequest);
+        ready(filter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_a_valid_range_and_status() {
+        let filter = RentalFilter::new(
+            Some("2024-07-01T00:00:00Z"),
+            Some("2024-07-31T23:59:59+02:00"),
+            Some("closed"),
+        )
+        .unwrap();
+        assert_eq!(
+            filter.from.unwrap().to_rfc3339(),
+            "2024-07-01T00:00:00+00:00"
+        );
+        assert_eq!(filter.to.unwrap().to_rfc3339(), "2024-07-31T21:59:59+00:00");
+        assert_eq!(filter.status, Some(RentalStatus::Closed));
+    }
+
+    #[test]
+    fn it_should_report_the_offending_field() {
+        assert_eq!(
+            RentalFilter::new(Some("yesterday"), None, None),
+            Err("from: must be an RFC 3339 timestamp".to_string())
+        );
+        assert_eq!(
+            RentalFilter::new(None, None, Some("late")),
+            Err("status: must be one of open, closed, overdue".to_string())
+        );
+    }
+
+    #[test]
+    fn it_should_parse_the_vehicle_filters_or_tell_the_offending_one() {
+        assert_eq!(
+            VehicleFilter::new(Some("pick_up"), Some("rented"), Some("5"), Some("manual")),
+            Ok(VehicleFilter {
+                vehicle_type: Some(VehicleType::PickUp),
+                status: Some(VehicleStatus::Rented),
+                min_seats: Some(5),
+                transmission: Some(Transmission::Manual),
+            })
+        );
+        assert_eq!(
+            VehicleFilter::new(None, None, None, None),
+            Ok(Default::default())
+        );
+        for (filter, error) in [
+            (
+                VehicleFilter::new(Some("bus"), None, None, None),
+                "vehicleType: must be one of car, pick_up, van, truck",
+            ),
+            (
+                VehicleFilter::new(None, Some("lost"), None, None),
+                "status: must be one of available, rented, maintenance, decommissioned",
+            ),
+            (
+                VehicleFilter::new(None, None, Some("0"), None),
+                "minSeats: must be a positive number",
+            ),
+            (
+                VehicleFilter::new(None, None, Some("four"), None),
+                "minSeats: must be a positive number",
+            ),
+            (
+                VehicleFilter::new(None, None, None, Some("cvt")),
+                "transmission: must be one of manual, automatic",
+            ),
+        ] {
+            assert_eq!(filter, Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn it_should_reject_a_range_ending_before_it_starts() {
+        assert!(RentalFilter::new(
+            Some("2024-07-02T00:00:00Z"),
+            Some("2024-07-01T00:00:00Z"),
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_should_default_the_report_period_to_the_last_days() {
+        let now = "2024-07-31T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let period = ReportPeriod::new(None, None, now).unwrap();
+        assert_eq!(period.to, now);
+        assert_eq!(period.from, now - Duration::days(DEFAULT_REPORT_DAYS));
+        assert_eq!(
+            ReportPeriod::new(None, Some("2024-07-31T12:00:00Z"), now),
+            ReportPeriod::new(Some("2024-07-01T12:00:00Z"), None, now)
+        );
+        assert!(ReportPeriod::new(Some("2024-07-31T12:00:00Z"), None, now).is_err());
+    }
+
+    #[test]
+    fn it_should_validate_the_top_customers_ranking() {
+        assert_eq!(
+            TopCustomersParams::new(None, None),
+            Ok(TopCustomersParams {
+                by: CustomerRanking::Rentals,
+                limit: DEFAULT_TOP_CUSTOMERS
+            })
+        );
+        assert_eq!(
+            TopCustomersParams::new(Some("revenue"), None),
+            Err("by: must be one of rentals, days".to_string())
+        );
+        assert!(TopCustomersParams::new(Some("days"), Some(MAX_TOP_CUSTOMERS + 1)).is_err());
+    }
+
+    #[test]
+    fn it_should_filter_the_audited_commands_by_known_values() {
+        let filter = CommandAuditFilter::new(
+            Some("StartRent"),
+            Some("domain_error"),
+            Some("NO_AVAILABLE_VEHICLES"),
+            None,
+            Some("req-1"),
+        )
+        .unwrap();
+        assert_eq!(filter.command, Some("StartRent"));
+        assert_eq!(filter.outcome, Some(CommandOutcome::DomainError));
+        assert_eq!(filter.error_code, Some(ErrorCode::NoAvailableVehicles));
+        assert_eq!(filter.correlation_id.as_deref(), Some("req-1"));
+        assert_eq!(
+            CommandAuditFilter::new(Some("RentCar"), None, None, None, None),
+            Err(
+                "command: must be one of RegisterVehicle, RegisterCustomer, StartRent, EndRent"
+                    .to_string()
+            )
+        );
+        assert!(CommandAuditFilter::new(None, Some("failed"), None, None, None).is_err());
+        assert!(CommandAuditFilter::new(None, None, Some("no_vans"), None, None).is_err());
+    }
+
+    #[test]
+    fn it_should_only_audit_known_event_types() {
+        let params =
+            AuditParams::new(None, None, Some("VehicleRented"), None, None, None, None).unwrap();
+        assert_eq!(params.event_types, Some(&["VehicleRented"][..]));
+        assert!(
+            AuditParams::new(None, None, Some("VehicleStolen"), None, None, None, None)
+                .unwrap_err()
+                .starts_with("type: must be one of")
+        );
+    }
+
+    #[test]
+    fn it_should_require_the_identifier_of_the_snapshot_query() {
+        assert_eq!(
+            SnapshotTarget::new(
+                Some("CustomerRentalStatus"),
+                Some("mario@example.com"),
+                None,
+                None
+            ),
+            Ok(SnapshotTarget::CustomerRentalStatus(
+                "mario@example.com".into()
+            ))
+        );
+        assert_eq!(
+            SnapshotTarget::new(
+                Some("VehicleAvailability"),
+                Some("mario@example.com"),
+                None,
+                None
+            ),
+            Err("vehicleType: is required by the query".to_string())
+        );
+    }
+
+    #[test]
+    fn it_should_cap_the_calendar_range() {
+        let range =
+            CalendarRange::new(Some("van"), Some("2024-07-01"), Some("2024-10-01")).unwrap();
+        assert_eq!(range.vehicle_type, VehicleType::Van);
+        assert_eq!((range.to - range.from).num_days() + 1, MAX_CALENDAR_DAYS);
+        assert_eq!(
+            CalendarRange::new(Some("van"), Some("2024-07-01"), Some("2024-10-02")),
+            Err(format!(
+                "to: must be less than {MAX_CALENDAR_DAYS} days after from"
+            ))
+        );
+        assert_eq!(
+            CalendarRange::new(Some("bike"), Some("2024-07-01"), Some("2024-07-31")),
+            Err("vehicleType: must be one of car, pick_up, van, truck".to_string())
+        );
+        assert_eq!(
+            CalendarRange::new(Some("car"), None, Some("2024-07-31")),
+            Err("from: is required".to_string())
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Histories and commands generated in bulk, for the tests and the benchmarks to run the
+//! decisions on fleets and rentals of any size.
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use disintegrate::StateMutate;
+
+use crate::domain::{
+    default_tenant, DomainEvent, Email, EndRent, PlateNumber, RegisterCustomer, RegisterVehicle,
+    StartRent, VehicleType,
+};
+
+/// The plate of the `n`th vehicle.
+pub fn plate(n: usize) -> PlateNumber {
+    format!("AA{n:06}")
+}
+
+/// The email of the `n`th customer.
+pub fn customer(n: usize) -> Email {
+    format!("customer{n}@example.com").into()
+}
+
+/// The registration of the first `count` customers.
+pub fn customers(count: usize) -> impl Iterator<Item = DomainEvent> {
+    (0..count).map(|n| DomainEvent::CustomerRegistered {
+        tenant_id: default_tenant(),
+        customer_id: customer(n),
+        first_name: "Mario".to_string(),
+        last_name: format!("Rossi {n}"),
+        phone: None,
+    })
+}
+
+/// The registration of the first `count` vehicles, all of the type.
+pub fn fleet(vehicle_type: VehicleType, count: usize) -> impl Iterator<Item = DomainEvent> {
+    (0..count).map(move |n| DomainEvent::VehicleAdded {
+        tenant_id: default_tenant(),
+        vehicle_id: plate(n),
+        vehicle_type: vehicle_type.clone(),
+        seats: None,
+        transmission: None,
+    })
+}
+
+/// `count` rentals of the fleet of `vehicles` by the first `customers`, each rented then
+/// returned an hour later, the vehicles and the customers taking turns: two events a rental,
+/// every vehicle being available again after them.
+pub fn rentals(
+    vehicle_type: VehicleType,
+    vehicles: usize,
+    customers: usize,
+    count: usize,
+) -> impl Iterator<Item = DomainEvent> {
+    (0..count).flat_map(move |n| {
+        let start_date = since() + Duration::hours(n as i64);
+        [
+            DomainEvent::VehicleRented {
+                tenant_id: default_tenant(),
+                customer_id: customer(n % customers),
+                vehicle_id: plate(n % vehicles),
+                vehicle_type: vehicle_type.clone(),
+                start_date,
+            },
+            DomainEvent::VehicleReturned {
+                tenant_id: default_tenant(),
+                customer_id: customer(n % customers),
+                vehicle_id: plate(n % vehicles),
+                vehicle_type: vehicle_type.clone(),
+                start_date: Some(start_date),
+                returned_date: start_date + Duration::hours(1),
+            },
+        ]
+    })
+}
+
+/// When the generated rentals start.
+fn since() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+}
+
+/// The state folded from the events of its stream, the others being skipped.
+pub fn fold<S, E>(mut state: S, events: impl IntoIterator<Item = E>) -> S
+where
+    S: StateMutate,
+    E: TryInto<S::Event>,
+{
+    for event in events {
+        if let Ok(event) = event.try_into() {
+            state.mutate(event);
+        }
+    }
+    state
+}
+
+pub fn register_vehicle(n: usize, vehicle_type: VehicleType) -> RegisterVehicle {
+    RegisterVehicle {
+        tenant_id: default_tenant(),
+        vehicle_id: plate(n),
+        vehicle_type,
+        seats: None,
+        transmission: None,
+    }
+}
+
+pub fn register_customer(n: usize) -> RegisterCustomer {
+    RegisterCustomer {
+        tenant_id: default_tenant(),
+        customer_id: customer(n),
+        first_name: "Mario".to_string(),
+        last_name: format!("Rossi {n}"),
+        phone: None,
+    }
+}
+
+pub fn start_rent(n: usize, vehicle_type: VehicleType) -> StartRent {
+    StartRent {
+        tenant_id: default_tenant(),
+        customer_id: customer(n),
+        vehicle_type,
+    }
+}
+
+pub fn end_rent(n: usize) -> EndRent {
+    EndRent {
+        tenant_id: default_tenant(),
+        customer_id: customer(n),
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{read_model::queries::ReadModelRepository, self_check::SelfCheck};
+
+/// Longest a probe waits for the database, even when every connection of the pool is busy.
+pub const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentStatus {
+    Ok,
+    Timeout,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Health {
+    pub status: ComponentStatus,
+    /// Left out with `EVENT_STORE=memory`, which has no database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub db: Option<ComponentStatus>,
+}
+
+impl Health {
+    pub fn is_ok(&self) -> bool {
+        self.status == ComponentStatus::Ok
+    }
+
+    /// The health of an `EVENT_STORE=memory` instance, always up as long as it answers.
+    pub fn in_memory() -> Self {
+        Health {
+            status: ComponentStatus::Ok,
+            db: None,
+        }
+    }
+}
+
+/// Checks that the database answers, without touching any table.
+pub async fn check(pool: &PgPool, timeout: Duration) -> Health {
+    let db = match tokio::time::timeout(timeout, sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(Ok(_)) => ComponentStatus::Ok,
+        Ok(Err(err)) => {
+            tracing::warn!(error = %err, "health check failed to query the database");
+            ComponentStatus::Error
+        }
+        Err(_) => {
+            tracing::warn!("health check timed out querying the database");
+            ComponentStatus::Timeout
+        }
+    };
+    Health {
+        status: db,
+        db: Some(db),
+    }
+}
+
+/// Whether the instance should receive traffic, shared between the startup, the event
+/// listener and the HTTP server.
+///
+/// An instance is ready once the startup found the stores set up, the event listener has started and every
+/// projection is at most `max_lag` behind, so that an instance replaying a long stream isn't
+/// served stale read models.
+#[derive(Debug, Clone, Default)]
+pub struct Readiness(Arc<ReadinessFlags>);
+
+#[derive(Debug, Default)]
+struct ReadinessFlags {
+    checked: OnceLock<SelfCheck>,
+    listening: AtomicBool,
+    caught_up: AtomicBool,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    /// What the startup found of the stores.
+    pub checks: SelfCheck,
+    pub listener: bool,
+    pub projections: bool,
+}
+
+impl Readiness {
+    /// For an instance leaving the projections to another one, whose progress the checkpoints
+    /// of their listener tell.
+    pub fn without_listener() -> Self {
+        let readiness = Self::default();
+        readiness.listening();
+        readiness
+    }
+
+    pub fn checked(&self, check: SelfCheck) {
+        // Checked once, at startup.
+        let _ = self.0.checked.set(check);
+    }
+
+    pub fn listening(&self) {
+        self.0.listening.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether every projection is caught up, as `refresh` tells.
+    pub fn caught_up(&self, caught_up: bool) {
+        self.0.caught_up.store(caught_up, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> ReadinessReport {
+        let checks = self.0.checked.get().copied().unwrap_or_default();
+        let listener = self.0.listening.load(Ordering::Relaxed);
+        let projections = self.0.caught_up.load(Ordering::Relaxed);
+        ReadinessReport {
+            ready: checks.passed() && listener && projections,
+            checks,
+            listener,
+            projections,
+        }
+    }
+
+    /// Checks once whether every projection but the `paused` ones is within `max_lag` of the
+    /// event store.
+    pub async fn refresh(
+        &self,
+        repository: &ReadModelRepository,
+        max_lag: Duration,
+        paused: &BTreeSet<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let caught_up = repository
+            .projection_lags()
+            .await?
+            .iter()
+            .filter(|lag| !paused.contains(lag.listener_id))
+            .all(|lag| lag.lag_seconds <= max_lag.as_secs_f64());
+        self.caught_up(caught_up);
+        Ok(())
+    }
+
+    /// Polls the projection lags until the process exits, leaving the `paused` ones out.
+    ///
+    /// The last outcome is kept when the lags can't be read, as `/healthz` reports the database.
+    pub async fn watch(
+        self,
+        repository: ReadModelRepository,
+        max_lag: Duration,
+        paused: BTreeSet<&'static str>,
+    ) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.refresh(&repository, max_lag, &paused).await {
+                tracing::warn!(error = %err, "failed to check the projection lags");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        application::{Application, DecisionMaker},
+        domain::RegisterVehicle,
+        pii::EncryptedJson,
+        self_check, test_support,
+    };
+    use disintegrate_postgres::{PgEventStore, PgSnapshotter};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use std::time::Instant;
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_report_a_reachable_database(pool: PgPool) {
+        let health = check(&pool, DATABASE_TIMEOUT).await;
+        assert_eq!(
+            health,
+            Health {
+                status: ComponentStatus::Ok,
+                db: Some(ComponentStatus::Ok)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_report_an_unreachable_database_within_the_timeout() {
+        let options = PgConnectOptions::new().host("127.0.0.1").port(1);
+        let pool = PgPoolOptions::new().connect_lazy_with(options);
+        let timeout = Duration::from_millis(500);
+
+        let started = Instant::now();
+        let health = check(&pool, timeout).await;
+        assert!(started.elapsed() < timeout * 2);
+        assert!(!health.is_ok());
+        assert_ne!(health.db, Some(ComponentStatus::Ok));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_be_ready_once_the_listener_caught_up(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let repository = ReadModelRepository::new(pool.clone());
+        let events = PgPool::connect_with(options).await.unwrap();
+        let event_store = PgEventStore::new(events.clone(), EncryptedJson::default())
+            .await
+            .unwrap();
+        let snapshotter = PgSnapshotter::new(events, 10).await.unwrap();
+        let application = Application::new(
+            DecisionMaker::snapshotted(event_store.clone(), snapshotter),
+            event_store,
+        );
+        application
+            .register_vehicle(
+                serde_json::from_value::<RegisterVehicle>(serde_json::json!({
+                    "vehicleId": "AA111AA",
+                    "vehicleType": "Car",
+                }))
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        sqlx::query(self_check::CREATE_CHECKPOINTS)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let readiness = Readiness::default();
+        let checks = SelfCheck {
+            database: true,
+            event_store: true,
+            migrations: true,
+            checkpoints: true,
+            sealed: true,
+        };
+        readiness.checked(checks);
+        readiness.listening();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        readiness
+            .refresh(&repository, Duration::ZERO, &BTreeSet::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            readiness.report(),
+            ReadinessReport {
+                ready: false,
+                checks,
+                listener: true,
+                projections: false,
+            }
+        );
+
+        for lag in repository.projection_lags().await.unwrap() {
+            sqlx::query(
+                "INSERT INTO public.event_listener (id, last_processed_event_id) VALUES($1, $2)",
+            )
+            .bind(lag.listener_id)
+            .bind(lag.latest_event_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        readiness
+            .refresh(&repository, Duration::ZERO, &BTreeSet::new())
+            .await
+            .unwrap();
+        assert!(readiness.report().ready);
+    }
+}
@@ -0,0 +1,927 @@
+mod admin;
+mod reports;
+mod routes;
+
+use std::{
+    future::{ready, Future},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+};
+
+use actix_web::{
+    dev::{Server, Service, ServiceRequest, ServiceResponse},
+    error, get,
+    http::{
+        header::{HeaderName, HeaderValue},
+        Method, StatusCode,
+    },
+    middleware::{Compress, DefaultHeaders, ErrorHandlers},
+    web::{scope, Data, ServiceConfig},
+    App, HttpResponse, HttpServer,
+};
+use disintegrate_postgres::PgSnapshotter;
+use sqlx::PgPool;
+use tracing_actix_web::TracingLogger;
+
+use crate::admin::{RebuildReadMode, RebuildStatus};
+use crate::application::Application;
+use crate::auth::is_admin;
+use crate::config::AppConfig;
+use crate::health::Readiness;
+use crate::live::LiveUpdates;
+use crate::metrics::Metrics;
+use crate::read_model::queries::ReadModelRepository;
+use crate::read_model::repository::ReadRepository;
+use crate::reporting::ErrorReporter;
+use crate::request_id::RequestSpan;
+use crate::scheduler::Scheduler;
+use crate::EventStore;
+use crate::{
+    application, auth, command_audit, errors, health, i18n, reporting, request_id, shutdown,
+    validation,
+};
+
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<ServiceResponse, actix_web::Error>>>>;
+
+/// Set on reads served while a projection is being rebuilt.
+const READ_MODEL_STALE: HeaderName = HeaderName::from_static("x-read-model-stale");
+
+/// Set on the responses to the deprecated unversioned paths.
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+pub(crate) const API_V1: &str = "/api/v1";
+
+/// What the server reads and writes in Postgres besides the read model, for the reports and
+/// the admin routes; missing with `EVENT_STORE=memory`.
+#[derive(Clone)]
+pub(crate) struct PgBackend {
+    pub event_store: EventStore,
+    pub snapshotter: PgSnapshotter,
+    pub pool: PgPool,
+    pub scheduler: Scheduler,
+}
+
+/// Binds the HTTP server, returning it along with the addresses it's bound to, which tell the
+/// actual port when binding to port 0.
+///
+/// The API isn't served in the `listener` mode, leaving the probes and the metrics. Without
+/// `postgres`, it's served without the reports and the admin routes.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn http_server(
+    config: &AppConfig,
+    app: Application,
+    reads: Data<dyn ReadRepository>,
+    postgres: Option<PgBackend>,
+    rebuild_status: RebuildStatus,
+    readiness: Readiness,
+    live: LiveUpdates,
+    metrics: Metrics,
+    reporter: Arc<dyn ErrorReporter>,
+) -> anyhow::Result<(Server, Vec<SocketAddr>)> {
+    let rebuild_mode = config.rebuild_mode;
+    let rate_limits = config.rate_limits.in_memory();
+    let api_keys = config.api_keys.clone();
+    let body_limit = config.body_limit;
+    let public_url = config.public_url.clone();
+    let serves_api = config.mode.serves_api();
+    let listener_config = config.listener.clone();
+    let policy = config.snapshot_policy();
+    let config = &config.http;
+    let tls = match &config.tls {
+        Some(tls) => Some((tls.port, tls.server_config().map_err(anyhow::Error::msg)?)),
+        None => None,
+    };
+    let mut server = HttpServer::new(move || {
+        App::new()
+            .app_data(application::command_service(app.clone()))
+            .app_data(reads.clone())
+            .configure(|cfg| {
+                if let Some(postgres) = &postgres {
+                    cfg.app_data(Data::new(postgres.event_store.clone()))
+                        .app_data(Data::new(postgres.snapshotter.clone()))
+                        .app_data(Data::new(postgres.pool.clone()))
+                        .app_data(Data::new(ReadModelRepository::new(postgres.pool.clone())))
+                        .app_data(Data::new(postgres.scheduler.clone()));
+                }
+            })
+            .app_data(Data::new(rebuild_status.clone()))
+            .app_data(Data::new(rebuild_mode))
+            .app_data(Data::new(readiness.clone()))
+            .app_data(Data::new(live.clone()))
+            .app_data(Data::new(public_url.clone()))
+            .app_data(Data::new(metrics.clone()))
+            .app_data(Data::new(listener_config.clone()))
+            .app_data(Data::new(policy))
+            .app_data(Data::from(reporter.clone()))
+            .app_data(validation::json_config(body_limit))
+            .configure(|cfg| {
+                if let Some(tokens) = api_keys.tokens() {
+                    cfg.app_data(Data::new(tokens.clone()));
+                }
+            })
+            .wrap_fn(hold_reads_during_rebuild)
+            .wrap(rate_limits.clone())
+            .wrap(api_keys.clone())
+            .wrap_fn(reporting::report_server_errors)
+            .wrap(ErrorHandlers::new().handler(StatusCode::INTERNAL_SERVER_ERROR, errors::internal))
+            .wrap(TracingLogger::<RequestSpan>::new())
+            .wrap_fn(i18n::negotiate)
+            .wrap_fn(request_id::propagate)
+            .wrap(Compress::default())
+            .service(healthz)
+            .service(readyz)
+            .service(prometheus_metrics)
+            .configure(|cfg| match &postgres {
+                _ if !serves_api => {}
+                Some(_) => api(cfg),
+                None => memory_api(cfg),
+            })
+    })
+    // Stopped along the event listener, see `shutdown::run`.
+    .disable_signals()
+    .shutdown_timeout(shutdown::DEFAULT_GRACE_PERIOD.as_secs());
+    for host in &config.hosts {
+        if let Some(port) = config.port {
+            server = server.bind((host.as_str(), port))?;
+        }
+        if let Some((port, tls)) = &tls {
+            server = server.bind_rustls_0_23((host.as_str(), *port), tls.clone())?;
+        }
+    }
+    let addrs = server.addrs();
+    for addr in &addrs {
+        tracing::info!(%addr, "HTTP server listening");
+    }
+    Ok((server.run(), addrs))
+}
+
+/// Serves the reads while a projection is being rebuilt as `READ_MODEL_REBUILD_MODE` says:
+/// flagged as stale, or held back with a `READ_MODEL_REBUILDING`.
+///
+/// The admin routes, the probes and the metrics are always served.
+fn hold_reads_during_rebuild<S>(req: ServiceRequest, srv: &S) -> ResponseFuture
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    let in_progress = req
+        .app_data::<Data<RebuildStatus>>()
+        .is_some_and(|status| status.in_progress());
+    let rebuilding = in_progress
+        && req.method() == Method::GET
+        && !is_admin(req.path())
+        && !matches!(req.path(), "/healthz" | "/readyz" | "/metrics");
+    let mode = req
+        .app_data::<Data<RebuildReadMode>>()
+        .map(|mode| *mode.get_ref())
+        .unwrap_or_default();
+    if rebuilding && mode == RebuildReadMode::Unavailable {
+        return Box::pin(ready(Ok(req.into_response(errors::rebuilding()))));
+    }
+    let response = srv.call(req);
+    Box::pin(async move {
+        let mut response = response.await?;
+        if rebuilding {
+            response
+                .headers_mut()
+                .insert(READ_MODEL_STALE, HeaderValue::from_static("true"));
+        }
+        Ok(response)
+    })
+}
+
+/// Mounts the API under `/api/v1`, along with the unversioned paths it was served on before as
+/// deprecated aliases.
+///
+/// Another version gets a scope of its own, with handlers sharing the same `Application`.
+pub(crate) fn api(cfg: &mut ServiceConfig) {
+    versioned(cfg, api_v1);
+}
+
+/// Mounts the API of `EVENT_STORE=memory`: the commands and the reads of the read model, the
+/// reports and the admin routes reading Postgres alone.
+pub(crate) fn memory_api(cfg: &mut ServiceConfig) {
+    versioned(cfg, routes::configure);
+}
+
+fn versioned(cfg: &mut ServiceConfig, v1: fn(&mut ServiceConfig)) {
+    cfg.service(
+        scope(API_V1)
+            .wrap_fn(command_audit::scope_actor)
+            .configure(v1),
+    )
+    .service(
+        scope("")
+            .wrap_fn(command_audit::scope_actor)
+            .wrap(DefaultHeaders::new().add((DEPRECATION, "true")))
+            .configure(v1),
+    );
+}
+
+fn api_v1(cfg: &mut ServiceConfig) {
+    cfg.configure(routes::configure)
+        .configure(reports::configure)
+        .service(
+            scope("/admin")
+                .wrap_fn(auth::require_admin)
+                .wrap_fn(crate::admin::audit)
+                .configure(admin::configure),
+        );
+}
+
+/// Liveness probe, answering `503 Service Unavailable` with the failing component.
+#[get("/healthz")]
+async fn healthz(pool: Option<Data<PgPool>>) -> HttpResponse {
+    let health = match pool {
+        Some(pool) => health::check(&pool, health::DATABASE_TIMEOUT).await,
+        None => health::Health::in_memory(),
+    };
+    if health.is_ok() {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
+}
+
+/// Readiness probe, answering `503 Service Unavailable` until the read models are caught up.
+#[get("/readyz")]
+async fn readyz(readiness: Data<Readiness>) -> HttpResponse {
+    let report = readiness.report();
+    if report.ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// Metrics of the application in the Prometheus text format, authenticated as the reads.
+#[get("/metrics")]
+///
+/// The lags of the projections are read afresh at each scrape; the last ones read are exported
+/// when they can't be.
+async fn prometheus_metrics(
+    metrics: Data<Metrics>,
+    repository: Option<Data<ReadModelRepository>>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(repository) = repository {
+        match repository.projection_lags().await {
+            Ok(lags) => metrics.projection_lags(&lags),
+            Err(err) => tracing::warn!(error = %err, "couldn't read the lags of the projections"),
+        }
+    }
+    let text = metrics.render().map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(text))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use actix_web::dev::ServerHandle;
+    use actix_web::{
+        http::header::{
+            ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, TRANSFER_ENCODING,
+        },
+        test,
+    };
+    use actix_web::{
+        http::{
+            header::{LOCATION, RETRY_AFTER},
+            Method, StatusCode,
+        },
+        web::{self, Data},
+        App,
+    };
+    use disintegrate_postgres::PgEventStore;
+    use futures_util::TryStreamExt;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use sqlx::PgPool;
+    use tracing_actix_web::TracingLogger;
+
+    use crate::application::Application;
+    use crate::auth::ApiKeys;
+    use crate::domain::{
+        default_tenant, DomainEvent, Email, RegisterCustomer, RegisterVehicle, VehicleType,
+    };
+    use crate::http::{api, DEPRECATION};
+    use crate::http_config::HttpConfig;
+    use crate::metrics::Metrics;
+    use crate::read_model::queries::ReadModelRepository;
+    use crate::read_model::repository::read_repository;
+    use crate::reporting::{ErrorContext, ErrorReporter};
+    use crate::request_id::{RequestSpan, REQUEST_ID};
+    use crate::test_support::{self, application, bind, probe};
+    use crate::tokens::{Role, TokenKeys};
+    use crate::EventStore;
+    use crate::{application, read_model, reporting, request_id, telemetry, tokens};
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_tell_to_retry_later_once_the_pool_is_exhausted(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let migrated = test_support::read_model(options).await;
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(200))
+            .connect_with((*migrated.connect_options()).clone())
+            .await
+            .unwrap();
+        let service = test::init_service(
+            App::new()
+                .app_data(read_repository(ReadModelRepository::new(pool.clone())))
+                .configure(api),
+        )
+        .await;
+        let _held = pool.acquire().await.unwrap();
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/vehicles")
+            .to_request();
+        let response = tokio::time::timeout(
+            Duration::from_secs(5),
+            test::call_service(&service, request),
+        )
+        .await
+        .expect("answered without waiting for the connection");
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "1");
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "STORE_UNAVAILABLE");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_check_the_role_of_the_tokens(_: PgPoolOptions, options: PgConnectOptions) {
+        let tokens = TokenKeys::new(tokens::test::SECRET).unwrap();
+        let pool = test_support::read_model(options.clone()).await;
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(application(options).await))
+                .app_data(Data::new(ReadModelRepository::new(pool)))
+                .app_data(Data::new(tokens.clone()))
+                .wrap(ApiKeys::new(None, None, None, Some(tokens)).unwrap())
+                .configure(api),
+        )
+        .await;
+        let admin = tokens::test::token("ops", Role::Admin, 60);
+        let mario = tokens::test::token("mario@example.com", Role::Customer, 60);
+        let luigi = tokens::test::token("luigi@example.com", Role::Customer, 60);
+        let request = |method: Method, uri: &str, token: Option<&str>| {
+            let request = test::TestRequest::default().method(method).uri(uri);
+            match token {
+                Some(token) => request.insert_header((AUTHORIZATION, format!("Bearer {token}"))),
+                None => request,
+            }
+        };
+        let start = |token: Option<&str>| {
+            request(Method::POST, "/api/v1/rent/start", token)
+                .set_json(serde_json::json!({
+                    "customerId": "mario@example.com", "vehicleType": "Van"
+                }))
+                .to_request()
+        };
+
+        for (uri, body) in [
+            (
+                "/api/v1/vehicle/register",
+                serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }),
+            ),
+            (
+                "/api/v1/customer/register",
+                serde_json::json!({
+                    "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                }),
+            ),
+        ] {
+            let request = request(Method::POST, uri, Some(&admin)).set_json(body);
+            let response = test::call_service(&service, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::CREATED, "{uri}");
+        }
+
+        let response = test::call_service(&service, start(None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&service, start(Some(&luigi))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "FORBIDDEN");
+        let response = test::call_service(&service, start(Some(&mario))).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let list_dead_letters = |token: Option<&str>| {
+            request(Method::GET, "/api/v1/admin/dead-letters", token).to_request()
+        };
+        let response = test::call_service(&service, list_dead_letters(None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&service, list_dead_letters(Some(&mario))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = test::call_service(&service, list_dead_letters(Some(&admin))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let expired = tokens::test::token("ops", Role::Admin, -3600);
+        let response = test::call_service(&service, list_dead_letters(Some(&expired))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "UNAUTHENTICATED");
+        assert_eq!(body["message"], "the token expired");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_count_the_commands_by_result_in_the_metrics(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let metrics = Metrics::default();
+        let application = application(options.clone()).await;
+        let pool = test_support::read_model(options).await;
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(
+                    application.with_metrics(metrics.clone()),
+                ))
+                .app_data(Data::new(ReadModelRepository::new(pool)))
+                .app_data(Data::new(metrics))
+                .service(prometheus_metrics)
+                .configure(api),
+        )
+        .await;
+        let register = test::TestRequest::post()
+            .uri("/api/v1/customer/register")
+            .set_json(serde_json::json!({
+                "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+            }))
+            .to_request();
+        let response = test::call_service(&service, register).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let start = test::TestRequest::post()
+            .uri("/api/v1/rent/start")
+            .set_json(serde_json::json!({
+                "customerId": "mario@example.com", "vehicleType": "Van"
+            }))
+            .to_request();
+        let response = test::call_service(&service, start).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let scrape = test::TestRequest::get().uri("/metrics").to_request();
+        let response = test::call_service(&service, scrape).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            prometheus::TEXT_FORMAT
+        );
+        let text = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        for series in [
+            r#"car_rental_commands_total{code="",command="RegisterCustomer",result="ok"} 1"#,
+            r#"car_rental_commands_total{code="NO_AVAILABLE_VEHICLES",command="StartRent",result="domain_error"} 1"#,
+            r#"car_rental_command_duration_seconds_count{command="RegisterCustomer"} 1"#,
+            r#"car_rental_command_duration_seconds_count{command="StartRent"} 1"#,
+            r#"car_rental_command_attempts_bucket{command="StartRent",le="1"} 1"#,
+            r#"car_rental_projection_pending_events{projection="drive_me_crazy_customers"} 1"#,
+        ] {
+            assert!(
+                text.lines().any(|line| line == series),
+                "{series} in {text}"
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_report_the_unexpected_errors_but_not_the_domain_ones(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        use std::sync::Arc;
+
+        use test_support::RecordingReporter;
+
+        let reporter = Arc::new(RecordingReporter::default());
+        let application = application(options.clone()).await;
+        let pool = test_support::read_model(options).await;
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(
+                    application.with_error_reporter(reporter.clone()),
+                ))
+                .app_data(Data::from(reporter.clone() as Arc<dyn ErrorReporter>))
+                .app_data(Data::new(ReadModelRepository::new(pool.clone())))
+                .configure(api)
+                .wrap_fn(reporting::report_server_errors)
+                .wrap_fn(request_id::propagate),
+        )
+        .await;
+        let start = test::TestRequest::post()
+            .uri("/api/v1/rent/start")
+            .insert_header((REQUEST_ID, "rejected"))
+            .set_json(serde_json::json!({
+                "customerId": "mario@example.com", "vehicleType": "Van"
+            }))
+            .to_request();
+        let response = test::call_service(&service, start).await;
+        assert!(response.status().is_client_error());
+        assert_eq!(reporter.reported(), vec![]);
+
+        sqlx::query("DROP TABLE vehicle CASCADE")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let list = test::TestRequest::get()
+            .uri("/api/v1/vehicles")
+            .insert_header((REQUEST_ID, "read"))
+            .to_request();
+        let response = test::call_service(&service, list).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let reported = reporter.reported();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(
+            reported[0].1,
+            ErrorContext {
+                request_id: Some("read".to_string()),
+                ..Default::default()
+            }
+        );
+
+        sqlx::query("DROP TABLE public.event CASCADE")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let register = test::TestRequest::post()
+            .uri("/api/v1/vehicle/register")
+            .insert_header((REQUEST_ID, "stored"))
+            .set_json(serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }))
+            .to_request();
+        let response = test::call_service(&service, register).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let reported = reporter.reported();
+        assert_eq!(reported.len(), 2);
+        assert_eq!(
+            reported[1].1,
+            ErrorContext {
+                request_id: Some("stored".to_string()),
+                command: Some("RegisterVehicle"),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_trace_a_command_from_its_request_to_its_projection(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        use disintegrate::{EventListener, EventStore as _, PersistedEvent};
+        use opentelemetry::{trace::TraceId, Value};
+        use opentelemetry_sdk::{
+            propagation::TraceContextPropagator,
+            trace::{InMemorySpanExporter, SdkTracerProvider, SpanData},
+        };
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store: EventStore = PgEventStore::new(
+            PgPool::connect_with(options.clone()).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let app = application(options).await;
+        app.register_vehicle(RegisterVehicle {
+            tenant_id: default_tenant(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Van,
+            seats: None,
+            transmission: None,
+        })
+        .await
+        .unwrap();
+        app.register_customer(RegisterCustomer {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: None,
+        })
+        .await
+        .unwrap();
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let _tracing = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(telemetry::layer(&provider)),
+        );
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(app))
+                .wrap(TracingLogger::<RequestSpan>::new())
+                .wrap_fn(request_id::propagate)
+                .configure(api),
+        )
+        .await;
+        let start = test::TestRequest::post()
+            .uri("/api/v1/rent/start")
+            .insert_header((
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ))
+            .set_json(serde_json::json!({
+                "customerId": "mario@example.com", "vehicleType": "Van"
+            }))
+            .to_request();
+        let started: serde_json::Value = test::call_and_read_body_json(&service, start).await;
+        let rent_id = started["rentId"].as_i64().unwrap();
+
+        let customer_id = Email::from("mario@example.com");
+        let rented = event_store
+            .stream(&disintegrate::query!(
+                DomainEvent,
+                customer_id == customer_id
+            ))
+            .try_filter(|event| std::future::ready(event.id() == rent_id))
+            .try_next()
+            .await
+            .unwrap()
+            .unwrap();
+        read_model::RentalProjection::new(pool)
+            .handle(PersistedEvent::new(
+                rent_id,
+                rented.into_inner().try_into().unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = |name: &str| -> &SpanData {
+            spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("no {name} span in {spans:?}"))
+        };
+        let attribute = |span: &SpanData, key: &str| {
+            span.attributes
+                .iter()
+                .find(|attribute| attribute.key.as_str() == key)
+                .map(|attribute| attribute.value.clone())
+        };
+        let request = span("HTTP request");
+        assert_eq!(
+            request.span_context.trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+        assert_eq!(request.parent_span_id.to_string(), "00f067aa0ba902b7");
+        assert_eq!(
+            attribute(request, "http.route"),
+            Some(Value::from("/api/v1/rent/start"))
+        );
+
+        let command = span("start_rent");
+        assert_eq!(
+            command.span_context.trace_id(),
+            request.span_context.trace_id()
+        );
+        assert_eq!(command.parent_span_id, request.span_context.span_id());
+        assert_eq!(
+            attribute(command, "command"),
+            Some(Value::from("StartRent"))
+        );
+        assert_eq!(attribute(command, "vehicle_type"), Some(Value::from("van")));
+        assert_eq!(
+            attribute(command, "customer_id"),
+            Some(Value::from("m***@example.com"))
+        );
+        assert_eq!(
+            attribute(command, "event_ids"),
+            Some(Value::from(rent_id.to_string()))
+        );
+
+        let projection = span("handle");
+        assert_eq!(
+            attribute(projection, "listener_id"),
+            Some(Value::from(read_model::RentalProjection::ID))
+        );
+        assert_eq!(
+            attribute(projection, "event_id"),
+            Some(Value::from(rent_id))
+        );
+    }
+
+    #[actix_web::test]
+    async fn it_should_keep_the_unversioned_paths_as_deprecated_aliases() {
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(Application::in_memory(
+                    Default::default(),
+                )))
+                .configure(api),
+        )
+        .await;
+        let register = |uri: &str, vehicle_id: &str| {
+            test::TestRequest::post()
+                .uri(uri)
+                .set_json(serde_json::json!({ "vehicleId": vehicle_id, "vehicleType": "Car" }))
+                .to_request()
+        };
+
+        let response =
+            test::call_service(&service, register("/api/v1/vehicle/register", "AA111AA")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(response.headers().get(DEPRECATION).is_none());
+
+        let response = test::call_service(&service, register("/vehicle/register", "BB222BB")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers().get(DEPRECATION).unwrap(), "true");
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            "/api/v1/vehicles/BB222BB"
+        );
+    }
+
+    #[actix_web::test]
+    async fn it_should_hold_the_reads_back_or_flag_them_as_stale_during_a_rebuild() {
+        let status = RebuildStatus::default();
+        status.started();
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        for mode in [RebuildReadMode::Unavailable, RebuildReadMode::Stale] {
+            let service = test::init_service(
+                App::new()
+                    .app_data(Data::new(status.clone()))
+                    .app_data(Data::new(mode))
+                    .wrap_fn(hold_reads_during_rebuild)
+                    .route("/api/v1/vehicles", web::get().to(HttpResponse::Ok))
+                    .route("/api/v1/admin/projections", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+
+            let response = test::call_service(&service, get("/api/v1/vehicles")).await;
+            if mode == RebuildReadMode::Unavailable {
+                assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "5");
+                let body: serde_json::Value = test::read_body_json(response).await;
+                assert_eq!(body["code"], "READ_MODEL_REBUILDING");
+            } else {
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(response.headers().get(READ_MODEL_STALE).unwrap(), "true");
+            }
+            let response = test::call_service(&service, get("/api/v1/admin/projections")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers().get(READ_MODEL_STALE).is_none());
+        }
+    }
+
+    /// Starts the server, returning the addresses it's bound to.
+    async fn serve(
+        options: PgConnectOptions,
+        config: &HttpConfig,
+    ) -> (ServerHandle, Vec<SocketAddr>) {
+        let (server, addrs) = bind(options, config).await;
+        let handle = server.handle();
+        tokio::spawn(server);
+        (handle, addrs)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_tell_the_port_it_is_bound_to(_: PgPoolOptions, options: PgConnectOptions) {
+        let config = HttpConfig::new(None, Some("0"), None).unwrap();
+        let (handle, addrs) = serve(options, &config).await;
+
+        assert_eq!(addrs.len(), 1);
+        assert_ne!(addrs[0].port(), 0);
+        let stream = tokio::net::TcpStream::connect(addrs[0]).await.unwrap();
+        let response = probe(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        handle.stop(false).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_stream_the_rentals_export_compressed(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date, duration_minutes)
+                SELECT i, 'mario@example.com', 'AA111AA', '2024-07-01'::timestamptz + i * interval '1 minute',
+                    '2024-07-01'::timestamptz + (i + 30) * interval '1 minute', 30
+                FROM generate_series(1, 20000) AS i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let config = HttpConfig::new(None, Some("0"), None).unwrap();
+        let (handle, addrs) = serve(options, &config).await;
+
+        // The client spawns its connections on the current thread.
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let mut response = awc::Client::new()
+                    .get(format!("http://{}/api/v1/rentals/export", addrs[0]))
+                    .insert_header((ACCEPT_ENCODING, "gzip"))
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+                assert_eq!(
+                    response.headers().get(TRANSFER_ENCODING).unwrap(),
+                    "chunked"
+                );
+                let body = response.body().limit(16 * 1024 * 1024).await.unwrap();
+                let mut export = csv::Reader::from_reader(body.as_ref());
+                let rent_ids: Vec<i64> = export
+                    .records()
+                    .map(|record| record.unwrap()[0].parse().unwrap())
+                    .collect();
+                assert_eq!(rent_ids, (1..=20000).collect::<Vec<_>>());
+            })
+            .await;
+        handle.stop(false).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_quote_the_request_id_of_the_response_in_the_errors(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let config = HttpConfig::new(None, Some("0"), None).unwrap();
+        let (handle, addrs) = serve(options, &config).await;
+        let url = |path: &str| format!("http://{}/api/v1{path}", addrs[0]);
+
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let client = awc::Client::new();
+                let register = serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Car" });
+                client
+                    .post(url("/vehicle/register"))
+                    .send_json(&register)
+                    .await
+                    .unwrap();
+                let mut response = client
+                    .post(url("/vehicle/register"))
+                    .send_json(&register)
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::CONFLICT);
+                let request_id = response.headers().get(REQUEST_ID).unwrap().clone();
+                let body: serde_json::Value = response.json().await.unwrap();
+                assert_eq!(body["code"], "ALREADY_REGISTERED_VEHICLE");
+                assert_eq!(body["docs"], "errors#ALREADY_REGISTERED_VEHICLE");
+                assert_eq!(body["requestId"], request_id.to_str().unwrap());
+
+                // The read model failing, its error isn't told.
+                sqlx::query("DROP TABLE vehicle CASCADE")
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+                let mut response = client.get(url("/vehicles")).send().await.unwrap();
+                assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+                let request_id = response.headers().get(REQUEST_ID).unwrap().clone();
+                let body: serde_json::Value = response.json().await.unwrap();
+                assert_eq!(
+                    body,
+                    serde_json::json!({
+                        "code": "STORE_ERROR",
+                        "message": "the request could not be processed",
+                        "details": {},
+                        "docs": "errors#STORE_ERROR",
+                        "requestId": request_id.to_str().unwrap(),
+                    })
+                );
+            })
+            .await;
+        handle.stop(false).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_serve_https_with_the_configured_certificate(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let (tls, cert) = test_support::self_signed("https");
+        let config = HttpConfig::new(None, None, Some(tls)).unwrap();
+        let (handle, addrs) = serve(options, &config).await;
+        assert_eq!(addrs.len(), 1);
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = tokio::net::TcpStream::connect(addrs[0]).await.unwrap();
+        let stream = tokio_rustls::TlsConnector::from(std::sync::Arc::new(client))
+            .connect("localhost".try_into().unwrap(), stream)
+            .await
+            .unwrap();
+        let response = probe(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        handle.stop(false).await;
+    }
+}
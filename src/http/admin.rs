@@ -0,0 +1,906 @@
+use actix_multipart::Multipart;
+use actix_web::{
+    delete, error, get, post,
+    web::{Bytes, Data, Json, Path, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use disintegrate_postgres::PgSnapshotter;
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::admin::{
+    AdminCall, ProjectionStatus, Rebuild, RebuildStatus, RetryOutcome, SnapshotInspection,
+};
+use crate::application::{CommandService, TenantEventStore};
+use crate::command_audit::AuditedCommand;
+use crate::config::{ListenerConfig, SnapshotPolicy};
+use crate::dead_letter::DeadLetter;
+use crate::filters::{AuditParams, CommandAuditFilter, SnapshotTarget};
+use crate::import::{ImportReport, UploadError};
+use crate::pagination::{Cursor, PageParams, Paginated};
+use crate::read_model::queries::ReadModelRepository;
+use crate::scheduler::{JobStatus, RunOutcome, Scheduler};
+use crate::tenant::Tenant;
+use crate::tokens::AuthError;
+use crate::validation::Valid;
+use crate::webhooks::{NewWebhook, Webhook, WebhookDeadLetter};
+use crate::EventStore;
+use crate::{admin, command_audit, errors, import, pagination, webhooks};
+
+/// The operator routes, mounted under `/admin`.
+pub(super) fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(projections)
+        .service(audit_events)
+        .service(admin_calls)
+        .service(audited_commands)
+        .service(snapshots)
+        .service(snapshot_policy)
+        .service(rebuild_projection)
+        .service(import_vehicles)
+        .service(import_customers)
+        .service(dead_letters)
+        .service(retry_dead_letter)
+        .service(register_webhook)
+        .service(list_webhooks)
+        .service(webhook_dead_letters)
+        .service(remove_webhook)
+        .service(scheduled_jobs)
+        .service(run_scheduled_job);
+}
+
+#[get("/projections")]
+async fn projections(
+    repository: ReadModelRepository,
+    config: Data<ListenerConfig>,
+) -> actix_web::Result<Json<Vec<ProjectionStatus>>> {
+    let lags = repository
+        .projection_lags()
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(
+        lags.into_iter()
+            .map(|lag| ProjectionStatus::new(lag, &config))
+            .collect(),
+    ))
+}
+
+#[get("/events")]
+async fn audit_events(pool: Data<PgPool>, tenant: Tenant, params: AuditParams) -> HttpResponse {
+    let page = admin::audit_events(&pool, &tenant.0, &params);
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .streaming(
+            pagination::stream_cursor_page(page, params.limit, |event| Cursor(event.event_id))
+                .map_ok(Bytes::from),
+        )
+}
+
+/// Calls of the tenant to the admin routes, the latest first.
+#[get("/audit")]
+async fn admin_calls(
+    pool: Data<PgPool>,
+    tenant: Tenant,
+    page: PageParams,
+) -> actix_web::Result<Json<Paginated<AdminCall>>> {
+    let (calls, total) = admin::admin_calls(&pool, &tenant.0, page)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(Paginated::new(calls, total, page)))
+}
+
+/// Commands of the tenant executed by the application, the latest first, rejected ones
+/// included.
+#[get("/command-audit")]
+async fn audited_commands(
+    pool: Data<PgPool>,
+    tenant: Tenant,
+    filter: CommandAuditFilter,
+    page: PageParams,
+) -> actix_web::Result<Json<Paginated<AuditedCommand>>> {
+    let (commands, total) = command_audit::audited_commands(&pool, &tenant.0, &filter, page)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(Paginated::new(commands, total, page)))
+}
+
+/// Inspects the snapshot of a state query of the tenant.
+#[get("/snapshots")]
+async fn snapshots(
+    snapshotter: Data<PgSnapshotter>,
+    event_store: Data<EventStore>,
+    tenant: Tenant,
+    target: SnapshotTarget,
+) -> actix_web::Result<Json<SnapshotInspection>> {
+    let event_store = TenantEventStore::new(event_store.get_ref().clone());
+    let inspection = admin::inspect_snapshot(&snapshotter, &event_store, tenant.0, target)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(Json(inspection))
+}
+
+#[get("/snapshots/policy")]
+async fn snapshot_policy(policy: Data<SnapshotPolicy>) -> Json<SnapshotPolicy> {
+    Json(**policy)
+}
+
+/// Rebuilds a projection from the events of every tenant, so only the platform admins, of no
+/// tenant, may.
+#[post("/projections/{listener_id}/rebuild")]
+async fn rebuild_projection(
+    req: HttpRequest,
+    pool: Data<PgPool>,
+    rebuild_status: Data<RebuildStatus>,
+    listener_id: Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    if Tenant::named(&req).is_some() {
+        return Err(
+            AuthError::forbidden("only the platform admins can rebuild the projections").into(),
+        );
+    }
+    let rebuild: Rebuild = admin::rebuild(&pool, &listener_id)
+        .await
+        .map_err(errors::read_model)?
+        .ok_or_else(|| error::ErrorNotFound("projection not found"))?;
+    rebuild_status.started();
+    Ok(HttpResponse::Accepted().json(rebuild))
+}
+
+#[get("/dead-letters")]
+async fn dead_letters(repository: ReadModelRepository) -> actix_web::Result<Json<Vec<DeadLetter>>> {
+    let letters = repository
+        .dead_letters()
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(letters))
+}
+
+#[post("/dead-letters/{id}/retry")]
+async fn retry_dead_letter(
+    pool: Data<PgPool>,
+    tenant: Tenant,
+    id: Path<i64>,
+) -> actix_web::Result<HttpResponse> {
+    let outcome = admin::retry_dead_letter(&pool, &tenant.0, *id)
+        .await
+        .map_err(errors::read_model)?
+        .ok_or_else(|| error::ErrorNotFound("dead letter not found"))?;
+    Ok(match outcome {
+        RetryOutcome::Reprocessed => HttpResponse::Ok().body("reprocessed"),
+        RetryOutcome::Failed(error) => HttpResponse::Conflict().body(error),
+    })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookRegistered {
+    webhook_id: i64,
+}
+
+/// Registers the vehicles of an uploaded CSV file, reporting the outcome of every row.
+#[post("/vehicles/import")]
+async fn import_vehicles(
+    app: Data<dyn CommandService>,
+    tenant: Tenant,
+    upload: Multipart,
+) -> Result<Json<ImportReport>, UploadError> {
+    let file = import::read_file(upload).await?;
+    Ok(Json(
+        import::import_vehicles(&**app, &tenant.0, &file).await?,
+    ))
+}
+
+/// Registers the customers of an uploaded CSV file, reporting the outcome of every row.
+#[post("/customers/import")]
+async fn import_customers(
+    app: Data<dyn CommandService>,
+    tenant: Tenant,
+    upload: Multipart,
+) -> Result<Json<ImportReport>, UploadError> {
+    let file = import::read_file(upload).await?;
+    Ok(Json(
+        import::import_customers(&**app, &tenant.0, &file).await?,
+    ))
+}
+
+#[post("/webhooks")]
+async fn register_webhook(
+    pool: Data<PgPool>,
+    tenant: Tenant,
+    webhook: Valid<NewWebhook>,
+) -> actix_web::Result<HttpResponse> {
+    let webhook_id = webhooks::register(&pool, &tenant.0, &webhook.0)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(HttpResponse::Created().json(WebhookRegistered { webhook_id }))
+}
+
+#[get("/webhooks")]
+async fn list_webhooks(
+    pool: Data<PgPool>,
+    tenant: Tenant,
+) -> actix_web::Result<Json<Vec<Webhook>>> {
+    let webhooks = webhooks::list(&pool, &tenant.0)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(webhooks))
+}
+
+/// Notifications given up on, with the error of their last attempt.
+#[get("/webhooks/dead-letters")]
+async fn webhook_dead_letters(
+    pool: Data<PgPool>,
+    tenant: Tenant,
+) -> actix_web::Result<Json<Vec<WebhookDeadLetter>>> {
+    let letters = webhooks::dead_letters(&pool, &tenant.0)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(letters))
+}
+
+#[delete("/webhooks/{id}")]
+async fn remove_webhook(
+    pool: Data<PgPool>,
+    tenant: Tenant,
+    id: Path<i64>,
+) -> actix_web::Result<HttpResponse> {
+    if webhooks::remove(&pool, &tenant.0, *id)
+        .await
+        .map_err(errors::read_model)?
+    {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(error::ErrorNotFound("webhook not found"))
+    }
+}
+
+/// The scheduled jobs run by this instance and how their last runs went.
+#[get("/jobs")]
+async fn scheduled_jobs(scheduler: Data<Scheduler>) -> Json<Vec<JobStatus>> {
+    Json(scheduler.statuses())
+}
+
+/// Runs a scheduled job at once, answering `409 Conflict` while it's running already.
+#[post("/jobs/{name}/run-now")]
+async fn run_scheduled_job(
+    scheduler: Data<Scheduler>,
+    name: Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let outcome = scheduler
+        .run_now(&name)
+        .await
+        .ok_or_else(|| error::ErrorNotFound("job not found"))?;
+    Ok(match outcome {
+        RunOutcome::Ran(status) => HttpResponse::Ok().json(status),
+        RunOutcome::Running => HttpResponse::Conflict().body("the job is running already"),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use actix_web::{http::header::AUTHORIZATION, test};
+    use actix_web::{
+        http::{Method, StatusCode},
+        web::{Bytes, Data},
+        App,
+    };
+    use disintegrate_postgres::{PgEventStore, PgSnapshotter};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use sqlx::PgPool;
+
+    use crate::admin::RebuildStatus;
+    use crate::auth::ApiKeys;
+    use crate::domain::{default_tenant, DomainEvent, VehicleType};
+    use crate::http::api;
+    use crate::pagination::Cursor;
+    use crate::read_model::queries::ReadModelRepository;
+    use crate::request_id::REQUEST_ID;
+    use crate::scheduler::{AppContext, Scheduler};
+    use crate::shutdown::Shutdown;
+    use crate::test_support::{self, application};
+    use crate::tokens::{Role, TokenKeys};
+    use crate::EventStore;
+    use crate::{application, import, request_id, scheduler, tokens};
+
+    /// The keys of the tokens minted by `tokens::test`, for the services calling the admin routes.
+    fn admin_tokens() -> Data<TokenKeys> {
+        Data::new(TokenKeys::new(tokens::test::SECRET).unwrap())
+    }
+
+    /// `request`, sent with an admin token.
+    fn as_admin(request: test::TestRequest) -> test::TestRequest {
+        let token = tokens::test::token("ops", Role::Admin, 60);
+        request.insert_header((AUTHORIZATION, format!("Bearer {token}")))
+    }
+
+    /// Fails every run.
+    struct Sweep;
+
+    #[async_trait::async_trait]
+    impl scheduler::ScheduledJob for Sweep {
+        fn name(&self) -> &'static str {
+            "sweep"
+        }
+
+        fn interval(&self) -> Duration {
+            Duration::from_secs(60)
+        }
+
+        async fn run(&self, _: &AppContext) -> anyhow::Result<()> {
+            anyhow::bail!("nothing to sweep")
+        }
+    }
+
+    #[actix_web::test]
+    async fn it_should_list_and_run_the_scheduled_jobs() {
+        let options = PgConnectOptions::new().host("127.0.0.1").port(1);
+        let context = AppContext {
+            read_model: PgPoolOptions::new().connect_lazy_with(options),
+        };
+        let scheduler = Scheduler::new(context, Shutdown::default()).with_job(Sweep);
+        let service = test::init_service(
+            App::new()
+                .app_data(Data::new(scheduler))
+                .app_data(admin_tokens())
+                .configure(api),
+        )
+        .await;
+
+        let request = as_admin(test::TestRequest::post().uri("/api/v1/admin/jobs/sweep/run-now"));
+        let response = test::call_service(&service, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["runs"], 1);
+        assert_eq!(body["lastError"], "nothing to sweep");
+
+        let request = as_admin(test::TestRequest::get().uri("/api/v1/admin/jobs"));
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&service, request.to_request()).await;
+        assert_eq!(body[0]["name"], "sweep");
+        assert_eq!(body[0]["intervalMs"], 60000);
+        assert_eq!(body[0]["running"], false);
+        assert_eq!(body[0]["runs"], 1);
+
+        let request = as_admin(test::TestRequest::post().uri("/api/v1/admin/jobs/unknown/run-now"));
+        let response = test::call_service(&service, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_keep_the_admin_routes_to_the_admins_and_audit_them(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let tokens = TokenKeys::new(tokens::test::SECRET).unwrap();
+        let pool = test_support::read_model(options.clone()).await;
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(application(options).await))
+                .app_data(Data::new(ReadModelRepository::new(pool.clone())))
+                .app_data(Data::new(pool))
+                .app_data(Data::new(tokens.clone()))
+                .wrap(ApiKeys::new(None, None, None, Some(tokens)).unwrap())
+                .configure(api),
+        )
+        .await;
+        let admin = tokens::test::token("ops", Role::Admin, 60);
+        let mario = tokens::test::token("mario@example.com", Role::Customer, 60);
+        let request = |method: Method, uri: &str, token: &str| {
+            test::TestRequest::default()
+                .method(method)
+                .uri(uri)
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+        };
+        for (uri, body) in [
+            (
+                "/api/v1/vehicle/register",
+                serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }),
+            ),
+            (
+                "/api/v1/customer/register",
+                serde_json::json!({
+                    "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                }),
+            ),
+        ] {
+            let request = request(Method::POST, uri, &admin).set_json(body);
+            let response = test::call_service(&service, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::CREATED, "{uri}");
+        }
+
+        let start = request(Method::POST, "/api/v1/rent/start", &mario).set_json(
+            serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Van" }),
+        );
+        let response = test::call_service(&service, start.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        for (method, uri) in [
+            (Method::POST, "/admin/rent/force-end"),
+            (Method::GET, "/api/v1/admin/dead-letters"),
+        ] {
+            let response =
+                test::call_service(&service, request(method, uri, &mario).to_request()).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{uri}");
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body["code"], "FORBIDDEN", "{uri}");
+        }
+
+        let audit = request(Method::GET, "/api/v1/admin/audit", &admin).to_request();
+        let page: serde_json::Value = test::call_and_read_body_json(&service, audit).await;
+        assert_eq!(page["total"], 2);
+        let calls: Vec<_> = page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|call| {
+                (
+                    call["actor"].as_str().unwrap(),
+                    call["method"].as_str().unwrap(),
+                    call["path"].as_str().unwrap(),
+                    call["status"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                (
+                    "mario@example.com",
+                    "GET",
+                    "/api/v1/admin/dead-letters",
+                    403
+                ),
+                ("mario@example.com", "POST", "/admin/rent/force-end", 403),
+            ]
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_audit_the_rejected_commands_with_their_error_code(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let tokens = TokenKeys::new(tokens::test::SECRET).unwrap();
+        let pool = test_support::read_model(options.clone()).await;
+        let app = application(options).await.with_command_audit(pool.clone());
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(app))
+                .app_data(Data::new(pool))
+                .app_data(Data::new(tokens.clone()))
+                .wrap(ApiKeys::new(None, None, None, Some(tokens)).unwrap())
+                .wrap_fn(request_id::propagate)
+                .configure(api),
+        )
+        .await;
+        let admin = tokens::test::token("ops", Role::Admin, 60);
+        let mario = tokens::test::token("mario@example.com", Role::Customer, 60);
+        let request = |method: Method, uri: &str, token: &str| {
+            test::TestRequest::default()
+                .method(method)
+                .uri(uri)
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+        };
+        let register = request(Method::POST, "/api/v1/customer/register", &admin).set_json(
+            serde_json::json!({
+                "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+            }),
+        );
+        let response = test::call_service(&service, register.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let start = request(Method::POST, "/api/v1/rent/start", &mario)
+            .insert_header((REQUEST_ID, "no-vans-1"))
+            .set_json(serde_json::json!({
+                "customerId": "mario@example.com", "vehicleType": "Van"
+            }));
+        let response = test::call_service(&service, start.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // The audit rows are written in the background.
+        let audit = "/api/v1/admin/command-audit?command=StartRent&outcome=domain_error";
+        let page = loop {
+            let page: serde_json::Value = test::call_and_read_body_json(
+                &service,
+                request(Method::GET, audit, &admin).to_request(),
+            )
+            .await;
+            if page["items"]
+                .as_array()
+                .is_some_and(|items| !items.is_empty())
+            {
+                break page;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let rejected = &page["items"][0];
+        assert_eq!(rejected["errorCode"], "NO_AVAILABLE_VEHICLES");
+        assert_eq!(rejected["actor"], "mario@example.com");
+        assert_eq!(rejected["correlationId"], "no-vans-1");
+        assert_eq!(
+            rejected["payload"],
+            serde_json::json!({ "customerId": "m***@example.com", "vehicleType": "Van" })
+        );
+        assert!(rejected["latencyMs"].as_f64().unwrap() > 0.0);
+
+        let registered = request(
+            Method::GET,
+            "/api/v1/admin/command-audit?outcome=success",
+            &admin,
+        );
+        let page: serde_json::Value =
+            test::call_and_read_body_json(&service, registered.to_request()).await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["items"][0]["command"], "RegisterCustomer");
+        assert_eq!(page["items"][0]["payload"]["firstName"], "***");
+        assert!(page["items"][0]["errorCode"].is_null());
+
+        let unknown = request(
+            Method::GET,
+            "/api/v1/admin/command-audit?outcome=failed",
+            &admin,
+        );
+        let response = test::call_service(&service, unknown.to_request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_only_show_a_tenant_what_belongs_to_it(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let tokens = TokenKeys::new(tokens::test::SECRET).unwrap();
+        let pool = test_support::read_model(options.clone()).await;
+        let app = application(options.clone())
+            .await
+            .with_command_audit(pool.clone());
+        let public = PgPool::connect_with(options).await.unwrap();
+        let event_store: EventStore = PgEventStore::new(public.clone(), Default::default())
+            .await
+            .unwrap();
+        let snapshotter = PgSnapshotter::new(public, crate::config::DEFAULT_SNAPSHOT_EVERY)
+            .await
+            .unwrap();
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(app))
+                .app_data(Data::new(event_store))
+                .app_data(Data::new(snapshotter))
+                .app_data(Data::new(ReadModelRepository::new(pool.clone())))
+                .app_data(Data::new(RebuildStatus::default()))
+                .app_data(Data::new(pool.clone()))
+                .app_data(Data::new(tokens.clone()))
+                .wrap(ApiKeys::new(None, None, None, Some(tokens)).unwrap())
+                .configure(api),
+        )
+        .await;
+        let north = tokens::test::tenant_token("ops", Role::Admin, 60, Some("north"));
+        let south = tokens::test::tenant_token("ops", Role::Admin, 60, Some("south"));
+        let platform = tokens::test::token("ops", Role::Admin, 60);
+        let request = |method: Method, uri: &str, token: &str| {
+            test::TestRequest::default()
+                .method(method)
+                .uri(uri)
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .to_request()
+        };
+        let get = |uri: &str, token: &str| request(Method::GET, uri, token);
+
+        let register = test::TestRequest::post()
+            .uri("/api/v1/vehicle/register")
+            .insert_header((AUTHORIZATION, format!("Bearer {north}")))
+            .set_json(serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }));
+        let response = test::call_service(&service, register.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let webhook = test::TestRequest::post()
+            .uri("/api/v1/admin/webhooks")
+            .insert_header((AUTHORIZATION, format!("Bearer {north}")))
+            .set_json(serde_json::json!({
+                "url": "http://localhost/hook", "secret": "s", "eventTypes": ["VehicleAdded"]
+            }));
+        let registered: serde_json::Value =
+            test::call_and_read_body_json(&service, webhook.to_request()).await;
+        sqlx::query(
+            r#"INSERT INTO projection_dead_letter (listener_id, event_id, event_type, error, tenant_id)
+                VALUES ('drive_me_crazy_vehicles', 1, 'VehicleAdded', 'poisoned', 'north')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // The audit rows are written in the background.
+        let commands = "/api/v1/admin/command-audit";
+        while test::call_and_read_body_json::<_, _, serde_json::Value>(
+            &service,
+            get(commands, &north),
+        )
+        .await["total"]
+            == 0
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        for (uri, owned) in [
+            (commands, "total"),
+            ("/api/v1/admin/events", "items"),
+            ("/api/v1/admin/webhooks", ""),
+            ("/api/v1/admin/dead-letters", ""),
+        ] {
+            let count = |body: serde_json::Value| match owned {
+                "total" => body["total"].as_u64().unwrap() as usize,
+                "" => body.as_array().unwrap().len(),
+                field => body[field].as_array().unwrap().len(),
+            };
+            let body = test::call_and_read_body_json(&service, get(uri, &north)).await;
+            assert_eq!(count(body), 1, "{uri} of north");
+            let body = test::call_and_read_body_json(&service, get(uri, &south)).await;
+            assert_eq!(count(body), 0, "{uri} of south");
+        }
+
+        let snapshot = "/api/v1/admin/snapshots?query=VehicleRegistration&vehicleId=AA111AA";
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&service, get(snapshot, &north)).await;
+        assert!(body["live"]["version"].as_i64().unwrap() > 0);
+        let body: serde_json::Value = test::call_and_read_body_json(
+            &service,
+            get(&format!("{snapshot}&tenantId=north"), &south),
+        )
+        .await;
+        assert_eq!(body["live"]["version"], 0);
+
+        let webhook = format!("/api/v1/admin/webhooks/{}", registered["webhookId"]);
+        let response =
+            test::call_service(&service, request(Method::DELETE, &webhook, &south)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let dead_letter_id: i64 = sqlx::query_scalar("SELECT id FROM projection_dead_letter")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let retry = format!("/api/v1/admin/dead-letters/{dead_letter_id}/retry");
+        let response = test::call_service(&service, request(Method::POST, &retry, &south)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let calls: serde_json::Value =
+            test::call_and_read_body_json(&service, get("/api/v1/admin/audit", &south)).await;
+        assert!(calls["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|call| call["actor"] == "ops"));
+        let (south_calls, north_calls): (i64, i64) = sqlx::query_as(
+            r#"SELECT count(*) FILTER (WHERE tenant_id = 'south'), count(*) FILTER (WHERE tenant_id = 'north')
+                FROM admin_call"#,
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        // The listing itself is recorded once answered.
+        assert_eq!(calls["total"], south_calls - 1);
+        assert!(north_calls > 0);
+
+        let rebuild = "/api/v1/admin/projections/unknown/rebuild";
+        let response = test::call_service(&service, request(Method::POST, rebuild, &north)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response =
+            test::call_service(&service, request(Method::POST, rebuild, &platform)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_page_through_the_audit_log_while_events_are_appended(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        use disintegrate::EventStore as _;
+
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store: EventStore = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let append = |event_store: EventStore, i: usize| async move {
+            let added = DomainEvent::VehicleAdded {
+                tenant_id: default_tenant(),
+                vehicle_id: format!("AA{i:03}AA"),
+                vehicle_type: VehicleType::Car,
+                seats: None,
+                transmission: None,
+            };
+            // Validated against events that never happen, so that the appends don't conflict.
+            let nobody = disintegrate::query!(DomainEvent, customer_id == "nobody");
+            event_store.append(vec![added], nobody, 0).await.unwrap();
+        };
+        for i in 0..20 {
+            append(event_store.clone(), i).await;
+        }
+        let service = test::init_service(
+            App::new()
+                .app_data(Data::new(event_store.clone()))
+                .app_data(Data::new(pool.clone()))
+                .app_data(admin_tokens())
+                .configure(api),
+        )
+        .await;
+        let appender = tokio::spawn({
+            let event_store = event_store.clone();
+            async move {
+                for i in 20..40 {
+                    append(event_store.clone(), i).await;
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            }
+        });
+
+        let mut event_ids = vec![];
+        let mut uri = "/api/v1/admin/events?limit=3".to_string();
+        loop {
+            let request = as_admin(test::TestRequest::get().uri(&uri)).to_request();
+            let page: serde_json::Value = test::call_and_read_body_json(&service, request).await;
+            event_ids.extend(
+                page["items"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|event| event["eventId"].as_i64().unwrap()),
+            );
+            match page["nextCursor"].as_str() {
+                Some(cursor) => uri = format!("/api/v1/admin/events?limit=3&cursor={cursor}"),
+                None => break,
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        appender.await.unwrap();
+
+        // Every event up to the newest of the first page, once and in order.
+        let expected: Vec<i64> = sqlx::query_scalar(
+            "SELECT event_id FROM event WHERE event_id <= $1 ORDER BY event_id DESC",
+        )
+        .bind(event_ids[0])
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert!(expected.len() >= 20);
+        assert_eq!(event_ids, expected);
+
+        let cursor = Cursor(event_ids[0]).to_string();
+        for tampered in [
+            format!("{:016x}{}", event_ids[1], &cursor[16..]),
+            "12".to_string(),
+        ] {
+            let request = as_admin(
+                test::TestRequest::get().uri(&format!("/api/v1/admin/events?cursor={tampered}")),
+            )
+            .to_request();
+            let response = test::call_service(&service, request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{tampered}");
+        }
+    }
+
+    /// A `multipart/form-data` request uploading `csv` as the `file` field.
+    fn upload(uri: &str, csv: &str) -> test::TestRequest {
+        let (body, headers) = actix_multipart::test::create_form_data_payload_and_headers(
+            "file",
+            Some("import.csv".to_string()),
+            Some(actix_web::mime::TEXT_CSV),
+            Bytes::from(csv.to_string()),
+        );
+        headers
+            .into_iter()
+            .fold(test::TestRequest::post().uri(uri), |request, header| {
+                request.insert_header(header)
+            })
+            .set_payload(body)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_import_the_valid_rows_of_a_fleet_file(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(application(options).await))
+                .app_data(admin_tokens())
+                .configure(api),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/api/v1/vehicle/register")
+            .set_json(serde_json::json!({ "vehicleId": "CC333CC", "vehicleType": "Van" }));
+        let response = test::call_service(&service, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let csv = "\u{feff}plate,type,make,model,year\n\
+            AA111AA,car,Fiat,Panda,2022\n\
+            BB222BB,spaceship,,,\n\
+            AA111AA,van,Fiat,Ducato,2021\n\
+            CC333CC,van,,,\n\
+            DD444DD,Truck,Iveco,Daily,1850\n\
+            EE555EE,pick_up,,,\n";
+        let request = as_admin(upload("/api/v1/admin/vehicles/import", csv));
+        let report: serde_json::Value =
+            test::call_and_read_body_json(&service, request.to_request()).await;
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "imported": [{ "line": 2, "id": "AA111AA" }, { "line": 7, "id": "EE555EE" }],
+                "skipped": [{ "line": 5, "id": "CC333CC" }],
+                "rejected": [
+                    { "line": 3, "reasons": ["type: must be one of car, pick_up, van, truck"] },
+                    { "line": 4, "reasons": ["plate: already given on line 2"] },
+                    { "line": 6, "reasons": ["year: must be a year from 1900 to the next one"] },
+                ],
+            })
+        );
+
+        let rows = format!(
+            "plate,type\n{}",
+            "AA111AA,car\n".repeat(import::MAX_IMPORT_ROWS + 1)
+        );
+        let request = as_admin(upload("/api/v1/admin/vehicles/import", &rows));
+        let response = test::call_service(&service, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_import_the_customers_once(_: PgPoolOptions, options: PgConnectOptions) {
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(application(options).await))
+                .app_data(admin_tokens())
+                .configure(api),
+        )
+        .await;
+        let csv = "email,first_name,last_name,phone\n\
+            Mario@Example.com ,Mario,Rossi,+39 02 1234567\n\
+            luigi@example.com,Luigi,Verdi,\n\
+            mario@example.com,Mario,Bianchi,\n\
+            peach@example.com,,Toadstool,call me\n";
+        let request = as_admin(upload("/api/v1/admin/customers/import", csv));
+        let report: serde_json::Value =
+            test::call_and_read_body_json(&service, request.to_request()).await;
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "imported": [
+                    { "line": 2, "id": "mario@example.com" },
+                    { "line": 3, "id": "luigi@example.com" },
+                ],
+                "skipped": [],
+                "rejected": [
+                    { "line": 4, "reasons": ["email: already given on line 2"] },
+                    {
+                        "line": 5,
+                        "reasons": [
+                            "first_name: must not be blank",
+                            "phone: must be a phone number of 6 to 15 digits",
+                        ],
+                    },
+                ],
+            })
+        );
+
+        // Once fixed, the file is uploaded again.
+        let csv = csv.replace(",,Toadstool,call me", ",Peach,Toadstool,");
+        let request = as_admin(upload("/api/v1/admin/customers/import", &csv));
+        let report: serde_json::Value =
+            test::call_and_read_body_json(&service, request.to_request()).await;
+        assert_eq!(
+            report["imported"],
+            serde_json::json!([{ "line": 5, "id": "peach@example.com" }])
+        );
+        assert_eq!(
+            report["skipped"],
+            serde_json::json!([
+                { "line": 2, "id": "mario@example.com" },
+                { "line": 3, "id": "luigi@example.com" },
+            ])
+        );
+        assert_eq!(
+            report["rejected"],
+            serde_json::json!([{ "line": 4, "reasons": ["email: already given on line 2"] }])
+        );
+    }
+}
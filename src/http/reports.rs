@@ -0,0 +1,221 @@
+use actix_web::{
+    error, get,
+    web::{Json, Query, ServiceConfig},
+};
+use serde::Deserialize;
+
+use crate::daily_stats::DailyStats;
+use crate::errors;
+use crate::filters::{ReportPeriod, TopCustomersParams};
+use crate::read_model::queries::ReadModelRepository;
+use crate::reports::{
+    DurationGroup, DurationStats, Negotiated, OverdueRental, TopCustomer, UtilizationGroup,
+    UtilizationReport,
+};
+
+/// The reports on the rentals, as JSON or CSV.
+pub(super) fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(overdue_report)
+        .service(utilization_report)
+        .service(daily_report)
+        .service(top_customers_report)
+        .service(durations_report);
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OverdueParams {
+    min_hours_overdue: Option<i32>,
+}
+
+#[get("/reports/overdue")]
+async fn overdue_report(
+    repository: ReadModelRepository,
+    params: Query<OverdueParams>,
+) -> actix_web::Result<Negotiated<Vec<OverdueRental>>> {
+    let min_hours_overdue = params.min_hours_overdue.unwrap_or(0);
+    if min_hours_overdue < 0 {
+        return Err(error::ErrorBadRequest(
+            "minHoursOverdue: must not be negative",
+        ));
+    }
+    let overdue = repository
+        .overdue_rentals(min_hours_overdue)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Negotiated(overdue))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UtilizationParams {
+    group_by: Option<UtilizationGroup>,
+}
+
+#[get("/reports/utilization")]
+async fn utilization_report(
+    repository: ReadModelRepository,
+    period: ReportPeriod,
+    params: Query<UtilizationParams>,
+) -> actix_web::Result<Negotiated<UtilizationReport>> {
+    let group = params.group_by.unwrap_or(UtilizationGroup::VehicleType);
+    let report = repository
+        .utilization(period, group)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Negotiated(report))
+}
+
+#[get("/reports/daily")]
+async fn daily_report(
+    repository: ReadModelRepository,
+    period: ReportPeriod,
+) -> actix_web::Result<Json<Vec<DailyStats>>> {
+    let stats = repository
+        .daily_stats(period)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(stats))
+}
+
+#[get("/reports/top-customers")]
+async fn top_customers_report(
+    repository: ReadModelRepository,
+    period: ReportPeriod,
+    params: TopCustomersParams,
+) -> actix_web::Result<Negotiated<Vec<TopCustomer>>> {
+    let top = repository
+        .top_customers(period, params)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Negotiated(top))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DurationParams {
+    group_by: Option<DurationGroup>,
+}
+
+#[get("/reports/durations")]
+async fn durations_report(
+    repository: ReadModelRepository,
+    period: ReportPeriod,
+    params: Query<DurationParams>,
+) -> actix_web::Result<Negotiated<Vec<DurationStats>>> {
+    let group = params.group_by.unwrap_or(DurationGroup::VehicleType);
+    let durations = repository
+        .rental_durations(period, group)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Negotiated(durations))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{
+        http::header::{ACCEPT, CONTENT_TYPE},
+        test,
+    };
+    use actix_web::{http::StatusCode, web::Data, App};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    use crate::http::api;
+    use crate::read_model::queries::ReadModelRepository;
+    use crate::test_support;
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_render_the_same_report_as_json_and_csv(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at) VALUES
+                ('AA111AA', 'car', '2024-06-01'), ('BB222BB', 'car', '2024-06-01'),
+                ('CC333CC', 'van', '2024-07-05')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date, duration_minutes)
+                VALUES
+                (1, 'mario@example.com', 'AA111AA', '2024-07-02', '2024-07-04 12:00', 3600),
+                (2, 'luigi@example.com', 'BB222BB', '2024-07-03', '2024-07-03 06:00', 360),
+                (3, 'mario@example.com', 'CC333CC', '2024-07-06', NULL, NULL)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let service = test::init_service(
+            App::new()
+                .app_data(Data::new(ReadModelRepository::new(pool)))
+                .configure(api),
+        )
+        .await;
+        let report = |uri: &str, accept: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header((ACCEPT, accept.to_string()))
+                .to_request()
+        };
+        let utilization =
+            "/api/v1/reports/utilization?from=2024-07-01T00:00:00Z&to=2024-07-11T00:00:00Z";
+
+        let response = test::call_service(&service, report(utilization, "application/json")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let json: serde_json::Value = test::read_body_json(response).await;
+        let response = test::call_service(&service, report(utilization, "text/csv")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "text/csv");
+        let csv = test::read_body(response).await;
+        let rows: Vec<std::collections::HashMap<String, String>> =
+            csv::Reader::from_reader(&csv[..])
+                .deserialize()
+                .map(Result::unwrap)
+                .collect();
+        let buckets = json["buckets"].as_array().unwrap();
+        assert_eq!(rows.len(), buckets.len() + 1);
+        for (row, bucket) in rows.iter().zip(buckets.iter().chain([&json["fleet"]])) {
+            assert_eq!(row["bucket"], bucket["bucket"].as_str().unwrap());
+            for column in ["vehicles", "availableHours", "rentedHours", "utilization"] {
+                assert_eq!(
+                    row[column].parse::<f64>().unwrap(),
+                    bucket[column].as_f64().unwrap(),
+                    "{column}"
+                );
+            }
+        }
+        assert_eq!(rows.last().unwrap()["vehicles"], "3");
+
+        let durations =
+            "/api/v1/reports/durations?from=2024-07-01T00:00:00Z&to=2024-07-11T00:00:00Z";
+        let json: serde_json::Value =
+            test::call_and_read_body_json(&service, report(durations, "text/csv;q=0, */*")).await;
+        let csv = test::call_and_read_body(&service, report(durations, "text/*")).await;
+        let rows: Vec<std::collections::HashMap<String, String>> =
+            csv::Reader::from_reader(&csv[..])
+                .deserialize()
+                .map(Result::unwrap)
+                .collect();
+        let csv_total: i64 = rows
+            .iter()
+            .map(|row| row["rentals"].parse::<i64>().unwrap())
+            .sum();
+        let json_total: i64 = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|stats| stats["rentals"].as_i64().unwrap())
+            .sum();
+        assert_eq!(csv_total, json_total);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["rentals"], "2");
+
+        let response = test::call_service(&service, report(durations, "application/xml")).await;
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "NOT_ACCEPTABLE");
+    }
+}
@@ -0,0 +1,1782 @@
+use actix_web::{
+    error, get,
+    http::{
+        header::{ContentDisposition, ContentEncoding, IfMatch, CACHE_CONTROL, LOCATION},
+        StatusCode,
+    },
+    post,
+    web::{Bytes, Data, Header, Json, Path, Payload, Query, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::application::{CommandService, RentStarted};
+use crate::domain::{Email, PlateNumber, TenantId, VehicleType};
+use crate::domain::{EndRent, RegisterCustomer, RegisterVehicle, StartRent, TenantScoped};
+use crate::errors::{CarRentalResponseError, ErrorBody, ErrorCode};
+use crate::filters::{CalendarRange, RentalFilter, VehicleFilter};
+use crate::http_config::PublicUrl;
+use crate::live::{LiveUpdates, RentalStatus};
+use crate::pagination::{Count, PageParams, Paginated};
+use crate::read_model::queries::{
+    AvailabilitySummary, CalendarDay, CustomerMatch, CustomerSummary, CustomerView,
+    RentalExportRow, RentalView, SearchHit, VehicleView, Versioned,
+};
+use crate::read_model::repository::Reads;
+use crate::sorting::SortParams;
+use crate::tenant::Tenant;
+use crate::tokens::Caller;
+use crate::validation::Valid;
+use crate::vehicle_stats::VehicleStats;
+use crate::{batch, conditional, errors, live, reports};
+
+/// The commands and the reads of the read model.
+pub(super) fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(register_vehicle)
+        .service(register_customer)
+        .service(rent_start)
+        .service(rent_end)
+        .service(rent_status)
+        .service(batch_commands)
+        .service(availability)
+        .service(availability_stream)
+        .service(availability_calendar)
+        .service(vehicles)
+        .service(vehicle_count)
+        .service(vehicle)
+        .service(vehicle_statistics)
+        .service(customers)
+        .service(customer_count)
+        .service(search_customers)
+        .service(customer)
+        .service(customer_summary)
+        .service(customer_rental_socket)
+        .service(rentals)
+        .service(rental_count)
+        .service(active_rentals)
+        .service(export_rentals)
+        .service(rental)
+        .service(search);
+}
+
+#[post("/vehicle/register")]
+async fn register_vehicle(
+    app: Data<dyn CommandService>,
+    public: PublicUrl,
+    caller: Caller,
+    tenant: Tenant,
+    data: Valid<RegisterVehicle>,
+) -> actix_web::Result<HttpResponse> {
+    caller.register()?;
+    let vehicle_id = app
+        .register_vehicle(data.into_inner().for_tenant(tenant.0))
+        .await
+        .map_err(CarRentalResponseError::from)?;
+    let url = public.of(&format!("/vehicles/{vehicle_id}"));
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, url.clone()))
+        .json(VehicleRegistered { vehicle_id, url }))
+}
+
+#[post("/customer/register")]
+async fn register_customer(
+    app: Data<dyn CommandService>,
+    public: PublicUrl,
+    caller: Caller,
+    tenant: Tenant,
+    data: Valid<RegisterCustomer>,
+) -> actix_web::Result<HttpResponse> {
+    caller.register()?;
+    let customer_id = app
+        .register_customer(data.into_inner().for_tenant(tenant.0))
+        .await
+        .map_err(CarRentalResponseError::from)?;
+    let url = public.of(&format!("/customers/{customer_id}"));
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, url.clone()))
+        .json(CustomerRegistered { customer_id, url }))
+}
+
+#[post("/rent/start")]
+async fn rent_start(
+    app: Data<dyn CommandService>,
+    public: PublicUrl,
+    caller: Caller,
+    tenant: Tenant,
+    if_match: Option<Header<IfMatch>>,
+    data: Valid<StartRent>,
+) -> actix_web::Result<HttpResponse> {
+    caller.act_for(&data.0.customer_id)?;
+    let command = data.into_inner().for_tenant(tenant.0);
+    customer_unchanged(&**app, &command.tenant_id, &command.customer_id, if_match).await?;
+    let started = app
+        .start_rent(command)
+        .await
+        .map_err(CarRentalResponseError::from)?;
+    let url = public.of(&format!("/rentals/{}", started.rent_id));
+    Ok(HttpResponse::Created()
+        .insert_header(conditional::etag(started.rent_id))
+        .insert_header((LOCATION, url.clone()))
+        .json(RentalStarted { started, url }))
+}
+
+#[post("/rent/end")]
+async fn rent_end(
+    app: Data<dyn CommandService>,
+    caller: Caller,
+    tenant: Tenant,
+    if_match: Option<Header<IfMatch>>,
+    data: Valid<EndRent>,
+) -> actix_web::Result<HttpResponse> {
+    caller.act_for(&data.0.customer_id)?;
+    let command = data.into_inner().for_tenant(tenant.0);
+    customer_unchanged(&**app, &command.tenant_id, &command.customer_id, if_match).await?;
+    let ended = app
+        .end_rent(command)
+        .await
+        .map_err(CarRentalResponseError::from)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(conditional::etag(ended.event_id))
+        .json(ended))
+}
+
+/// Checks `If-Match`, when given, against the current version of the customer, before
+/// deciding.
+///
+/// The decision may still race with another one changing the customer meanwhile.
+async fn customer_unchanged(
+    app: &dyn CommandService,
+    tenant_id: &TenantId,
+    customer_id: &Email,
+    if_match: Option<Header<IfMatch>>,
+) -> actix_web::Result<()> {
+    let Some(Header(if_match)) = if_match else {
+        return Ok(());
+    };
+    let version = app
+        .customer_version(tenant_id, customer_id)
+        .await
+        .map_err(CarRentalResponseError::from)?;
+    Ok(conditional::check(&if_match, version)?)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VehicleRegistered {
+    vehicle_id: PlateNumber,
+    url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CustomerRegistered {
+    customer_id: Email,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct RentalStarted {
+    #[serde(flatten)]
+    started: RentStarted,
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AvailabilityParams {
+    vehicle_type: Option<VehicleType>,
+}
+
+#[get("/availability")]
+async fn availability(
+    repository: Reads,
+    params: Query<AvailabilityParams>,
+) -> actix_web::Result<Json<Vec<AvailabilitySummary>>> {
+    let summary = repository
+        .availability_summary(params.into_inner().vehicle_type)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(summary))
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchParams {
+    #[serde(default)]
+    atomic: bool,
+}
+
+/// Executes an array of commands, answering `207 Multi-Status` with the outcome of each one.
+#[post("/commands/batch")]
+async fn batch_commands(
+    app: Data<dyn CommandService>,
+    caller: Caller,
+    tenant: Tenant,
+    params: Query<BatchParams>,
+    items: Json<Vec<serde_json::Value>>,
+) -> HttpResponse {
+    let items = items.into_inner();
+    if items.len() > batch::MAX_BATCH_SIZE {
+        return HttpResponse::PayloadTooLarge().json(ErrorBody::localized(
+            ErrorCode::PayloadTooLarge,
+            format!("a batch holds at most {} commands", batch::MAX_BATCH_SIZE),
+            &[("limit", &batch::MAX_BATCH_SIZE)],
+        ));
+    }
+    let outcome = batch::execute(&**app, &caller, &tenant.0, items, params.atomic).await;
+    HttpResponse::build(StatusCode::MULTI_STATUS).json(outcome)
+}
+
+/// Server-Sent Events telling the vehicles available of a type whenever it changes.
+///
+/// They're left uncompressed, so that each event reaches the client as it's sent.
+#[get("/availability/stream")]
+async fn availability_stream(live: Data<LiveUpdates>, tenant: Tenant) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .insert_header(ContentEncoding::Identity)
+        .streaming(live.availability_events(tenant.0))
+}
+
+#[get("/availability/calendar")]
+async fn availability_calendar(
+    repository: Reads,
+    range: CalendarRange,
+) -> actix_web::Result<Json<Vec<CalendarDay>>> {
+    let calendar = repository
+        .availability_calendar(&range)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(calendar))
+}
+
+#[get("/vehicles")]
+async fn vehicles(
+    repository: Reads,
+    filter: VehicleFilter,
+    sort: SortParams<VehicleView>,
+    page: PageParams,
+) -> actix_web::Result<Json<Paginated<VehicleView>>> {
+    let (vehicles, total) = repository
+        .list_vehicles(&filter, &sort, page)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(Paginated::new(vehicles, total, page).sorted_by(sort)))
+}
+
+#[get("/vehicles/count")]
+async fn vehicle_count(repository: Reads, filter: VehicleFilter) -> actix_web::Result<Json<Count>> {
+    let count = repository
+        .count_vehicles(&filter)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(Count { count }))
+}
+
+#[get("/vehicles/{vehicle_id}")]
+async fn vehicle(repository: Reads, vehicle_id: Path<String>) -> actix_web::Result<HttpResponse> {
+    repository
+        .find_vehicle(&vehicle_id)
+        .await
+        .map_err(errors::read_model)?
+        .map(versioned)
+        .ok_or_else(|| error::ErrorNotFound("vehicle not found"))
+}
+
+#[get("/vehicles/{vehicle_id}/stats")]
+async fn vehicle_statistics(
+    repository: Reads,
+    vehicle_id: Path<String>,
+) -> actix_web::Result<Json<VehicleStats>> {
+    repository
+        .vehicle_stats(&vehicle_id)
+        .await
+        .map_err(errors::read_model)?
+        .map(Json)
+        .ok_or_else(|| error::ErrorNotFound("vehicle not found"))
+}
+
+#[get("/customers")]
+async fn customers(
+    repository: Reads,
+    page: PageParams,
+) -> actix_web::Result<Json<Paginated<CustomerView>>> {
+    let (customers, total) = repository
+        .list_customers(page)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(Paginated::new(customers, total, page)))
+}
+
+#[get("/customers/count")]
+async fn customer_count(repository: Reads) -> actix_web::Result<Json<Count>> {
+    let count = repository
+        .count_customers()
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(Count { count }))
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchParams {
+    q: String,
+}
+
+impl SearchParams {
+    fn text(&self) -> actix_web::Result<&str> {
+        let text = self.q.trim();
+        if text.chars().count() < 2 {
+            return Err(error::ErrorBadRequest("q: must be at least 2 characters"));
+        }
+        Ok(text)
+    }
+}
+
+const MAX_SEARCH_RESULTS: i64 = 20;
+
+#[get("/customers/search")]
+async fn search_customers(
+    repository: Reads,
+    params: Query<SearchParams>,
+) -> actix_web::Result<Json<Vec<CustomerMatch>>> {
+    let matches = repository
+        .search_customers(params.text()?, MAX_SEARCH_RESULTS)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(matches))
+}
+
+#[get("/customers/{customer_id}")]
+async fn customer(repository: Reads, customer_id: Path<String>) -> actix_web::Result<HttpResponse> {
+    repository
+        .customer_detail(&customer_id)
+        .await
+        .map_err(errors::read_model)?
+        .map(versioned)
+        .ok_or_else(|| error::ErrorNotFound("customer not found"))
+}
+
+/// Renders the resource with its version as `ETag`, for clients to send back as `If-Match`.
+fn versioned<T: Serialize>(resource: Versioned<T>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(conditional::etag(resource.version))
+        .json(resource.value)
+}
+
+/// WebSocket pushing the rental status of the customer: the current one, from the read model,
+/// on connect, then whenever a rental starts or ends.
+#[get("/ws/customers/{customer_id}")]
+async fn customer_rental_socket(
+    req: HttpRequest,
+    body: Payload,
+    caller: Caller,
+    repository: Reads,
+    live: Data<LiveUpdates>,
+    customer_id: Path<Email>,
+) -> actix_web::Result<HttpResponse> {
+    caller.act_for(&customer_id)?;
+    // Subscribed before reading the current status, so that no change is missed in between.
+    let updates = live.rentals();
+    let summary = repository
+        .customer_summary(&customer_id)
+        .await
+        .map_err(errors::read_model)?
+        .ok_or_else(|| error::ErrorNotFound("customer not found"))?;
+    let snapshot = RentalStatus::new(
+        repository.tenant_id().clone(),
+        customer_id.into_inner(),
+        summary.active_rental.map(Into::into),
+    );
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(live::push_rental_status(
+        session,
+        messages,
+        snapshot,
+        updates,
+        live.closed(),
+    ));
+    Ok(response)
+}
+
+#[get("/customers/{customer_id}/summary")]
+async fn customer_summary(
+    repository: Reads,
+    customer_id: Path<String>,
+) -> actix_web::Result<Json<CustomerSummary>> {
+    repository
+        .customer_summary(&customer_id)
+        .await
+        .map_err(errors::read_model)?
+        .map(Json)
+        .ok_or_else(|| error::ErrorNotFound("customer not found"))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RentStatusParams {
+    customer_id: Email,
+}
+
+/// Whether the customer is renting a vehicle, read from the read model alone so that the desk
+/// gets it right away. It's never cached, the answer changing with every rental.
+#[get("/rent/status")]
+async fn rent_status(
+    repository: Reads,
+    params: Query<RentStatusParams>,
+) -> actix_web::Result<HttpResponse> {
+    let status = repository
+        .rent_status(&params.customer_id)
+        .await
+        .map_err(errors::read_model)?
+        .ok_or_else(|| error::ErrorNotFound("customer not found"))?;
+    Ok(HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(status))
+}
+
+#[get("/search")]
+async fn search(
+    repository: Reads,
+    params: Query<SearchParams>,
+) -> actix_web::Result<Json<Vec<SearchHit>>> {
+    let hits = repository
+        .search(params.text()?, MAX_SEARCH_RESULTS)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(hits))
+}
+
+#[get("/rentals")]
+async fn rentals(
+    repository: Reads,
+    filter: RentalFilter,
+    sort: SortParams<RentalView>,
+    page: PageParams,
+) -> actix_web::Result<Json<Paginated<RentalView>>> {
+    let (rentals, total) = repository
+        .list_rentals(&filter, &sort, page)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(Paginated::new(rentals, total, page).sorted_by(sort)))
+}
+
+#[get("/rentals/count")]
+async fn rental_count(repository: Reads, filter: RentalFilter) -> actix_web::Result<Json<Count>> {
+    let count = repository
+        .count_rentals(&filter)
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(Count { count }))
+}
+
+#[get("/rentals/active")]
+async fn active_rentals(repository: Reads) -> actix_web::Result<Json<Vec<RentalView>>> {
+    let active = repository
+        .active_rentals()
+        .await
+        .map_err(errors::read_model)?;
+    Ok(Json(active))
+}
+
+#[get("/rentals/{rent_id:\\d+}")]
+async fn rental(repository: Reads, rent_id: Path<i64>) -> actix_web::Result<Json<RentalView>> {
+    repository
+        .find_rental(*rent_id)
+        .await
+        .map_err(errors::read_model)?
+        .map(Json)
+        .ok_or_else(|| error::ErrorNotFound("rental not found"))
+}
+
+#[get("/rentals/export")]
+async fn export_rentals(repository: Reads, filter: RentalFilter) -> HttpResponse {
+    let filename = reports::rental_export_filename(&filter, Utc::now().date_naive());
+    let rows = repository.export_rentals(filter);
+    HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header(ContentDisposition::attachment(filename))
+        .streaming(reports::stream_csv(RentalExportRow::HEADER, rows).map_ok(Bytes::from))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use actix_web::{
+        dev::ServiceResponse,
+        http::{
+            header::{CACHE_CONTROL, LOCATION},
+            Method, StatusCode,
+        },
+        web::Data,
+        App,
+    };
+    use actix_web::{
+        http::header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH},
+        test,
+    };
+    use disintegrate_postgres::PgEventStore;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use sqlx::PgPool;
+
+    use crate::application::{Application, CommandService};
+    use crate::auth::ApiKeys;
+    use crate::config::ListenerConfig;
+    use crate::event_listener;
+    use crate::health::Readiness;
+    use crate::http::api;
+    use crate::http_config::PublicUrl;
+    use crate::live::LiveUpdates;
+    use crate::read_model::queries::ReadModelRepository;
+    use crate::read_model::repository::read_repository;
+    use crate::seed::SeedConfig;
+    use crate::shutdown::Shutdown;
+    use crate::test_support::{self, application};
+    use crate::tokens::{Role, TokenKeys};
+    use crate::{application, batch, domain, reporting, seed, tokens};
+
+    /// The handlers against a `MockCommandService`, without a database.
+    mod handlers {
+        use super::*;
+
+        use std::sync::Arc;
+
+        use actix_web::http::header::HeaderMap;
+        use application::ApplicationError;
+        use test_support::{Failure, MockCommandService};
+
+        async fn send(
+            commands: &Arc<MockCommandService>,
+            request: test::TestRequest,
+        ) -> (StatusCode, HeaderMap, serde_json::Value) {
+            let service = test::init_service(
+                App::new()
+                    .app_data(Data::from(commands.clone() as Arc<dyn CommandService>))
+                    .app_data(Data::new(TokenKeys::new(tokens::test::SECRET).unwrap()))
+                    .configure(api),
+            )
+            .await;
+            let response = test::call_service(&service, request.to_request()).await;
+            let (status, headers) = (response.status(), response.headers().clone());
+            (status, headers, test::read_body_json(response).await)
+        }
+
+        fn start() -> test::TestRequest {
+            test::TestRequest::post()
+                .uri("/api/v1/rent/start")
+                .set_json(serde_json::json!({
+                    "customerId": "mario@example.com", "vehicleType": "Van"
+                }))
+        }
+
+        #[actix_web::test]
+        async fn it_should_locate_what_the_commands_created() {
+            let commands = Arc::new(MockCommandService::default());
+            let request = test::TestRequest::post()
+                .uri("/api/v1/vehicle/register")
+                .set_json(serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }));
+            let (status, headers, body) = send(&commands, request).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(headers.get(LOCATION).unwrap(), "/api/v1/vehicles/AA111AA");
+            assert_eq!(
+                body,
+                serde_json::json!({ "vehicleId": "AA111AA", "url": "/api/v1/vehicles/AA111AA" })
+            );
+
+            let (status, headers, body) = send(&commands, start()).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(headers.get(LOCATION).unwrap(), "/api/v1/rentals/7");
+            assert_eq!(headers.get(ETAG).unwrap(), "\"7\"");
+            assert_eq!(body["vehicleId"], "AA111AA");
+            assert_eq!(body["vehicleType"], "Van");
+            assert_eq!(commands.sent(), ["RegisterVehicle", "StartRent"]);
+        }
+
+        #[actix_web::test]
+        async fn it_should_map_the_errors_to_their_status_and_code() {
+            let cases: [(Failure, StatusCode, &str); 5] = [
+                (
+                    || ApplicationError::Domain(domain::Error::NoAvailableVehicles),
+                    StatusCode::CONFLICT,
+                    "NO_AVAILABLE_VEHICLES",
+                ),
+                (
+                    || ApplicationError::Domain(domain::Error::CustomerNotFound),
+                    StatusCode::NOT_FOUND,
+                    "CUSTOMER_NOT_FOUND",
+                ),
+                (
+                    || {
+                        ApplicationError::StateStore(Box::new(
+                            disintegrate_postgres::Error::Concurrency,
+                        ))
+                    },
+                    StatusCode::CONFLICT,
+                    "CONCURRENT_MODIFICATION",
+                ),
+                (
+                    || ApplicationError::Timeout(Duration::from_secs(5)),
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "TIMEOUT",
+                ),
+                (
+                    || ApplicationError::EventStore("connection refused".into()),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "STORE_ERROR",
+                ),
+            ];
+            for (error, status, code) in cases {
+                let commands = Arc::new(MockCommandService::failing(error));
+                let (actual, _, body) = send(&commands, start()).await;
+                assert_eq!(actual, status, "{code}");
+                assert_eq!(body["code"], code);
+                assert_eq!(body["docs"], format!("errors#{code}"));
+            }
+        }
+
+        #[actix_web::test]
+        async fn it_should_not_send_the_commands_failing_their_checks() {
+            let commands = Arc::new(MockCommandService::default().with_customer_version(3));
+            let (status, _, body) =
+                send(&commands, start().insert_header((IF_MATCH, "\"2\""))).await;
+            assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+            assert_eq!(body["code"], "PRECONDITION_FAILED");
+
+            let request = test::TestRequest::post()
+                .uri("/api/v1/customer/register")
+                .set_json(serde_json::json!({ "customerId": "mario", "firstName": "Mario" }));
+            let (status, _, _) = send(&commands, request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+
+            let (status, _, _) = send(&commands, start().insert_header((IF_MATCH, "\"3\""))).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(commands.sent(), ["StartRent"]);
+        }
+
+        #[actix_web::test]
+        async fn it_should_report_every_command_of_a_batch() {
+            let commands = Arc::new(MockCommandService::failing(|| {
+                ApplicationError::Domain(domain::Error::RentalInProgress)
+            }));
+            let request = test::TestRequest::post()
+                .uri("/api/v1/commands/batch?atomic=true")
+                .set_json(serde_json::json!([
+                    { "type": "endRent", "payload": { "customerId": "mario@example.com" } },
+                    { "type": "endRent", "payload": { "customerId": "luigi@example.com" } },
+                ]));
+            let (status, _, body) = send(&commands, request).await;
+            assert_eq!(status, StatusCode::MULTI_STATUS);
+            assert_eq!(body["applied"], 0);
+            assert_eq!(body["results"][0]["status"], 409);
+            assert_eq!(body["results"][0]["error"]["code"], "RENTAL_IN_PROGRESS");
+            assert_eq!(body["results"][1]["status"], 424);
+            assert_eq!(commands.sent(), ["EndRent"]);
+        }
+
+        #[actix_web::test]
+        async fn it_should_forbid_the_customers_to_register() {
+            let commands = Arc::new(MockCommandService::default());
+            let token = tokens::test::token("mario@example.com", Role::Customer, 60);
+            let as_customer = |uri: &str, body: serde_json::Value| {
+                test::TestRequest::post()
+                    .uri(uri)
+                    .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                    .set_json(body)
+            };
+
+            for (uri, body) in [
+                (
+                    "/api/v1/vehicle/register",
+                    serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }),
+                ),
+                (
+                    "/api/v1/customer/register",
+                    serde_json::json!({
+                        "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                    }),
+                ),
+            ] {
+                let (status, _, body) = send(&commands, as_customer(uri, body)).await;
+                assert_eq!(status, StatusCode::FORBIDDEN, "{uri}");
+                assert_eq!(body["code"], "FORBIDDEN", "{uri}");
+            }
+
+            let request = as_customer(
+                "/api/v1/commands/batch",
+                serde_json::json!([
+                    { "type": "registerVehicle", "payload": { "vehicleId": "AA111AA", "vehicleType": "Van" } },
+                    { "type": "registerCustomer", "payload": {
+                        "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                    } },
+                ]),
+            );
+            let (status, _, body) = send(&commands, request).await;
+            assert_eq!(status, StatusCode::MULTI_STATUS);
+            assert_eq!(body["applied"], 0);
+            assert_eq!(body["results"][0]["status"], 403);
+            assert_eq!(body["results"][1]["status"], 403);
+            assert!(commands.sent().is_empty());
+
+            let token = tokens::test::token("admin@example.com", Role::Admin, 60);
+            let request = test::TestRequest::post()
+                .uri("/api/v1/vehicle/register")
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+                .set_json(serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }));
+            let (status, _, _) = send(&commands, request).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(commands.sent(), ["RegisterVehicle"]);
+        }
+    }
+
+    #[actix_web::test]
+    async fn it_should_describe_the_outcome_of_the_commands() {
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(Application::in_memory(
+                    Default::default(),
+                )))
+                .configure(api),
+        )
+        .await;
+        let post = |uri: &str, body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri(uri)
+                .set_json(body)
+                .to_request()
+        };
+
+        let response = test::call_service(
+            &service,
+            post(
+                "/api/v1/vehicle/register",
+                serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }),
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            "/api/v1/vehicles/AA111AA"
+        );
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "vehicleId": "AA111AA", "url": "/api/v1/vehicles/AA111AA" })
+        );
+
+        let response = test::call_service(
+            &service,
+            post(
+                "/api/v1/customer/register",
+                serde_json::json!({
+                    "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                }),
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            "/api/v1/customers/mario@example.com"
+        );
+
+        let response = test::call_service(
+            &service,
+            post(
+                "/api/v1/rent/start",
+                serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Van" }),
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let started: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(started["vehicleId"], "AA111AA");
+        assert_eq!(started["vehicleType"], "Van");
+        assert!(started["rentId"].as_i64().is_some());
+
+        let response = test::call_service(
+            &service,
+            post(
+                "/api/v1/rent/end",
+                serde_json::json!({ "customerId": "mario@example.com" }),
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let ended: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(ended["vehicleId"], "AA111AA");
+        assert_eq!(ended["startDate"], started["startDate"]);
+        assert_eq!(ended["durationMinutes"], 0);
+        assert_eq!(ended["alreadyReturned"], false);
+
+        let response = test::call_service(
+            &service,
+            post(
+                "/api/v1/rent/end",
+                serde_json::json!({ "customerId": "mario@example.com" }),
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let ended_again: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(ended_again["alreadyReturned"], true);
+        assert_eq!(ended_again["returnedDate"], ended["returnedDate"]);
+        assert_eq!(ended_again["vehicleId"], "AA111AA");
+
+        let response = test::call_service(
+            &service,
+            post(
+                "/api/v1/rent/end",
+                serde_json::json!({ "customerId": "luigi@example.com" }),
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_tell_the_desk_whether_a_customer_is_renting(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name)
+                VALUES ('mario@example.com', 'Mario', 'Rossi')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at, status,
+                    current_renter_email, rented_since)
+                VALUES ('AA111AA', 'van', '2024-06-01T00:00:00Z', 'rented', 'mario@example.com',
+                    '2024-07-01T08:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let service = test::init_service(
+            App::new()
+                .app_data(read_repository(ReadModelRepository::new(pool)))
+                .configure(api),
+        )
+        .await;
+        let status = |customer_id: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/api/v1/rent/status?customerId={customer_id}"))
+                .to_request()
+        };
+
+        let response = test::call_service(&service, status("mario@example.com")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "active": true,
+                "vehicleId": "AA111AA",
+                "since": "2024-07-01T08:00:00Z",
+                "dueDate": "2024-07-31T08:00:00Z",
+            })
+        );
+        let response = test::call_service(&service, status("luigi@example.com")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_keep_the_fleets_of_the_tenants_apart(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let tokens = TokenKeys::new(tokens::test::SECRET).unwrap();
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options.clone()).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let shutdown = Shutdown::default();
+        tokio::spawn(event_listener(
+            pool.clone(),
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig {
+                poll_interval: Duration::from_millis(20),
+                ..ListenerConfig::default()
+            },
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+        let repository = ReadModelRepository::new(pool.clone());
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(application(options).await))
+                .app_data(read_repository(repository.clone()))
+                .app_data(Data::new(tokens.clone()))
+                .wrap(ApiKeys::new(None, None, None, Some(tokens)).unwrap())
+                .configure(api),
+        )
+        .await;
+        let north = tokens::test::tenant_token("ops", Role::Admin, 60, Some("north"));
+        let south = tokens::test::tenant_token("ops", Role::Admin, 60, Some("south"));
+        let request = |method: Method, uri: &str, token: &str| {
+            test::TestRequest::default()
+                .method(method)
+                .uri(uri)
+                .insert_header((AUTHORIZATION, format!("Bearer {token}")))
+        };
+        let start = |token: &str| {
+            request(Method::POST, "/api/v1/rent/start", token)
+                .set_json(serde_json::json!({
+                    "customerId": "mario@example.com", "vehicleType": "Van"
+                }))
+                .to_request()
+        };
+
+        // The same plate in both fleets, the same customer in one of them.
+        for token in [&north, &south] {
+            let register = request(Method::POST, "/api/v1/vehicle/register", token)
+                .set_json(serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }));
+            let response = test::call_service(&service, register.to_request()).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+        let register = request(Method::POST, "/api/v1/customer/register", &north).set_json(
+            serde_json::json!({
+                "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+            }),
+        );
+        let response = test::call_service(&service, register.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = test::call_service(&service, start(&south)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let started: serde_json::Value =
+            test::call_and_read_body_json(&service, start(&north)).await;
+        let started_rental = format!("/api/v1/rentals/{}", started["rentId"]);
+
+        while repository
+            .projection_lags()
+            .await
+            .map_or(true, |lags| lags.iter().any(|lag| lag.pending_events > 0))
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        shutdown.trigger();
+
+        let get = |uri: &str, token: &str| request(Method::GET, uri, token).to_request();
+        let registered: serde_json::Value =
+            test::call_and_read_body_json(&service, get("/api/v1/vehicles/AA111AA", &north)).await;
+        assert_eq!(registered["currentRenterEmail"], "mario@example.com");
+        let registered: serde_json::Value =
+            test::call_and_read_body_json(&service, get("/api/v1/vehicles/AA111AA", &south)).await;
+        assert_eq!(registered["currentRenterEmail"], serde_json::Value::Null);
+        for (uri, token, status) in [
+            (started_rental.as_str(), &north, StatusCode::OK),
+            (started_rental.as_str(), &south, StatusCode::NOT_FOUND),
+            (
+                "/api/v1/customers/mario@example.com",
+                &north,
+                StatusCode::OK,
+            ),
+            (
+                "/api/v1/customers/mario@example.com",
+                &south,
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let response = test::call_service(&service, get(uri, token)).await;
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+
+    /// Runs the projections of the events stored so far until they're caught up; they keep
+    /// running until the shutdown returned is triggered.
+    async fn projected(options: PgConnectOptions, pool: PgPool) -> Shutdown {
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let shutdown = Shutdown::default();
+        tokio::spawn(event_listener(
+            pool.clone(),
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig::default(),
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+        let repository = ReadModelRepository::new(pool);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                // The listener creates its checkpoints table once started.
+                let lags = repository.projection_lags().await.unwrap_or_default();
+                if !lags.is_empty() && lags.iter().all(|lag| lag.pending_events == 0) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the projections didn't catch up");
+        shutdown
+    }
+
+    /// Seeds the demo dataset through the decisions and projects it, as a fixture of the tests
+    /// reading the read model.
+    async fn seeded(
+        options: PgConnectOptions,
+        config: SeedConfig,
+    ) -> (Application, PgPool, Shutdown) {
+        let pool = test_support::read_model(options.clone()).await;
+        let app = application(options.clone()).await;
+        seed::run(&app, config).await.unwrap();
+        let shutdown = projected(options, pool.clone()).await;
+        (app, pool, shutdown)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_locate_the_created_resources_at_the_public_url(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let base = "https://rentals.example.com";
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(
+                    application(options.clone()).await,
+                ))
+                .app_data(read_repository(ReadModelRepository::new(pool.clone())))
+                .app_data(Data::new(PublicUrl::new(Some(base)).unwrap()))
+                .configure(api),
+        )
+        .await;
+        let mut located = vec![];
+        for (uri, body) in [
+            (
+                "/api/v1/vehicle/register",
+                serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }),
+            ),
+            (
+                "/api/v1/customer/register",
+                serde_json::json!({
+                    "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                }),
+            ),
+            (
+                "/api/v1/rent/start",
+                serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Van" }),
+            ),
+        ] {
+            let request = test::TestRequest::post().uri(uri).set_json(&body);
+            let response = test::call_service(&service, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::CREATED, "{uri}");
+            let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+            let location = location.to_string();
+            let created: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(created["url"], location.as_str());
+            located.push(location);
+
+            let request = test::TestRequest::post().uri(uri).set_json(&body);
+            let response = test::call_service(&service, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::CONFLICT, "{uri}");
+            assert!(response.headers().get(LOCATION).is_none());
+        }
+        assert_eq!(
+            located[..2],
+            [
+                "https://rentals.example.com/api/v1/vehicles/AA111AA",
+                "https://rentals.example.com/api/v1/customers/mario@example.com",
+            ]
+        );
+        assert!(located[2].starts_with("https://rentals.example.com/api/v1/rentals/"));
+
+        let shutdown = projected(options, pool).await;
+        for location in located {
+            let request = test::TestRequest::get()
+                .uri(location.strip_prefix(base).unwrap())
+                .to_request();
+            let response = test::call_service(&service, request).await;
+            assert_eq!(response.status(), StatusCode::OK, "{location}");
+        }
+        shutdown.trigger();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_seed_a_browsable_read_model_once(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let config = SeedConfig::default();
+        let (app, pool, shutdown) = seeded(options, config).await;
+        let service = test::init_service(
+            App::new()
+                .app_data(read_repository(ReadModelRepository::new(pool)))
+                .configure(api),
+        )
+        .await;
+        let read = |uri: &'static str| {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            test::call_and_read_body_json::<_, _, serde_json::Value>(&service, request)
+        };
+
+        let fleet = read("/api/v1/vehicles?limit=500").await;
+        assert_eq!(fleet["total"], config.vehicles);
+        for vehicle_type in ["Car", "PickUp", "Van", "Truck"] {
+            assert!(fleet["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|item| item["vehicleType"] == vehicle_type));
+        }
+        assert_eq!(read("/api/v1/customers").await["total"], config.customers);
+        let open = read("/api/v1/rentals?status=open").await["total"]
+            .as_i64()
+            .unwrap();
+        let closed = read("/api/v1/rentals?status=closed").await["total"]
+            .as_i64()
+            .unwrap();
+        assert!(open > 0 && closed > 0, "{open} open, {closed} closed");
+
+        let again = seed::run(
+            &app,
+            SeedConfig {
+                rentals: 0,
+                ..config
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            again,
+            seed::Seeded {
+                applied: 0,
+                skipped: config.customers + config.vehicles,
+            }
+        );
+        shutdown.trigger();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_flip_the_order_of_the_listings_with_the_sort_direction(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at) VALUES
+                ('BB222BB', 'car', now()), ('AA111AA', 'van', now()), ('CC333CC', 'truck', now())"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date) VALUES
+                (1, 'mario@example.com', 'AA111AA', '2024-07-02T10:00:00Z'),
+                (2, 'luigi@example.com', 'BB222BB', '2024-07-01T10:00:00Z'),
+                (3, 'anna@example.com', 'CC333CC', '2024-07-03T10:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let service = test::init_service(
+            App::new()
+                .app_data(read_repository(ReadModelRepository::new(pool)))
+                .configure(api),
+        )
+        .await;
+        let list = |uri: &'static str, field: &'static str| {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            let service = &service;
+            async move {
+                let page: serde_json::Value = test::call_and_read_body_json(service, request).await;
+                let items = page["items"].as_array().unwrap().iter();
+                let order: serde_json::Value = items.map(|item| item[field].clone()).collect();
+                (order, page["sort"].clone())
+            }
+        };
+
+        assert_eq!(
+            list("/api/v1/rentals?sort=startDate:asc", "rentId").await,
+            (
+                serde_json::json!([2, 1, 3]),
+                serde_json::json!("startDate:asc")
+            )
+        );
+        assert_eq!(
+            list("/api/v1/rentals?sort=startDate:desc", "rentId").await,
+            (
+                serde_json::json!([3, 1, 2]),
+                serde_json::json!("startDate:desc")
+            )
+        );
+        assert_eq!(
+            list("/api/v1/vehicles?sort=vehicleId:asc", "vehicleId").await,
+            (
+                serde_json::json!(["AA111AA", "BB222BB", "CC333CC"]),
+                serde_json::json!("vehicleId:asc")
+            )
+        );
+        assert_eq!(
+            list("/api/v1/vehicles?sort=vehicleId:desc", "vehicleId").await,
+            (
+                serde_json::json!(["CC333CC", "BB222BB", "AA111AA"]),
+                serde_json::json!("vehicleId:desc")
+            )
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_only_change_a_customer_at_the_version_read(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options.clone()).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(application(options).await))
+                .app_data(read_repository(ReadModelRepository::new(pool.clone())))
+                .configure(api),
+        )
+        .await;
+        let shutdown = Shutdown::default();
+        tokio::spawn(event_listener(
+            pool,
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig::default(),
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+        let post = |uri: &str, if_match: Option<&str>, body: serde_json::Value| {
+            let request = test::TestRequest::post().uri(uri).set_json(body);
+            match if_match {
+                Some(etag) => request.insert_header((IF_MATCH, etag)),
+                None => request,
+            }
+            .to_request()
+        };
+        let start = |if_match| {
+            post(
+                "/api/v1/rent/start",
+                if_match,
+                serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Van" }),
+            )
+        };
+        let end = |if_match| {
+            post(
+                "/api/v1/rent/end",
+                if_match,
+                serde_json::json!({ "customerId": "mario@example.com" }),
+            )
+        };
+        let etag = |response: &ServiceResponse| {
+            response
+                .headers()
+                .get(ETAG)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        for (uri, body) in [
+            (
+                "/api/v1/vehicle/register",
+                serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }),
+            ),
+            (
+                "/api/v1/customer/register",
+                serde_json::json!({
+                    "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                }),
+            ),
+        ] {
+            let response = test::call_service(&service, post(uri, None, body)).await;
+            assert_eq!(response.status(), StatusCode::CREATED, "{uri}");
+        }
+        let read = loop {
+            let request = test::TestRequest::get()
+                .uri("/api/v1/customers/mario@example.com")
+                .to_request();
+            let response = test::call_service(&service, request).await;
+            if response.status() == StatusCode::OK {
+                break etag(&response);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+
+        let response = test::call_service(&service, start(Some("\"0\""))).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "PRECONDITION_FAILED");
+
+        let response = test::call_service(&service, start(Some(&read))).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let started = etag(&response);
+        assert_ne!(started, read);
+
+        let response = test::call_service(&service, end(Some(&read))).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = test::call_service(&service, end(None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(etag(&response), started);
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/vehicles/AA111AA")
+            .to_request();
+        let response = test::call_service(&service, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(etag(&response).starts_with('"'));
+        shutdown.trigger();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_settle_concurrent_rentals_of_the_last_vehicles(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(
+                    application(options).await.with_conflict_retries(5),
+                ))
+                .configure(api),
+        )
+        .await;
+        let post = |uri: &str, body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri(uri)
+                .set_json(body)
+                .to_request()
+        };
+        let register = |emails: &[&str], plates: &[&str]| {
+            let emails = emails.iter().map(|email| {
+                post(
+                    "/api/v1/customer/register",
+                    serde_json::json!({
+                        "customerId": email, "firstName": "Mario", "lastName": "Rossi"
+                    }),
+                )
+            });
+            let plates = plates.iter().map(|plate| {
+                post(
+                    "/api/v1/vehicle/register",
+                    serde_json::json!({ "vehicleId": plate, "vehicleType": "Van" }),
+                )
+            });
+            emails.chain(plates).collect::<Vec<_>>()
+        };
+        let start = |email: &&str| {
+            post(
+                "/api/v1/rent/start",
+                serde_json::json!({ "customerId": email, "vehicleType": "Van" }),
+            )
+        };
+
+        let emails = ["mario@example.com", "luigi@example.com"];
+        for request in register(&emails, &["AA111AA"]) {
+            let response = test::call_service(&service, request).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+        let responses = futures_util::future::join_all(
+            emails
+                .iter()
+                .map(|email| test::call_service(&service, start(email))),
+        )
+        .await;
+        let (started, refused): (Vec<_>, Vec<_>) = responses
+            .into_iter()
+            .partition(|response| response.status() == StatusCode::CREATED);
+        assert_eq!(started.len(), 1);
+        let [refused] = <[_; 1]>::try_from(refused).unwrap();
+        assert_eq!(refused.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(refused).await;
+        assert!(
+            ["NO_AVAILABLE_VEHICLES", "CONCURRENT_MODIFICATION"]
+                .contains(&body["code"].as_str().unwrap()),
+            "{body}"
+        );
+
+        // Retried, the decisions losing the race pick the plates left.
+        let emails = ["peach@example.com", "toad@example.com", "yoshi@example.com"];
+        for request in register(&emails, &["BB222BB", "CC333CC", "DD444DD"]) {
+            let response = test::call_service(&service, request).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+        let responses = futures_util::future::join_all(
+            emails
+                .iter()
+                .map(|email| test::call_service(&service, start(email))),
+        )
+        .await;
+        let mut plates = Vec::new();
+        for response in responses {
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            plates.push(body["vehicleId"].as_str().unwrap().to_string());
+        }
+        plates.sort();
+        assert_eq!(plates, ["BB222BB", "CC333CC", "DD444DD"]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_stream_the_availability_changes(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options.clone()).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let shutdown = Shutdown::default();
+        let live = LiveUpdates::new(shutdown.clone());
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(application(options).await))
+                .app_data(Data::new(live.clone()))
+                .configure(api),
+        )
+        .await;
+        tokio::spawn(event_listener(
+            pool,
+            event_store,
+            Readiness::default(),
+            live,
+            ListenerConfig::default(),
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/availability/stream")
+            .to_request();
+        let response = test::call_service(&service, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+        let mut stream = response.into_body();
+        for (uri, body) in [
+            (
+                "/api/v1/vehicle/register",
+                serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }),
+            ),
+            (
+                "/api/v1/customer/register",
+                serde_json::json!({
+                    "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                }),
+            ),
+        ] {
+            let request = test::TestRequest::post().uri(uri).set_json(body);
+            let response = test::call_service(&service, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::CREATED, "{uri}");
+        }
+        assert_eq!(
+            next_chunk(&mut stream).await.unwrap(),
+            "event: availability\ndata: {\"vehicleType\":\"Van\",\"available\":1}\n\n"
+        );
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/rent/start")
+            .set_json(
+                serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Van" }),
+            );
+        let response = test::call_service(&service, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(
+            next_chunk(&mut stream).await.unwrap(),
+            "event: availability\ndata: {\"vehicleType\":\"Van\",\"available\":0}\n\n"
+        );
+
+        shutdown.trigger();
+        assert_eq!(next_chunk(&mut stream).await, None);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_push_the_rental_status_again_on_reconnect(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options.clone()).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let shutdown = Shutdown::default();
+        let live = LiveUpdates::new(shutdown.clone());
+        let app = application(options).await;
+        let server = {
+            let (pool, live) = (pool.clone(), live.clone());
+            actix_test::start(move || {
+                App::new()
+                    .app_data(application::command_service(app.clone()))
+                    .app_data(read_repository(ReadModelRepository::new(pool.clone())))
+                    .app_data(Data::new(live.clone()))
+                    .configure(api)
+            })
+        };
+        tokio::spawn(event_listener(
+            pool,
+            event_store,
+            Readiness::default(),
+            live,
+            ListenerConfig::default(),
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+        // The client spawns its connections on the current thread.
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let client = awc::Client::new();
+                for (uri, body) in [
+                    (
+                        "/api/v1/vehicle/register",
+                        serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Van" }),
+                    ),
+                    (
+                        "/api/v1/customer/register",
+                        serde_json::json!({
+                            "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                        }),
+                    ),
+                ] {
+                    let response = client.post(server.url(uri)).send_json(&body).await.unwrap();
+                    assert_eq!(response.status(), StatusCode::CREATED, "{uri}");
+                }
+                let socket = "/api/v1/ws/customers/mario@example.com";
+                let connect = || async {
+                    // The customer may not be projected yet.
+                    loop {
+                        match awc::Client::new().ws(server.url(socket)).connect().await {
+                            Ok((_, socket)) => break socket,
+                            Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+                        }
+                    }
+                };
+
+                let mut first = connect().await;
+                let status = next_message(&mut first).await;
+                assert_eq!(status["active"], false);
+                let response = client
+                    .post(server.url("/api/v1/rent/start"))
+                    .send_json(
+                        &serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Van" }),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::CREATED);
+                let status = next_message(&mut first).await;
+                assert_eq!(status["active"], true);
+                assert_eq!(status["rental"]["vehicleId"], "AA111AA");
+                drop(first);
+
+                // Reconnected, the client is told the current status right away, once the read model
+                // caught up with the rental.
+                let status = loop {
+                    let mut socket = connect().await;
+                    let status = next_message(&mut socket).await;
+                    if status["active"] == true {
+                        break status;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                };
+                assert_eq!(status["customerId"], "mario@example.com");
+                assert_eq!(status["rental"]["vehicleType"], "Van");
+                assert!(status["rental"]["dueDate"].is_string());
+
+                let response = client
+                    .get(server.url("/api/v1/ws/customers/luigi@example.com"))
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            })
+            .await;
+        shutdown.trigger();
+    }
+
+    #[actix_web::test]
+    async fn it_should_execute_every_command_of_a_batch() {
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(Application::in_memory(
+                    Default::default(),
+                )))
+                .configure(api),
+        )
+        .await;
+        let batch = |uri: &str, items: serde_json::Value| {
+            test::TestRequest::post()
+                .uri(uri)
+                .set_json(items)
+                .to_request()
+        };
+        let items = serde_json::json!([
+            { "type": "registerVehicle", "payload": { "vehicleId": "AA111AA", "vehicleType": "Van" } },
+            { "type": "registerVehicle", "payload": { "vehicleId": "AA111AA", "vehicleType": "Van" } },
+            { "type": "registerCustomer", "payload": { "customerId": "not-an-email", "firstName": "Mario", "lastName": "Rossi" } },
+            { "type": "fly", "payload": {} },
+            {
+                "type": "registerCustomer",
+                "payload": { "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi" }
+            },
+            { "type": "startRent", "payload": { "customerId": "mario@example.com", "vehicleType": "Van" } },
+        ]);
+
+        let response = test::call_service(&service, batch("/api/v1/commands/batch", items)).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["applied"], 3);
+        let results = body["results"].as_array().unwrap();
+        let statuses: Vec<_> = results
+            .iter()
+            .map(|result| result["status"].clone())
+            .collect();
+        assert_eq!(statuses, [201, 409, 422, 400, 201, 201]);
+        assert_eq!(results[0]["id"], "AA111AA");
+        assert_eq!(results[1]["error"]["code"], "ALREADY_REGISTERED_VEHICLE");
+        assert_eq!(results[2]["error"]["errors"][0]["field"], "customerId");
+        assert_eq!(results[3]["error"]["code"], "MALFORMED_BODY");
+        assert_eq!(results[4]["id"], "mario@example.com");
+        assert!(results[5]["id"].is_i64());
+    }
+
+    #[actix_web::test]
+    async fn it_should_stop_an_atomic_batch_at_the_first_failure() {
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(Application::in_memory(
+                    Default::default(),
+                )))
+                .configure(api),
+        )
+        .await;
+        let register = |plate: &str| {
+            serde_json::json!({
+                "type": "registerVehicle", "payload": { "vehicleId": plate, "vehicleType": "Car" }
+            })
+        };
+        let items = serde_json::json!([
+            register("AA111AA"),
+            register("AA111AA"),
+            register("BB222BB")
+        ]);
+        let request = test::TestRequest::post()
+            .uri("/api/v1/commands/batch?atomic=true")
+            .set_json(items)
+            .to_request();
+        let response = test::call_service(&service, request).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["atomic"], true);
+        assert_eq!(body["applied"], 1);
+        let results = body["results"].as_array().unwrap();
+        let outcomes: Vec<_> = results
+            .iter()
+            .map(|result| (result["status"].clone(), result["applied"].clone()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                (serde_json::json!(201), serde_json::json!(true)),
+                (serde_json::json!(409), serde_json::json!(false)),
+                (serde_json::json!(424), serde_json::json!(false)),
+            ]
+        );
+
+        let items = vec![register("CC333CC"); batch::MAX_BATCH_SIZE + 1];
+        let request = test::TestRequest::post()
+            .uri("/api/v1/commands/batch")
+            .set_json(items)
+            .to_request();
+        let response = test::call_service(&service, request).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+    }
+
+    #[actix_web::test]
+    async fn it_should_reject_malformed_commands_before_deciding() {
+        let service = test::init_service(
+            App::new()
+                .app_data(application::command_service(Application::in_memory(
+                    Default::default(),
+                )))
+                .service(register_customer),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/customer/register")
+            .set_json(serde_json::json!({
+                "customerId": "mario",
+                "firstName": "",
+                "lastName": "Rossi",
+            }))
+            .to_request();
+
+        let response = test::call_service(&service, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "errors": [
+                { "field": "customerId", "message": "must be a valid email" },
+                { "field": "firstName", "message": "must not be blank" },
+            ] })
+        );
+    }
+
+    /// Reads the next chunk of a streamed body, or `None` once it ended.
+    async fn next_chunk<B>(body: &mut B) -> Option<String>
+    where
+        B: actix_web::body::MessageBody + Unpin,
+        B::Error: std::fmt::Debug,
+    {
+        let chunk = std::future::poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_next(cx));
+        let chunk = tokio::time::timeout(Duration::from_secs(10), chunk)
+            .await
+            .expect("nothing streamed")?;
+        Some(String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+    }
+
+    /// Reads the next WebSocket message as JSON.
+    async fn next_message<S, E>(socket: &mut S) -> serde_json::Value
+    where
+        S: futures_util::Stream<Item = Result<awc::ws::Frame, E>> + Unpin,
+        E: std::fmt::Debug,
+    {
+        use futures_util::StreamExt;
+
+        let frame = tokio::time::timeout(Duration::from_secs(10), socket.next());
+        match frame.await.expect("no message received") {
+            Some(Ok(awc::ws::Frame::Text(text))) => serde_json::from_slice(&text).unwrap(),
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    fs::File,
+    future::{ready, Ready},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use actix_web::{web::Data, FromRequest, HttpRequest};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    InconsistentKeys, ServerConfig,
+};
+
+/// Addresses the HTTP server binds to: every host of the comma separated `HTTP_HOST`, in
+/// plain HTTP on `HTTP_PORT` and, when TLS is configured, in HTTPS on `HTTPS_PORT`.
+///
+/// With TLS, plain HTTP is only served when `HTTP_PORT` is set, e.g. for the health checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpConfig {
+    pub hosts: Vec<String>,
+    pub port: Option<u16>,
+    pub tls: Option<TlsConfig>,
+}
+
+impl HttpConfig {
+    const DEFAULT_HOST: &'static str = "127.0.0.1";
+    const DEFAULT_PORT: u16 = 8080;
+
+    pub fn new(
+        hosts: Option<&str>,
+        port: Option<&str>,
+        tls: Option<TlsConfig>,
+    ) -> Result<Self, String> {
+        let hosts: Vec<String> = hosts
+            .unwrap_or(Self::DEFAULT_HOST)
+            .split(',')
+            .map(|host| host.trim().to_string())
+            .collect();
+        if hosts.iter().any(String::is_empty) {
+            return Err("HTTP_HOST: hosts must not be empty".to_string());
+        }
+        let port = match (port, &tls) {
+            (Some(port), _) => Some(parse_port("HTTP_PORT", port)?),
+            (None, None) => Some(Self::DEFAULT_PORT),
+            (None, Some(_)) => None,
+        };
+        Ok(Self { hosts, port, tls })
+    }
+}
+
+/// Where clients reach the API, set by `PUBLIC_BASE_URL` when it's served behind a reverse
+/// proxy, e.g. `https://rentals.example.com`; the URLs of the resources are relative otherwise.
+///
+/// Handlers get it from the app data, the default when there's none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublicUrl {
+    base: Option<String>,
+}
+
+impl PublicUrl {
+    pub fn new(base: Option<&str>) -> Result<Self, String> {
+        let Some(base) = base else {
+            return Ok(Self::default());
+        };
+        match reqwest::Url::parse(base) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.query().is_none() => {
+                Ok(Self {
+                    base: Some(base.trim_end_matches('/').to_string()),
+                })
+            }
+            _ => Err(format!(
+                "PUBLIC_BASE_URL: `{base}` must be an http or https URL"
+            )),
+        }
+    }
+
+    /// The canonical URL of a resource of the current API version, e.g. `/vehicles/AA123BB`.
+    pub fn of(&self, path: &str) -> String {
+        format!(
+            "{}{}{path}",
+            self.base.as_deref().unwrap_or_default(),
+            crate::http::API_V1
+        )
+    }
+}
+
+impl FromRequest for PublicUrl {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let url = req.app_data::<Data<PublicUrl>>();
+        ready(Ok(url.map(|url| url.get_ref().clone()).unwrap_or_default()))
+    }
+}
+
+/// Certificate chain and private key, both PEM files, set by `TLS_CERT_FILE` and
+/// `TLS_KEY_FILE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    pub port: u16,
+}
+
+impl TlsConfig {
+    const DEFAULT_PORT: u16 = 8443;
+
+    /// Returns `None` when neither file is set.
+    pub fn new(
+        cert_file: Option<&str>,
+        key_file: Option<&str>,
+        port: Option<&str>,
+    ) -> Result<Option<Self>, String> {
+        let (cert_file, key_file) = match (cert_file, key_file) {
+            (None, None) => return Ok(None),
+            (Some(cert_file), Some(key_file)) => (cert_file.into(), key_file.into()),
+            _ => return Err("TLS_CERT_FILE and TLS_KEY_FILE must be set together".to_string()),
+        };
+        let port = match port {
+            Some(port) => parse_port("HTTPS_PORT", port)?,
+            None => Self::DEFAULT_PORT,
+        };
+        Ok(Some(Self {
+            cert_file,
+            key_file,
+            port,
+        }))
+    }
+
+    /// Loads the certificate and the key, making sure that they belong together.
+    pub fn server_config(&self) -> Result<ServerConfig, String> {
+        let certs = rustls_pemfile::certs(&mut open("TLS_CERT_FILE", &self.cert_file)?)
+            .collect::<Result<Vec<CertificateDer>, _>>()
+            .map_err(|err| invalid("TLS_CERT_FILE", &self.cert_file, err))?;
+        if certs.is_empty() {
+            return Err(format!(
+                "TLS_CERT_FILE: no certificate in `{}`",
+                self.cert_file.display()
+            ));
+        }
+        let key: PrivateKeyDer =
+            rustls_pemfile::private_key(&mut open("TLS_KEY_FILE", &self.key_file)?)
+                .map_err(|err| invalid("TLS_KEY_FILE", &self.key_file, err))?
+                .ok_or_else(|| {
+                    format!(
+                        "TLS_KEY_FILE: no private key in `{}`",
+                        self.key_file.display()
+                    )
+                })?;
+        ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|err| match err {
+                rustls::Error::InconsistentKeys(InconsistentKeys::KeyMismatch) => format!(
+                    "TLS_KEY_FILE: `{}` is not the key of the certificate in `{}`",
+                    self.key_file.display(),
+                    self.cert_file.display()
+                ),
+                err => format!("TLS_CERT_FILE, TLS_KEY_FILE: {err}"),
+            })
+    }
+}
+
+fn open(var: &str, path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| format!("{var}: cannot read `{}`: {err}", path.display()))
+}
+
+fn invalid(var: &str, path: &Path, err: std::io::Error) -> String {
+    format!("{var}: invalid PEM in `{}`: {err}", path.display())
+}
+
+fn parse_port(var: &str, port: &str) -> Result<u16, String> {
+    port.trim()
+        .parse()
+        .map_err(|_| format!("{var}: invalid port `{port}`"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn it_should_parse_the_http_config() {
+        assert_eq!(
+            HttpConfig::new(None, None, None),
+            Ok(HttpConfig {
+                hosts: vec!["127.0.0.1".to_string()],
+                port: Some(8080),
+                tls: None,
+            })
+        );
+        assert_eq!(
+            HttpConfig::new(Some("0.0.0.0, ::1"), Some("9000"), None),
+            Ok(HttpConfig {
+                hosts: vec!["0.0.0.0".to_string(), "::1".to_string()],
+                port: Some(9000),
+                tls: None,
+            })
+        );
+        assert!(HttpConfig::new(Some("0.0.0.0,"), None, None).is_err());
+        assert!(HttpConfig::new(None, Some("80a"), None).is_err());
+        assert!(HttpConfig::new(None, Some("65536"), None).is_err());
+    }
+
+    #[test]
+    fn it_should_only_serve_plain_http_along_tls_when_asked() {
+        let tls = TlsConfig::new(Some("cert.pem"), Some("key.pem"), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(tls.port, 8443);
+        let config = HttpConfig::new(None, None, Some(tls.clone())).unwrap();
+        assert_eq!(config.port, None);
+        let config = HttpConfig::new(None, Some("8080"), Some(tls)).unwrap();
+        assert_eq!(config.port, Some(8080));
+
+        assert_eq!(TlsConfig::new(None, None, Some("8443")), Ok(None));
+        assert!(TlsConfig::new(Some("cert.pem"), None, None).is_err());
+        assert!(TlsConfig::new(Some("cert.pem"), Some("key.pem"), Some("https")).is_err());
+    }
+
+    #[test]
+    fn it_should_prefix_the_resource_urls_with_the_public_base_url() {
+        assert_eq!(
+            PublicUrl::default().of("/vehicles/AA111AA"),
+            "/api/v1/vehicles/AA111AA"
+        );
+        let public = PublicUrl::new(Some("https://rentals.example.com/cars/")).unwrap();
+        assert_eq!(
+            public.of("/vehicles/AA111AA"),
+            "https://rentals.example.com/cars/api/v1/vehicles/AA111AA"
+        );
+        assert!(PublicUrl::new(Some("rentals.example.com")).is_err());
+        assert!(PublicUrl::new(Some("ftp://rentals.example.com")).is_err());
+    }
+
+    #[test]
+    fn it_should_load_a_certificate_and_its_key() {
+        let (tls, _) = test_support::self_signed("matching");
+        assert!(tls.server_config().is_ok());
+    }
+
+    #[test]
+    fn it_should_tell_why_the_certificate_cannot_be_used() {
+        let (tls, _) = test_support::self_signed("mismatched");
+        let (other, _) = test_support::self_signed("other");
+        let mismatched = TlsConfig {
+            key_file: other.key_file,
+            ..tls.clone()
+        };
+        let err = mismatched.server_config().unwrap_err();
+        assert!(
+            err.starts_with("TLS_KEY_FILE:") && err.contains("is not the key of the certificate"),
+            "{err}"
+        );
+
+        let missing = TlsConfig {
+            cert_file: "missing.pem".into(),
+            ..tls.clone()
+        };
+        let err = missing.server_config().unwrap_err();
+        assert!(
+            err.starts_with("TLS_CERT_FILE: cannot read `missing.pem`"),
+            "{err}"
+        );
+
+        let swapped = TlsConfig {
+            cert_file: tls.key_file.clone(),
+            ..tls
+        };
+        let err = swapped.server_config().unwrap_err();
+        assert!(err.starts_with("TLS_CERT_FILE: no certificate"), "{err}");
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashMap, fmt::Display, future::Future, sync::LazyLock};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::ACCEPT_LANGUAGE,
+};
+
+use crate::errors::ErrorCode;
+
+/// Message templates by error code, `{name}` standing for the argument of that name.
+type Catalog = HashMap<ErrorCode, String>;
+
+static ENGLISH: LazyLock<Catalog> = LazyLock::new(|| catalog(include_str!("i18n/en.json")));
+static ITALIAN: LazyLock<Catalog> = LazyLock::new(|| catalog(include_str!("i18n/it.json")));
+
+fn catalog(json: &str) -> Catalog {
+    serde_json::from_str(json).expect("the message catalogs are valid")
+}
+
+tokio::task_local! {
+    static CURRENT: Language;
+}
+
+/// A language the error messages are available in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    Italian,
+}
+
+impl Language {
+    /// The language of the request being handled, English outside of one.
+    pub fn current() -> Self {
+        CURRENT.try_with(|language| *language).unwrap_or_default()
+    }
+
+    /// The language of an `Accept-Language` header the client prefers among the available
+    /// ones, English when there is none.
+    pub fn negotiate(accept_language: &str) -> Self {
+        let mut preferred = None;
+        for range in accept_language.split(',') {
+            let mut params = range.split(';');
+            let Some(language) = params.next().and_then(Self::of) else {
+                continue;
+            };
+            let quality = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.trim().parse().unwrap_or(0.0),
+                None => 1.0,
+            };
+            if quality > preferred.map_or(0.0, |(_, best)| best) {
+                preferred = Some((language, quality));
+            }
+        }
+        preferred.map_or_else(Self::default, |(language, _)| language)
+    }
+
+    /// The language of a tag such as `it-IT`, whatever its region.
+    fn of(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split('-').next()?;
+        if primary.eq_ignore_ascii_case("en") {
+            Some(Self::English)
+        } else if primary.eq_ignore_ascii_case("it") {
+            Some(Self::Italian)
+        } else {
+            None
+        }
+    }
+
+    fn catalog(self) -> &'static Catalog {
+        match self {
+            Self::English => &ENGLISH,
+            Self::Italian => &ITALIAN,
+        }
+    }
+}
+
+/// The message of `code` in the current language, `args` filling the template.
+///
+/// The English template is used when the language has none for the code, or one naming an
+/// argument that isn't given; `None` when neither can be filled: the English catalog leaves
+/// out the codes whose message tells the cause, given by the caller.
+pub fn message(code: ErrorCode, args: &[(&str, &dyn Display)]) -> Option<String> {
+    [Language::current(), Language::English]
+        .into_iter()
+        .find_map(|language| fill(language.catalog().get(&code)?, args))
+}
+
+/// Fills the `{name}` placeholders of `template` with `args`, `None` when one isn't given.
+pub(crate) fn fill(template: &str, args: &[(&str, &dyn Display)]) -> Option<String> {
+    let mut message = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}')?;
+        let (_, value) = args
+            .iter()
+            .find(|(name, _)| *name == &rest[start + 1..end])?;
+        message.push_str(&rest[..start]);
+        message.push_str(&value.to_string());
+        rest = &rest[end + 1..];
+    }
+    message.push_str(rest);
+    Some(message)
+}
+
+/// Middleware negotiating the language of every request from its `Accept-Language`, available
+/// through `Language::current` while handling it.
+pub fn negotiate<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let language = req
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .map_or_else(Language::default, Language::negotiate);
+    let response = CURRENT.sync_scope(language, || srv.call(req));
+    CURRENT.scope(language, response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{application::ApplicationError, domain, errors::CarRentalResponseError};
+    use actix_web::{test::TestRequest, web, App, HttpResponse};
+
+    async fn no_vans() -> Result<HttpResponse, CarRentalResponseError> {
+        Err(ApplicationError::Domain(domain::Error::NoAvailableVehicles).into())
+    }
+
+    #[test]
+    fn it_should_negotiate_the_preferred_available_language() {
+        let cases = [
+            ("it", Language::Italian),
+            ("it-IT,it;q=0.9,en;q=0.8", Language::Italian),
+            ("en-GB,it;q=0.5", Language::English),
+            ("fr-FR,it;q=0.3,en;q=0.2", Language::Italian),
+            ("fr, de", Language::English),
+            ("it;q=0", Language::English),
+            ("*", Language::English),
+            ("", Language::English),
+        ];
+        for (accept_language, language) in cases {
+            assert_eq!(
+                Language::negotiate(accept_language),
+                language,
+                "{accept_language}"
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_translate_every_code() {
+        // As many as the variants of `ErrorCode`.
+        assert_eq!(ITALIAN.len(), 20);
+        assert!(ENGLISH.keys().all(|code| ITALIAN.contains_key(code)));
+    }
+
+    #[tokio::test]
+    async fn it_should_fill_the_templates_and_fall_back_to_english() {
+        let italian = CURRENT
+            .scope(Language::Italian, async {
+                (
+                    message(ErrorCode::PayloadTooLarge, &[("limit", &500)]),
+                    message(ErrorCode::PayloadTooLarge, &[]),
+                    message(ErrorCode::NoAvailableVehicles, &[]),
+                )
+            })
+            .await;
+        assert_eq!(
+            italian,
+            (
+                Some("la richiesta supera il limite di 500".to_string()),
+                None,
+                Some("Nessun veicolo disponibile".to_string())
+            )
+        );
+        assert_eq!(
+            message(ErrorCode::NoAvailableVehicles, &[]).as_deref(),
+            Some("No Available Vehicles")
+        );
+        assert_eq!(
+            fill("{plate} è già registrato", &[("plate", &"AA123BB")]).as_deref(),
+            Some("AA123BB è già registrato")
+        );
+    }
+
+    #[actix_web::test]
+    async fn it_should_localize_the_error_messages_but_not_their_code() {
+        let service = actix_web::test::init_service(
+            App::new()
+                .wrap_fn(negotiate)
+                .route("/", web::get().to(no_vans)),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .insert_header((ACCEPT_LANGUAGE, "it-IT,it;q=0.9,en;q=0.8"))
+            .to_request();
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&service, request).await;
+        assert_eq!(body["code"], "NO_AVAILABLE_VEHICLES");
+        assert_eq!(body["message"], "Nessun veicolo disponibile");
+
+        let request = TestRequest::get()
+            .insert_header((ACCEPT_LANGUAGE, "de-DE"))
+            .to_request();
+        let body: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&service, request).await;
+        assert_eq!(body["code"], "NO_AVAILABLE_VEHICLES");
+        assert_eq!(body["message"], "No Available Vehicles");
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use actix_multipart::Multipart;
+use actix_web::{error::ResponseError, http::StatusCode, HttpResponse};
+use futures_util::TryStreamExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    application::{ApplicationError, ApplicationResult, CommandService},
+    domain::{self, RegisterCustomer, RegisterVehicle, TenantId, TenantScoped},
+    errors::{ErrorBody, ErrorCode},
+    validation::Validate,
+};
+
+/// Bytes an uploaded file is at most, rejected with a `413` beyond.
+pub const MAX_IMPORT_BYTES: usize = 1024 * 1024;
+/// Rows an uploaded file holds at most, its header aside, rejected with a `413` beyond.
+pub const MAX_IMPORT_ROWS: usize = 1000;
+
+/// A row of a fleet import, e.g. `AA123BB,car,Fiat,Ducato,2021`.
+///
+/// The make, model and year are optional and only checked: vehicles don't record them.
+#[derive(Debug, Deserialize)]
+pub struct VehicleRow {
+    pub plate: String,
+    #[serde(rename = "type")]
+    pub vehicle_type: String,
+    #[serde(default)]
+    pub make: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub year: Option<String>,
+}
+
+impl From<VehicleRow> for RegisterVehicle {
+    fn from(row: VehicleRow) -> Self {
+        RegisterVehicle {
+            tenant_id: domain::default_tenant(),
+            vehicle_id: row.plate,
+            vehicle_type: row
+                .vehicle_type
+                .to_ascii_lowercase()
+                .parse()
+                .expect("validated"),
+            seats: None,
+            transmission: None,
+        }
+    }
+}
+
+/// A row of a customer import, e.g. `mario@example.com,Mario,Rossi,+39 02 1234567`.
+///
+/// The email is normalized, trimmed and lowercased; the phone is optional.
+#[derive(Debug, Deserialize)]
+pub struct CustomerRow {
+    #[serde(deserialize_with = "normalized_email")]
+    pub email: String,
+    pub first_name: String,
+    pub last_name: String,
+    #[serde(default)]
+    pub phone: Option<String>,
+}
+
+fn normalized_email<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let email = String::deserialize(deserializer)?;
+    Ok(email.trim().to_lowercase())
+}
+
+impl From<CustomerRow> for RegisterCustomer {
+    fn from(row: CustomerRow) -> Self {
+        RegisterCustomer {
+            tenant_id: domain::default_tenant(),
+            customer_id: row.email.into(),
+            first_name: row.first_name,
+            last_name: row.last_name,
+            phone: row.phone,
+        }
+    }
+}
+
+/// Outcome of an import, each row being imported, skipped or rejected; the line numbers count
+/// the header as line 1.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: Vec<ImportedRow>,
+    /// Rows already registered, by a previous import or otherwise.
+    pub skipped: Vec<ImportedRow>,
+    pub rejected: Vec<RejectedRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportedRow {
+    pub line: u64,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RejectedRow {
+    pub line: u64,
+    /// What's wrong with the row, e.g. `type: must be one of car, pick_up, van, truck`.
+    pub reasons: Vec<String>,
+}
+
+/// An upload that can't be imported at all, rendered as the other errors.
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("the file must be sent as multipart/form-data: {0}")]
+    Multipart(String),
+    #[error("the upload must have a file field")]
+    MissingFile,
+    #[error("the file must be at most {0} bytes")]
+    TooLarge(usize),
+    #[error("the file must hold at most {0} rows")]
+    TooManyRows(usize),
+    #[error("the file must be a CSV starting with a header: {0}")]
+    Malformed(String),
+}
+
+impl ResponseError for UploadError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UploadError::TooLarge(_) | UploadError::TooManyRows(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = match self {
+            UploadError::TooLarge(limit) | UploadError::TooManyRows(limit) => ErrorBody::localized(
+                ErrorCode::PayloadTooLarge,
+                self.to_string(),
+                &[("limit", limit)],
+            ),
+            _ => ErrorBody::new(ErrorCode::MalformedBody, self.to_string()),
+        };
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+/// Reads the `file` field of the upload, or its first field with a filename.
+pub async fn read_file(mut upload: Multipart) -> Result<Vec<u8>, UploadError> {
+    let multipart = |err: actix_multipart::MultipartError| UploadError::Multipart(err.to_string());
+    while let Some(mut field) = upload.try_next().await.map_err(multipart)? {
+        let is_file = field.name() == Some("file")
+            || field
+                .content_disposition()
+                .is_some_and(|disposition| disposition.get_filename().is_some());
+        if !is_file {
+            continue;
+        }
+        let mut file = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(multipart)? {
+            if file.len() + chunk.len() > MAX_IMPORT_BYTES {
+                return Err(UploadError::TooLarge(MAX_IMPORT_BYTES));
+            }
+            file.extend_from_slice(&chunk);
+        }
+        return Ok(file);
+    }
+    Err(UploadError::MissingFile)
+}
+
+/// Rows of a file along with their line.
+type Rows<T> = Vec<(u64, T)>;
+
+/// Rows of a file, each validated up front: those that can be imported along with their line,
+/// and the others with every reason they can't be.
+///
+/// A valid row repeating the `key` of an earlier valid one is rejected as well.
+fn parse_rows<T>(
+    file: &[u8],
+    key_column: &str,
+    key: impl Fn(&T) -> String,
+) -> Result<(Rows<T>, Vec<RejectedRow>), UploadError>
+where
+    T: DeserializeOwned + Validate,
+{
+    let file = file.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(file);
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(file);
+    let headers = reader
+        .headers()
+        .map_err(|err| UploadError::Malformed(err.to_string()))?
+        .clone();
+    let (mut valid, mut rejected) = (Vec::new(), Vec::new());
+    let mut lines_by_key = HashMap::new();
+    for (index, record) in reader.records().enumerate() {
+        if index == MAX_IMPORT_ROWS {
+            return Err(UploadError::TooManyRows(MAX_IMPORT_ROWS));
+        }
+        let record = record.map_err(|err| UploadError::Malformed(err.to_string()))?;
+        let line = record
+            .position()
+            .map_or(index as u64 + 2, |position| position.line());
+        let row: T = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(err) => {
+                let reason = match err.kind() {
+                    csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                    _ => err.to_string(),
+                };
+                rejected.push(RejectedRow {
+                    line,
+                    reasons: vec![reason],
+                });
+                continue;
+            }
+        };
+        let mut reasons: Vec<String> = match row.validate() {
+            Ok(()) => vec![],
+            Err(errors) => errors
+                .errors
+                .iter()
+                .map(|error| format!("{}: {}", error.field, error.message))
+                .collect(),
+        };
+        if reasons.is_empty() {
+            match lines_by_key.entry(key(&row)) {
+                Entry::Occupied(first) => reasons.push(format!(
+                    "{key_column}: already given on line {}",
+                    first.get()
+                )),
+                Entry::Vacant(entry) => {
+                    entry.insert(line);
+                }
+            }
+        }
+        if reasons.is_empty() {
+            valid.push((line, row));
+        } else {
+            rejected.push(RejectedRow { line, reasons });
+        }
+    }
+    Ok((valid, rejected))
+}
+
+/// Registers the valid rows one after the other, the rows already registered being skipped:
+/// importing a file again only imports the rows that weren't.
+async fn register<T, F, I>(
+    mut report: ImportReport,
+    rows: Rows<T>,
+    id: impl Fn(&T) -> String,
+    register: impl Fn(T) -> F,
+) -> ImportReport
+where
+    F: std::future::Future<Output = ApplicationResult<I>>,
+    I: Into<String>,
+{
+    for (line, row) in rows {
+        let id = id(&row);
+        match register(row).await {
+            Ok(id) => report.imported.push(ImportedRow {
+                line,
+                id: id.into(),
+            }),
+            Err(ApplicationError::Domain(
+                domain::Error::AlreadyRegisteredVehicle | domain::Error::AlreadyRegisteredCustomer,
+            )) => report.skipped.push(ImportedRow { line, id }),
+            Err(err) => report.rejected.push(RejectedRow {
+                line,
+                reasons: vec![err.to_string()],
+            }),
+        }
+    }
+    report.rejected.sort_by_key(|row| row.line);
+    report
+}
+
+/// Registers the vehicles of a CSV file with a `plate,type,make,model,year` header, into the
+/// fleet of `tenant_id`.
+pub async fn import_vehicles(
+    app: &dyn CommandService,
+    tenant_id: &TenantId,
+    file: &[u8],
+) -> Result<ImportReport, UploadError> {
+    let (rows, rejected) = parse_rows(file, "plate", |row: &VehicleRow| row.plate.clone())?;
+    let report = ImportReport {
+        rejected,
+        ..Default::default()
+    };
+    Ok(register(
+        report,
+        rows,
+        |row| row.plate.clone(),
+        |row| app.register_vehicle(RegisterVehicle::from(row).for_tenant(tenant_id.clone())),
+    )
+    .await)
+}
+
+/// Registers the customers of a CSV file with an `email,first_name,last_name,phone` header,
+/// the phone being optional, as customers of `tenant_id`.
+pub async fn import_customers(
+    app: &dyn CommandService,
+    tenant_id: &TenantId,
+    file: &[u8],
+) -> Result<ImportReport, UploadError> {
+    let (rows, rejected) = parse_rows(file, "email", |row: &CustomerRow| row.email.clone())?;
+    let report = ImportReport {
+        rejected,
+        ..Default::default()
+    };
+    Ok(register(
+        report,
+        rows,
+        |row| row.email.clone(),
+        |row| app.register_customer(RegisterCustomer::from(row).for_tenant(tenant_id.clone())),
+    )
+    .await)
+}
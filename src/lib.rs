@@ -0,0 +1,1722 @@
+mod admin;
+pub mod application;
+mod auth;
+mod backoff;
+mod batch;
+mod cli;
+mod command_audit;
+mod conditional;
+mod config;
+mod daily_stats;
+mod dead_letter;
+mod delivery;
+pub mod domain;
+mod email;
+mod errors;
+mod filters;
+pub mod fixtures;
+mod health;
+mod http;
+mod http_config;
+mod i18n;
+mod import;
+mod live;
+pub mod loadgen;
+mod memory;
+mod metrics;
+mod pagination;
+pub mod pii;
+mod rate_limit;
+mod read_model;
+mod reporting;
+mod reports;
+mod request_id;
+mod scheduler;
+mod seed;
+mod self_check;
+mod shutdown;
+mod sms;
+mod sorting;
+mod telemetry;
+mod tenant;
+#[cfg(test)]
+mod test_support;
+mod tokens;
+mod validation;
+mod vehicle_stats;
+mod webhooks;
+
+use std::{future::Future, net::SocketAddr, sync::Arc};
+
+use admin::RebuildStatus;
+use application::Application;
+use clap::Parser;
+use cli::{Cli, Command, Stores};
+use config::{AppConfig, EventStoreBackend, ListenerConfig, Polling, Secret};
+use disintegrate_postgres::PgEventStore;
+use domain::DomainEvent;
+use futures_util::FutureExt;
+use health::Readiness;
+use http::PgBackend;
+use live::LiveUpdates;
+use memory::MemoryEventStore;
+use metrics::Metrics;
+use read_model::{
+    memory::MemoryReadModel, queries::ReadModelRepository, repository::read_repository,
+};
+use reporting::{ErrorContext, ErrorReporter};
+use scheduler::{AppContext, Scheduler};
+use self_check::SelfCheck;
+use shutdown::Shutdown;
+use telemetry::Telemetry;
+
+type EventStore = PgEventStore<DomainEvent, pii::EncryptedJson>;
+
+/// Runs the command line of the service, serving the API unless told otherwise.
+pub async fn main() -> anyhow::Result<()> {
+    // The TLS of the server and of every client, some of which only take the provider of the
+    // process.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let cli = Cli::parse();
+    // The variables may all come from the environment instead.
+    dotenv::dotenv().ok();
+    let config = AppConfig::from_env(&cli.overrides.variables())?;
+    let telemetry = Telemetry::init(
+        config.telemetry.otlp_endpoint.as_deref(),
+        &config.telemetry.service_name,
+    )?;
+    tracing::info!(files = ?config.files, "configured with {}", config.effective());
+    let result = run(cli, config).await;
+    telemetry.shutdown();
+    result
+}
+
+async fn run(cli: Cli, config: AppConfig) -> anyhow::Result<()> {
+    let command = cli.command.unwrap_or(Command::Serve);
+    let migrate = config.database.auto_migrate || matches!(command, Command::Migrate);
+    match &config.pii_key {
+        Some(key) => pii::set_master_key(key.clone()).map_err(anyhow::Error::msg)?,
+        None => tracing::warn!(
+            "no PII_ENCRYPTION_KEY set, the personal data is sealed with the development key"
+        ),
+    }
+    if let Some(dir) = &config.pii_key_dir {
+        let keys = pii::KeyStore::open(dir)?;
+        pii::set_key_provider(Arc::new(keys)).map_err(anyhow::Error::msg)?;
+    }
+    if config.event_store == EventStoreBackend::Memory {
+        anyhow::ensure!(
+            matches!(command, Command::Serve),
+            "EVENT_STORE: the subcommands read and write Postgres, set EVENT_STORE=postgres"
+        );
+        return serve_in_memory(config, cli.seed).await;
+    }
+    let stores = Stores::connect(&config, migrate).await?;
+    match command {
+        Command::Serve => {
+            if cli.seed {
+                cli::seed(&config, &stores).await?;
+            }
+            serve(config, stores).await
+        }
+        Command::Migrate => {
+            tracing::info!("migrated the read model");
+            Ok(())
+        }
+        Command::Seed => cli::seed(&config, &stores).await.map(drop),
+        Command::Replay { listener } => cli::replay(&config, &stores, &listener).await.map(drop),
+        Command::ExportEvents {
+            out,
+            after,
+            event_type,
+        } => cli::export_events(&stores, &out, after, event_type.as_deref())
+            .await
+            .map(drop),
+        Command::PruneSnapshots { max_age_days } => {
+            cli::prune_snapshots(&config, &stores, max_age_days)
+                .await
+                .map(drop)
+        }
+        Command::ImportEvents {
+            input,
+            dry_run,
+            force_append,
+        } => cli::import_events(&stores, &input, dry_run, force_append)
+            .await
+            .map(drop),
+        Command::SealEvents => cli::seal_events(&stores).await.map(drop),
+        Command::ShredCustomer { email, tenant } => {
+            let tenant_id = tenant.as_deref().unwrap_or(domain::DEFAULT_TENANT);
+            cli::shred_customer(&config, tenant_id, &email)
+        }
+    }
+}
+
+/// Serves the API and runs the projections, as `config.mode` tells, until the shutdown.
+async fn serve(config: AppConfig, stores: Stores) -> anyhow::Result<()> {
+    let shutdown = Shutdown::default();
+    tokio::spawn(shutdown.clone().listen());
+    let (running, _) = start(config, stores, shutdown).await?;
+    running.await
+}
+
+/// Serves the API on the events kept in memory, as with `EVENT_STORE=memory`, until the
+/// shutdown, seeding the demo data first when `seed`.
+async fn serve_in_memory(config: AppConfig, seed: bool) -> anyhow::Result<()> {
+    let shutdown = Shutdown::default();
+    tokio::spawn(shutdown.clone().listen());
+    let event_store = MemoryEventStore::default();
+    if seed {
+        let seeded = seed::run(
+            &memory_application(&config, event_store.clone()),
+            config.seed,
+        )
+        .await?;
+        tracing::info!(
+            applied = seeded.applied,
+            skipped = seeded.skipped,
+            "seeded the demo data"
+        );
+    }
+    let (running, _) = start_in_memory(config, event_store, shutdown).await?;
+    running.await
+}
+
+/// The application deciding on `event_store` as configured, as `cli::application` does on
+/// Postgres, without the snapshots nor the audit of the commands.
+fn memory_application(config: &AppConfig, event_store: MemoryEventStore) -> Application {
+    Application::in_memory(event_store)
+        .with_conflict_retries(config.decisions.conflict_retries)
+        .with_conflict_backoff(config.decisions.conflict_backoff)
+        .with_decision_timeout(config.decisions.timeout)
+}
+
+/// Binds the HTTP server on `event_store` and starts projecting its events into a
+/// `MemoryReadModel`, returning them running until `shutdown`, along with the addresses the
+/// server is bound to.
+///
+/// The reports and the admin routes, reading Postgres alone, aren't served.
+async fn start_in_memory(
+    config: AppConfig,
+    event_store: MemoryEventStore,
+    shutdown: Shutdown,
+) -> anyhow::Result<(impl Future<Output = anyhow::Result<()>>, Vec<SocketAddr>)> {
+    if let Some(url) = &config.error_docs_url {
+        errors::set_docs_url(url).map_err(anyhow::Error::msg)?;
+    }
+    let reporter = error_reporter(config.sentry_dsn.as_ref().map(Secret::expose))?;
+    let metrics = Metrics::default();
+    let application = memory_application(&config, event_store.clone())
+        .with_metrics(metrics.clone())
+        .with_error_reporter(reporter.clone());
+    if config.api_keys.is_empty() {
+        tracing::warn!("no API_KEYS set, anyone can send commands");
+    }
+    let read_model = MemoryReadModel::default();
+    let readiness = Readiness::default();
+    readiness.checked(SelfCheck::in_memory());
+    let live = LiveUpdates::new(shutdown.clone());
+    let (server, addrs) = http::http_server(
+        &config,
+        application,
+        read_repository(read_model.clone()),
+        None,
+        RebuildStatus::default(),
+        readiness.clone(),
+        live.clone(),
+        metrics,
+        reporter,
+    )?;
+    tracing::info!(event_store = %config.event_store, "started");
+    let listener = memory::listen(
+        event_store,
+        read_model,
+        readiness,
+        live,
+        shutdown.requested(),
+    );
+    let running = shutdown::run(server, listener, shutdown, shutdown::DEFAULT_GRACE_PERIOD);
+    Ok((running, addrs))
+}
+
+/// Binds the HTTP server and starts the event listener as `config.mode` tells, returning them
+/// running until `shutdown`, along with the addresses the server is bound to.
+///
+/// A `listener` instance serves the probes and the metrics only, while an `api` one tells it's
+/// ready from the checkpoints of the listener of another instance.
+async fn start(
+    config: AppConfig,
+    stores: Stores,
+    shutdown: Shutdown,
+) -> anyhow::Result<(impl Future<Output = anyhow::Result<()>>, Vec<SocketAddr>)> {
+    if let Some(url) = &config.error_docs_url {
+        errors::set_docs_url(url).map_err(anyhow::Error::msg)?;
+    }
+    let reporter = error_reporter(config.sentry_dsn.as_ref().map(Secret::expose))?;
+    let metrics = Metrics::default();
+    let application = cli::application(&config, &stores)
+        .await?
+        .with_metrics(metrics.clone())
+        .with_error_reporter(reporter.clone());
+    metrics.register_pools(stores.pools.clone());
+    let Stores {
+        event_store,
+        snapshotter,
+        read_model: pool,
+        listener: listener_stores,
+        check: stores_check,
+        ..
+    } = stores;
+    let readiness = if config.mode.runs_listener() {
+        Readiness::default()
+    } else {
+        Readiness::without_listener()
+    };
+    readiness.checked(stores_check);
+
+    let rebuild_status = RebuildStatus::default();
+    if config.mode.serves_api() {
+        tokio::spawn(rebuild_status.clone().watch(pool.clone()));
+        if config.api_keys.is_empty() {
+            tracing::warn!("no API_KEYS set, anyone can send commands");
+        }
+        if !config.api_keys.admits_admins() {
+            tracing::warn!(
+                "neither ADMIN_API_KEYS nor JWT_SECRET set, the admin routes are closed"
+            );
+        }
+    }
+    tokio::spawn(readiness.clone().watch(
+        ReadModelRepository::new(pool.clone()),
+        config.ready_max_lag,
+        config.listener.paused.clone(),
+    ));
+    let mut scheduler = Scheduler::new(
+        AppContext {
+            read_model: pool.clone(),
+        },
+        shutdown.clone(),
+    );
+    if config.mode.runs_listener() {
+        if let Some(sender) = sms::sender(&config.sms) {
+            scheduler =
+                scheduler.with_job(sms::OverdueNotifier::new(sender, config.sms.check_interval));
+        }
+        scheduler.start();
+    }
+
+    let live = LiveUpdates::new(shutdown.clone());
+    let (server, addrs) = http::http_server(
+        &config,
+        application,
+        read_repository(ReadModelRepository::new(pool.clone())),
+        Some(PgBackend {
+            event_store: event_store.clone(),
+            snapshotter,
+            pool: pool.clone(),
+            scheduler,
+        }),
+        rebuild_status,
+        readiness.clone(),
+        live.clone(),
+        metrics,
+        reporter.clone(),
+    )?;
+    tracing::info!(mode = %config.mode, "started");
+    let listener = if config.mode.runs_listener() {
+        let restarts = config.listener.restarts;
+        let listener_config = config.listener;
+        shutdown::supervise(
+            {
+                let shutdown = shutdown.clone();
+                move || {
+                    event_listener(
+                        listener_stores.read_model.clone(),
+                        listener_stores.event_store.clone(),
+                        readiness.clone(),
+                        live.clone(),
+                        listener_config.clone(),
+                        reporter.clone(),
+                        shutdown.requested(),
+                    )
+                }
+            },
+            restarts,
+            shutdown.clone(),
+        )
+        .boxed()
+    } else {
+        shutdown.requested().map(Ok).boxed()
+    };
+    let running = shutdown::run(server, listener, shutdown, shutdown::DEFAULT_GRACE_PERIOD);
+    Ok((running, addrs))
+}
+
+/// Reports the unexpected errors to Sentry when `SENTRY_DSN` is set, which requires the
+/// `sentry` feature.
+fn error_reporter(dsn: Option<&str>) -> anyhow::Result<Arc<dyn ErrorReporter>> {
+    match dsn {
+        #[cfg(feature = "sentry")]
+        Some(dsn) => Ok(Arc::new(
+            reporting::SentryReporter::new(dsn).map_err(anyhow::Error::msg)?,
+        )),
+        // Rejected along the configuration.
+        #[cfg(not(feature = "sentry"))]
+        Some(_) => unreachable!("SENTRY_DSN requires the sentry feature"),
+        None => Ok(reporting::noop()),
+    }
+}
+
+async fn event_listener(
+    pool: sqlx::PgPool,
+    event_store: EventStore,
+    readiness: Readiness,
+    live: LiveUpdates,
+    config: ListenerConfig,
+    reporter: Arc<dyn ErrorReporter>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    let mailer = email::mailer(&config.email).map_err(anyhow::Error::msg)?;
+    readiness.listening();
+    let listener = delivery::Listeners::new(pool.clone(), event_store.clone(), &config)
+        .register_listener(
+            read_model::CustomerProjection::new(pool.clone()).with_error_reporter(reporter.clone()),
+            polling(&config, read_model::CustomerProjection::ID),
+        )
+        .register_listener(
+            read_model::VehicleProjection::new(pool.clone()).with_error_reporter(reporter.clone()),
+            polling(&config, read_model::VehicleProjection::ID),
+        )
+        .register_listener(
+            read_model::RentalProjection::new(pool.clone()).with_error_reporter(reporter.clone()),
+            polling(&config, read_model::RentalProjection::ID),
+        )
+        .register_listener(
+            daily_stats::DailyStatsProjection::new(pool.clone())
+                .with_error_reporter(reporter.clone()),
+            polling(&config, daily_stats::DailyStatsProjection::ID),
+        )
+        .register_listener(
+            vehicle_stats::VehicleStatsProjection::new(pool.clone())
+                .with_error_reporter(reporter.clone()),
+            polling(&config, vehicle_stats::VehicleStatsProjection::ID),
+        )
+        .register_listener(
+            live::LiveFeed::new(event_store.clone(), live),
+            polling(&config, live::LiveFeed::ID),
+        )
+        .register_listener(
+            webhooks::WebhookDispatcher::new(pool.clone(), config.webhook_retries),
+            polling(&config, webhooks::WebhookDispatcher::ID),
+        )
+        .register_listener(
+            email::EmailNotifier::new(pool.clone(), mailer),
+            polling(&config, email::EmailNotifier::ID),
+        );
+    listener.start(shutdown).await.map_err(|e| {
+        reporter.report(e.as_ref(), &ErrorContext::default());
+        anyhow::anyhow!("event listener exited with error: {}", e)
+    })
+}
+
+/// The polling of `listener_id` as configured, logged as the listener starts.
+fn polling(config: &ListenerConfig, listener_id: &str) -> Polling {
+    let polling = config.polling(listener_id);
+    tracing::info!(
+        listener_id,
+        delivery = ?config.delivery,
+        poll_interval = ?polling.interval,
+        batch_size = polling.batch_size,
+        "polling for events"
+    );
+    polling
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+    use application::Application;
+    use cli::Stores;
+    use config::{AppConfig, DatabaseConfig, ListenerConfig, RunMode};
+    use disintegrate_postgres::PgSnapshotter;
+    use domain::{
+        default_tenant, EndRent, RegisterCustomer, RegisterVehicle, StartRent, VehicleType,
+    };
+    use errors::ErrorCode;
+    use http::api;
+    use http_config::HttpConfig;
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use sqlx::PgPool;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use test_support::{application, bind, probe};
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_decide_the_same_with_snapshots_or_without(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        // Each run gets a database of its own, for the events to get the same ids.
+        let pool = PgPool::connect_with(options.clone()).await.unwrap();
+        let unsnapshotted = format!("{}_unsnapshotted", options.get_database().unwrap());
+        sqlx::query(&format!("DROP DATABASE IF EXISTS {unsnapshotted}"))
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(&format!("CREATE DATABASE {unsnapshotted}"))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut outcomes = Vec::new();
+        for (options, snapshotted) in [
+            (options.clone(), true),
+            (options.clone().database(&unsnapshotted), false),
+        ] {
+            let pool = PgPool::connect_with(options).await.unwrap();
+            let event_store: EventStore = PgEventStore::new(pool.clone(), Default::default())
+                .await
+                .unwrap();
+            let decision_maker = if snapshotted {
+                let snapshotter = PgSnapshotter::new(pool, 1).await.unwrap();
+                application::DecisionMaker::snapshotted(event_store.clone(), snapshotter)
+            } else {
+                application::DecisionMaker::unsnapshotted(event_store.clone())
+            };
+            let app = Application::new(decision_maker, event_store);
+            let told = |result: Result<Option<i64>, application::ApplicationError>| {
+                result.map_err(|err| ErrorCode::from(&err).to_string())
+            };
+            let start = |customer_id: &str| StartRent {
+                tenant_id: default_tenant(),
+                customer_id: customer_id.into(),
+                vehicle_type: VehicleType::Van,
+            };
+            let end = |customer_id: &str| EndRent {
+                tenant_id: default_tenant(),
+                customer_id: customer_id.into(),
+            };
+            let mut run = Vec::new();
+            for vehicle_id in ["AA111AA", "AA111AA"] {
+                let registered = app
+                    .register_vehicle(RegisterVehicle {
+                        tenant_id: default_tenant(),
+                        vehicle_id: vehicle_id.to_string(),
+                        vehicle_type: VehicleType::Van,
+                        seats: None,
+                        transmission: None,
+                    })
+                    .await;
+                run.push(told(registered.map(|_| None)));
+            }
+            for (customer_id, first_name) in [
+                ("mario@example.com", "Mario"),
+                ("luigi@example.com", "Luigi"),
+            ] {
+                let registered = app
+                    .register_customer(RegisterCustomer {
+                        tenant_id: default_tenant(),
+                        customer_id: customer_id.into(),
+                        first_name: first_name.to_string(),
+                        last_name: "Rossi".to_string(),
+                        phone: None,
+                    })
+                    .await;
+                run.push(told(registered.map(|_| None)));
+            }
+            for (customer_id, starts) in [
+                ("mario@example.com", true),
+                ("mario@example.com", true),
+                ("luigi@example.com", true),
+                ("mario@example.com", false),
+                ("mario@example.com", false),
+                ("luigi@example.com", true),
+            ] {
+                let result = if starts {
+                    app.start_rent(start(customer_id))
+                        .await
+                        .map(|started| Some(started.rent_id))
+                } else {
+                    app.end_rent(end(customer_id))
+                        .await
+                        .map(|ended| Some(ended.event_id))
+                };
+                run.push(told(result));
+            }
+            outcomes.push(run);
+        }
+
+        sqlx::query(&format!("DROP DATABASE {unsnapshotted} WITH (FORCE)"))
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(outcomes[0].iter().any(Result::is_err), "{:?}", outcomes[0]);
+        assert_eq!(outcomes[0], outcomes[1]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_keep_the_personal_data_out_of_the_event_store(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let app = application(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let shutdown = Shutdown::default();
+        tokio::spawn(event_listener(
+            pool.clone(),
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig {
+                poll_interval: Duration::from_millis(20),
+                ..ListenerConfig::default()
+            },
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+        let mario = RegisterCustomer {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: Some("+39021234567".to_string()),
+        };
+        app.register_customer(mario.clone()).await.unwrap();
+        app.register_vehicle(RegisterVehicle {
+            tenant_id: default_tenant(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Van,
+            seats: None,
+            transmission: None,
+        })
+        .await
+        .unwrap();
+        app.start_rent(StartRent {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_type: VehicleType::Van,
+        })
+        .await
+        .unwrap();
+        app.end_rent(EndRent {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+        })
+        .await
+        .unwrap();
+
+        let rows: Vec<String> = sqlx::query_scalar(
+            r#"SELECT concat_ws(' ', e.customer_id, s.customer_id, convert_from(e.payload, 'UTF8'))
+                FROM public.event e JOIN public.event_sequence s USING (event_id)"#,
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(rows.len(), 4);
+        for row in rows {
+            for plaintext in ["mario@example.com", "Mario", "Rossi", "+39021234567"] {
+                assert!(!row.contains(plaintext), "{row}");
+            }
+        }
+
+        // The decisions find the customer by the token of their email.
+        assert!(matches!(
+            app.register_customer(mario).await,
+            Err(application::ApplicationError::Domain(
+                domain::Error::AlreadyRegisteredCustomer
+            ))
+        ));
+        let repository = ReadModelRepository::new(pool.clone());
+        while repository
+            .projection_lags()
+            .await
+            .map_or(true, |lags| lags.iter().any(|lag| lag.pending_events > 0))
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        shutdown.trigger();
+        let registered: (String, String, Option<String>) = sqlx::query_as(
+            "SELECT first_name, last_name, phone FROM customer WHERE customer_id = 'mario@example.com'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            registered,
+            (
+                "Mario".to_string(),
+                "Rossi".to_string(),
+                Some("+39021234567".to_string())
+            )
+        );
+        let returned: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM rent WHERE customer_id = 'mario@example.com' AND end_date IS NOT NULL",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(returned, 1);
+    }
+
+    #[actix_web::test]
+    async fn it_should_execute_the_commands_as_the_named_methods() {
+        let app = Application::in_memory(Default::default());
+        let mario = RegisterCustomer {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: None,
+        };
+        let van = RegisterVehicle {
+            tenant_id: default_tenant(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Van,
+            seats: None,
+            transmission: None,
+        };
+        assert_eq!(
+            app.execute(mario.clone()).await.unwrap(),
+            "mario@example.com"
+        );
+        assert!(matches!(
+            app.register_customer(mario).await,
+            Err(application::ApplicationError::Domain(
+                domain::Error::AlreadyRegisteredCustomer
+            ))
+        ));
+        assert_eq!(app.register_vehicle(van.clone()).await.unwrap(), "AA111AA");
+        assert!(matches!(
+            app.execute(van).await,
+            Err(application::ApplicationError::Domain(
+                domain::Error::AlreadyRegisteredVehicle
+            ))
+        ));
+
+        let started = app
+            .execute(StartRent {
+                tenant_id: default_tenant(),
+                customer_id: "mario@example.com".into(),
+                vehicle_type: VehicleType::Van,
+            })
+            .await
+            .unwrap();
+        assert_eq!(started.vehicle_id, "AA111AA");
+        let end = EndRent {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+        };
+        let ended = app.execute(end.clone()).await.unwrap();
+        assert_eq!(ended.start_date, Some(started.start_date));
+        assert!(!ended.already_returned);
+
+        // Only the named method reports the return again.
+        assert!(matches!(
+            app.execute(end.clone()).await,
+            Err(application::ApplicationError::Domain(
+                domain::Error::AlreadyReturned
+            ))
+        ));
+        let again = app.end_rent(end).await.unwrap();
+        assert!(again.already_returned);
+        assert_eq!(again.event_id, ended.event_id);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_rent_both_vans_to_the_customers_racing_for_them(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let app = application(options)
+            .await
+            .with_conflict_retries(5)
+            .with_conflict_backoff(Duration::from_millis(5));
+        let emails = ["mario@example.com", "luigi@example.com"];
+        for email in emails {
+            app.register_customer(RegisterCustomer {
+                tenant_id: default_tenant(),
+                customer_id: email.into(),
+                first_name: "Mario".to_string(),
+                last_name: "Rossi".to_string(),
+                phone: None,
+            })
+            .await
+            .unwrap();
+        }
+        for plate in ["AA111AA", "BB222BB"] {
+            app.register_vehicle(RegisterVehicle {
+                tenant_id: default_tenant(),
+                vehicle_id: plate.to_string(),
+                vehicle_type: VehicleType::Van,
+                seats: None,
+                transmission: None,
+            })
+            .await
+            .unwrap();
+        }
+
+        let (mario, luigi) = tokio::join!(
+            app.start_rent(StartRent {
+                tenant_id: default_tenant(),
+                customer_id: emails[0].into(),
+                vehicle_type: VehicleType::Van,
+            }),
+            app.start_rent(StartRent {
+                tenant_id: default_tenant(),
+                customer_id: emails[1].into(),
+                vehicle_type: VehicleType::Van,
+            }),
+        );
+        let mut plates = [mario.unwrap().vehicle_id, luigi.unwrap().vehicle_id];
+        plates.sort();
+        assert_eq!(plates, ["AA111AA", "BB222BB"]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_leave_a_timed_out_rental_started_in_full_or_not_at_all(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let app = application(options.clone()).await;
+        let pool = PgPool::connect_with(options).await.unwrap();
+        let rented = |customer_id: String| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_scalar::<_, i64>(
+                    "SELECT count(*) FROM event WHERE event_type = 'VehicleRented' AND customer_id = $1",
+                )
+                .bind(pii::token(&customer_id))
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+        let mut timed_out = 0;
+        for i in 0..20 {
+            let customer_id = format!("customer{i}@example.com");
+            app.register_customer(RegisterCustomer {
+                tenant_id: default_tenant(),
+                customer_id: customer_id.clone().into(),
+                first_name: "Mario".to_string(),
+                last_name: "Rossi".to_string(),
+                phone: None,
+            })
+            .await
+            .unwrap();
+            app.register_vehicle(RegisterVehicle {
+                tenant_id: default_tenant(),
+                vehicle_id: format!("AA{i:03}AA"),
+                vehicle_type: VehicleType::Van,
+                seats: None,
+                transmission: None,
+            })
+            .await
+            .unwrap();
+            let start = StartRent {
+                tenant_id: default_tenant(),
+                customer_id: customer_id.clone().into(),
+                vehicle_type: VehicleType::Van,
+            };
+
+            // From abandoning it before it starts to letting it finish.
+            let timeout = Duration::from_micros(i * 500);
+            let outcome = app
+                .clone()
+                .with_decision_timeout(timeout)
+                .start_rent(start.clone())
+                .await;
+            match outcome {
+                Ok(_) => assert_eq!(rented(customer_id).await, 1),
+                Err(err) => {
+                    assert!(
+                        matches!(err, application::ApplicationError::Timeout(_)),
+                        "{err}"
+                    );
+                    timed_out += 1;
+                    // Started again, it fails only when the abandoned decision was made, its
+                    // van being rented already: either way, the rental is started once. The
+                    // abandoned decision may still be committing meanwhile, a conflict then
+                    // telling to decide again on the state it left.
+                    match app.clone().with_conflict_retries(5).start_rent(start).await {
+                        Ok(_) => {}
+                        Err(application::ApplicationError::Domain(
+                            domain::Error::RentalInProgress | domain::Error::NoAvailableVehicles,
+                        )) => {}
+                        Err(err) => panic!("{err:?}"),
+                    }
+                    assert_eq!(rented(customer_id).await, 1);
+                }
+            }
+        }
+        assert!(timed_out > 0);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_stop_the_server_and_the_listener_on_shutdown(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let config = HttpConfig::new(None, Some("0"), None).unwrap();
+        let (server, addrs) = bind(options.clone(), &config).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options.clone()).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let pool = test_support::read_model(options).await;
+        let shutdown = Shutdown::default();
+        let listener = event_listener(
+            pool,
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig::default(),
+            reporting::noop(),
+            shutdown.requested(),
+        );
+        let running = tokio::spawn(shutdown::run(
+            server,
+            listener,
+            shutdown.clone(),
+            Duration::from_secs(10),
+        ));
+
+        let stream = tokio::net::TcpStream::connect(addrs[0]).await.unwrap();
+        let response = probe(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        shutdown.trigger();
+        let stopped = tokio::time::timeout(Duration::from_secs(10), running)
+            .await
+            .expect("the shutdown timed out")
+            .unwrap();
+        assert!(stopped.is_ok(), "{stopped:?}");
+        assert!(tokio::net::TcpStream::connect(addrs[0]).await.is_err());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_stop_the_server_when_the_listener_dies(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let config = HttpConfig::new(None, Some("0"), None).unwrap();
+        let (server, addrs) = bind(options.clone(), &config).await;
+        let public_pool = PgPool::connect_with(options.clone()).await.unwrap();
+        let event_store = PgEventStore::new(public_pool.clone(), Default::default())
+            .await
+            .unwrap();
+        let pool = test_support::read_model(options).await;
+        let shutdown = Shutdown::default();
+        let listener = event_listener(
+            pool,
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            ListenerConfig::default(),
+            reporting::noop(),
+            shutdown.requested(),
+        );
+        let running = tokio::spawn(shutdown::run(
+            server,
+            listener,
+            shutdown.clone(),
+            Duration::from_secs(10),
+        ));
+        // Once the projections are registered, the table isn't created again.
+        let registered = "SELECT count(*) FROM event_listener";
+        while sqlx::query_scalar::<_, i64>(registered)
+            .fetch_one(&public_pool)
+            .await
+            .map_or(true, |count| count < 6)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        sqlx::query("DROP TABLE event_listener")
+            .execute(&public_pool)
+            .await
+            .unwrap();
+        let stopped = tokio::time::timeout(Duration::from_secs(10), running)
+            .await
+            .expect("the server kept running")
+            .unwrap();
+        let err = stopped.unwrap_err();
+        assert!(err.to_string().contains("event listener"), "{err:#}");
+        assert!(shutdown.is_requested());
+        assert!(tokio::net::TcpStream::connect(addrs[0]).await.is_err());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_poll_a_listener_at_its_own_interval(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let application = application(options.clone()).await;
+        for command in [
+            serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Car" }),
+            serde_json::json!({ "vehicleId": "BB222BB", "vehicleType": "Truck" }),
+        ] {
+            application
+                .register_vehicle(serde_json::from_value(command).unwrap())
+                .await
+                .unwrap();
+        }
+        let mario = serde_json::json!({
+            "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+        });
+        application
+            .register_customer(serde_json::from_value(mario).unwrap())
+            .await
+            .unwrap();
+        let slow = Duration::from_secs(5);
+        let config = ListenerConfig {
+            poll_interval: Duration::from_millis(20),
+            overrides: [(
+                daily_stats::DailyStatsProjection::ID,
+                config::Polling {
+                    interval: slow,
+                    batch_size: config::DEFAULT_BATCH_SIZE,
+                },
+            )]
+            .into(),
+            ..ListenerConfig::default()
+        };
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let shutdown = Shutdown::default();
+        tokio::spawn(event_listener(
+            pool.clone(),
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            config,
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+        let repository = ReadModelRepository::new(pool);
+        let pending = |listener_id: &'static str| {
+            let repository = repository.clone();
+            async move {
+                let lags = repository.projection_lags().await.unwrap_or_default();
+                lags.iter()
+                    .find(|lag| lag.listener_id == listener_id)
+                    .map(|lag| lag.pending_events)
+            }
+        };
+        // Every listener polls once as it starts.
+        while pending(daily_stats::DailyStatsProjection::ID).await != Some(0) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let rent = serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Car" });
+        application
+            .start_rent(serde_json::from_value(rent).unwrap())
+            .await
+            .unwrap();
+        let started = std::time::Instant::now();
+        while pending(read_model::RentalProjection::ID).await != Some(0) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(
+            pending(daily_stats::DailyStatsProjection::ID).await,
+            Some(1)
+        );
+        tokio::time::timeout(slow * 2, async {
+            while pending(daily_stats::DailyStatsProjection::ID).await != Some(0) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("the daily stats were never projected");
+        assert!(
+            started.elapsed() > slow - Duration::from_secs(1),
+            "{:?}",
+            started.elapsed()
+        );
+        shutdown.trigger();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_leave_the_vehicle_stats_behind_while_paused(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let application = application(options.clone()).await;
+        let car = serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Car" });
+        application
+            .register_vehicle(serde_json::from_value(car).unwrap())
+            .await
+            .unwrap();
+        let mario = serde_json::json!({
+            "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+        });
+        application
+            .register_customer(serde_json::from_value(mario).unwrap())
+            .await
+            .unwrap();
+        let rent = serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Car" });
+        application
+            .start_rent(serde_json::from_value(rent).unwrap())
+            .await
+            .unwrap();
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let listen = |config: ListenerConfig| {
+            let shutdown = Shutdown::default();
+            let listening = tokio::spawn(event_listener(
+                pool.clone(),
+                event_store.clone(),
+                Readiness::default(),
+                LiveUpdates::new(shutdown.clone()),
+                config,
+                reporting::noop(),
+                shutdown.requested(),
+            ));
+            (shutdown, listening)
+        };
+        let repository = ReadModelRepository::new(pool.clone());
+        let pending = |listener_id: &'static str| {
+            let repository = repository.clone();
+            async move {
+                let lags = repository.projection_lags().await.unwrap_or_default();
+                lags.iter()
+                    .find(|lag| lag.listener_id == listener_id)
+                    .map(|lag| lag.pending_events)
+            }
+        };
+        let config = ListenerConfig {
+            poll_interval: Duration::from_millis(20),
+            ..ListenerConfig::default()
+        };
+
+        let (shutdown, listening) = listen(ListenerConfig {
+            paused: [vehicle_stats::VehicleStatsProjection::ID].into(),
+            ..config.clone()
+        });
+        while pending(read_model::RentalProjection::ID).await != Some(0)
+            || pending(read_model::VehicleProjection::ID).await != Some(0)
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let service = test::init_service(
+            App::new()
+                .app_data(read_repository(repository.clone()))
+                .configure(api),
+        )
+        .await;
+        let stats = || {
+            test::TestRequest::get()
+                .uri("/api/v1/vehicles/AA111AA/stats")
+                .to_request()
+        };
+        let response = test::call_service(&service, stats()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            pending(vehicle_stats::VehicleStatsProjection::ID).await,
+            Some(2)
+        );
+        shutdown.trigger();
+        listening.await.unwrap().unwrap();
+
+        let (shutdown, listening) = listen(config);
+        while pending(vehicle_stats::VehicleStatsProjection::ID).await != Some(0) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let stats: serde_json::Value = test::call_and_read_body_json(&service, stats()).await;
+        assert_eq!(stats["vehicleId"], "AA111AA");
+        assert_eq!(stats["rentals"], 1);
+        assert_eq!(stats["rentedMinutes"], 0);
+        assert!(stats["lastRentedAt"].is_string(), "{stats}");
+        shutdown.trigger();
+        listening.await.unwrap().unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_keep_projecting_the_customers_while_a_rental_fails(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let application = application(options.clone()).await;
+        // Every rental fails to be projected, without ever being set aside during the test.
+        sqlx::raw_sql(
+            r#"CREATE FUNCTION poison() RETURNS trigger AS $$
+                BEGIN RAISE EXCEPTION 'poisoned'; END $$ LANGUAGE plpgsql;
+            CREATE TRIGGER poison BEFORE INSERT ON rent FOR EACH ROW EXECUTE FUNCTION poison()"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let register = |customer_id: &str| {
+            serde_json::from_value(serde_json::json!({
+                "customerId": customer_id, "firstName": "Mario", "lastName": "Rossi"
+            }))
+            .unwrap()
+        };
+        let car = serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Car" });
+        application
+            .register_vehicle(serde_json::from_value(car).unwrap())
+            .await
+            .unwrap();
+        application
+            .register_customer(register("mario@example.com"))
+            .await
+            .unwrap();
+        let rent = serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Car" });
+        application
+            .start_rent(serde_json::from_value(rent).unwrap())
+            .await
+            .unwrap();
+        application
+            .register_customer(register("luigi@example.com"))
+            .await
+            .unwrap();
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let config = ListenerConfig {
+            poll_interval: Duration::from_millis(20),
+            overrides: [(
+                read_model::RentalProjection::ID,
+                config::Polling {
+                    interval: Duration::from_millis(500),
+                    batch_size: config::DEFAULT_BATCH_SIZE,
+                },
+            )]
+            .into(),
+            ..ListenerConfig::default()
+        };
+        let shutdown = Shutdown::default();
+        let listening = tokio::spawn(event_listener(
+            pool.clone(),
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            config,
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+        let repository = ReadModelRepository::new(pool.clone());
+        let checkpoint = |listener_id: &'static str| {
+            let repository = repository.clone();
+            async move {
+                let lags = repository.projection_lags().await.unwrap();
+                let lag = lags
+                    .into_iter()
+                    .find(|lag| lag.listener_id == listener_id)
+                    .unwrap();
+                (
+                    lag.last_processed_event_id,
+                    lag.latest_event_id,
+                    lag.pending_events,
+                )
+            }
+        };
+
+        while repository
+            .customer_detail("luigi@example.com")
+            .await
+            .unwrap()
+            .is_none()
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        // The checkpoint is saved once the batch is projected, after its rows.
+        while checkpoint(read_model::CustomerProjection::ID).await.2 != 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let (projected, latest, _) = checkpoint(read_model::CustomerProjection::ID).await;
+        assert_eq!(projected, latest);
+        let (rented, _, pending) = checkpoint(read_model::RentalProjection::ID).await;
+        assert!(rented < latest, "{rented} {latest}");
+        assert_eq!(pending, 1);
+
+        sqlx::query("DROP TRIGGER poison ON rent")
+            .execute(&pool)
+            .await
+            .unwrap();
+        while checkpoint(read_model::RentalProjection::ID).await.2 != 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let rented: i64 = sqlx::query_scalar("SELECT count(*) FROM rent")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rented, 1);
+        shutdown.trigger();
+        listening.await.unwrap().unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_project_the_events_as_soon_as_notified(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options.clone()).await;
+        let application = application(options.clone()).await;
+        let config = ListenerConfig {
+            delivery: config::Delivery::Notify,
+            ..ListenerConfig::default()
+        };
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let shutdown = Shutdown::default();
+        tokio::spawn(event_listener(
+            pool.clone(),
+            event_store,
+            Readiness::default(),
+            LiveUpdates::new(shutdown.clone()),
+            config.clone(),
+            reporting::noop(),
+            shutdown.requested(),
+        ));
+        let repository = ReadModelRepository::new(pool);
+        let pending = || async {
+            let lags = repository.projection_lags().await.unwrap_or_default();
+            lags.iter()
+                .find(|lag| lag.listener_id == read_model::VehicleProjection::ID)
+                .map(|lag| lag.pending_events)
+        };
+        while pending().await != Some(0) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Past the first turn of every listener, which comes right as they start.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        for (i, vehicle_id) in ["AA111AA", "BB222BB", "CC333CC"].into_iter().enumerate() {
+            let command = serde_json::json!({ "vehicleId": vehicle_id, "vehicleType": "Car" });
+            application
+                .register_vehicle(serde_json::from_value(command).unwrap())
+                .await
+                .unwrap();
+            let appended = std::time::Instant::now();
+            while pending().await != Some(0) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let latency = appended.elapsed();
+            assert!(
+                latency < config.fallback_poll_interval / 10,
+                "vehicle {i} projected after {latency:?}"
+            );
+        }
+        shutdown.trigger();
+    }
+
+    /// Starts an instance in `mode`, returning it running along with its address.
+    async fn start_instance(
+        options: PgConnectOptions,
+        mode: RunMode,
+        shutdown: Shutdown,
+    ) -> (tokio::task::JoinHandle<anyhow::Result<()>>, SocketAddr) {
+        let config = AppConfig {
+            mode,
+            database: DatabaseConfig {
+                options,
+                ..AppConfig::default().database
+            },
+            read_model_schema: "test_read_model".parse().unwrap(),
+            http: HttpConfig::new(None, Some("0"), None).unwrap(),
+            listener: ListenerConfig {
+                poll_interval: Duration::from_millis(20),
+                ..ListenerConfig::default()
+            },
+            ..AppConfig::default()
+        };
+        let stores = Stores::connect(&config, true).await.unwrap();
+        let (running, addrs) = start(config, stores, shutdown).await.unwrap();
+        (tokio::spawn(running), addrs[0])
+    }
+
+    /// Starts an instance on the events kept in memory, returning it running along with its
+    /// address.
+    async fn start_memory_instance(
+        shutdown: Shutdown,
+    ) -> (tokio::task::JoinHandle<anyhow::Result<()>>, SocketAddr) {
+        let config = AppConfig {
+            event_store: EventStoreBackend::Memory,
+            http: HttpConfig::new(None, Some("0"), None).unwrap(),
+            ..AppConfig::default()
+        };
+        let (running, addrs) = start_in_memory(config, MemoryEventStore::default(), shutdown)
+            .await
+            .unwrap();
+        (tokio::spawn(running), addrs[0])
+    }
+
+    /// The commands the parity tests send, in order, along with the status they're answered.
+    ///
+    /// A single vehicle of each type, for both instances to rent the same.
+    fn parity_commands() -> Vec<(&'static str, serde_json::Value, StatusCode)> {
+        use serde_json::json;
+        vec![
+            (
+                "/vehicle/register",
+                json!({ "vehicleId": "AA111AA", "vehicleType": "Car" }),
+                StatusCode::CREATED,
+            ),
+            (
+                "/vehicle/register",
+                json!({ "vehicleId": "BB222BB", "vehicleType": "Truck" }),
+                StatusCode::CREATED,
+            ),
+            (
+                "/vehicle/register",
+                json!({ "vehicleId": "CC333CC", "vehicleType": "Van" }),
+                StatusCode::CREATED,
+            ),
+            (
+                "/customer/register",
+                json!({
+                    "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                }),
+                StatusCode::CREATED,
+            ),
+            (
+                "/customer/register",
+                json!({
+                    "customerId": "anna@example.com", "firstName": "Anna", "lastName": "Bianchi"
+                }),
+                StatusCode::CREATED,
+            ),
+            (
+                "/rent/start",
+                json!({ "customerId": "mario@example.com", "vehicleType": "Car" }),
+                StatusCode::CREATED,
+            ),
+            (
+                "/rent/end",
+                json!({ "customerId": "mario@example.com" }),
+                StatusCode::OK,
+            ),
+            (
+                "/rent/start",
+                json!({ "customerId": "anna@example.com", "vehicleType": "Van" }),
+                StatusCode::CREATED,
+            ),
+            (
+                "/rent/start",
+                json!({ "customerId": "mario@example.com", "vehicleType": "Van" }),
+                StatusCode::CONFLICT,
+            ),
+        ]
+    }
+
+    /// The reads the parity tests compare, every read of the read model the API serves.
+    fn parity_reads() -> Vec<String> {
+        let today = chrono::Utc::now().date_naive();
+        let calendar = format!(
+            "/availability/calendar?vehicleType=car&from={}&to={}",
+            today - chrono::Days::new(1),
+            today + chrono::Days::new(1)
+        );
+        [
+            "/availability",
+            "/availability?vehicleType=van",
+            calendar.as_str(),
+            "/vehicles",
+            "/vehicles?sort=-vehicleId&limit=2",
+            "/vehicles?status=available&vehicleType=car",
+            "/vehicles/count",
+            "/vehicles/AA111AA",
+            "/vehicles/ZZ999ZZ",
+            "/vehicles/AA111AA/stats",
+            "/customers",
+            "/customers/count",
+            "/customers/search?q=rosi",
+            "/customers/mario@example.com",
+            "/customers/mario@example.com/summary",
+            "/rent/status?customerId=anna@example.com",
+            "/rent/status?customerId=mario@example.com",
+            "/rentals",
+            "/rentals?sort=-rentId",
+            "/rentals?status=open",
+            "/rentals/count",
+            "/rentals/active",
+            "/rentals/export",
+            "/search?q=mar",
+        ]
+        .map(str::to_string)
+        .to_vec()
+    }
+
+    /// The response to `GET path`, its timestamps and its scores left out, which the instances
+    /// can't agree on.
+    async fn parity_read(client: &awc::Client, addr: SocketAddr, path: &str) -> String {
+        fn normalize(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(fields) => {
+                    fields.remove("score");
+                    fields.values_mut().for_each(normalize);
+                }
+                serde_json::Value::Array(values) => values.iter_mut().for_each(normalize),
+                serde_json::Value::String(text)
+                    if chrono::DateTime::parse_from_rfc3339(text).is_ok() =>
+                {
+                    *text = "<timestamp>".to_string();
+                }
+                _ => {}
+            }
+        }
+        let mut response = client
+            .get(format!("http://{addr}/api/v1{path}"))
+            .send()
+            .await
+            .unwrap();
+        let etag = response.headers().get("etag").cloned();
+        let body = response.body().await.unwrap();
+        let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(mut json) => {
+                normalize(&mut json);
+                json.to_string()
+            }
+            // The CSV export.
+            Err(_) => String::from_utf8_lossy(&body)
+                .split(',')
+                .map(|field| match chrono::DateTime::parse_from_rfc3339(field) {
+                    Ok(_) => "<timestamp>",
+                    Err(_) => field,
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+        };
+        format!("{} {etag:?} {body}", response.status())
+    }
+
+    #[actix_web::test]
+    async fn it_should_serve_the_api_on_the_events_kept_in_memory() {
+        let shutdown = Shutdown::default();
+        let (instance, addr) = start_memory_instance(shutdown.clone()).await;
+        let client = awc::Client::new();
+        let url = |path: &str| format!("http://{addr}{path}");
+
+        for (path, command, status) in parity_commands() {
+            let response = client
+                .post(url(&format!("/api/v1{path}")))
+                .send_json(&command)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{path} {command}");
+        }
+        let mut status = serde_json::Value::Null;
+        for _ in 0..100 {
+            status = client
+                .get(url("/api/v1/rent/status?customerId=anna@example.com"))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            if status["active"] == true {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(status["vehicleId"], "CC333CC");
+
+        let response = client.get(url("/healthz")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.get(url("/readyz")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // The reports and the admin routes read Postgres alone.
+        let response = client
+            .get(url("/api/v1/reports/overdue"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        shutdown.trigger();
+        let stopped = tokio::time::timeout(Duration::from_secs(10), instance)
+            .await
+            .expect("the shutdown timed out")
+            .unwrap();
+        assert!(stopped.is_ok(), "{stopped:?}");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_read_the_same_in_memory_as_in_postgres(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let shutdown = Shutdown::default();
+        let (postgres, postgres_addr) =
+            start_instance(options, RunMode::All, shutdown.clone()).await;
+        let (memory, memory_addr) = start_memory_instance(shutdown.clone()).await;
+
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let client = awc::Client::new();
+                for (path, command, status) in parity_commands() {
+                    for addr in [postgres_addr, memory_addr] {
+                        let response = client
+                            .post(format!("http://{addr}/api/v1{path}"))
+                            .send_json(&command)
+                            .await
+                            .unwrap();
+                        assert_eq!(response.status(), status, "{path} {command}");
+                    }
+                }
+                for path in parity_reads() {
+                    // Until the listeners of both have projected every event.
+                    let mut reads = (String::new(), String::new());
+                    for _ in 0..100 {
+                        reads = (
+                            parity_read(&client, postgres_addr, &path).await,
+                            parity_read(&client, memory_addr, &path).await,
+                        );
+                        if reads.0 == reads.1 {
+                            break;
+                        }
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                    assert_eq!(reads.1, reads.0, "{path}");
+                }
+            })
+            .await;
+
+        shutdown.trigger();
+        for instance in [postgres, memory] {
+            let stopped = tokio::time::timeout(Duration::from_secs(10), instance)
+                .await
+                .expect("the shutdown timed out")
+                .unwrap();
+            assert!(stopped.is_ok(), "{stopped:?}");
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_run_the_api_and_the_listener_apart(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let api_shutdown = Shutdown::default();
+        let (api_instance, api_addr) =
+            start_instance(options.clone(), RunMode::Api, api_shutdown.clone()).await;
+        let listener_shutdown = Shutdown::default();
+        let (listener_instance, listener_addr) =
+            start_instance(options, RunMode::Listener, listener_shutdown.clone()).await;
+        let url = |path: &str| format!("http://{api_addr}/api/v1{path}");
+
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let client = awc::Client::new();
+                let status = |active: bool| {
+                    let client = client.clone();
+                    let url = url("/rent/status?customerId=mario@example.com");
+                    async move {
+                        for _ in 0..200 {
+                            let mut response = client.get(&url).send().await.unwrap();
+                            if response.status() == StatusCode::OK {
+                                let status: serde_json::Value = response.json().await.unwrap();
+                                if status["active"] == active {
+                                    return;
+                                }
+                            }
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                        }
+                        panic!("the listener instance never projected the rent");
+                    }
+                };
+                let register = serde_json::json!({ "vehicleId": "AA111AA", "vehicleType": "Car" });
+                let response = client
+                    .post(url("/vehicle/register"))
+                    .send_json(&register)
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::CREATED);
+                let mario = serde_json::json!({
+                    "customerId": "mario@example.com", "firstName": "Mario", "lastName": "Rossi"
+                });
+                let response = client
+                    .post(url("/customer/register"))
+                    .send_json(&mario)
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::CREATED);
+                let rent =
+                    serde_json::json!({ "customerId": "mario@example.com", "vehicleType": "Car" });
+                let response = client
+                    .post(url("/rent/start"))
+                    .send_json(&rent)
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::CREATED);
+                status(true).await;
+
+                let rent = serde_json::json!({ "customerId": "mario@example.com" });
+                let response = client
+                    .post(url("/rent/end"))
+                    .send_json(&rent)
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                status(false).await;
+
+                // Told by the checkpoints of the listener instance, polled every second.
+                let ready_url = format!("http://{api_addr}/readyz");
+                let mut response = client.get(&ready_url).send().await.unwrap();
+                for _ in 0..50 {
+                    if response.status() == StatusCode::OK {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    response = client.get(&ready_url).send().await.unwrap();
+                }
+                assert_eq!(response.status(), StatusCode::OK);
+
+                // The listener instance serves the probes and the metrics, but not the API.
+                let listener_url = |path: &str| format!("http://{listener_addr}{path}");
+                let response = client.get(listener_url("/healthz")).send().await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let response = client.get(listener_url("/metrics")).send().await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let response = client
+                    .get(listener_url("/api/v1/vehicles"))
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            })
+            .await;
+
+        for (shutdown, instance) in [
+            (api_shutdown, api_instance),
+            (listener_shutdown, listener_instance),
+        ] {
+            shutdown.trigger();
+            let stopped = tokio::time::timeout(Duration::from_secs(10), instance)
+                .await
+                .expect("the shutdown timed out")
+                .unwrap();
+            assert!(stopped.is_ok(), "{stopped:?}");
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::time::Duration;
+
+use actix_web::web::Bytes;
+use actix_ws::{CloseCode, Message, MessageStream, Session};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use disintegrate::{
+    query, Event, EventListener, EventStore, PersistedEvent, StateMutate, StreamQuery,
+};
+use futures_util::{Stream, TryStreamExt};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    application::{DomainEventStore, TenantEventStore},
+    domain::{Email, PlateNumber, RentEvent, TenantId, VehicleAvailability, VehicleType},
+    filters::MAX_RENTAL_DAYS,
+    read_model::queries::ActiveRental,
+    shutdown::Shutdown,
+};
+
+/// How often the streams send a comment, so that proxies don't close them as idle.
+pub const HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Updates a subscriber can fall behind by before being dropped.
+const CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailabilityChanged {
+    /// Only sent to the subscribers of the tenant.
+    #[serde(skip)]
+    pub tenant_id: TenantId,
+    pub vehicle_type: VehicleType,
+    pub available: usize,
+}
+
+/// Rental status of a customer, sent when its rental starts or ends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RentalStatus {
+    #[serde(skip)]
+    pub tenant_id: TenantId,
+    pub customer_id: Email,
+    pub active: bool,
+    pub rental: Option<LiveRental>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveRental {
+    pub vehicle_id: PlateNumber,
+    pub vehicle_type: Option<VehicleType>,
+    pub start_date: DateTime<Utc>,
+    /// `MAX_RENTAL_DAYS` after the start.
+    pub due_date: DateTime<Utc>,
+}
+
+impl RentalStatus {
+    pub fn new(tenant_id: TenantId, customer_id: Email, rental: Option<LiveRental>) -> Self {
+        Self {
+            tenant_id,
+            customer_id,
+            active: rental.is_some(),
+            rental,
+        }
+    }
+}
+
+impl LiveRental {
+    fn new(
+        vehicle_id: PlateNumber,
+        vehicle_type: Option<VehicleType>,
+        start_date: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            vehicle_id,
+            vehicle_type,
+            start_date,
+            due_date: start_date + chrono::Duration::days(MAX_RENTAL_DAYS.into()),
+        }
+    }
+}
+
+impl From<ActiveRental> for LiveRental {
+    fn from(rental: ActiveRental) -> Self {
+        Self::new(rental.vehicle_id, rental.vehicle_type, rental.start_date)
+    }
+}
+
+/// Updates pushed to the clients of the live streams, published by the `LiveFeed`.
+///
+/// The streams end on shutdown, so that they don't hold the HTTP server for its whole grace
+/// period.
+#[derive(Debug, Clone)]
+pub struct LiveUpdates {
+    availability: broadcast::Sender<AvailabilityChanged>,
+    rentals: broadcast::Sender<RentalStatus>,
+    shutdown: Shutdown,
+}
+
+impl LiveUpdates {
+    pub fn new(shutdown: Shutdown) -> Self {
+        Self {
+            availability: broadcast::channel(CAPACITY).0,
+            rentals: broadcast::channel(CAPACITY).0,
+            shutdown,
+        }
+    }
+
+    /// Changes of the rental status of every customer, for the subscriber to filter.
+    ///
+    /// Its receiver lagging means the subscriber fell more than `CAPACITY` updates behind.
+    pub fn rentals(&self) -> broadcast::Receiver<RentalStatus> {
+        self.rentals.subscribe()
+    }
+
+    pub(crate) fn publish_rental(&self, status: RentalStatus) {
+        let _ = self.rentals.send(status);
+    }
+
+    /// Whether anyone follows the availability, which is only counted for them.
+    pub(crate) fn follows_availability(&self) -> bool {
+        self.availability.receiver_count() > 0
+    }
+
+    pub(crate) fn publish_availability(&self, changed: AvailabilityChanged) {
+        // The subscribers may have left meanwhile.
+        let _ = self.availability.send(changed);
+    }
+
+    /// Resolves once the streams are to end.
+    pub fn closed(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        self.shutdown.requested()
+    }
+
+    /// Server-Sent Events of the availability changes of `tenant_id`, with a heartbeat comment
+    /// every `HEARTBEAT`.
+    ///
+    /// Subscribers falling more than `CAPACITY` updates behind are dropped; they can reconnect
+    /// and read `/availability` again.
+    pub fn availability_events(
+        &self,
+        tenant_id: TenantId,
+    ) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+        let mut updates = self.availability.subscribe();
+        let shutdown = self.shutdown.requested();
+        async_stream::stream! {
+            tokio::pin!(shutdown);
+            let mut heartbeat = tokio::time::interval_at(tokio::time::Instant::now() + HEARTBEAT, HEARTBEAT);
+            loop {
+                tokio::select! {
+                    update = updates.recv() => match update {
+                        Ok(update) if update.tenant_id == tenant_id => {
+                            yield Ok(server_sent_event("availability", &update))
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(skipped)) => {
+                            tracing::warn!(skipped, "dropping a slow availability subscriber");
+                            break;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = heartbeat.tick() => yield Ok(Bytes::from_static(b": heartbeat\n\n")),
+                    () = &mut shutdown => break,
+                }
+            }
+        }
+    }
+}
+
+/// Serves the WebSocket of the rental status of a customer: `snapshot` first, then its
+/// changes, pinging the client every `HEARTBEAT`.
+///
+/// Clients falling more than `CAPACITY` updates behind are disconnected with a `1013` (try
+/// again later); reconnecting gets them the current status again.
+pub async fn push_rental_status(
+    mut session: Session,
+    mut messages: MessageStream,
+    snapshot: RentalStatus,
+    mut updates: broadcast::Receiver<RentalStatus>,
+    closed: impl std::future::Future<Output = ()>,
+) {
+    let (tenant_id, customer_id) = (snapshot.tenant_id.clone(), snapshot.customer_id.clone());
+    if session.text(to_json(&snapshot)).await.is_err() {
+        return;
+    }
+    tokio::pin!(closed);
+    let mut heartbeat =
+        tokio::time::interval_at(tokio::time::Instant::now() + HEARTBEAT, HEARTBEAT);
+    let reason = loop {
+        let sent = tokio::select! {
+            update = updates.recv() => match update {
+                Ok(status) if status.tenant_id == tenant_id && status.customer_id == customer_id => {
+                    session.text(to_json(&status)).await
+                }
+                Ok(_) => Ok(()),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "dropping a slow rental status subscriber");
+                    break Some(CloseCode::Again.into());
+                }
+                Err(RecvError::Closed) => break None,
+            },
+            message = messages.recv() => match message {
+                Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await,
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break None,
+                Some(Ok(_)) => Ok(()),
+            },
+            _ = heartbeat.tick() => session.ping(b"").await,
+            () = &mut closed => break Some(CloseCode::Away.into()),
+        };
+        if sent.is_err() {
+            return;
+        }
+    };
+    let _ = session.close(reason).await;
+}
+
+fn to_json(data: &impl Serialize) -> String {
+    serde_json::to_string(data).expect("updates serialize to JSON")
+}
+
+fn server_sent_event(event: &str, data: &impl Serialize) -> Bytes {
+    Bytes::from(format!("event: {event}\ndata: {}\n\n", to_json(data)))
+}
+
+/// Publishes the number of vehicles available of a type whenever it changes, and the rental
+/// status of the customers as their rentals start and end.
+///
+/// It isn't a projection: nothing is stored, and the events that find no subscriber are
+/// skipped.
+pub struct LiveFeed {
+    query: StreamQuery<RentEvent>,
+    event_store: TenantEventStore,
+    updates: LiveUpdates,
+}
+
+impl LiveFeed {
+    pub const ID: &'static str = "drive_me_crazy_live_feed";
+
+    pub fn new(event_store: DomainEventStore, updates: LiveUpdates) -> Self {
+        Self {
+            query: query(None),
+            event_store: TenantEventStore::new(event_store),
+            updates,
+        }
+    }
+
+    /// Folds the vehicles of the type up to the event, the way the decisions do, as the read
+    /// model may not have caught up with it yet.
+    async fn available(
+        &self,
+        tenant_id: &TenantId,
+        vehicle_type: &VehicleType,
+        event_id: i64,
+    ) -> Result<usize, disintegrate_postgres::Error> {
+        let query = query!(
+            RentEvent,
+            (tenant_id == tenant_id.clone()) and (vehicle_type == vehicle_type.clone())
+        );
+        let availability = self
+            .event_store
+            .stream(&query)
+            .try_take_while(|event| std::future::ready(Ok(event.id() <= event_id)))
+            .try_fold(
+                VehicleAvailability::new(tenant_id.clone(), vehicle_type.clone()),
+                |mut state, event| {
+                    state.mutate(event.into_inner());
+                    std::future::ready(Ok(state))
+                },
+            )
+            .await?;
+        Ok(availability.available_vehicles.len())
+    }
+}
+
+#[async_trait]
+impl EventListener<RentEvent> for LiveFeed {
+    type Error = disintegrate_postgres::Error;
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn query(&self) -> &StreamQuery<RentEvent> {
+        &self.query
+    }
+
+    #[tracing::instrument(skip_all, fields(listener_id = self.id(), event_id = event.id(), event_type = event.name()))]
+    async fn handle(&self, event: PersistedEvent<RentEvent>) -> Result<(), Self::Error> {
+        let event_id = event.id();
+        let (tenant_id, vehicle_type, status) = changes(event.into_inner());
+        if let Some(status) = status {
+            self.updates.publish_rental(status);
+        }
+        if !self.updates.follows_availability() {
+            return Ok(());
+        }
+        let available = self.available(&tenant_id, &vehicle_type, event_id).await?;
+        self.updates.publish_availability(AvailabilityChanged {
+            tenant_id,
+            vehicle_type,
+            available,
+        });
+        Ok(())
+    }
+}
+
+/// The tenant and the type of the vehicles whose availability the event changes, along with
+/// the rental status it changes, if any.
+pub(crate) fn changes(event: RentEvent) -> (TenantId, VehicleType, Option<RentalStatus>) {
+    match event {
+        RentEvent::VehicleAdded {
+            tenant_id,
+            vehicle_type,
+            ..
+        } => (tenant_id, vehicle_type, None),
+        RentEvent::VehicleRented {
+            tenant_id,
+            customer_id,
+            vehicle_id,
+            vehicle_type,
+            start_date,
+        } => {
+            let rental = LiveRental::new(vehicle_id, Some(vehicle_type.clone()), start_date);
+            let status = RentalStatus::new(tenant_id.clone(), customer_id, Some(rental));
+            (tenant_id, vehicle_type, Some(status))
+        }
+        RentEvent::VehicleReturned {
+            tenant_id,
+            customer_id,
+            vehicle_type,
+            ..
+        } => {
+            let status = RentalStatus::new(tenant_id.clone(), customer_id, None);
+            (tenant_id, vehicle_type, Some(status))
+        }
+    }
+}
@@ -0,0 +1,476 @@
+//! Drives the commands of the HTTP API of a running service, to show it under load: the
+//! customers and vehicles are registered first, then the customers take turns starting and
+//! ending rentals at the rate asked for.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use chrono::Utc;
+use clap::Parser;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::domain::{
+    default_tenant, Email, EndRent, RegisterCustomer, RegisterVehicle, StartRent, VehicleType,
+};
+
+/// The vehicles a run can register, their plates being the tag of the run and a number of at
+/// most four digits.
+const MAX_VEHICLES: usize = 10_000;
+
+/// Sends the commands of the car rental API at a steady rate, then prints their latencies, the
+/// errors by code and the throughput achieved.
+#[derive(Debug, Clone, Parser)]
+#[command(about)]
+pub struct LoadConfig {
+    /// The base URL of the service.
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub url: String,
+    /// Sent as `Authorization: Bearer <key>`, for a service requiring API keys.
+    #[arg(long)]
+    pub api_key: Option<String>,
+    /// The customers registered, who then take turns renting.
+    #[arg(long, default_value_t = 100)]
+    pub customers: usize,
+    /// The vehicles registered, of every type in turn.
+    #[arg(long, default_value_t = 30)]
+    pub vehicles: usize,
+    /// The rentals started and ended once all are registered.
+    #[arg(long, default_value_t = 1_000)]
+    pub requests: usize,
+    /// The rentals started and ended a second.
+    #[arg(long, default_value_t = 50)]
+    pub rps: u32,
+    /// The requests in flight at most.
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+    /// The share of each command, e.g. `start=3,end=2`.
+    #[arg(long, default_value = "start=1,end=1")]
+    pub mix: Mix,
+}
+
+impl LoadConfig {
+    fn check(&self) -> Result<(), String> {
+        if self.rps == 0 {
+            return Err("rps: must be at least 1".to_string());
+        }
+        if !(1..=self.customers).contains(&self.concurrency) {
+            return Err("concurrency: must be between 1 and the customers".to_string());
+        }
+        if !(1..=MAX_VEHICLES).contains(&self.vehicles) {
+            return Err(format!("vehicles: must be between 1 and {MAX_VEHICLES}"));
+        }
+        Ok(())
+    }
+}
+
+/// How the rentals are shared between the commands, as weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mix {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Mix {
+    /// The command of the `n`th request, the commands taking turns as they weigh.
+    fn pick(&self, n: usize) -> Kind {
+        if (n as u64 % u64::from(self.start + self.end)) < u64::from(self.start) {
+            Kind::Start
+        } else {
+            Kind::End
+        }
+    }
+}
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mix = Mix { start: 0, end: 0 };
+        for share in s.split(',') {
+            let (kind, weight) = share.split_once('=').ok_or(format!(
+                "{share}: must be a command and its weight, e.g. start=1"
+            ))?;
+            let weight = weight
+                .parse()
+                .map_err(|_| format!("{share}: the weight must be a number"))?;
+            match kind {
+                "start" => mix.start = weight,
+                "end" => mix.end = weight,
+                _ => return Err(format!("{kind}: must be one of start, end")),
+            }
+        }
+        if mix.start + mix.end == 0 {
+            return Err("must weigh at least one command".to_string());
+        }
+        Ok(mix)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Start,
+    End,
+}
+
+/// The customers waiting for their turn, those idle to start a rental and those renting to end
+/// theirs. A customer is sent one command at a time.
+struct Turns {
+    idle: VecDeque<usize>,
+    renting: VecDeque<usize>,
+}
+
+impl Turns {
+    /// The next customer to send the command, or the other one when no customer can be sent it.
+    fn next(&mut self, kind: Kind) -> Option<(Kind, usize)> {
+        let kind = match kind {
+            Kind::Start if self.idle.is_empty() => Kind::End,
+            Kind::End if self.renting.is_empty() => Kind::Start,
+            kind => kind,
+        };
+        let customers = match kind {
+            Kind::Start => &mut self.idle,
+            Kind::End => &mut self.renting,
+        };
+        customers.pop_front().map(|customer| (kind, customer))
+    }
+
+    fn done(&mut self, kind: Kind, customer: usize, outcome: &Result<(), String>) {
+        let renting = match (kind, outcome) {
+            (Kind::Start, outcome) => outcome.is_ok(),
+            (Kind::End, Ok(())) => false,
+            (Kind::End, Err(code)) => code != "RENTAL_NOT_FOUND",
+        };
+        if renting {
+            self.renting.push_back(customer);
+        } else {
+            self.idle.push_back(customer);
+        }
+    }
+}
+
+/// The requests of a run, once everyone was registered.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Of every request, the failed ones included, in the order they ended.
+    pub latencies: Vec<Duration>,
+    /// The requests failed, by the code of their error.
+    pub errors: BTreeMap<String, u64>,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    pub fn sent(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// The requests sent a second.
+    pub fn throughput(&self) -> f64 {
+        self.sent() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The latency under which the `p`th percentile of the requests ended.
+    pub fn percentile(&self, p: f64) -> Duration {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let rank = ((p / 100.0) * latencies.len() as f64).ceil() as usize;
+        latencies
+            .get(rank.saturating_sub(1))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} requests in {:.2?}, {:.1} requests/s",
+            self.sent(),
+            self.elapsed,
+            self.throughput()
+        )?;
+        writeln!(
+            f,
+            "latency p50 {:.2?}, p95 {:.2?}, p99 {:.2?}, max {:.2?}",
+            self.percentile(50.0),
+            self.percentile(95.0),
+            self.percentile(99.0),
+            self.percentile(100.0)
+        )?;
+        // Buckets doubling from a millisecond, from the fastest request to the slowest.
+        let (min, max) = (self.percentile(0.0), self.percentile(100.0));
+        let mut bound = Duration::from_millis(1);
+        while bound < min {
+            bound *= 2;
+        }
+        let mut below = 0;
+        loop {
+            let count = self.latencies.iter().filter(|l| **l <= bound).count() - below;
+            let bar = "#".repeat(count * 50 / self.sent().max(1));
+            writeln!(f, "  <= {:>8}  {count:>7} {bar}", format!("{bound:?}"))?;
+            below += count;
+            if bound >= max {
+                break;
+            }
+            bound *= 2;
+        }
+        if self.errors.is_empty() {
+            return writeln!(f, "no errors");
+        }
+        writeln!(f, "errors:")?;
+        for (code, count) in &self.errors {
+            writeln!(f, "  {code:<28} {count:>7}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends the commands to the API, telling the code of the error of those failing.
+#[derive(Clone)]
+struct Client {
+    http: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+}
+
+impl Client {
+    async fn send(&self, path: &str, command: &impl Serialize) -> Result<(), String> {
+        let mut request = self
+            .http
+            .post(format!("{}/api/v1{path}", self.url))
+            .json(command);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let response = request.send().await.map_err(|err| {
+            if err.is_timeout() {
+                "TIMEOUT".to_string()
+            } else {
+                "CONNECTION_FAILED".to_string()
+            }
+        })?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        Err(body["code"]
+            .as_str()
+            .map_or_else(|| format!("HTTP_{}", status.as_u16()), str::to_string))
+    }
+}
+
+/// Registers the customers and the vehicles, named after the run for it not to meet those of
+/// the previous ones, then sends the rentals.
+pub async fn run(config: &LoadConfig) -> anyhow::Result<Report> {
+    config.check().map_err(anyhow::Error::msg)?;
+    let client = Client {
+        http: reqwest::Client::new(),
+        url: config.url.trim_end_matches('/').to_string(),
+        api_key: config.api_key.clone(),
+    };
+    let tag = tag(Utc::now().timestamp() as u64);
+    let customer = |n: usize| Email::from(format!("loadgen{n}.{tag}@example.com"));
+    let vehicle_type = |n: usize| VehicleType::ALL[n % VehicleType::ALL.len()].clone();
+
+    stream::iter(0..config.vehicles)
+        .map(|n| {
+            let command = RegisterVehicle {
+                tenant_id: default_tenant(),
+                vehicle_id: format!("{tag}{n:04}"),
+                vehicle_type: vehicle_type(n),
+                seats: None,
+                transmission: None,
+            };
+            let client = &client;
+            async move {
+                client
+                    .send("/vehicle/register", &command)
+                    .await
+                    .map_err(anyhow::Error::msg)
+                    .with_context(|| format!("registering {}", command.vehicle_id))
+            }
+        })
+        .buffer_unordered(config.concurrency)
+        .try_collect::<()>()
+        .await?;
+    stream::iter(0..config.customers)
+        .map(|n| {
+            let command = RegisterCustomer {
+                tenant_id: default_tenant(),
+                customer_id: customer(n),
+                first_name: "Load".to_string(),
+                last_name: format!("Generator {n}"),
+                phone: None,
+            };
+            let client = &client;
+            async move {
+                client
+                    .send("/customer/register", &command)
+                    .await
+                    .map_err(anyhow::Error::msg)
+                    .with_context(|| format!("registering {}", command.customer_id))
+            }
+        })
+        .buffer_unordered(config.concurrency)
+        .try_collect::<()>()
+        .await?;
+
+    let turns = Arc::new(Mutex::new(Turns {
+        idle: (0..config.customers).collect(),
+        renting: VecDeque::new(),
+    }));
+    let report = Arc::new(Mutex::new(Report::default()));
+    let permits = Arc::new(Semaphore::new(config.concurrency));
+    let mut ticks = tokio::time::interval(Duration::from_secs(1) / config.rps);
+    let mut requests = JoinSet::new();
+    let started = Instant::now();
+    for n in 0..config.requests {
+        ticks.tick().await;
+        let permit = permits.clone().acquire_owned().await?;
+        // Fewer requests are in flight than there are customers, one of them waits.
+        let (kind, nth) = turns.lock().unwrap().next(config.mix.pick(n)).unwrap();
+        let (client, turns, report) = (client.clone(), turns.clone(), report.clone());
+        let (customer_id, vehicle_type) = (customer(nth), vehicle_type(nth));
+        requests.spawn(async move {
+            let sent = Instant::now();
+            let outcome = match kind {
+                Kind::Start => {
+                    let command = StartRent {
+                        tenant_id: default_tenant(),
+                        customer_id,
+                        vehicle_type,
+                    };
+                    client.send("/rent/start", &command).await
+                }
+                Kind::End => {
+                    let command = EndRent {
+                        tenant_id: default_tenant(),
+                        customer_id,
+                    };
+                    client.send("/rent/end", &command).await
+                }
+            };
+            let latency = sent.elapsed();
+            turns.lock().unwrap().done(kind, nth, &outcome);
+            let mut report = report.lock().unwrap();
+            report.latencies.push(latency);
+            if let Err(code) = outcome {
+                *report.errors.entry(code).or_default() += 1;
+            }
+            drop(permit);
+        });
+        while requests.try_join_next().is_some() {}
+    }
+    while requests.join_next().await.is_some() {}
+
+    let mut report = std::mem::take(&mut *report.lock().unwrap());
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+/// The seconds in base 36, six letters and digits until 2038.
+fn tag(mut secs: u64) -> String {
+    let mut tag = Vec::new();
+    while secs > 0 {
+        tag.push(char::from_digit((secs % 36) as u32, 36).unwrap());
+        secs /= 36;
+    }
+    tag.iter().rev().collect::<String>().to_uppercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::{AppConfig, EventStoreBackend, RateLimitConfig},
+        http_config::HttpConfig,
+        memory::MemoryEventStore,
+        rate_limit::Quota,
+        shutdown::Shutdown,
+    };
+
+    #[actix_web::test]
+    async fn it_should_drive_the_rentals_and_report_their_latencies() {
+        // The service as `EVENT_STORE=memory` runs it, the registrations and the rentals
+        // within its limits.
+        let config = AppConfig {
+            event_store: EventStoreBackend::Memory,
+            http: HttpConfig::new(None, Some("0"), None).unwrap(),
+            rate_limits: RateLimitConfig {
+                commands: Quota::per_minute(1000),
+                ..RateLimitConfig::default()
+            },
+            ..AppConfig::default()
+        };
+        let shutdown = Shutdown::default();
+        let (running, addrs) =
+            crate::start_in_memory(config, MemoryEventStore::default(), shutdown.clone())
+                .await
+                .unwrap();
+        let service = tokio::spawn(running);
+        let url = format!("http://{}/", addrs[0]);
+        let config = LoadConfig::parse_from([
+            "loadgen",
+            "--url",
+            &url,
+            "--customers",
+            "6",
+            "--vehicles",
+            "12",
+            "--requests",
+            "40",
+            "--rps",
+            "400",
+            "--concurrency",
+            "3",
+            "--mix",
+            "start=2,end=1",
+        ]);
+
+        let report = run(&config).await.unwrap();
+        assert_eq!(report.sent(), 40);
+        assert!(report.throughput() > 0.0);
+        assert!(report.percentile(50.0) <= report.percentile(99.0));
+        // The in-memory store tells the starts racing on the vehicles of a type apart.
+        assert!(
+            report
+                .errors
+                .keys()
+                .all(|code| code == "CONCURRENT_MODIFICATION"),
+            "{report}"
+        );
+        assert!(report.to_string().contains("requests/s"));
+
+        // Projected by the listener of the memory backend.
+        let count = format!("{url}api/v1/vehicles/count");
+        let mut vehicles = serde_json::Value::Null;
+        for _ in 0..100 {
+            vehicles = reqwest::get(&count).await.unwrap().json().await.unwrap();
+            if vehicles["count"] == 12 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(vehicles["count"], 12);
+        shutdown.trigger();
+        assert!(service.await.unwrap().is_ok());
+    }
+
+    #[test]
+    fn it_should_share_the_requests_as_the_mix_weighs() {
+        let mix: Mix = "start=3,end=1".parse().unwrap();
+        let kinds: Vec<Kind> = (0..4).map(|n| mix.pick(n)).collect();
+        assert_eq!(kinds, [Kind::Start, Kind::Start, Kind::Start, Kind::End]);
+        assert!("quote=1".parse::<Mix>().is_err());
+        assert!("start=0".parse::<Mix>().is_err());
+        assert_eq!(tag(36 * 36 + 35), "10Z");
+    }
+}
@@ -0,0 +1,170 @@
+//! The `EVENT_STORE=memory` backend: the events kept in the process, and projected into a
+//! `MemoryReadModel` as they are appended, for trying the API out without Postgres.
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use disintegrate::{stream_query::StreamFilter, Event, EventStore, PersistedEvent, StreamQuery};
+use futures_util::stream::{self, BoxStream};
+use tokio::sync::watch;
+
+use crate::{
+    application::matches,
+    domain::{DomainEvent, RentEvent},
+    health::Readiness,
+    live::{self, AvailabilityChanged, LiveUpdates},
+    read_model::{memory::MemoryReadModel, repository::ReadRepository},
+};
+
+/// An event store kept in memory.
+///
+/// It filters the events as `PgEventStore` does, an identifier missing from an event
+/// matching any value, and reports the conflicts with the same error.
+#[derive(Clone)]
+pub struct MemoryEventStore {
+    events: Arc<Mutex<Vec<PersistedEvent<DomainEvent>>>>,
+    /// The id of the last event appended, for the listener to follow.
+    head: Arc<watch::Sender<i64>>,
+}
+
+impl Default for MemoryEventStore {
+    fn default() -> Self {
+        Self {
+            events: Arc::default(),
+            head: Arc::new(watch::Sender::new(0)),
+        }
+    }
+}
+
+impl MemoryEventStore {
+    fn matching(&self, filter: &StreamFilter) -> Vec<PersistedEvent<DomainEvent>> {
+        let events = self.events.lock().unwrap();
+        events
+            .iter()
+            .filter(|event| matches(filter, event))
+            .cloned()
+            .collect()
+    }
+
+    /// The events appended after `event_id`, in order.
+    pub fn after(&self, event_id: i64) -> Vec<PersistedEvent<DomainEvent>> {
+        let events = self.events.lock().unwrap();
+        // The ids are the positions, from 1.
+        events
+            .get(event_id.max(0) as usize..)
+            .unwrap_or_default()
+            .to_vec()
+    }
+}
+
+#[async_trait]
+impl EventStore<DomainEvent> for MemoryEventStore {
+    type Error = disintegrate_postgres::Error;
+
+    fn stream<'a, QE>(
+        &'a self,
+        query: &'a StreamQuery<QE>,
+    ) -> BoxStream<'a, Result<PersistedEvent<QE>, Self::Error>>
+    where
+        QE: TryFrom<DomainEvent> + Event + 'static + Clone + Send + Sync,
+        <QE as TryFrom<DomainEvent>>::Error: std::error::Error + 'static + Send + Sync,
+    {
+        let events = self.matching(query.filter()).into_iter().map(|event| {
+            let id = event.id();
+            QE::try_from(event.into_inner())
+                .map(|event| PersistedEvent::new(id, event))
+                .map_err(|err| disintegrate_postgres::Error::QueryEventMapping(Box::new(err)))
+        });
+        Box::pin(stream::iter(events))
+    }
+
+    async fn append<QE>(
+        &self,
+        events: Vec<DomainEvent>,
+        query: StreamQuery<QE>,
+        last_event_id: i64,
+    ) -> Result<Vec<PersistedEvent<DomainEvent>>, Self::Error>
+    where
+        DomainEvent: Clone + 'async_trait,
+        QE: Event + 'static + Clone + Send + Sync,
+    {
+        let mut stored = self.events.lock().unwrap();
+        let query = query.change_origin(last_event_id);
+        if stored.iter().any(|event| matches(query.filter(), event)) {
+            return Err(disintegrate_postgres::Error::Concurrency);
+        }
+        let mut appended = Vec::with_capacity(events.len());
+        for event in events {
+            let event = PersistedEvent::new(stored.len() as i64 + 1, event);
+            stored.push(event.clone());
+            appended.push(event);
+        }
+        self.head.send_replace(stored.len() as i64);
+        Ok(appended)
+    }
+}
+
+/// The event listener of `EVENT_STORE=memory`: projects the events into `read_model` as they
+/// are appended, and publishes them to the live streams, until `shutdown`.
+///
+/// The projections are caught up as soon as it has applied the events appended before it
+/// started.
+pub async fn listen(
+    event_store: MemoryEventStore,
+    read_model: MemoryReadModel,
+    readiness: Readiness,
+    live: LiveUpdates,
+    shutdown: impl Future<Output = ()> + Send,
+) -> anyhow::Result<()> {
+    let mut head = event_store.head.subscribe();
+    let mut last_event_id = 0;
+    readiness.listening();
+    tokio::pin!(shutdown);
+    loop {
+        head.borrow_and_update();
+        for event in event_store.after(last_event_id) {
+            last_event_id = event.id();
+            read_model.apply(&event);
+            publish(&read_model, &live, event).await?;
+        }
+        readiness.caught_up(true);
+        tokio::select! {
+            changed = head.changed() => changed?,
+            () = &mut shutdown => return Ok(()),
+        }
+    }
+}
+
+/// Publishes the changes of the event as `LiveFeed` does, the availability being read from
+/// the read model, which has just applied it.
+async fn publish(
+    read_model: &MemoryReadModel,
+    live: &LiveUpdates,
+    event: PersistedEvent<DomainEvent>,
+) -> anyhow::Result<()> {
+    let Ok(event) = RentEvent::try_from(event.into_inner()) else {
+        return Ok(());
+    };
+    let (tenant_id, vehicle_type, status) = live::changes(event);
+    if let Some(status) = status {
+        live.publish_rental(status);
+    }
+    if !live.follows_availability() {
+        return Ok(());
+    }
+    let available = read_model
+        .for_tenant(tenant_id.clone())
+        .availability_summary(Some(vehicle_type.clone()))
+        .await?
+        .iter()
+        .map(|summary| summary.available)
+        .sum::<i64>();
+    live.publish_availability(AvailabilityChanged {
+        tenant_id,
+        vehicle_type,
+        available: available as usize,
+    });
+    Ok(())
+}
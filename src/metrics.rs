@@ -0,0 +1,291 @@
+use std::time::Duration;
+
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::PgPool;
+
+use crate::{
+    admin::ProjectionLag,
+    application::{ApplicationError, ApplicationResult},
+    errors::{is_conflict, is_unavailable, ErrorCode},
+};
+
+/// The metrics of the application, scraped from `/metrics` in the Prometheus text format.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    commands: IntCounterVec,
+    command_duration: HistogramVec,
+    command_attempts: HistogramVec,
+    projection_lag: GaugeVec,
+    projection_pending_events: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let commands = IntCounterVec::new(
+            Opts::new("commands_total", "Commands executed, by command and result")
+                .namespace("car_rental"),
+            &["command", "result", "code"],
+        )
+        .expect("valid metric");
+        let command_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "command_duration_seconds",
+                "Time taken to execute the commands, their retries included",
+            )
+            .namespace("car_rental"),
+            &["command"],
+        )
+        .expect("valid metric");
+        let command_attempts = HistogramVec::new(
+            HistogramOpts::new(
+                "command_attempts",
+                "Attempts made at the decisions of the commands, more than one after conflicts",
+            )
+            .namespace("car_rental")
+            .buckets(vec![1.0, 2.0, 3.0, 5.0, 8.0]),
+            &["command"],
+        )
+        .expect("valid metric");
+        let projection_lag = GaugeVec::new(
+            Opts::new(
+                "projection_lag_seconds",
+                "Age of the oldest event the projections have not processed yet",
+            )
+            .namespace("car_rental"),
+            &["projection"],
+        )
+        .expect("valid metric");
+        let projection_pending_events = IntGaugeVec::new(
+            Opts::new(
+                "projection_pending_events",
+                "Events the projections have not processed yet",
+            )
+            .namespace("car_rental"),
+            &["projection"],
+        )
+        .expect("valid metric");
+        for collector in [
+            Box::new(commands.clone()) as Box<dyn Collector>,
+            Box::new(command_duration.clone()),
+            Box::new(command_attempts.clone()),
+            Box::new(projection_lag.clone()),
+            Box::new(projection_pending_events.clone()),
+        ] {
+            registry.register(collector).expect("registered once");
+        }
+        Self {
+            registry,
+            commands,
+            command_duration,
+            command_attempts,
+            projection_lag,
+            projection_pending_events,
+        }
+    }
+
+    /// Counts the command by the class of its result, `ok`, `domain_error`, `conflict`,
+    /// `timeout`, `unavailable` or `store_error`, along with its error code, and records how long it took.
+    pub fn command<T>(&self, command: &str, result: &ApplicationResult<T>, duration: Duration) {
+        let (class, code) = match result {
+            Ok(_) => ("ok", String::new()),
+            Err(error) => {
+                let class = match error {
+                    ApplicationError::Domain(_) => "domain_error",
+                    ApplicationError::Timeout(_) => "timeout",
+                    error if is_conflict(error) => "conflict",
+                    error if is_unavailable(error) => "unavailable",
+                    _ => "store_error",
+                };
+                (class, ErrorCode::from(error).to_string())
+            }
+        };
+        self.commands
+            .with_label_values(&[command, class, &code])
+            .inc();
+        self.command_duration
+            .with_label_values(&[command])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Records the attempts made at the decision of the command, the conflicts retried plus one.
+    pub fn attempts(&self, command: &str, attempts: u32) {
+        self.command_attempts
+            .with_label_values(&[command])
+            .observe(f64::from(attempts));
+    }
+
+    /// Sets the lag of the projections, as read from `ReadModelRepository::projection_lags`.
+    pub fn projection_lags(&self, lags: &[ProjectionLag]) {
+        for lag in lags {
+            self.projection_lag
+                .with_label_values(&[lag.listener_id])
+                .set(lag.lag_seconds);
+            self.projection_pending_events
+                .with_label_values(&[lag.listener_id])
+                .set(lag.pending_events);
+        }
+    }
+
+    /// Exports the connections of `pools`, by name, as read when scraped.
+    pub fn register_pools(&self, pools: Vec<(&'static str, PgPool)>) {
+        self.registry
+            .register(Box::new(PoolCollector::new(pools)))
+            .expect("registered once");
+    }
+
+    /// Every metric, in the Prometheus text format.
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut text = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut text)?;
+        Ok(String::from_utf8(text).expect("the text format is UTF-8"))
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The connections of the database pools, idle or in use, and how many they may open.
+struct PoolCollector {
+    pools: Vec<(&'static str, PgPool)>,
+    connections: IntGaugeVec,
+    max_connections: IntGaugeVec,
+}
+
+impl PoolCollector {
+    fn new(pools: Vec<(&'static str, PgPool)>) -> Self {
+        let connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Connections of the pools, by pool and state",
+            )
+            .namespace("car_rental"),
+            &["pool", "state"],
+        )
+        .expect("valid metric");
+        let max_connections = IntGaugeVec::new(
+            Opts::new("db_pool_max_connections", "Connections the pools may open")
+                .namespace("car_rental"),
+            &["pool"],
+        )
+        .expect("valid metric");
+        Self {
+            pools,
+            connections,
+            max_connections,
+        }
+    }
+}
+
+impl Collector for PoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = self.connections.desc();
+        descs.extend(self.max_connections.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        for (name, pool) in &self.pools {
+            let idle = pool.num_idle() as i64;
+            self.connections
+                .with_label_values(&[name, "idle"])
+                .set(idle);
+            self.connections
+                .with_label_values(&[name, "in_use"])
+                .set(i64::from(pool.size()) - idle);
+            self.max_connections
+                .with_label_values(&[name])
+                .set(i64::from(pool.options().get_max_connections()));
+        }
+        let mut families = self.connections.collect();
+        families.extend(self.max_connections.collect());
+        families
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_should_class_the_infrastructure_errors_apart() {
+        let metrics = Metrics::new();
+        let failed = |error: ApplicationError| -> ApplicationResult { Err(error) };
+        let conflict =
+            || ApplicationError::EventStore(Box::new(disintegrate_postgres::Error::Concurrency));
+        metrics.command("EndRent", &failed(conflict()), Duration::ZERO);
+        let timeout = ApplicationError::Timeout(Duration::from_secs(1));
+        metrics.command("EndRent", &failed(timeout), Duration::ZERO);
+        metrics.command(
+            "EndRent",
+            &failed(ApplicationError::StateStore("down".into())),
+            Duration::ZERO,
+        );
+        let text = metrics.render().unwrap();
+        for series in [
+            r#"car_rental_commands_total{code="CONCURRENT_MODIFICATION",command="EndRent",result="conflict"} 1"#,
+            r#"car_rental_commands_total{code="TIMEOUT",command="EndRent",result="timeout"} 1"#,
+            r#"car_rental_commands_total{code="STORE_ERROR",command="EndRent",result="store_error"} 1"#,
+        ] {
+            assert!(
+                text.lines().any(|line| line == series),
+                "{series} in {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_export_the_attempts_and_the_projection_lags() {
+        let metrics = Metrics::new();
+        metrics.attempts("StartRent", 1);
+        metrics.attempts("StartRent", 3);
+        metrics.projection_lags(&[ProjectionLag {
+            listener_id: "rental",
+            last_processed_event_id: 4,
+            updated_at: None,
+            latest_event_id: 6,
+            pending_events: 2,
+            lag_seconds: 1.5,
+        }]);
+        let text = metrics.render().unwrap();
+        for series in [
+            r#"car_rental_command_attempts_bucket{command="StartRent",le="1"} 1"#,
+            r#"car_rental_command_attempts_bucket{command="StartRent",le="3"} 2"#,
+            r#"car_rental_command_attempts_sum{command="StartRent"} 4"#,
+            r#"car_rental_projection_lag_seconds{projection="rental"} 1.5"#,
+            r#"car_rental_projection_pending_events{projection="rental"} 2"#,
+        ] {
+            assert!(
+                text.lines().any(|line| line == series),
+                "{series} in {text}"
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_export_the_pool_connections(pool: PgPool) {
+        let metrics = Metrics::new();
+        metrics.register_pools(vec![("read_model", pool.clone())]);
+        let _held = pool.acquire().await.unwrap();
+        let text = metrics.render().unwrap();
+        let max = pool.options().get_max_connections();
+        for series in [
+            r#"car_rental_db_pool_connections{pool="read_model",state="in_use"} 1"#.to_string(),
+            format!(r#"car_rental_db_pool_max_connections{{pool="read_model"}} {max}"#),
+        ] {
+            assert!(
+                text.lines().any(|line| line == series),
+                "{series} in {text}"
+            );
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    error::Error,
+    fmt,
+    future::{ready, Ready},
+    str::FromStr,
+};
+
+use actix_web::{error, web::Query, FromRequest, HttpRequest};
+use async_stream::try_stream;
+use futures_util::{pin_mut, Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+/// Page requested through the `?limit=` and `?offset=` query parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageParams {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl PageParams {
+    pub fn new(limit: Option<i64>, offset: Option<i64>) -> Result<Self, String> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {MAX_LIMIT}"));
+        }
+        let offset = offset.unwrap_or(0);
+        if offset < 0 {
+            return Err("offset must not be negative".to_string());
+        }
+        Ok(Self { limit, offset })
+    }
+}
+
+impl Default for PageParams {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawPageParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl FromRequest for PageParams {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let page = Query::<RawPageParams>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| PageParams::new(params.limit, params.offset))
+            .map_err(error::ErrorBadRequest);
+        ready(page)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(items: Vec<T>, total: i64, page: PageParams) -> Self {
+        Self {
+            items,
+            total,
+            limit: page.limit,
+            offset: page.offset,
+            sort: None,
+        }
+    }
+
+    /// Reports the sort applied to the page, e.g. `startDate:desc`.
+    pub fn sorted_by(mut self, sort: impl ToString) -> Self {
+        self.sort = Some(sort.to_string());
+        self
+    }
+}
+
+/// Number of items a listing would return across all of its pages.
+#[derive(Debug, Serialize)]
+pub struct Count {
+    pub count: i64,
+}
+
+/// Opaque position in a listing, the id of the last item of the previous page, resumed
+/// strictly after it.
+///
+/// It carries a checksum of the id, so that cursors edited or cut short are rejected rather
+/// than resuming elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor(pub i64);
+
+impl Cursor {
+    fn checksum(id: i64) -> u32 {
+        let digest = Sha256::digest(format!("cursor:{id}"));
+        u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}{:08x}", self.0, Self::checksum(self.0))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || "cursor: must be the nextCursor of a previous page".to_string();
+        if s.len() != 24 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let id = u64::from_str_radix(&s[..16], 16).map_err(|_| invalid())? as i64;
+        let checksum = u32::from_str_radix(&s[16..], 16).map_err(|_| invalid())?;
+        if checksum != Self::checksum(id) {
+            return Err(invalid());
+        }
+        Ok(Self(id))
+    }
+}
+
+/// Streams a page of at most `limit` items as `{"items": [...], "nextCursor": ...}`, each item
+/// serialized as it's read.
+///
+/// The next cursor is that of the last item when the page is full, `null` otherwise; it comes
+/// last, as it's only known once every item was read.
+pub fn stream_cursor_page<T, E>(
+    items: impl Stream<Item = Result<T, E>> + 'static,
+    limit: i64,
+    cursor: impl Fn(&T) -> Cursor + 'static,
+) -> impl Stream<Item = Result<Vec<u8>, Box<dyn Error>>> + 'static
+where
+    T: Serialize,
+    E: Error + 'static,
+{
+    try_stream! {
+        yield br#"{"items":["#.to_vec();
+        pin_mut!(items);
+        let (mut count, mut last) = (0, None);
+        while let Some(item) = items.try_next().await? {
+            let mut chunk = if count == 0 { vec![] } else { vec![b','] };
+            serde_json::to_writer(&mut chunk, &item)?;
+            yield chunk;
+            count += 1;
+            last = Some(cursor(&item));
+        }
+        let next_cursor = match last.filter(|_| count == limit) {
+            Some(cursor) => format!(r#""{cursor}""#),
+            None => "null".to_string(),
+        };
+        yield format!(r#"],"nextCursor":{next_cursor}}}"#).into_bytes();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_should_use_defaults_when_no_params_are_given() {
+        assert_eq!(PageParams::new(None, None), Ok(PageParams::default()));
+    }
+
+    #[test]
+    fn it_should_accept_the_max_limit_and_reject_anything_above() {
+        assert_eq!(
+            PageParams::new(Some(MAX_LIMIT), None).unwrap().limit,
+            MAX_LIMIT
+        );
+        assert!(PageParams::new(Some(MAX_LIMIT + 1), None).is_err());
+    }
+
+    #[test]
+    fn it_should_reject_negative_values() {
+        assert!(PageParams::new(Some(-1), None).is_err());
+        assert!(PageParams::new(None, Some(-1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn it_should_stream_the_page_with_the_cursor_of_its_last_item_when_full() {
+        async fn page(items: Vec<i64>, limit: i64) -> serde_json::Value {
+            let items = futures_util::stream::iter(items.into_iter().map(Ok::<_, std::io::Error>));
+            let chunks: Vec<Vec<u8>> = stream_cursor_page(items, limit, |item| Cursor(*item))
+                .try_collect()
+                .await
+                .unwrap();
+            serde_json::from_slice(&chunks.concat()).unwrap()
+        }
+
+        assert_eq!(
+            page(vec![9, 7, 4], 3).await,
+            serde_json::json!({ "items": [9, 7, 4], "nextCursor": Cursor(4).to_string() })
+        );
+        assert_eq!(
+            page(vec![9, 7], 3).await,
+            serde_json::json!({ "items": [9, 7], "nextCursor": null })
+        );
+        assert_eq!(
+            page(vec![], 3).await,
+            serde_json::json!({ "items": [], "nextCursor": null })
+        );
+    }
+
+    #[test]
+    fn it_should_read_back_its_cursors_and_reject_tampered_ones() {
+        for id in [1, 4242, i64::MAX] {
+            assert_eq!(Cursor(id).to_string().parse(), Ok(Cursor(id)));
+        }
+        let cursor = Cursor(4242).to_string();
+        let tampered = format!("{:016x}{}", 4243, &cursor[16..]);
+        for invalid in [
+            &tampered,
+            &cursor[..23],
+            "4242",
+            "",
+            "zzzzzzzzzzzzzzzzzzzzzzzz",
+        ] {
+            assert!(invalid.parse::<Cursor>().is_err(), "{invalid}");
+        }
+    }
+}
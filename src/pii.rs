@@ -0,0 +1,672 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use disintegrate::serde::{Deserializer, Serializer};
+use disintegrate_serde::Error;
+use futures_util::TryStreamExt;
+use hmac::{Hmac, Mac};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::domain::{DomainEvent, Email, TenantId};
+
+/// The fields of the events holding personal data, sealed in their payload.
+const PERSONAL: [&str; 4] = ["customer_id", "first_name", "last_name", "phone"];
+
+/// The names of the customers whose key was shredded.
+pub const ERASED: &str = "[erased]";
+
+/// The key the others are derived from when `PII_ENCRYPTION_KEY` isn't set, with
+/// `APP_ENV=dev` only.
+const DEVELOPMENT_KEY: &[u8; 32] = b"car-rental-development-pii-key!!";
+
+/// A 256-bit key, never logged.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; 32]);
+
+impl Key {
+    /// Parses the 64 hex digits of `PII_ENCRYPTION_KEY`, e.g. from `openssl rand -hex 32`.
+    pub fn parse(hex: &str) -> Result<Self, String> {
+        let invalid = || "PII_ENCRYPTION_KEY: must be 64 hex digits".to_string();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut key = [0; 32];
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(key))
+    }
+
+    /// A key drawn at random.
+    fn random() -> Self {
+        let mut key = [0; 32];
+        SystemRandom::new()
+            .fill(&mut key)
+            .expect("the system random generator should not fail");
+        Self(key)
+    }
+
+    fn hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn mac(&self, label: &str, value: &str) -> [u8; 32] {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC takes keys of any size");
+        mac.update(label.as_bytes());
+        mac.update(b"/");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    fn aead(&self) -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.0).expect("the key is 256 bits"))
+    }
+
+    /// `plaintext` encrypted for `subject`, as the base64 of its random nonce and ciphertext.
+    fn seal(&self, subject: &str, plaintext: &[u8]) -> String {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("the system random generator should not fail");
+        let mut sealed = plaintext.to_vec();
+        self.aead()
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(subject.as_bytes()),
+                &mut sealed,
+            )
+            .expect("the payloads are far smaller than what AES-GCM seals");
+        BASE64.encode([nonce.as_slice(), &sealed].concat())
+    }
+
+    /// The plaintext `seal` encrypted for `subject`, `None` unless it was sealed with this key.
+    fn open(&self, subject: &str, sealed: &str) -> Option<Vec<u8>> {
+        let sealed = BASE64.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        let len = self
+            .aead()
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce).ok()?,
+                Aad::from(subject.as_bytes()),
+                &mut plaintext,
+            )
+            .ok()?
+            .len();
+        plaintext.truncate(len);
+        Some(plaintext)
+    }
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("***")
+    }
+}
+
+/// The key the tokens and the keys of the customers are derived from, set by
+/// `PII_ENCRYPTION_KEY`.
+static MASTER_KEY: OnceLock<Key> = OnceLock::new();
+
+/// Derives the tokens and the keys from `key` rather than from the development one.
+pub fn set_master_key(key: Key) -> Result<(), String> {
+    MASTER_KEY
+        .set(key)
+        .map_err(|_| "PII_ENCRYPTION_KEY: is already set".to_string())
+}
+
+fn master_key() -> &'static Key {
+    MASTER_KEY.get_or_init(|| Key(*DEVELOPMENT_KEY))
+}
+
+/// The token the events of the customer `email` are indexed and queried by: the hex
+/// HMAC-SHA256 of the email, the same every time but telling nothing of it.
+pub fn token(email: &str) -> String {
+    master_key()
+        .mac("token", email)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Where the keys sealing the personal data of each customer come from, e.g. a KMS.
+///
+/// Each customer has a key of their own, the subject being their tenant and the token of their
+/// email, so that shredding it erases their personal data from every event at once.
+pub trait KeyProvider: Send + Sync {
+    /// The key of `subject`, `None` once shredded.
+    fn key(&self, subject: &str) -> Option<Key>;
+}
+
+/// The keys derived from the master key, which can't be shredded one by one.
+pub struct DerivedKeys(Key);
+
+impl KeyProvider for DerivedKeys {
+    fn key(&self, subject: &str) -> Option<Key> {
+        Some(Key(self.0.mac("key", subject)))
+    }
+}
+
+/// The keys of the customers kept in a directory, set by `PII_KEY_DIR`, a file each: drawn at
+/// random the first time a customer needs one, nothing can derive it again once shredded.
+///
+/// The instances sharing the database share the directory, e.g. a mounted volume. It panics
+/// when a file can't be read or written rather than take its customer for shredded.
+pub struct KeyStore {
+    dir: PathBuf,
+}
+
+impl KeyStore {
+    pub fn open(dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// The file of the key of `subject`, named by its hash as the tenants may hold a `/`.
+    fn path(&self, subject: &str) -> PathBuf {
+        let hash = Sha256::digest(subject.as_bytes());
+        let name: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        self.dir.join(name)
+    }
+
+    /// Writes `contents` to a file of its own, then moves it to `path`, so that the key is
+    /// never read half written.
+    fn write(&self, path: &Path, contents: &str, replace: bool) -> std::io::Result<()> {
+        let temporary = self.dir.join(format!(".{}", Key::random().hex()));
+        std::fs::write(&temporary, contents)?;
+        let written = if replace {
+            std::fs::rename(&temporary, path)
+        } else {
+            // Fails when another instance drew the key first.
+            std::fs::hard_link(&temporary, path)
+        };
+        std::fs::remove_file(&temporary).or_else(|err| match err.kind() {
+            ErrorKind::NotFound => Ok(()),
+            _ => Err(err),
+        })?;
+        written
+    }
+
+    /// Shreds the key of the customer `email` of the tenant, erasing their personal data from
+    /// every event, those recorded after included.
+    pub fn shred(&self, tenant_id: &str, email: &str) -> std::io::Result<()> {
+        let subject = format!("{tenant_id}/{}", token(email));
+        self.write(&self.path(&subject), "", true)
+    }
+}
+
+impl KeyProvider for KeyStore {
+    fn key(&self, subject: &str) -> Option<Key> {
+        let path = self.path(subject);
+        loop {
+            match std::fs::read_to_string(&path) {
+                // Emptied when shredded.
+                Ok(hex) if hex.is_empty() => return None,
+                Ok(hex) => {
+                    let key = Key::parse(&hex);
+                    return Some(key.unwrap_or_else(|_| panic!("{}: is no key", path.display())));
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    match self.write(&path, &Key::random().hex(), false) {
+                        Err(err) if err.kind() != ErrorKind::AlreadyExists => {
+                            panic!("{}: {err}", path.display())
+                        }
+                        _ => continue,
+                    }
+                }
+                Err(err) => panic!("{}: {err}", path.display()),
+            }
+        }
+    }
+}
+
+/// The keys the personal data is sealed with, the ones derived from the master key unless set.
+static KEYS: OnceLock<Arc<dyn KeyProvider>> = OnceLock::new();
+
+/// Seals the personal data with the keys of `keys` rather than with the derived ones.
+pub fn set_key_provider(keys: Arc<dyn KeyProvider>) -> Result<(), String> {
+    KEYS.set(keys)
+        .map_err(|_| "PII_KEY_DIR: is already set".to_string())
+}
+
+/// The personal fields of an event, encrypted for the customer they belong to; `data` is
+/// missing when the key of the customer was shredded before the event was recorded.
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    subject: String,
+    #[serde(default)]
+    data: Option<String>,
+}
+
+/// Serializes the events as JSON, their personal fields sealed with AES-256-GCM under the key
+/// of their customer in a `sealed` field.
+///
+/// The events recorded in plaintext, before sealing, are read as they are; those of a customer
+/// whose key was shredded are read with their email replaced by its token and their names by
+/// `ERASED`.
+#[derive(Clone)]
+pub struct EncryptedJson {
+    keys: Arc<dyn KeyProvider>,
+}
+
+impl EncryptedJson {
+    pub fn new(keys: Arc<dyn KeyProvider>) -> Self {
+        Self { keys }
+    }
+
+    /// Seals with the keys derived from the master key.
+    fn derived() -> Self {
+        Self::new(Arc::new(DerivedKeys(master_key().clone())))
+    }
+}
+
+impl Default for EncryptedJson {
+    /// Seals with the keys set by `set_key_provider`, the derived ones otherwise.
+    fn default() -> Self {
+        match KEYS.get() {
+            Some(keys) => Self::new(keys.clone()),
+            None => Self::derived(),
+        }
+    }
+}
+
+/// The customer of `event`, `None` for the events of no customer.
+fn customer(event: &DomainEvent) -> Option<(&TenantId, &Email)> {
+    match event {
+        DomainEvent::CustomerRegistered {
+            tenant_id,
+            customer_id,
+            ..
+        }
+        | DomainEvent::VehicleRented {
+            tenant_id,
+            customer_id,
+            ..
+        }
+        | DomainEvent::VehicleReturned {
+            tenant_id,
+            customer_id,
+            ..
+        } => Some((tenant_id, customer_id)),
+        DomainEvent::VehicleAdded { .. } => None,
+    }
+}
+
+/// The key subject of the customer of `event`: their tenant and the token of their email.
+fn subject(event: &DomainEvent) -> Option<String> {
+    customer(event).map(|(tenant_id, customer_id)| format!("{tenant_id}/{}", token(customer_id)))
+}
+
+/// The fields of the externally tagged `value`, e.g. `{"VehicleRented": {...}}`.
+fn fields(value: &mut Value) -> Option<&mut Map<String, Value>> {
+    value.as_object_mut()?.values_mut().next()?.as_object_mut()
+}
+
+impl Serializer<DomainEvent> for EncryptedJson {
+    fn serialize(&self, event: DomainEvent) -> Vec<u8> {
+        let subject = subject(&event);
+        let mut value = serde_json::to_value(&event).expect("json serialization should not fail");
+        if let (Some(subject), Some(fields)) = (subject, fields(&mut value)) {
+            let personal: Map<String, Value> = PERSONAL
+                .iter()
+                .filter_map(|name| Some((name.to_string(), fields.remove(*name)?)))
+                .collect();
+            let plaintext =
+                serde_json::to_vec(&personal).expect("json serialization should not fail");
+            let data = self
+                .keys
+                .key(&subject)
+                .map(|key| key.seal(&subject, &plaintext));
+            let sealed = Sealed { subject, data };
+            fields.insert(
+                "sealed".to_string(),
+                serde_json::to_value(sealed).expect("json serialization should not fail"),
+            );
+        }
+        serde_json::to_vec(&value).expect("json serialization should not fail")
+    }
+}
+
+impl Deserializer<DomainEvent> for EncryptedJson {
+    fn deserialize(&self, data: Vec<u8>) -> Result<DomainEvent, Error> {
+        let invalid = |err: String| Error::Deserialization(err.into());
+        let mut value: Value =
+            serde_json::from_slice(&data).map_err(|e| Error::Deserialization(Box::new(e)))?;
+        if let Some(fields) = fields(&mut value) {
+            if let Some(sealed) = fields.remove("sealed") {
+                let sealed: Sealed = serde_json::from_value(sealed)
+                    .map_err(|e| Error::Deserialization(Box::new(e)))?;
+                let key = self.keys.key(&sealed.subject);
+                let personal = match (key, &sealed.data) {
+                    (Some(key), Some(data)) => {
+                        let plaintext = key.open(&sealed.subject, data).ok_or_else(|| {
+                            invalid(format!("the data of {} can't be opened", sealed.subject))
+                        })?;
+                        serde_json::from_slice(&plaintext)
+                            .map_err(|e| Error::Deserialization(Box::new(e)))?
+                    }
+                    _ => erased(&sealed.subject),
+                };
+                fields.extend(personal);
+            }
+        }
+        serde_json::from_value(value).map_err(|e| Error::Deserialization(Box::new(e)))
+    }
+}
+
+/// The personal fields of a customer whose key was shredded.
+fn erased(subject: &str) -> Map<String, Value> {
+    let token = subject.rsplit('/').next().unwrap_or(subject);
+    [
+        ("customer_id", Value::from(token)),
+        ("first_name", Value::from(ERASED)),
+        ("last_name", Value::from(ERASED)),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+/// Seals the events recorded in plaintext, before sealing, and those sealed under the keys
+/// derived from the master key when `serde` seals under others, e.g. the ones of a
+/// `KeyStore`, returning how many were sealed.
+///
+/// A migration of its own, run once by `seal-events` before serving with the keys of `serde`:
+/// the payloads are rewritten in place. The plaintext events are indexed by the token of their
+/// customer rather than by their email, as is the index of their sequence, so that the
+/// decisions keep finding them. The events `serde` opens already are left alone.
+///
+/// The snapshots aren't sealed, their states and queries holding the emails in plaintext: they
+/// are pruned instead, the decisions folding the sealed events again.
+pub async fn seal_events(pool: &PgPool, serde: &EncryptedJson) -> Result<u64, sqlx::Error> {
+    let derived = EncryptedJson::derived();
+    let mut sealed = 0;
+    let mut tx = pool.begin().await?;
+    let mut rows = sqlx::query_as::<_, (i64, Vec<u8>, String)>(
+        r#"SELECT event_id, payload, customer_id FROM public.event
+            WHERE customer_id IS NOT NULL ORDER BY event_id"#,
+    )
+    .fetch(pool);
+    while let Some((event_id, payload, customer_id)) = rows.try_next().await? {
+        // The emails are the only identifiers with an `@`.
+        let plaintext = customer_id.contains('@');
+        if !plaintext && serde.deserialize(payload.clone()).is_ok() {
+            continue;
+        }
+        let event = derived
+            .deserialize(payload)
+            .map_err(|err| sqlx::Error::Decode(Box::new(err)))?;
+        let Some(customer_id) = customer(&event).map(|(_, email)| token(email)) else {
+            continue;
+        };
+        sqlx::query("UPDATE public.event SET payload = $2, customer_id = $3 WHERE event_id = $1")
+            .bind(event_id)
+            .bind(serde.serialize(event))
+            .bind(customer_id)
+            .execute(&mut *tx)
+            .await?;
+        sealed += 1;
+    }
+    let emails: Vec<String> = sqlx::query_scalar(
+        "SELECT DISTINCT customer_id FROM public.event_sequence WHERE customer_id LIKE '%@%'",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for email in emails {
+        sqlx::query("UPDATE public.event_sequence SET customer_id = $2 WHERE customer_id = $1")
+            .bind(&email)
+            .bind(token(&email))
+            .execute(&mut *tx)
+            .await?;
+    }
+    let pruned =
+        sqlx::query("DELETE FROM public.snapshot WHERE query LIKE '%@%' OR payload LIKE '%@%'")
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    if pruned > 0 {
+        tracing::info!(pruned, "pruned the snapshots holding plaintext emails");
+    }
+    tx.commit().await?;
+    Ok(sealed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::{default_tenant, VehicleType, DEFAULT_TENANT};
+    use chrono::Utc;
+    use disintegrate::{serde::json::Json, EventStore as _};
+    use disintegrate_postgres::{PgEventStore, PgSnapshotter};
+
+    fn registered() -> DomainEvent {
+        DomainEvent::CustomerRegistered {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: Some("+39021234567".to_string()),
+        }
+    }
+
+    /// Every key shredded.
+    struct Shredded;
+
+    /// A key store in a directory of its own, emptied.
+    fn key_store(name: &str) -> KeyStore {
+        let dir = std::env::temp_dir().join(format!("car-rental-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        KeyStore::open(&dir).unwrap()
+    }
+
+    fn registered_as(email: &str, first_name: &str) -> DomainEvent {
+        DomainEvent::CustomerRegistered {
+            tenant_id: default_tenant(),
+            customer_id: email.into(),
+            first_name: first_name.to_string(),
+            last_name: "Rossi".to_string(),
+            phone: None,
+        }
+    }
+
+    impl KeyProvider for Shredded {
+        fn key(&self, _: &str) -> Option<Key> {
+            None
+        }
+    }
+
+    #[test]
+    fn it_should_seal_the_personal_fields_of_the_events() {
+        let serde = EncryptedJson::default();
+        let rented = DomainEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Van,
+            start_date: Utc::now(),
+        };
+        let added = DomainEvent::VehicleAdded {
+            tenant_id: default_tenant(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Van,
+            seats: None,
+            transmission: None,
+        };
+        for event in [registered(), rented, added] {
+            let payload = serde.serialize(event.clone());
+            let raw = String::from_utf8(payload.clone()).unwrap();
+            for plaintext in ["mario@example.com", "Mario", "Rossi", "+39021234567"] {
+                assert!(!raw.contains(plaintext), "{raw}");
+            }
+            assert_eq!(serde.deserialize(payload).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn it_should_read_the_plaintext_events_and_erase_the_shredded_ones() {
+        let serde = EncryptedJson::default();
+        let plaintext = serde_json::to_vec(&registered()).unwrap();
+        assert_eq!(serde.deserialize(plaintext).unwrap(), registered());
+
+        let payload = serde.serialize(registered());
+        let erased = EncryptedJson::new(Arc::new(Shredded))
+            .deserialize(payload)
+            .unwrap();
+        assert_eq!(
+            erased,
+            DomainEvent::CustomerRegistered {
+                tenant_id: default_tenant(),
+                customer_id: token("mario@example.com").into(),
+                first_name: ERASED.to_string(),
+                last_name: ERASED.to_string(),
+                phone: None,
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_erase_the_customer_whose_key_is_shredded() {
+        let keys = key_store("shred");
+        let dir = keys.dir.clone();
+        let serde = EncryptedJson::new(Arc::new(keys));
+        let (mario, luigi) = (
+            registered_as("mario@example.com", "Mario"),
+            registered_as("luigi@example.com", "Luigi"),
+        );
+        let payloads = [
+            serde.serialize(mario.clone()),
+            serde.serialize(luigi.clone()),
+        ];
+        assert_eq!(serde.deserialize(payloads[0].clone()).unwrap(), mario);
+
+        KeyStore::open(&dir)
+            .unwrap()
+            .shred(DEFAULT_TENANT, "mario@example.com")
+            .unwrap();
+        let erased = DomainEvent::CustomerRegistered {
+            tenant_id: default_tenant(),
+            customer_id: token("mario@example.com").into(),
+            first_name: ERASED.to_string(),
+            last_name: ERASED.to_string(),
+            phone: None,
+        };
+        assert_eq!(serde.deserialize(payloads[0].clone()).unwrap(), erased);
+        assert_eq!(serde.deserialize(payloads[1].clone()).unwrap(), luigi);
+        let recorded_after = serde.serialize(mario);
+        assert_eq!(serde.deserialize(recorded_after).unwrap(), erased);
+        // The instances sharing the directory.
+        let other = EncryptedJson::new(Arc::new(KeyStore::open(&dir).unwrap()));
+        assert_eq!(other.deserialize(payloads[1].clone()).unwrap(), luigi);
+    }
+
+    #[test]
+    fn it_should_tell_the_customers_apart_by_their_token() {
+        assert_eq!(token("mario@example.com"), token("mario@example.com"));
+        assert_ne!(token("mario@example.com"), token("luigi@example.com"));
+        assert_eq!(token("mario@example.com").len(), 64);
+        assert!(Key::parse(&"ab".repeat(32)).is_ok());
+        assert!(Key::parse("abcd").is_err());
+        assert!(Key::parse(&"zz".repeat(32)).is_err());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_seal_the_events_recorded_in_plaintext(pool: PgPool) {
+        let plaintext = PgEventStore::new(pool.clone(), Json::<DomainEvent>::default())
+            .await
+            .unwrap();
+        PgSnapshotter::new(pool.clone(), 1).await.unwrap();
+        sqlx::query(
+            r#"INSERT INTO snapshot (id, name, query, version, payload) VALUES
+                (gen_random_uuid(), 'customer', '{"customer_id":"mario@example.com"}', 1, '{}'),
+                (gen_random_uuid(), 'availability', '{"vehicle_type":"Van"}', 1, '{}')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        plaintext
+            .append(vec![registered()], disintegrate::query!(DomainEvent), 0)
+            .await
+            .unwrap();
+        // Indexed by the email itself, as before the tokens.
+        for table in ["event", "event_sequence"] {
+            sqlx::query(&format!(
+                "UPDATE {table} SET customer_id = 'mario@example.com'"
+            ))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let serde = EncryptedJson::default();
+        assert_eq!(seal_events(&pool, &serde).await.unwrap(), 1);
+        assert_eq!(seal_events(&pool, &serde).await.unwrap(), 0);
+        let row: String = sqlx::query_scalar(
+            r#"SELECT concat_ws(' ', e.customer_id, s.customer_id, convert_from(e.payload, 'UTF8'))
+                FROM event e JOIN event_sequence s USING (event_id)"#,
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        for plaintext in ["mario@example.com", "Mario", "Rossi", "+39021234567"] {
+            assert!(!row.contains(plaintext), "{row}");
+        }
+        let snapshots: Vec<String> = sqlx::query_scalar("SELECT name FROM snapshot")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(snapshots, ["availability"]);
+        let event_store = PgEventStore::new(pool, serde).await.unwrap();
+        let customer_id = Email::from("mario@example.com");
+        let events: Vec<DomainEvent> = event_store
+            .stream(&disintegrate::query!(
+                DomainEvent,
+                customer_id == customer_id
+            ))
+            .map_ok(|event| event.into_inner())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(events, [registered()]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_seal_the_events_sealed_under_the_derived_keys_again(pool: PgPool) {
+        let derived = PgEventStore::new(pool.clone(), EncryptedJson::default())
+            .await
+            .unwrap();
+        PgSnapshotter::new(pool.clone(), 1).await.unwrap();
+        derived
+            .append(vec![registered()], disintegrate::query!(DomainEvent), 0)
+            .await
+            .unwrap();
+
+        let serde = EncryptedJson::new(Arc::new(key_store("seal")));
+        assert_eq!(seal_events(&pool, &serde).await.unwrap(), 1);
+        assert_eq!(seal_events(&pool, &serde).await.unwrap(), 0);
+        let event_store = PgEventStore::new(pool, serde).await.unwrap();
+        let events: Vec<DomainEvent> = event_store
+            .stream(&disintegrate::query!(DomainEvent))
+            .map_ok(|event| event.into_inner())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(events, [registered()]);
+    }
+}
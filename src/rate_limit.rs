@@ -0,0 +1,263 @@
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header::RETRY_AFTER, Method},
+    HttpMessage, HttpResponse,
+};
+use async_trait::async_trait;
+use futures_util::future::LocalBoxFuture;
+
+use crate::{
+    auth::{is_admin, Principal},
+    errors::{ErrorBody, ErrorCode},
+};
+
+/// Requests allowed per client in a window, replenished steadily over it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl Quota {
+    pub const fn per_minute(requests: u32) -> Self {
+        Self {
+            requests,
+            per: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Routes sharing a quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    Commands,
+    Reads,
+    /// The admin routes, whatever their method.
+    Admin,
+}
+
+/// Keeps the token buckets of the clients.
+///
+/// Buckets are kept in memory by `InMemoryRateLimiter`, which is enough for a single instance;
+/// instances sharing their quotas need a shared store instead.
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// Takes a token from the bucket of the client, or tells how long until one is available.
+    async fn acquire(&self, group: RouteGroup, client: &str, quota: Quota) -> Result<(), Duration>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryRateLimiter {
+    buckets: Mutex<HashMap<(RouteGroup, String), Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+#[async_trait]
+impl RateLimiter for InMemoryRateLimiter {
+    async fn acquire(&self, group: RouteGroup, client: &str, quota: Quota) -> Result<(), Duration> {
+        let now = Instant::now();
+        let capacity = quota.requests as f64;
+        let rate = capacity / quota.per.as_secs_f64();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry((group, client.to_string()))
+            .or_insert(Bucket {
+                tokens: capacity,
+                refilled_at: now,
+            });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.refilled_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Quotas of the route groups and the limiter enforcing them.
+///
+/// Clients are told apart by API key, or by IP address when they have none. The probes and the
+/// metrics, scraped by the monitoring, aren't limited.
+#[derive(Clone)]
+pub struct RateLimits {
+    pub commands: Quota,
+    pub reads: Quota,
+    pub admin: Quota,
+    pub limiter: Arc<dyn RateLimiter>,
+}
+
+impl RateLimits {
+    pub const DEFAULT_COMMANDS: Quota = Quota::per_minute(60);
+    pub const DEFAULT_READS: Quota = Quota::per_minute(600);
+    pub const DEFAULT_ADMIN: Quota = Quota::per_minute(30);
+
+    pub fn in_memory(commands: Quota, reads: Quota, admin: Quota) -> Self {
+        Self {
+            commands,
+            reads,
+            admin,
+            limiter: Arc::new(InMemoryRateLimiter::default()),
+        }
+    }
+
+    fn group(req: &ServiceRequest) -> Option<RouteGroup> {
+        if matches!(req.path(), "/healthz" | "/readyz" | "/metrics") {
+            None
+        } else if is_admin(req.path()) {
+            Some(RouteGroup::Admin)
+        } else if req.method() == Method::GET || req.method() == Method::HEAD {
+            Some(RouteGroup::Reads)
+        } else {
+            Some(RouteGroup::Commands)
+        }
+    }
+
+    fn quota(&self, group: RouteGroup) -> Quota {
+        match group {
+            RouteGroup::Commands => self.commands,
+            RouteGroup::Reads => self.reads,
+            RouteGroup::Admin => self.admin,
+        }
+    }
+}
+
+/// Middleware answering `429 Too Many Requests` once a client used up the quota of the route
+/// group, with `Retry-After` telling when the next request is allowed.
+impl<S> Transform<S, ServiceRequest> for RateLimits
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limits: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limits: RateLimits,
+}
+
+impl<S> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = actix_web::Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let Some(group) = RateLimits::group(&req) else {
+            return Box::pin(self.service.call(req));
+        };
+        let client = match req.extensions().get::<Principal>() {
+            Some(Principal(name)) => format!("key:{name}"),
+            None => req
+                .peer_addr()
+                .map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string()),
+        };
+        let (service, limits) = (self.service.clone(), self.limits.clone());
+        Box::pin(async move {
+            let quota = limits.quota(group);
+            match limits.limiter.acquire(group, &client, quota).await {
+                Ok(()) => service.call(req).await,
+                Err(retry_after) => Ok(req.into_response(too_many_requests(retry_after))),
+            }
+        })
+    }
+}
+
+fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((
+            RETRY_AFTER,
+            retry_after.as_secs_f64().ceil().max(1.0).to_string(),
+        ))
+        .json(ErrorBody::new(
+            ErrorCode::RateLimited,
+            "too many requests, retry later".to_string(),
+        ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    #[actix_web::test]
+    async fn it_should_limit_each_client_until_its_bucket_refills() {
+        let quota = Quota {
+            requests: 2,
+            per: Duration::from_millis(300),
+        };
+        let limits = RateLimits::in_memory(quota, quota, Quota::per_minute(30));
+        let service = test::init_service(
+            App::new()
+                .wrap(limits)
+                .route("/rent/start", web::post().to(HttpResponse::Ok))
+                .route("/vehicles", web::get().to(HttpResponse::Ok))
+                .route("/healthz", web::get().to(HttpResponse::Ok))
+                .route("/metrics", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = |method: Method, uri: &str, ip: &str| {
+            test::TestRequest::default()
+                .method(method)
+                .uri(uri)
+                .peer_addr(format!("{ip}:40000").parse().unwrap())
+                .to_request()
+        };
+        let start = |ip: &str| request(Method::POST, "/rent/start", ip);
+
+        for _ in 0..2 {
+            let response = test::call_service(&service, start("10.0.0.1")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = test::call_service(&service, start("10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "1");
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "RATE_LIMITED");
+
+        let response = test::call_service(&service, start("10.0.0.2")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response =
+            test::call_service(&service, request(Method::GET, "/vehicles", "10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for uri in ["/healthz", "/metrics"].repeat(5) {
+            let response =
+                test::call_service(&service, request(Method::GET, uri, "10.0.0.1")).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+
+        tokio::time::sleep(quota.per).await;
+        let response = test::call_service(&service, start("10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,118 +1,1146 @@
-use crate::domain::DomainEvent;
+pub mod memory;
+pub mod queries;
+pub mod repository;
+
+use crate::{
+    dead_letter,
+    domain::{CustomerActivity, RentEvent, VehicleType},
+    reporting::{self, ErrorReporter},
+};
 use async_trait::async_trait;
 
-use disintegrate::{query, EventListener, PersistedEvent, StreamQuery};
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use disintegrate::{query, Event, EventListener, PersistedEvent, StreamQuery};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool,
+};
+use std::{fmt::Display, str::FromStr, sync::Arc};
+
+/// Schema holding the read model tables, set by `READ_MODEL_SCHEMA`.
+///
+/// The event store stays in `public`, so instances of the application can keep their read
+/// models apart in a single database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadModelSchema(String);
+
+impl Default for ReadModelSchema {
+    fn default() -> Self {
+        Self("read_model".to_string())
+    }
+}
+
+impl FromStr for ReadModelSchema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = s.len() <= 63
+            && s.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+            && s.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid || s == "public" {
+            return Err(format!(
+                "READ_MODEL_SCHEMA: must be a lowercase identifier other than public, got {s}"
+            ));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
 
-pub struct ReadModelProjection {
-    query: StreamQuery<DomainEvent>,
+impl std::fmt::Display for ReadModelSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ReadModelSchema {
+    /// Connects to the read model through a pool of `pool`, creating its schema if needed.
+    ///
+    /// Unqualified names are looked up in the schema first, then in `public`, where the event
+    /// store and the listener checkpoints are.
+    pub async fn connect(
+        &self,
+        pool: PgPoolOptions,
+        options: PgConnectOptions,
+    ) -> Result<PgPool, sqlx::Error> {
+        let search_path = format!("{},public", self.0);
+        let pool = pool
+            .connect_with(options.options([("search_path", search_path)]))
+            .await?;
+        sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", self.0))
+            .execute(&pool)
+            .await?;
+        Ok(pool)
+    }
+}
+
+/// Projects customers into the `customer` table.
+///
+/// It follows their rentals too, for the id of the last event of each customer.
+pub struct CustomerProjection {
+    query: StreamQuery<CustomerActivity>,
     pool: PgPool,
+    reporter: Arc<dyn ErrorReporter>,
 }
 
-impl ReadModelProjection {
-    pub async fn new(pool: PgPool) -> Result<Self, sqlx::Error> {
-        sqlx::query(
-            r#"CREATE TABLE IF NOT EXISTS vehicle (
-                vehicle_id TEXT PRIMARY KEY,
-                vehicle_type TEXT
-            )"#,
-        )
-        .execute(&pool)
-        .await?;
+impl CustomerProjection {
+    pub const ID: &'static str = "drive_me_crazy_customers";
+    pub const TABLES: &'static [&'static str] = &["customer"];
 
-        sqlx::query(
-            r#"CREATE TABLE IF NOT EXISTS customer (
-                customer_id TEXT PRIMARY KEY,
-                first_name TEXT,
-                last_name TEXT
-            )"#,
-        )
-        .execute(&pool)
-        .await?;
-        sqlx::query(
-            r#"CREATE TABLE IF NOT EXISTS rent (
-                customer_id TEXT,
-                vehicle_id TEXT,
-                start_date timestamptz, 
-                end_date timestamptz NULL,
-                PRIMARY KEY(customer_id, vehicle_id)
-            )"#,
-        )
-        .execute(&pool)
-        .await?;
-        Ok(Self {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
             query: query(None),
             pool,
-        })
+            reporter: reporting::noop(),
+        }
+    }
+
+    /// Reports the events set aside as dead letters to `reporter`.
+    pub fn with_error_reporter(mut self, reporter: Arc<dyn ErrorReporter>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    async fn apply(&self, event_id: i64, event: CustomerActivity) -> Result<(), sqlx::Error> {
+        match event {
+            CustomerActivity::CustomerRegistered {
+                tenant_id,
+                customer_id,
+                first_name,
+                last_name,
+                phone,
+            } => {
+                sqlx::query!(
+                    "INSERT INTO customer (tenant_id, customer_id, first_name, last_name, phone, last_event_id) VALUES($1, $2, $3, $4, $5, $6) ON CONFLICT (tenant_id, customer_id) DO NOTHING",
+                    tenant_id,
+                    customer_id.as_str(),
+                    first_name,
+                    last_name,
+                    phone,
+                    event_id,
+                )
+                .execute(&self.pool)
+                .await?;
+            }
+            CustomerActivity::VehicleRented {
+                tenant_id,
+                customer_id,
+                ..
+            }
+            | CustomerActivity::VehicleReturned {
+                tenant_id,
+                customer_id,
+                ..
+            } => {
+                sqlx::query!(
+                    "UPDATE customer SET last_event_id = $3 WHERE tenant_id = $1 AND customer_id = $2 AND last_event_id < $3",
+                    tenant_id,
+                    customer_id.as_str(),
+                    event_id,
+                )
+                .execute(&self.pool)
+                .await?;
+            }
+        };
+        Ok(())
     }
 }
 
 #[async_trait]
-impl EventListener<DomainEvent> for ReadModelProjection {
+impl EventListener<CustomerActivity> for CustomerProjection {
     type Error = sqlx::Error;
     fn id(&self) -> &'static str {
-        "drive_me_crazy_rentals"
+        Self::ID
     }
 
-    fn query(&self) -> &StreamQuery<DomainEvent> {
+    fn query(&self) -> &StreamQuery<CustomerActivity> {
         &self.query
     }
 
-    async fn handle(&self, event: PersistedEvent<DomainEvent>) -> Result<(), Self::Error> {
-        match event.into_inner() {
-            DomainEvent::CustomerRegistered {
-                customer_id,
-                first_name,
-                last_name,
-            } =>  sqlx::query(
-                    "INSERT INTO customer (customer_id, first_name, last_name) VALUES($1, $2, $3)",
-                )
-                .bind(customer_id)
-                .bind(first_name)
-                .bind(last_name)
-                .execute(&self.pool)
-                .await
-                .unwrap(),
-            DomainEvent::VehicleAdded {
+    #[tracing::instrument(skip_all, fields(listener_id = self.id(), event_id = event.id(), event_type = event.name()))]
+    async fn handle(&self, event: PersistedEvent<CustomerActivity>) -> Result<(), Self::Error> {
+        let (event_id, event_type) = (event.id(), event.name());
+        let result = self.apply(event_id, event.into_inner()).await;
+        dead_letter::settle(
+            &self.pool,
+            &*self.reporter,
+            self.id(),
+            event_id,
+            event_type,
+            result,
+        )
+        .await
+    }
+}
+
+/// Projects vehicles, along with their rental status, into the `vehicle` table.
+///
+/// It follows the rent events rather than the vehicle ones, as rentals move vehicles in and
+/// out of the fleet.
+pub struct VehicleProjection {
+    query: StreamQuery<RentEvent>,
+    pool: PgPool,
+    reporter: Arc<dyn ErrorReporter>,
+}
+
+impl VehicleProjection {
+    pub const ID: &'static str = "drive_me_crazy_vehicles";
+    pub const TABLES: &'static [&'static str] = &["vehicle"];
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            query: query(None),
+            pool,
+            reporter: reporting::noop(),
+        }
+    }
+
+    /// Reports the events set aside as dead letters to `reporter`.
+    pub fn with_error_reporter(mut self, reporter: Arc<dyn ErrorReporter>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    async fn apply(&self, event_id: i64, event: RentEvent) -> Result<(), sqlx::Error> {
+        match event {
+            RentEvent::VehicleAdded {
+                tenant_id,
                 vehicle_id,
                 vehicle_type,
-            } => sqlx::query(
-                    "INSERT INTO vehicle (vehicle_id, vehicle_type) VALUES($1, $2)",
+                seats,
+                transmission,
+            } => {
+                // Events carry no timestamp, the registration date is the one recorded by the
+                // event store.
+                sqlx::query!(
+                    r#"INSERT INTO vehicle (tenant_id, vehicle_id, vehicle_type, last_event_id, registered_at, seats, transmission)
+                        VALUES($1, $2, $3, $4, coalesce((SELECT inserted_at FROM event WHERE event_id = $4), now()), $5, $6)
+                        ON CONFLICT (tenant_id, vehicle_id) DO NOTHING"#,
+                    tenant_id,
+                    vehicle_id,
+                    vehicle_type as VehicleType,
+                    event_id,
+                    seats.map(i16::from),
+                    transmission.map(|transmission| transmission.to_string()),
                 )
-                .bind(vehicle_id)
-                .bind(vehicle_type.to_string())
                 .execute(&self.pool)
-                .await
-                .unwrap(),
-            DomainEvent::VehicleRented {
+                .await?;
+            }
+            RentEvent::VehicleRented {
+                tenant_id,
                 customer_id,
                 vehicle_id,
                 vehicle_type: _,
                 start_date,
-            } => sqlx::query(
-                    "INSERT INTO rent (customer_id, vehicle_id, start_date) VALUES($1, $2, $3)",
+            } => {
+                self.update_rental(
+                    &tenant_id,
+                    &vehicle_id,
+                    VehicleStatus::Rented,
+                    Some((&customer_id, start_date)),
+                    event_id,
                 )
-                .bind(customer_id)
-                .bind(vehicle_id)
-                .bind(start_date)
-                .execute(&self.pool)
-                .await
-                .unwrap(),
-            DomainEvent::VehicleReturned {
+                .await?;
+            }
+            RentEvent::VehicleReturned {
+                tenant_id,
+                vehicle_id,
+                ..
+            } => {
+                self.update_rental(
+                    &tenant_id,
+                    &vehicle_id,
+                    VehicleStatus::Available,
+                    None,
+                    event_id,
+                )
+                .await?;
+            }
+        };
+        Ok(())
+    }
+
+    /// Moves the vehicle to `status` and records its current renter, if any, unless a later
+    /// event has already been applied to it.
+    ///
+    /// The renter is overwritten rather than compared, so a return always clears it.
+    async fn update_rental(
+        &self,
+        tenant_id: &str,
+        vehicle_id: &str,
+        status: VehicleStatus,
+        renter: Option<(&str, DateTime<Utc>)>,
+        event_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        let (renter_email, rented_since) = renter.unzip();
+        sqlx::query!(
+            r#"UPDATE vehicle SET status = $3, current_renter_email = $4, rented_since = $5, last_event_id = $6
+                WHERE tenant_id = $1 AND vehicle_id = $2 AND last_event_id < $6"#,
+            tenant_id,
+            vehicle_id,
+            status.to_string(),
+            renter_email,
+            rented_since,
+            event_id,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventListener<RentEvent> for VehicleProjection {
+    type Error = sqlx::Error;
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn query(&self) -> &StreamQuery<RentEvent> {
+        &self.query
+    }
+
+    #[tracing::instrument(skip_all, fields(listener_id = self.id(), event_id = event.id(), event_type = event.name()))]
+    async fn handle(&self, event: PersistedEvent<RentEvent>) -> Result<(), Self::Error> {
+        let (event_id, event_type) = (event.id(), event.name());
+        let result = self.apply(event_id, event.into_inner()).await;
+        dead_letter::settle(
+            &self.pool,
+            &*self.reporter,
+            self.id(),
+            event_id,
+            event_type,
+            result,
+        )
+        .await
+    }
+}
+
+/// Projects rentals into the `rent` table.
+pub struct RentalProjection {
+    query: StreamQuery<RentEvent>,
+    pool: PgPool,
+    reporter: Arc<dyn ErrorReporter>,
+}
+
+impl RentalProjection {
+    pub const ID: &'static str = "drive_me_crazy_rentals";
+    pub const TABLES: &'static [&'static str] = &["rent"];
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            query: query(None),
+            pool,
+            reporter: reporting::noop(),
+        }
+    }
+
+    /// Reports the events set aside as dead letters to `reporter`.
+    pub fn with_error_reporter(mut self, reporter: Arc<dyn ErrorReporter>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    async fn apply(&self, event_id: i64, event: RentEvent) -> Result<(), sqlx::Error> {
+        match event {
+            RentEvent::VehicleAdded { .. } => {}
+            RentEvent::VehicleRented {
+                tenant_id,
                 customer_id,
                 vehicle_id,
                 vehicle_type: _,
-                returned_date,
-            } => sqlx::query(
-                    "UPDATE rent SET end_date = $3 where customer_id = $1 and vehicle_id = $2 and end_date is null",
+                start_date,
+            } => {
+                sqlx::query!(
+                    "INSERT INTO rent (rent_id, tenant_id, customer_id, vehicle_id, start_date) VALUES($5, $1, $2, $3, $4) ON CONFLICT (rent_id) DO NOTHING",
+                    tenant_id,
+                    customer_id.as_str(),
+                    vehicle_id,
+                    start_date,
+                    event_id,
                 )
-                .bind(customer_id)
-                .bind(vehicle_id)
-                .bind(returned_date)
                 .execute(&self.pool)
-                .await
-                .unwrap(),
+                .await?;
+            }
+            RentEvent::VehicleReturned {
+                tenant_id,
+                customer_id,
+                vehicle_id,
+                returned_date,
+                ..
+            } => {
+                let mut tx = self.pool.begin().await?;
+                let open_rent = sqlx::query!(
+                    r#"SELECT rent_id, start_date AS "start_date!" FROM rent where tenant_id = $1 and customer_id = $2 and vehicle_id = $3 and end_date is null and rent_id < $4 FOR UPDATE"#,
+                    tenant_id,
+                    customer_id.as_str(),
+                    vehicle_id,
+                    event_id,
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+                if let Some(open_rent) = open_rent {
+                    let (rent_id, start_date) = (open_rent.rent_id, open_rent.start_date);
+                    let duration_minutes = rental_duration_minutes(start_date, returned_date);
+                    if duration_minutes.is_none() {
+                        tracing::warn!(
+                            event_id,
+                            rent_id,
+                            %start_date,
+                            %returned_date,
+                            "vehicle returned before it was rented, leaving the rental duration empty"
+                        );
+                    }
+                    sqlx::query!(
+                        "UPDATE rent SET end_date = $2, duration_minutes = $3 WHERE rent_id = $1",
+                        rent_id,
+                        returned_date,
+                        duration_minutes,
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                tx.commit().await?;
+            }
         };
         Ok(())
     }
 }
+
+/// Whole minutes between the start and the end of a rental, or `None` when it ends before
+/// it starts.
+pub(crate) fn rental_duration_minutes(
+    start_date: DateTime<Utc>,
+    end_date: DateTime<Utc>,
+) -> Option<i64> {
+    let duration = end_date - start_date;
+    (duration >= chrono::Duration::zero()).then(|| duration.num_minutes())
+}
+
+#[async_trait]
+impl EventListener<RentEvent> for RentalProjection {
+    type Error = sqlx::Error;
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn query(&self) -> &StreamQuery<RentEvent> {
+        &self.query
+    }
+
+    #[tracing::instrument(skip_all, fields(listener_id = self.id(), event_id = event.id(), event_type = event.name()))]
+    async fn handle(&self, event: PersistedEvent<RentEvent>) -> Result<(), Self::Error> {
+        let (event_id, event_type) = (event.id(), event.name());
+        let result = self.apply(event_id, event.into_inner()).await;
+        dead_letter::settle(
+            &self.pool,
+            &*self.reporter,
+            self.id(),
+            event_id,
+            event_type,
+            result,
+        )
+        .await
+    }
+}
+
+/// Tells whether retrying a failed write can never succeed.
+pub(crate) fn is_permanent(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(err) => err.code().is_some_and(|code| is_permanent_code(&code)),
+        sqlx::Error::Decode(_) | sqlx::Error::ColumnDecode { .. } => true,
+        _ => false,
+    }
+}
+
+/// Data exceptions (class 22) and integrity constraint violations (class 23) depend on the
+/// event content, so they fail the same way on every retry.
+fn is_permanent_code(code: &str) -> bool {
+    code.starts_with("22") || code.starts_with("23")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VehicleStatus {
+    Available,
+    Rented,
+    Maintenance,
+    Decommissioned,
+}
+
+impl Display for VehicleStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VehicleStatus::Available => write!(f, "available"),
+            VehicleStatus::Rented => write!(f, "rented"),
+            VehicleStatus::Maintenance => write!(f, "maintenance"),
+            VehicleStatus::Decommissioned => write!(f, "decommissioned"),
+        }
+    }
+}
+
+impl FromStr for VehicleStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "available" => Ok(VehicleStatus::Available),
+            "rented" => Ok(VehicleStatus::Rented),
+            "maintenance" => Ok(VehicleStatus::Maintenance),
+            "decommissioned" => Ok(VehicleStatus::Decommissioned),
+            _ => Err(format!("unknown vehicle status: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{queries::ReadModelRepository, *};
+    use crate::domain::{default_tenant, DomainEvent, Transmission};
+    use crate::test_support;
+    use sqlx::postgres::PgPoolOptions;
+
+    #[test]
+    fn it_should_compute_the_rental_duration_in_whole_minutes() {
+        let start_date = "2024-07-01T09:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let end_date = "2024-07-02T10:30:59Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            rental_duration_minutes(start_date, end_date),
+            Some(25 * 60 + 30)
+        );
+        assert_eq!(rental_duration_minutes(start_date, start_date), Some(0));
+    }
+
+    #[test]
+    fn it_should_not_compute_a_duration_when_the_return_precedes_the_start() {
+        let start_date = "2024-07-01T09:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let end_date = "2024-07-01T08:59:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(rental_duration_minutes(start_date, end_date), None);
+    }
+
+    #[test]
+    fn it_should_parse_vehicle_statuses_from_their_display_name() {
+        for status in [
+            VehicleStatus::Available,
+            VehicleStatus::Rented,
+            VehicleStatus::Maintenance,
+            VehicleStatus::Decommissioned,
+        ] {
+            assert_eq!(status.to_string().parse(), Ok(status));
+        }
+    }
+
+    #[test]
+    fn it_should_retry_only_errors_that_can_go_away() {
+        assert!(!is_permanent(&sqlx::Error::PoolTimedOut));
+        assert!(is_permanent(&sqlx::Error::Decode(
+            "bad vehicle type".into()
+        )));
+        assert!(is_permanent_code("23505"));
+        assert!(is_permanent_code("22001"));
+        assert!(!is_permanent_code("42P01"));
+        assert!(!is_permanent_code("40001"));
+    }
+
+    #[test]
+    fn it_should_accept_only_plain_identifiers_as_schema() {
+        assert!("read_model_2".parse::<ReadModelSchema>().is_ok());
+        assert!("public".parse::<ReadModelSchema>().is_err());
+        assert!("ReadModel".parse::<ReadModelSchema>().is_err());
+        assert!("rm; DROP TABLE event".parse::<ReadModelSchema>().is_err());
+        assert!("2rm".parse::<ReadModelSchema>().is_err());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_keep_the_read_model_out_of_the_public_schema(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let public: Vec<String> = sqlx::query_scalar(
+            "SELECT table_name::text FROM information_schema.tables WHERE table_schema = 'public'",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        for tables in [
+            CustomerProjection::TABLES,
+            VehicleProjection::TABLES,
+            RentalProjection::TABLES,
+        ] {
+            for table in tables {
+                assert!(!public.iter().any(|t| t == table), "{table} is in public");
+            }
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_store_and_read_back_every_vehicle_type(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let projection = VehicleProjection::new(pool.clone());
+        for (event_id, vehicle_type) in (1..).zip(VehicleType::ALL) {
+            let vehicle_id = format!("AA{event_id:03}");
+            projection
+                .apply(
+                    event_id,
+                    RentEvent::VehicleAdded {
+                        tenant_id: default_tenant(),
+                        vehicle_id: vehicle_id.clone(),
+                        vehicle_type: vehicle_type.clone(),
+                        seats: None,
+                        transmission: None,
+                    },
+                )
+                .await
+                .unwrap();
+            let vehicle = ReadModelRepository::new(pool.clone())
+                .find_vehicle(&vehicle_id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(vehicle.value.vehicle_type, vehicle_type);
+            assert_eq!(vehicle.version, event_id);
+        }
+
+        let stored: Vec<VehicleType> =
+            sqlx::query_scalar("SELECT unnest(enum_range(NULL::vehicle_type))")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(stored, VehicleType::ALL);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_keep_the_seats_and_transmission_unknown_for_legacy_vehicles(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let projection = VehicleProjection::new(pool.clone());
+        let legacy: DomainEvent =
+            serde_json::from_str(r#"{"VehicleAdded":{"vehicle_id":"AA001","vehicle_type":"Car"}}"#)
+                .unwrap();
+        projection
+            .apply(1, legacy.try_into().unwrap())
+            .await
+            .unwrap();
+        projection
+            .apply(
+                2,
+                RentEvent::VehicleAdded {
+                    tenant_id: default_tenant(),
+                    vehicle_id: "AA002".to_string(),
+                    vehicle_type: VehicleType::Car,
+                    seats: Some(5),
+                    transmission: Some(Transmission::Automatic),
+                },
+            )
+            .await
+            .unwrap();
+
+        let repository = ReadModelRepository::new(pool);
+        let legacy = repository.find_vehicle("AA001").await.unwrap().unwrap();
+        assert_eq!(legacy.value.seats, None);
+        assert_eq!(legacy.value.transmission, None);
+        let vehicle = repository.find_vehicle("AA002").await.unwrap().unwrap();
+        assert_eq!(vehicle.value.seats, Some(5));
+        assert_eq!(vehicle.value.transmission, Some(Transmission::Automatic));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_version_customers_by_their_last_event(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let projection = CustomerProjection::new(pool.clone());
+        let repository = ReadModelRepository::new(pool);
+        let customer_id = "mario@example.com".to_string();
+        let rented = CustomerActivity::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: customer_id.clone().into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Van,
+            start_date: Utc::now(),
+        };
+        let version = || async {
+            repository
+                .customer_detail(&customer_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .version
+        };
+
+        projection
+            .apply(
+                1,
+                CustomerActivity::CustomerRegistered {
+                    tenant_id: default_tenant(),
+                    customer_id: customer_id.clone().into(),
+                    first_name: "Mario".to_string(),
+                    last_name: "Rossi".to_string(),
+                    phone: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(version().await, 1);
+        projection.apply(5, rented.clone()).await.unwrap();
+        assert_eq!(version().await, 5);
+        // Events delivered again don't move the version back.
+        projection.apply(3, rented).await.unwrap();
+        assert_eq!(version().await, 5);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_surface_the_errors_of_a_dropped_table_rather_than_panic(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query("DROP TABLE rent CASCADE")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let projection = RentalProjection::new(pool.clone());
+        let rented = RentEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Van,
+            start_date: Utc::now(),
+        };
+
+        let err = projection
+            .handle(PersistedEvent::new(1, rented))
+            .await
+            .unwrap_err();
+        let sqlx::Error::Database(err) = err else {
+            panic!("not a database error: {err}");
+        };
+        assert_eq!(err.code().as_deref(), Some("42P01"));
+        // A missing table can come back, so the event is retried rather than set aside.
+        let attempts: i32 = sqlx::query_scalar(
+            "SELECT attempts FROM projection_failure WHERE listener_id = $1 AND event_id = 1",
+        )
+        .bind(RentalProjection::ID)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(attempts, 1);
+    }
+
+    /// The rows of `table`, in an order independent of how they were written.
+    async fn rows(pool: &PgPool, table: &str) -> serde_json::Value {
+        sqlx::query_scalar(&format!(
+            "SELECT coalesce(jsonb_agg(to_jsonb(t) ORDER BY to_jsonb(t)::text), '[]') FROM {table} t"
+        ))
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_leave_the_tables_unchanged_when_an_event_is_delivered_again(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let customers = CustomerProjection::new(pool.clone());
+        let vehicles = VehicleProjection::new(pool.clone());
+        let rentals = RentalProjection::new(pool.clone());
+        let date = |date: &str| date.parse::<DateTime<Utc>>().unwrap();
+        let rented = |start_date: &str| RentEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: date(start_date),
+        };
+        let returned = RentEvent::VehicleReturned {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: Some(date("2024-07-01T09:00:00Z")),
+            returned_date: date("2024-07-01T18:00:00Z"),
+        };
+        let registered = CustomerActivity::CustomerRegistered {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            first_name: "Mario".to_string(),
+            last_name: "Rossi".to_string(),
+            phone: None,
+        };
+        let events = [
+            RentEvent::VehicleAdded {
+                tenant_id: default_tenant(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Car,
+                seats: None,
+                transmission: None,
+            },
+            rented("2024-07-01T09:00:00Z"),
+            returned.clone(),
+            rented("2024-07-02T09:00:00Z"),
+        ];
+        let tables = || async {
+            (
+                rows(&pool, "customer").await,
+                rows(&pool, "vehicle").await,
+                rows(&pool, "rent").await,
+            )
+        };
+        let deliver = |event_id: i64, event: RentEvent| {
+            let (vehicles, rentals) = (&vehicles, &rentals);
+            async move {
+                vehicles
+                    .handle(PersistedEvent::new(event_id, event.clone()))
+                    .await
+                    .unwrap();
+                rentals
+                    .handle(PersistedEvent::new(event_id, event))
+                    .await
+                    .unwrap();
+            }
+        };
+
+        customers
+            .handle(PersistedEvent::new(1, registered.clone()))
+            .await
+            .unwrap();
+        for (event_id, event) in (2..).zip(events.clone()) {
+            deliver(event_id, event).await;
+        }
+        let projected = tables().await;
+
+        customers
+            .handle(PersistedEvent::new(1, registered))
+            .await
+            .unwrap();
+        for (event_id, event) in (2..).zip(events) {
+            deliver(event_id, event).await;
+        }
+        // The return of the first rental must not close the second one.
+        deliver(4, returned).await;
+        assert_eq!(tables().await, projected);
+        let open: i64 = sqlx::query_scalar("SELECT count(*) FROM rent WHERE end_date IS NULL")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(open, 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_record_each_rental_of_the_same_vehicle_by_the_same_customer(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let projection = RentalProjection::new(pool.clone());
+        let date = |date: &str| date.parse::<DateTime<Utc>>().unwrap();
+        let rented = |start_date: &str| RentEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: date(start_date),
+        };
+        let returned = |start_date: &str, returned_date: &str| RentEvent::VehicleReturned {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: Some(date(start_date)),
+            returned_date: date(returned_date),
+        };
+        let events = [
+            rented("2024-07-01T09:00:00Z"),
+            returned("2024-07-01T09:00:00Z", "2024-07-01T18:00:00Z"),
+            rented("2024-07-03T09:00:00Z"),
+            returned("2024-07-03T09:00:00Z", "2024-07-03T10:00:00Z"),
+        ];
+        for (event_id, event) in (1..).zip(events) {
+            projection.apply(event_id, event).await.unwrap();
+        }
+
+        let rentals: Vec<(i64, Option<DateTime<Utc>>)> =
+            sqlx::query_as("SELECT rent_id, end_date FROM rent ORDER BY rent_id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            rentals,
+            [
+                (1, Some(date("2024-07-01T18:00:00Z"))),
+                (3, Some(date("2024-07-03T10:00:00Z"))),
+            ]
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_set_the_current_renter_of_a_vehicle_and_clear_it_on_return(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let projection = VehicleProjection::new(pool.clone());
+        let repository = ReadModelRepository::new(pool);
+        let start_date = "2024-07-01T09:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let returned = RentEvent::VehicleReturned {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: Some(start_date),
+            returned_date: start_date + chrono::Duration::hours(3),
+        };
+        let renter = || async {
+            let vehicle = repository.find_vehicle("AA111AA").await.unwrap().unwrap();
+            (
+                vehicle.value.status,
+                vehicle.value.current_renter_email,
+                vehicle.value.rented_since,
+            )
+        };
+        projection
+            .apply(
+                1,
+                RentEvent::VehicleAdded {
+                    tenant_id: default_tenant(),
+                    vehicle_id: "AA111AA".to_string(),
+                    vehicle_type: VehicleType::Car,
+                    seats: None,
+                    transmission: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(renter().await, (VehicleStatus::Available, None, None));
+
+        projection
+            .apply(
+                2,
+                RentEvent::VehicleRented {
+                    tenant_id: default_tenant(),
+                    customer_id: "mario@example.com".into(),
+                    vehicle_id: "AA111AA".to_string(),
+                    vehicle_type: VehicleType::Car,
+                    start_date,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            renter().await,
+            (
+                VehicleStatus::Rented,
+                Some("mario@example.com".to_string()),
+                Some(start_date)
+            )
+        );
+
+        projection.apply(3, returned.clone()).await.unwrap();
+        assert_eq!(renter().await, (VehicleStatus::Available, None, None));
+        // Replayed, on a row that already looks returned.
+        projection.apply(3, returned).await.unwrap();
+        assert_eq!(renter().await, (VehicleStatus::Available, None, None));
+    }
+
+    /// The tables and columns of the read model schema, in a stable order.
+    async fn columns(pool: &PgPool) -> Vec<(String, String, String)> {
+        sqlx::query_as(
+            r#"SELECT table_name::text, column_name::text, data_type::text
+                FROM information_schema.columns WHERE table_schema = current_schema()
+                ORDER BY table_name, column_name"#,
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_migrate_an_empty_database_then_change_nothing_when_run_again(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let migrated = columns(&pool).await;
+        for tables in [
+            CustomerProjection::TABLES,
+            VehicleProjection::TABLES,
+            RentalProjection::TABLES,
+            crate::daily_stats::DailyStatsProjection::TABLES,
+        ] {
+            for table in tables {
+                assert!(
+                    migrated.iter().any(|(t, _, _)| t == table),
+                    "{table} is missing"
+                );
+            }
+        }
+        let rent: Vec<&str> = migrated
+            .iter()
+            .filter(|(table, _, _)| table == "rent")
+            .map(|(_, column, _)| column.as_str())
+            .collect();
+        for column in ["rent_id", "tenant_id", "duration_minutes", "end_date"] {
+            assert!(rent.contains(&column), "rent.{column} is missing");
+        }
+        let applied = || async {
+            sqlx::query_scalar::<_, i64>("SELECT count(*) FROM _sqlx_migrations WHERE success")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        };
+        assert_eq!(applied().await, sqlx::migrate!().iter().count() as i64);
+
+        sqlx::migrate!().run(&pool).await.unwrap();
+        assert_eq!(columns(&pool).await, migrated);
+        assert_eq!(applied().await, sqlx::migrate!().iter().count() as i64);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_keep_the_offline_query_data_in_line_with_the_migrations(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        use sqlx::{Column, Executor};
+
+        let pool = test_support::read_model(options).await;
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(".sqlx");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let data: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            let query = data["query"].as_str().unwrap();
+            let described = pool
+                .describe(query)
+                .await
+                .unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+            let columns: Vec<&str> = described.columns.iter().map(|c| c.name()).collect();
+            let recorded: Vec<&str> = data["describe"]["columns"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|column| column["name"].as_str().unwrap())
+                .collect();
+            assert_eq!(columns, recorded, "{}", path.display());
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_project_the_customers_again_to_fill_their_last_event(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        use crate::{
+            config::ListenerConfig, health::Readiness, live::LiveUpdates, shutdown::Shutdown,
+        };
+        use disintegrate::EventStore;
+        use disintegrate_postgres::PgEventStore;
+
+        let pool = test_support::read_model(options.clone()).await;
+        let event_store = PgEventStore::new(
+            PgPool::connect_with(options).await.unwrap(),
+            crate::pii::EncryptedJson::default(),
+        )
+        .await
+        .unwrap();
+        let appended = event_store
+            .append(
+                vec![
+                    DomainEvent::CustomerRegistered {
+                        tenant_id: default_tenant(),
+                        customer_id: "mario@example.com".into(),
+                        first_name: "Mario".to_string(),
+                        last_name: "Rossi".to_string(),
+                        phone: None,
+                    },
+                    DomainEvent::VehicleRented {
+                        tenant_id: default_tenant(),
+                        customer_id: "mario@example.com".into(),
+                        vehicle_id: "AA111AA".to_string(),
+                        vehicle_type: VehicleType::Car,
+                        start_date: Utc::now(),
+                    },
+                ],
+                disintegrate::query!(DomainEvent),
+                0,
+            )
+            .await
+            .unwrap();
+        let last_event_id = appended[1].id();
+        let checkpoint = || async {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT last_processed_event_id FROM event_listener WHERE id = $1",
+            )
+            .bind(CustomerProjection::ID)
+            .fetch_optional(&pool)
+            .await
+            .ok()
+            .flatten()
+        };
+        let project = || async {
+            let shutdown = Shutdown::default();
+            let listening = tokio::spawn(crate::event_listener(
+                pool.clone(),
+                event_store.clone(),
+                Readiness::default(),
+                LiveUpdates::new(shutdown.clone()),
+                ListenerConfig {
+                    poll_interval: std::time::Duration::from_millis(20),
+                    ..ListenerConfig::default()
+                },
+                crate::reporting::noop(),
+                shutdown.requested(),
+            ));
+            while checkpoint().await != Some(last_event_id) {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            shutdown.trigger();
+            listening.await.unwrap().unwrap();
+        };
+        let versions = || async {
+            sqlx::query_as::<_, (String, i64)>("SELECT customer_id, last_event_id FROM customer")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+        };
+        project().await;
+        let projected = vec![("mario@example.com".to_string(), last_event_id)];
+        assert_eq!(versions().await, projected);
+
+        // A read model projected before the customers had their last event.
+        sqlx::query("ALTER TABLE customer DROP COLUMN last_event_id")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE description = 'customer last event'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        assert_eq!(versions().await, vec![]);
+        assert_eq!(checkpoint().await, Some(0));
+        let target: i64 = sqlx::query_scalar(
+            "SELECT target_event_id FROM projection_rebuild WHERE listener_id = $1",
+        )
+        .bind(CustomerProjection::ID)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(target, last_event_id);
+
+        project().await;
+        assert_eq!(versions().await, projected);
+    }
+}
@@ -0,0 +1,1051 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SubsecRound, Utc};
+use disintegrate::PersistedEvent;
+use futures_util::stream::{self, BoxStream, StreamExt};
+
+use super::{
+    queries::{
+        ActiveRental, AvailabilitySummary, CalendarDay, CustomerMatch, CustomerSummary,
+        CustomerView, RentStatus, RentalExportRow, RentalView, SearchHit, VehicleView, Versioned,
+    },
+    rental_duration_minutes,
+    repository::ReadRepository,
+    VehicleStatus,
+};
+use crate::{
+    domain::{default_tenant, DomainEvent, TenantId, Transmission, VehicleType},
+    filters::{CalendarRange, RentalFilter, RentalStatus, VehicleFilter, MAX_RENTAL_DAYS},
+    pagination::PageParams,
+    sorting::{SortDirection, SortParams},
+    vehicle_stats::VehicleStats,
+};
+
+/// `CUSTOMER_SEARCH_THRESHOLD` of `ReadModelRepository::search_customers`.
+const CUSTOMER_SEARCH_THRESHOLD: f32 = 0.4;
+
+/// The read model of `EVENT_STORE=memory`: the tables of the projections kept in maps, and
+/// read the way `ReadModelRepository` reads them.
+///
+/// `apply` projects the events as the projections do, in the order of the event store; the
+/// searches rank their hits close to the Postgres ones, not always the same.
+#[derive(Clone)]
+pub struct MemoryReadModel {
+    tables: Arc<RwLock<Tables>>,
+    tenant_id: TenantId,
+}
+
+impl Default for MemoryReadModel {
+    fn default() -> Self {
+        Self {
+            tables: Arc::default(),
+            tenant_id: default_tenant(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Tables {
+    customers: HashMap<(TenantId, String), CustomerRow>,
+    vehicles: HashMap<(TenantId, String), VehicleRow>,
+    rents: HashMap<i64, RentRow>,
+    vehicle_stats: HashMap<(TenantId, String), VehicleStatsRow>,
+}
+
+struct CustomerRow {
+    customer_id: String,
+    first_name: String,
+    last_name: String,
+    last_event_id: i64,
+}
+
+struct VehicleRow {
+    vehicle_id: String,
+    vehicle_type: VehicleType,
+    status: VehicleStatus,
+    current_renter_email: Option<String>,
+    rented_since: Option<DateTime<Utc>>,
+    last_event_id: i64,
+    registered_at: DateTime<Utc>,
+    seats: Option<i16>,
+    transmission: Option<Transmission>,
+}
+
+struct RentRow {
+    rent_id: i64,
+    tenant_id: TenantId,
+    customer_id: String,
+    vehicle_id: String,
+    start_date: DateTime<Utc>,
+    end_date: Option<DateTime<Utc>>,
+    duration_minutes: Option<i64>,
+}
+
+#[derive(Default)]
+struct VehicleStatsRow {
+    rentals: i64,
+    rented_minutes: i64,
+    last_rented_at: Option<DateTime<Utc>>,
+    rented_since: Option<DateTime<Utc>>,
+    last_event_id: i64,
+}
+
+impl MemoryReadModel {
+    pub fn for_tenant(&self, tenant_id: TenantId) -> Self {
+        Self {
+            tables: self.tables.clone(),
+            tenant_id,
+        }
+    }
+
+    /// Projects the event into the tables of the customers, vehicles, rentals and vehicle
+    /// stats, as their projections do, an event applied again changing nothing.
+    pub fn apply(&self, event: &PersistedEvent<DomainEvent>) {
+        let event_id = event.id();
+        let mut tables = self.tables.write().unwrap();
+        match &**event {
+            DomainEvent::CustomerRegistered {
+                tenant_id,
+                customer_id,
+                first_name,
+                last_name,
+                ..
+            } => {
+                tables
+                    .customers
+                    .entry((tenant_id.clone(), customer_id.to_string()))
+                    .or_insert_with(|| CustomerRow {
+                        customer_id: customer_id.to_string(),
+                        first_name: first_name.clone(),
+                        last_name: last_name.clone(),
+                        last_event_id: event_id,
+                    });
+            }
+            DomainEvent::VehicleAdded {
+                tenant_id,
+                vehicle_id,
+                vehicle_type,
+                seats,
+                transmission,
+            } => {
+                // The events kept in memory carry no timestamp, the vehicles are projected as
+                // they are registered.
+                tables
+                    .vehicles
+                    .entry((tenant_id.clone(), vehicle_id.clone()))
+                    .or_insert_with(|| VehicleRow {
+                        vehicle_id: vehicle_id.clone(),
+                        vehicle_type: vehicle_type.clone(),
+                        status: VehicleStatus::Available,
+                        current_renter_email: None,
+                        rented_since: None,
+                        last_event_id: event_id,
+                        registered_at: timestamptz(Utc::now()),
+                        seats: seats.map(i16::from),
+                        transmission: *transmission,
+                    });
+                tables.count_vehicle(tenant_id, vehicle_id, event_id, |_| {});
+            }
+            DomainEvent::VehicleRented {
+                tenant_id,
+                customer_id,
+                vehicle_id,
+                start_date,
+                ..
+            } => {
+                let start_date = &timestamptz(*start_date);
+                tables.touch_customer(tenant_id, customer_id, event_id);
+                tables.update_rental(
+                    tenant_id,
+                    vehicle_id,
+                    VehicleStatus::Rented,
+                    Some((customer_id.as_str(), *start_date)),
+                    event_id,
+                );
+                tables.rents.entry(event_id).or_insert_with(|| RentRow {
+                    rent_id: event_id,
+                    tenant_id: tenant_id.clone(),
+                    customer_id: customer_id.to_string(),
+                    vehicle_id: vehicle_id.clone(),
+                    start_date: *start_date,
+                    end_date: None,
+                    duration_minutes: None,
+                });
+                tables.count_vehicle(tenant_id, vehicle_id, event_id, |stats| {
+                    stats.rentals += 1;
+                    stats.last_rented_at = Some(*start_date);
+                    stats.rented_since = Some(*start_date);
+                });
+            }
+            DomainEvent::VehicleReturned {
+                tenant_id,
+                customer_id,
+                vehicle_id,
+                start_date,
+                returned_date,
+                ..
+            } => {
+                let (start_date, returned_date) =
+                    (start_date.map(timestamptz), &timestamptz(*returned_date));
+                tables.touch_customer(tenant_id, customer_id, event_id);
+                tables.update_rental(
+                    tenant_id,
+                    vehicle_id,
+                    VehicleStatus::Available,
+                    None,
+                    event_id,
+                );
+                let open_rent = tables.rents.values_mut().find(|rent| {
+                    rent.tenant_id == *tenant_id
+                        && rent.customer_id == customer_id.as_str()
+                        && rent.vehicle_id == *vehicle_id
+                        && rent.end_date.is_none()
+                        && rent.rent_id < event_id
+                });
+                if let Some(rent) = open_rent {
+                    rent.end_date = Some(*returned_date);
+                    rent.duration_minutes =
+                        rental_duration_minutes(rent.start_date, *returned_date);
+                }
+                tables.count_vehicle(tenant_id, vehicle_id, event_id, |stats| {
+                    let minutes = start_date
+                        .or(stats.rented_since)
+                        .and_then(|start_date| rental_duration_minutes(start_date, *returned_date));
+                    stats.rented_minutes += minutes.unwrap_or(0);
+                    stats.rented_since = None;
+                });
+            }
+        }
+    }
+
+    fn read<T>(&self, read: impl FnOnce(&Tables) -> T) -> Result<T, sqlx::Error> {
+        Ok(read(&self.tables.read().unwrap()))
+    }
+}
+
+impl Tables {
+    fn touch_customer(&mut self, tenant_id: &TenantId, customer_id: &str, event_id: i64) {
+        if let Some(customer) = self
+            .customers
+            .get_mut(&(tenant_id.clone(), customer_id.to_string()))
+        {
+            customer.last_event_id = customer.last_event_id.max(event_id);
+        }
+    }
+
+    fn update_rental(
+        &mut self,
+        tenant_id: &TenantId,
+        vehicle_id: &str,
+        status: VehicleStatus,
+        renter: Option<(&str, DateTime<Utc>)>,
+        event_id: i64,
+    ) {
+        let Some(vehicle) = self
+            .vehicles
+            .get_mut(&(tenant_id.clone(), vehicle_id.to_string()))
+        else {
+            return;
+        };
+        if vehicle.last_event_id >= event_id {
+            return;
+        }
+        let (renter_email, rented_since) = renter.unzip();
+        vehicle.status = status;
+        vehicle.current_renter_email = renter_email.map(str::to_string);
+        vehicle.rented_since = rented_since;
+        vehicle.last_event_id = event_id;
+    }
+
+    fn count_vehicle(
+        &mut self,
+        tenant_id: &TenantId,
+        vehicle_id: &str,
+        event_id: i64,
+        count: impl FnOnce(&mut VehicleStatsRow),
+    ) {
+        let stats = self
+            .vehicle_stats
+            .entry((tenant_id.clone(), vehicle_id.to_string()))
+            .or_default();
+        if stats.last_event_id >= event_id {
+            return;
+        }
+        count(stats);
+        stats.last_event_id = event_id;
+    }
+
+    fn vehicles<'a>(&'a self, tenant_id: &'a TenantId) -> impl Iterator<Item = &'a VehicleRow> {
+        self.vehicles
+            .iter()
+            .filter(move |((tenant, _), _)| tenant == tenant_id)
+            .map(|(_, vehicle)| vehicle)
+    }
+
+    fn customers<'a>(&'a self, tenant_id: &'a TenantId) -> impl Iterator<Item = &'a CustomerRow> {
+        self.customers
+            .iter()
+            .filter(move |((tenant, _), _)| tenant == tenant_id)
+            .map(|(_, customer)| customer)
+    }
+
+    fn rents<'a>(&'a self, tenant_id: &'a TenantId) -> impl Iterator<Item = &'a RentRow> {
+        self.rents
+            .values()
+            .filter(move |rent| rent.tenant_id == *tenant_id)
+    }
+
+    fn vehicle(&self, tenant_id: &TenantId, vehicle_id: &str) -> Option<&VehicleRow> {
+        self.vehicles
+            .get(&(tenant_id.clone(), vehicle_id.to_string()))
+    }
+
+    fn customer(&self, tenant_id: &TenantId, customer_id: &str) -> Option<&CustomerRow> {
+        self.customers
+            .get(&(tenant_id.clone(), customer_id.to_string()))
+    }
+
+    fn filtered_vehicles<'a>(
+        &'a self,
+        tenant_id: &'a TenantId,
+        filter: &'a VehicleFilter,
+    ) -> impl Iterator<Item = &'a VehicleRow> {
+        // Vehicles registered without seats or transmission match no filter on them.
+        self.vehicles(tenant_id).filter(move |vehicle| {
+            filter
+                .vehicle_type
+                .as_ref()
+                .is_none_or(|vehicle_type| vehicle.vehicle_type == *vehicle_type)
+                && filter.status.is_none_or(|status| vehicle.status == status)
+                && filter
+                    .min_seats
+                    .is_none_or(|min_seats| vehicle.seats.is_some_and(|seats| seats >= min_seats))
+                && filter
+                    .transmission
+                    .is_none_or(|transmission| vehicle.transmission == Some(transmission))
+        })
+    }
+
+    fn filtered_rents<'a>(
+        &'a self,
+        tenant_id: &'a TenantId,
+        filter: &'a RentalFilter,
+    ) -> impl Iterator<Item = &'a RentRow> {
+        let overdue_since = overdue_since();
+        self.rents(tenant_id).filter(move |rent| {
+            let status = match filter.status {
+                Some(RentalStatus::Open) => rent.end_date.is_none(),
+                Some(RentalStatus::Closed) => rent.end_date.is_some(),
+                Some(RentalStatus::Overdue) => {
+                    rent.end_date.is_none() && rent.start_date < overdue_since
+                }
+                None => true,
+            };
+            let date = if filter.status == Some(RentalStatus::Closed) {
+                rent.end_date
+            } else {
+                Some(rent.start_date)
+            };
+            status
+                && filter
+                    .from
+                    .is_none_or(|from| date.is_some_and(|date| date >= from))
+                && filter
+                    .to
+                    .is_none_or(|to| date.is_some_and(|date| date <= to))
+        })
+    }
+
+    fn open_rent<'a>(&'a self, tenant_id: &'a TenantId, vehicle_id: &str) -> Option<&'a RentRow> {
+        self.rents(tenant_id)
+            .find(|rent| rent.vehicle_id == vehicle_id && rent.end_date.is_none())
+    }
+}
+
+/// The open rentals started before are overdue.
+fn overdue_since() -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::days(MAX_RENTAL_DAYS.into())
+}
+
+impl From<&VehicleRow> for VehicleView {
+    fn from(row: &VehicleRow) -> Self {
+        VehicleView {
+            vehicle_id: row.vehicle_id.clone(),
+            vehicle_type: row.vehicle_type.clone(),
+            status: row.status,
+            current_renter_email: row.current_renter_email.clone(),
+            rented_since: row.rented_since,
+            seats: row.seats,
+            transmission: row.transmission,
+        }
+    }
+}
+
+impl From<&CustomerRow> for CustomerView {
+    fn from(row: &CustomerRow) -> Self {
+        CustomerView {
+            customer_id: row.customer_id.clone(),
+            first_name: row.first_name.clone(),
+            last_name: row.last_name.clone(),
+        }
+    }
+}
+
+impl From<&RentRow> for RentalView {
+    fn from(row: &RentRow) -> Self {
+        RentalView {
+            rent_id: row.rent_id,
+            customer_id: row.customer_id.clone(),
+            vehicle_id: row.vehicle_id.clone(),
+            start_date: row.start_date,
+            end_date: row.end_date,
+            duration_minutes: row.duration_minutes,
+        }
+    }
+}
+
+/// Orders as Postgres does: the missing values last, or first in descending order.
+fn nulls_last<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (a, b) => b.is_none().cmp(&a.is_none()).reverse(),
+    }
+}
+
+fn directed(ordering: Ordering, direction: SortDirection) -> Ordering {
+    match direction {
+        SortDirection::Asc => ordering,
+        SortDirection::Desc => ordering.reverse(),
+    }
+}
+
+/// The timestamp as Postgres keeps it, to the microsecond.
+fn timestamptz(timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    timestamp.trunc_subsecs(6)
+}
+
+fn page_of<T>(rows: Vec<T>, page: PageParams) -> Vec<T> {
+    rows.into_iter()
+        .skip(page.offset.max(0) as usize)
+        .take(page.limit.max(0) as usize)
+        .collect()
+}
+
+/// The distinct words of the text as `to_tsvector('simple', ...)` splits it, lowercased, the
+/// emails kept whole besides their parts.
+fn lexemes(text: &str) -> Vec<String> {
+    let mut lexemes: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        let word = word.to_lowercase();
+        let parts = word
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .map(str::to_string);
+        for lexeme in word
+            .contains('@')
+            .then(|| word.clone())
+            .into_iter()
+            .chain(parts)
+        {
+            if !lexemes.contains(&lexeme) {
+                lexemes.push(lexeme);
+            }
+        }
+    }
+    lexemes
+}
+
+/// How well `lexemes` match every word of the search as a prefix, `None` when one of them
+/// matches none: the number of lexemes matched, standing for `ts_rank`.
+fn prefix_rank(words: &[String], lexemes: &[String]) -> Option<usize> {
+    let matched = |word: &String| {
+        lexemes
+            .iter()
+            .any(|lexeme| lexeme.starts_with(word.as_str()))
+    };
+    words.iter().all(matched).then(|| {
+        lexemes
+            .iter()
+            .filter(|lexeme| words.iter().any(|word| lexeme.starts_with(word.as_str())))
+            .count()
+    })
+}
+
+/// The trigrams of the words of the text, in order, as pg_trgm extracts them.
+fn trigrams(text: &str) -> Vec<[char; 3]> {
+    let mut trigrams = Vec::new();
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let padded: Vec<char> = format!("  {} ", word.to_lowercase()).chars().collect();
+        trigrams.extend(
+            padded
+                .windows(3)
+                .map(|window| [window[0], window[1], window[2]]),
+        );
+    }
+    trigrams
+}
+
+/// `word_similarity(text, document)` of pg_trgm: the greatest similarity between the trigrams
+/// of the text and those of any extent of the document.
+fn word_similarity(text: &str, document: &str) -> f32 {
+    let searched: HashSet<[char; 3]> = trigrams(text).into_iter().collect();
+    if searched.is_empty() {
+        return 0.0;
+    }
+    let document = trigrams(document);
+    let mut best = 0.0f32;
+    for start in 0..document.len() {
+        let mut extent = HashSet::new();
+        for trigram in &document[start..] {
+            extent.insert(*trigram);
+            let common = extent.intersection(&searched).count();
+            let similarity = common as f32 / (searched.len() + extent.len() - common) as f32;
+            best = best.max(similarity);
+        }
+    }
+    best
+}
+
+#[async_trait]
+impl ReadRepository for MemoryReadModel {
+    fn tenant_id(&self) -> &TenantId {
+        &self.tenant_id
+    }
+
+    fn for_tenant(&self, tenant_id: TenantId) -> Arc<dyn ReadRepository> {
+        Arc::new(MemoryReadModel::for_tenant(self, tenant_id))
+    }
+
+    async fn availability_summary(
+        &self,
+        vehicle_type: Option<VehicleType>,
+    ) -> Result<Vec<AvailabilitySummary>, sqlx::Error> {
+        self.read(|tables| {
+            VehicleType::ALL
+                .into_iter()
+                .filter(|of_type| vehicle_type.as_ref().is_none_or(|t| t == of_type))
+                .map(|vehicle_type| {
+                    let vehicles: Vec<_> = tables
+                        .vehicles(&self.tenant_id)
+                        .filter(|vehicle| vehicle.vehicle_type == vehicle_type)
+                        .collect();
+                    let rented = vehicles
+                        .iter()
+                        .filter(|vehicle| {
+                            tables
+                                .open_rent(&self.tenant_id, &vehicle.vehicle_id)
+                                .is_some()
+                        })
+                        .count() as i64;
+                    let maintenance = vehicles
+                        .iter()
+                        .filter(|vehicle| vehicle.status == VehicleStatus::Maintenance)
+                        .count() as i64;
+                    let available = vehicles
+                        .iter()
+                        .filter(|vehicle| {
+                            vehicle.status != VehicleStatus::Maintenance
+                                && tables
+                                    .open_rent(&self.tenant_id, &vehicle.vehicle_id)
+                                    .is_none()
+                        })
+                        .count() as i64;
+                    AvailabilitySummary {
+                        vehicle_type,
+                        total: vehicles.len() as i64,
+                        available,
+                        rented,
+                        maintenance,
+                    }
+                })
+                .collect()
+        })
+    }
+
+    async fn availability_calendar(
+        &self,
+        range: &CalendarRange,
+    ) -> Result<Vec<CalendarDay>, sqlx::Error> {
+        self.read(|tables| {
+            range
+                .from
+                .iter_days()
+                .take_while(|day| *day <= range.to)
+                .map(|day| {
+                    let day_start = day.and_time(chrono::NaiveTime::MIN).and_utc();
+                    let day_end = day_start + chrono::Duration::days(1);
+                    let total = tables
+                        .vehicles(&self.tenant_id)
+                        .filter(|vehicle| {
+                            vehicle.vehicle_type == range.vehicle_type
+                                && vehicle.registered_at < day_end
+                        })
+                        .count() as i64;
+                    let unavailable = tables
+                        .rents(&self.tenant_id)
+                        .filter(|rent| {
+                            rent.start_date < day_end
+                                && rent.end_date.is_none_or(|end_date| end_date > day_start)
+                                && tables
+                                    .vehicle(&self.tenant_id, &rent.vehicle_id)
+                                    .is_some_and(|vehicle| {
+                                        vehicle.vehicle_type == range.vehicle_type
+                                    })
+                        })
+                        .map(|rent| rent.vehicle_id.as_str())
+                        .collect::<HashSet<_>>()
+                        .len() as i64;
+                    CalendarDay {
+                        day,
+                        total,
+                        available: (total - unavailable).max(0),
+                    }
+                })
+                .collect()
+        })
+    }
+
+    async fn find_vehicle(
+        &self,
+        vehicle_id: &str,
+    ) -> Result<Option<Versioned<VehicleView>>, sqlx::Error> {
+        self.read(|tables| {
+            tables
+                .vehicle(&self.tenant_id, vehicle_id)
+                .map(|vehicle| Versioned {
+                    value: vehicle.into(),
+                    version: vehicle.last_event_id,
+                })
+        })
+    }
+
+    async fn list_vehicles(
+        &self,
+        filter: &VehicleFilter,
+        sort: &SortParams<VehicleView>,
+        page: PageParams,
+    ) -> Result<(Vec<VehicleView>, i64), sqlx::Error> {
+        self.read(|tables| {
+            let mut vehicles: Vec<_> = tables.filtered_vehicles(&self.tenant_id, filter).collect();
+            let total = vehicles.len() as i64;
+            vehicles.sort_by(|a, b| {
+                let ordering = match sort.field {
+                    "vehicleType" => a.vehicle_type.to_string().cmp(&b.vehicle_type.to_string()),
+                    "status" => a.status.to_string().cmp(&b.status.to_string()),
+                    _ => a.vehicle_id.cmp(&b.vehicle_id),
+                };
+                directed(ordering, sort.direction).then_with(|| a.vehicle_id.cmp(&b.vehicle_id))
+            });
+            let vehicles = page_of(vehicles, page)
+                .into_iter()
+                .map(Into::into)
+                .collect();
+            (vehicles, total)
+        })
+    }
+
+    async fn count_vehicles(&self, filter: &VehicleFilter) -> Result<i64, sqlx::Error> {
+        self.read(|tables| tables.filtered_vehicles(&self.tenant_id, filter).count() as i64)
+    }
+
+    async fn vehicle_stats(&self, vehicle_id: &str) -> Result<Option<VehicleStats>, sqlx::Error> {
+        self.read(|tables| {
+            tables
+                .vehicle_stats
+                .get(&(self.tenant_id.clone(), vehicle_id.to_string()))
+                .map(|stats| VehicleStats {
+                    vehicle_id: vehicle_id.to_string(),
+                    rentals: stats.rentals,
+                    rented_minutes: stats.rented_minutes,
+                    last_rented_at: stats.last_rented_at,
+                })
+        })
+    }
+
+    async fn customer_detail(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<Versioned<CustomerView>>, sqlx::Error> {
+        self.read(|tables| {
+            tables
+                .customer(&self.tenant_id, customer_id)
+                .map(|customer| Versioned {
+                    value: customer.into(),
+                    version: customer.last_event_id,
+                })
+        })
+    }
+
+    async fn list_customers(
+        &self,
+        page: PageParams,
+    ) -> Result<(Vec<CustomerView>, i64), sqlx::Error> {
+        self.read(|tables| {
+            let mut customers: Vec<_> = tables.customers(&self.tenant_id).collect();
+            customers.sort_by(|a, b| a.customer_id.cmp(&b.customer_id));
+            let total = customers.len() as i64;
+            let customers = page_of(customers, page)
+                .into_iter()
+                .map(Into::into)
+                .collect();
+            (customers, total)
+        })
+    }
+
+    async fn count_customers(&self) -> Result<i64, sqlx::Error> {
+        self.read(|tables| tables.customers(&self.tenant_id).count() as i64)
+    }
+
+    async fn search_customers(
+        &self,
+        text: &str,
+        limit: i64,
+    ) -> Result<Vec<CustomerMatch>, sqlx::Error> {
+        self.read(|tables| {
+            let mut matches: Vec<_> = tables
+                .customers(&self.tenant_id)
+                .filter_map(|customer| {
+                    let document = format!(
+                        "{} {} {}",
+                        customer.first_name, customer.last_name, customer.customer_id
+                    );
+                    let score = word_similarity(text, &document);
+                    (score >= CUSTOMER_SEARCH_THRESHOLD).then(|| CustomerMatch {
+                        customer_id: customer.customer_id.clone(),
+                        first_name: customer.first_name.clone(),
+                        last_name: customer.last_name.clone(),
+                        score,
+                    })
+                })
+                .collect();
+            matches.sort_by(|a, b| {
+                b.score
+                    .total_cmp(&a.score)
+                    .then_with(|| a.customer_id.cmp(&b.customer_id))
+            });
+            matches.truncate(limit.max(0) as usize);
+            matches
+        })
+    }
+
+    async fn customer_summary(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<CustomerSummary>, sqlx::Error> {
+        self.read(|tables| {
+            let customer = tables.customer(&self.tenant_id, customer_id)?;
+            let rents: Vec<_> = tables
+                .rents(&self.tenant_id)
+                .filter(|rent| rent.customer_id == customer_id)
+                .collect();
+            let active = rents
+                .iter()
+                .filter(|rent| rent.end_date.is_none())
+                .max_by_key(|rent| rent.start_date);
+            let overdue = active.is_some_and(|rent| rent.start_date < overdue_since());
+            let active_rental = active.map(|rent| ActiveRental {
+                rent_id: rent.rent_id,
+                vehicle_id: rent.vehicle_id.clone(),
+                vehicle_type: tables
+                    .vehicle(&self.tenant_id, &rent.vehicle_id)
+                    .map(|vehicle| vehicle.vehicle_type.clone()),
+                start_date: rent.start_date,
+            });
+            Some(CustomerSummary {
+                profile: customer.into(),
+                active_rental,
+                past_rentals: rents.iter().filter(|rent| rent.end_date.is_some()).count() as i64,
+                loyalty_balance: None,
+                outstanding_balance: None,
+                blacklisted: None,
+                overdue,
+            })
+        })
+    }
+
+    async fn rent_status(&self, customer_id: &str) -> Result<Option<RentStatus>, sqlx::Error> {
+        self.read(|tables| {
+            tables.customer(&self.tenant_id, customer_id)?;
+            let rented = tables
+                .vehicles(&self.tenant_id)
+                .find(|vehicle| vehicle.current_renter_email.as_deref() == Some(customer_id));
+            let (vehicle_id, since) = rented
+                .map(|vehicle| (Some(vehicle.vehicle_id.clone()), vehicle.rented_since))
+                .unwrap_or_default();
+            Some(RentStatus {
+                active: vehicle_id.is_some(),
+                vehicle_id,
+                since,
+                due_date: since.map(|since| since + chrono::Duration::days(MAX_RENTAL_DAYS.into())),
+            })
+        })
+    }
+
+    async fn active_rentals(&self) -> Result<Vec<RentalView>, sqlx::Error> {
+        self.read(|tables| {
+            let mut active: Vec<_> = tables
+                .rents(&self.tenant_id)
+                .filter(|rent| rent.end_date.is_none())
+                .collect();
+            active.sort_by_key(|rent| (rent.start_date, rent.rent_id));
+            active.into_iter().map(Into::into).collect()
+        })
+    }
+
+    async fn find_rental(&self, rent_id: i64) -> Result<Option<RentalView>, sqlx::Error> {
+        self.read(|tables| {
+            tables
+                .rents
+                .get(&rent_id)
+                .filter(|rent| rent.tenant_id == self.tenant_id)
+                .map(Into::into)
+        })
+    }
+
+    async fn list_rentals(
+        &self,
+        filter: &RentalFilter,
+        sort: &SortParams<RentalView>,
+        page: PageParams,
+    ) -> Result<(Vec<RentalView>, i64), sqlx::Error> {
+        self.read(|tables| {
+            let mut rentals: Vec<_> = tables.filtered_rents(&self.tenant_id, filter).collect();
+            let total = rentals.len() as i64;
+            rentals.sort_by(|a, b| {
+                let ordering = match sort.field {
+                    "rentId" => a.rent_id.cmp(&b.rent_id),
+                    "endDate" => nulls_last(a.end_date, b.end_date),
+                    "durationMinutes" => nulls_last(a.duration_minutes, b.duration_minutes),
+                    "customerId" => a.customer_id.cmp(&b.customer_id),
+                    "vehicleId" => a.vehicle_id.cmp(&b.vehicle_id),
+                    _ => a.start_date.cmp(&b.start_date),
+                };
+                directed(ordering, sort.direction).then_with(|| a.rent_id.cmp(&b.rent_id))
+            });
+            let rentals = page_of(rentals, page).into_iter().map(Into::into).collect();
+            (rentals, total)
+        })
+    }
+
+    async fn count_rentals(&self, filter: &RentalFilter) -> Result<i64, sqlx::Error> {
+        self.read(|tables| tables.filtered_rents(&self.tenant_id, filter).count() as i64)
+    }
+
+    fn export_rentals(
+        &self,
+        filter: RentalFilter,
+    ) -> BoxStream<'static, Result<RentalExportRow, sqlx::Error>> {
+        let tables = self.tables.read().unwrap();
+        let mut rents: Vec<_> = tables.filtered_rents(&self.tenant_id, &filter).collect();
+        rents.sort_by_key(|rent| (rent.start_date, rent.rent_id));
+        let rows: Vec<_> = rents
+            .into_iter()
+            .map(|rent| {
+                let customer = tables.customer(&self.tenant_id, &rent.customer_id);
+                RentalExportRow {
+                    rent_id: rent.rent_id,
+                    customer_id: rent.customer_id.clone(),
+                    first_name: customer.map(|c| c.first_name.clone()).unwrap_or_default(),
+                    last_name: customer.map(|c| c.last_name.clone()).unwrap_or_default(),
+                    vehicle_id: rent.vehicle_id.clone(),
+                    vehicle_type: tables
+                        .vehicle(&self.tenant_id, &rent.vehicle_id)
+                        .map(|vehicle| vehicle.vehicle_type.clone()),
+                    start_date: rent.start_date,
+                    end_date: rent.end_date,
+                    duration_minutes: rent.duration_minutes,
+                }
+            })
+            .collect();
+        stream::iter(rows.into_iter().map(Ok)).boxed()
+    }
+
+    async fn search(&self, text: &str, limit: i64) -> Result<Vec<SearchHit>, sqlx::Error> {
+        let words = lexemes(text);
+        let limit = limit.max(0) as usize;
+        self.read(|tables| {
+            let mut vehicles: Vec<_> = tables
+                .vehicles(&self.tenant_id)
+                .filter_map(|vehicle| {
+                    let document = format!("{} {}", vehicle.vehicle_id, vehicle.vehicle_type);
+                    prefix_rank(&words, &lexemes(&document)).map(|rank| (rank, vehicle))
+                })
+                .collect();
+            vehicles.sort_by(|(a_rank, a), (b_rank, b)| {
+                b_rank
+                    .cmp(a_rank)
+                    .then_with(|| a.vehicle_id.cmp(&b.vehicle_id))
+            });
+            let mut customers: Vec<_> = tables
+                .customers(&self.tenant_id)
+                .filter_map(|customer| {
+                    let document = format!(
+                        "{} {} {}",
+                        customer.customer_id, customer.first_name, customer.last_name
+                    );
+                    prefix_rank(&words, &lexemes(&document)).map(|rank| (rank, customer))
+                })
+                .collect();
+            customers.sort_by(|(a_rank, a), (b_rank, b)| {
+                b_rank
+                    .cmp(a_rank)
+                    .then_with(|| a.customer_id.cmp(&b.customer_id))
+            });
+            let mut rentals: Vec<_> = tables
+                .rents(&self.tenant_id)
+                .filter_map(|rent| {
+                    let document = format!("{} {}", rent.customer_id, rent.vehicle_id);
+                    prefix_rank(&words, &lexemes(&document)).map(|rank| (rank, rent))
+                })
+                .collect();
+            rentals.sort_by(|(a_rank, a), (b_rank, b)| {
+                b_rank
+                    .cmp(a_rank)
+                    .then_with(|| b.start_date.cmp(&a.start_date))
+            });
+
+            let vehicles = vehicles.into_iter().take(limit);
+            let customers = customers.into_iter().take(limit);
+            let rentals = rentals.into_iter().take(limit);
+            vehicles
+                .map(|(_, vehicle)| SearchHit::Vehicle(vehicle.into()))
+                .chain(customers.map(|(_, customer)| SearchHit::Customer(customer.into())))
+                .chain(rentals.map(|(_, rent)| SearchHit::Rental(rent.into())))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::DEFAULT_TENANT;
+
+    fn rented(
+        event_id: i64,
+        customer_id: &str,
+        vehicle_id: &str,
+        start_date: &str,
+    ) -> PersistedEvent<DomainEvent> {
+        PersistedEvent::new(
+            event_id,
+            DomainEvent::VehicleRented {
+                tenant_id: default_tenant(),
+                customer_id: customer_id.into(),
+                vehicle_id: vehicle_id.to_string(),
+                vehicle_type: VehicleType::Car,
+                start_date: start_date.parse().unwrap(),
+            },
+        )
+    }
+
+    fn returned(
+        event_id: i64,
+        customer_id: &str,
+        vehicle_id: &str,
+        returned_date: &str,
+    ) -> PersistedEvent<DomainEvent> {
+        PersistedEvent::new(
+            event_id,
+            DomainEvent::VehicleReturned {
+                tenant_id: default_tenant(),
+                customer_id: customer_id.into(),
+                vehicle_id: vehicle_id.to_string(),
+                vehicle_type: VehicleType::Car,
+                start_date: None,
+                returned_date: returned_date.parse().unwrap(),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn it_should_project_the_rentals_once_each() {
+        let read_model = MemoryReadModel::default();
+        let events = [
+            PersistedEvent::new(
+                1,
+                DomainEvent::VehicleAdded {
+                    tenant_id: default_tenant(),
+                    vehicle_id: "AA111AA".to_string(),
+                    vehicle_type: VehicleType::Car,
+                    seats: Some(5),
+                    transmission: None,
+                },
+            ),
+            PersistedEvent::new(
+                2,
+                DomainEvent::CustomerRegistered {
+                    tenant_id: default_tenant(),
+                    customer_id: "mario@example.com".into(),
+                    first_name: "Mario".to_string(),
+                    last_name: "Rossi".to_string(),
+                    phone: None,
+                },
+            ),
+            rented(3, "mario@example.com", "AA111AA", "2024-06-01T10:00:00Z"),
+            returned(4, "mario@example.com", "AA111AA", "2024-06-01T12:30:00Z"),
+            rented(5, "mario@example.com", "AA111AA", "2024-06-02T10:00:00Z"),
+        ];
+        // Delivered again, as the listeners may.
+        for event in events.iter().chain(&events) {
+            read_model.apply(event);
+        }
+
+        let rentals = read_model.active_rentals().await.unwrap();
+        assert_eq!(rentals.len(), 1);
+        assert_eq!(rentals[0].rent_id, 5);
+        let closed = read_model.find_rental(3).await.unwrap().unwrap();
+        assert_eq!(closed.duration_minutes, Some(150));
+        let stats = read_model.vehicle_stats("AA111AA").await.unwrap().unwrap();
+        assert_eq!((stats.rentals, stats.rented_minutes), (2, 150));
+        let customer = read_model
+            .customer_detail("mario@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(customer.version, 5);
+        let status = read_model
+            .rent_status("mario@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.vehicle_id.as_deref(), Some("AA111AA"));
+
+        let summary = read_model.availability_summary(None).await.unwrap();
+        let cars = summary
+            .iter()
+            .find(|summary| summary.vehicle_type == VehicleType::Car)
+            .unwrap();
+        assert_eq!((cars.total, cars.available, cars.rented), (1, 0, 1));
+        let other = read_model.for_tenant("north".to_string());
+        assert_eq!(other.count_customers().await.unwrap(), 0);
+        assert_eq!(read_model.tenant_id(), DEFAULT_TENANT);
+    }
+
+    #[test]
+    fn it_should_match_every_word_as_a_prefix() {
+        let document = lexemes("mario@example.com Mario Rossi");
+        assert_eq!(
+            prefix_rank(&lexemes("mar ros"), &document),
+            Some(3),
+            "{document:?}"
+        );
+        assert_eq!(prefix_rank(&lexemes("mar verdi"), &document), None);
+    }
+
+    #[test]
+    fn it_should_find_misspelled_words_similar() {
+        let document = "Mario Rossi mario@example.com";
+        assert!(word_similarity("rosi", document) >= CUSTOMER_SEARCH_THRESHOLD);
+        assert!(word_similarity("luigi", document) < CUSTOMER_SEARCH_THRESHOLD);
+        assert_eq!(word_similarity("rossi", document), 1.0);
+    }
+}
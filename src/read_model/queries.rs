@@ -0,0 +1,1354 @@
+use actix_web::{
+    dev::Payload, error::ErrorInternalServerError, web::Data, FromRequest, HttpRequest,
+};
+use async_stream::try_stream;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::{
+    future::{ready, Ready},
+    Stream, TryStreamExt,
+};
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+
+use super::VehicleStatus;
+use crate::{
+    domain::{default_tenant, TenantId, Transmission, VehicleType},
+    filters::{CalendarRange, RentalFilter, RentalStatus, VehicleFilter, MAX_RENTAL_DAYS},
+    pagination::PageParams,
+    sorting::{SortDirection, SortParams, Sortable},
+    tenant::Tenant,
+};
+
+/// Reads of the read model, shared by the HTTP handlers.
+///
+/// Queries of a single concern, such as reports, are implemented in their own module.
+///
+/// Reads the rows of a single tenant, the default one unless scoped by `for_tenant`; the
+/// handlers extract it scoped to the tenant of the caller.
+#[derive(Debug, Clone)]
+pub struct ReadModelRepository {
+    pub(crate) pool: PgPool,
+    pub(crate) tenant_id: TenantId,
+}
+
+impl ReadModelRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            tenant_id: default_tenant(),
+        }
+    }
+
+    pub fn for_tenant(&self, tenant_id: TenantId) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            tenant_id,
+        }
+    }
+}
+
+impl FromRequest for ReadModelRepository {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let repository = req
+            .app_data::<Data<ReadModelRepository>>()
+            .ok_or_else(|| ErrorInternalServerError("the read model is not configured"))
+            .map(|repository| repository.for_tenant(Tenant::of(req).0));
+        ready(repository)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailabilitySummary {
+    pub vehicle_type: VehicleType,
+    pub total: i64,
+    pub available: i64,
+    pub rented: i64,
+    pub maintenance: i64,
+}
+
+impl ReadModelRepository {
+    /// Counts the registered, available, rented and in maintenance vehicles of each type in a
+    /// single query.
+    ///
+    /// Types without any registered vehicle are reported with zero counts.
+    pub async fn availability_summary(
+        &self,
+        vehicle_type: Option<VehicleType>,
+    ) -> Result<Vec<AvailabilitySummary>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"SELECT t.vehicle_type AS "vehicle_type!: VehicleType",
+                    count(v.vehicle_id) AS "total!",
+                    count(v.vehicle_id) FILTER (
+                        WHERE r.vehicle_id IS NULL AND v.status <> 'maintenance'
+                    ) AS "available!",
+                    count(r.vehicle_id) AS "rented!",
+                    count(v.vehicle_id) FILTER (WHERE v.status = 'maintenance') AS "maintenance!"
+                FROM unnest(enum_range(NULL::vehicle_type)) WITH ORDINALITY AS t(vehicle_type, position)
+                LEFT JOIN vehicle v ON v.vehicle_type = t.vehicle_type AND v.tenant_id = $2
+                LEFT JOIN rent r ON r.tenant_id = v.tenant_id AND r.vehicle_id = v.vehicle_id
+                    AND r.end_date IS NULL
+                WHERE $1::vehicle_type IS NULL OR t.vehicle_type = $1
+                GROUP BY t.vehicle_type, t.position
+                ORDER BY t.position"#,
+            vehicle_type as Option<VehicleType>,
+            self.tenant_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| AvailabilitySummary {
+                vehicle_type: row.vehicle_type,
+                total: row.total,
+                available: row.available,
+                rented: row.rented,
+                maintenance: row.maintenance,
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarDay {
+    pub day: NaiveDate,
+    pub total: i64,
+    pub available: i64,
+}
+
+impl ReadModelRepository {
+    /// Counts, for every day of the range, the vehicles of the type that are free the whole
+    /// day: registered by its end and not in a rental overlapping it, even partially.
+    ///
+    /// Days are calendar days in UTC. Open rentals have no known end, so they keep their
+    /// vehicle unavailable on every day that follows.
+    pub async fn availability_calendar(
+        &self,
+        range: &CalendarRange,
+    ) -> Result<Vec<CalendarDay>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"SELECT d.day::date AS "day!",
+                    (SELECT count(*) FROM vehicle v
+                        WHERE v.tenant_id = $4 AND v.vehicle_type = $1
+                            AND v.registered_at < b.day_end) AS "total!",
+                    (SELECT count(DISTINCT r.vehicle_id) FROM rent r
+                        JOIN vehicle v ON v.tenant_id = r.tenant_id AND v.vehicle_id = r.vehicle_id
+                        WHERE r.tenant_id = $4 AND v.vehicle_type = $1
+                            AND r.start_date < b.day_end
+                            AND (r.end_date IS NULL OR r.end_date > b.day_start)) AS "unavailable!"
+                FROM generate_series($2::date::timestamp, $3::date::timestamp, interval '1 day') AS d(day)
+                CROSS JOIN LATERAL (
+                    SELECT d.day AT TIME ZONE 'UTC' AS day_start,
+                        (d.day + interval '1 day') AT TIME ZONE 'UTC' AS day_end
+                ) b
+                ORDER BY d.day"#,
+            range.vehicle_type.clone() as VehicleType,
+            range.from,
+            range.to,
+            self.tenant_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| CalendarDay {
+                day: row.day,
+                total: row.total,
+                available: (row.total - row.unavailable).max(0),
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleView {
+    pub vehicle_id: String,
+    pub vehicle_type: VehicleType,
+    pub status: VehicleStatus,
+    pub current_renter_email: Option<String>,
+    pub rented_since: Option<DateTime<Utc>>,
+    /// Unknown for the vehicles registered without them.
+    pub seats: Option<i16>,
+    pub transmission: Option<Transmission>,
+}
+
+const VEHICLE_COLUMNS: &str =
+    "vehicle_id, vehicle_type, status, current_renter_email, rented_since, last_event_id, seats, transmission";
+
+#[derive(sqlx::FromRow)]
+struct VehicleRow {
+    vehicle_id: String,
+    vehicle_type: VehicleType,
+    status: String,
+    current_renter_email: Option<String>,
+    rented_since: Option<DateTime<Utc>>,
+    last_event_id: i64,
+    seats: Option<i16>,
+    transmission: Option<String>,
+}
+
+/// A resource along with its version, the id of the last event applied to it.
+#[derive(Debug)]
+pub struct Versioned<T> {
+    pub value: T,
+    pub version: i64,
+}
+
+impl TryFrom<VehicleRow> for VehicleView {
+    type Error = sqlx::Error;
+
+    fn try_from(row: VehicleRow) -> Result<Self, Self::Error> {
+        Ok(VehicleView {
+            vehicle_id: row.vehicle_id,
+            vehicle_type: row.vehicle_type,
+            status: row
+                .status
+                .parse()
+                .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+            current_renter_email: row.current_renter_email,
+            rented_since: row.rented_since,
+            seats: row.seats,
+            transmission: row
+                .transmission
+                .map(|transmission| transmission.parse())
+                .transpose()
+                .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+        })
+    }
+}
+
+impl Sortable for VehicleView {
+    const SORT_FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("vehicleId", "vehicle_id"),
+        ("vehicleType", "vehicle_type::text"),
+        ("status", "status"),
+    ];
+    const DEFAULT_SORT: (&'static str, SortDirection) = ("vehicleId", SortDirection::Asc);
+}
+
+impl ReadModelRepository {
+    pub async fn find_vehicle(
+        &self,
+        vehicle_id: &str,
+    ) -> Result<Option<Versioned<VehicleView>>, sqlx::Error> {
+        let row = sqlx::query_as!(
+            VehicleRow,
+            r#"SELECT vehicle_id, vehicle_type AS "vehicle_type: VehicleType", status, current_renter_email, rented_since, last_event_id, seats, transmission
+                FROM vehicle WHERE tenant_id = $1 AND vehicle_id = $2"#,
+            self.tenant_id,
+            vehicle_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(|row| {
+            let version = row.last_event_id;
+            VehicleView::try_from(row).map(|value| Versioned { value, version })
+        })
+        .transpose()
+    }
+
+    pub async fn list_vehicles(
+        &self,
+        filter: &VehicleFilter,
+        sort: &SortParams<VehicleView>,
+        page: PageParams,
+    ) -> Result<(Vec<VehicleView>, i64), sqlx::Error> {
+        let mut select = QueryBuilder::new(format!("SELECT {VEHICLE_COLUMNS} FROM vehicle"));
+        push_vehicle_filter(&mut select, &self.tenant_id, filter);
+        select
+            .push(format!(" ORDER BY {}, vehicle_id LIMIT ", sort.order_by()))
+            .push_bind(page.limit)
+            .push(" OFFSET ")
+            .push_bind(page.offset);
+        let rows: Vec<VehicleRow> = select.build_query_as().fetch_all(&self.pool).await?;
+        let total = self.count_vehicles(filter).await?;
+
+        let vehicles = rows
+            .into_iter()
+            .map(VehicleView::try_from)
+            .collect::<Result<_, _>>()?;
+        Ok((vehicles, total))
+    }
+
+    pub async fn count_vehicles(&self, filter: &VehicleFilter) -> Result<i64, sqlx::Error> {
+        let mut count = QueryBuilder::new("SELECT count(*) FROM vehicle");
+        push_vehicle_filter(&mut count, &self.tenant_id, filter);
+        count.build_query_scalar().fetch_one(&self.pool).await
+    }
+}
+
+fn push_vehicle_filter(
+    builder: &mut QueryBuilder<Postgres>,
+    tenant_id: &TenantId,
+    filter: &VehicleFilter,
+) {
+    builder
+        .push(" WHERE tenant_id = ")
+        .push_bind(tenant_id.clone());
+    if let Some(vehicle_type) = &filter.vehicle_type {
+        builder
+            .push(" AND vehicle_type = ")
+            .push_bind(vehicle_type.clone());
+    }
+    if let Some(status) = filter.status {
+        builder.push(" AND status = ").push_bind(status.to_string());
+    }
+    // Vehicles registered without seats or transmission match no filter on them.
+    if let Some(min_seats) = filter.min_seats {
+        builder.push(" AND seats >= ").push_bind(min_seats);
+    }
+    if let Some(transmission) = filter.transmission {
+        builder
+            .push(" AND transmission = ")
+            .push_bind(transmission.to_string());
+    }
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomerView {
+    pub customer_id: String,
+    pub first_name: String,
+    pub last_name: String,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomerMatch {
+    pub customer_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub score: f32,
+}
+
+/// Minimum `word_similarity` for a customer to match a search, lower than the
+/// pg_trgm default (0.6) so that misspelled names are still found.
+const CUSTOMER_SEARCH_THRESHOLD: f32 = 0.4;
+
+impl ReadModelRepository {
+    pub async fn customer_detail(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<Versioned<CustomerView>>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"SELECT customer_id, first_name AS "first_name!", last_name AS "last_name!", last_event_id
+                FROM customer WHERE tenant_id = $1 AND customer_id = $2"#,
+            self.tenant_id,
+            customer_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| Versioned {
+            value: CustomerView {
+                customer_id: row.customer_id,
+                first_name: row.first_name,
+                last_name: row.last_name,
+            },
+            version: row.last_event_id,
+        }))
+    }
+
+    pub async fn list_customers(
+        &self,
+        page: PageParams,
+    ) -> Result<(Vec<CustomerView>, i64), sqlx::Error> {
+        let customers = sqlx::query_as!(
+            CustomerView,
+            r#"SELECT customer_id, first_name AS "first_name!", last_name AS "last_name!"
+                FROM customer WHERE tenant_id = $3 ORDER BY customer_id LIMIT $1 OFFSET $2"#,
+            page.limit,
+            page.offset,
+            self.tenant_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let total = self.count_customers().await?;
+        Ok((customers, total))
+    }
+
+    pub async fn count_customers(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!" FROM customer WHERE tenant_id = $1"#,
+            self.tenant_id,
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Ranks the customers whose name or email resembles `text`, tolerating typos.
+    pub async fn search_customers(
+        &self,
+        text: &str,
+        limit: i64,
+    ) -> Result<Vec<CustomerMatch>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "SELECT set_config('pg_trgm.word_similarity_threshold', $1, true)",
+            CUSTOMER_SEARCH_THRESHOLD.to_string(),
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let matches = sqlx::query_as!(
+            CustomerMatch,
+            r#"SELECT customer_id, first_name AS "first_name!", last_name AS "last_name!",
+                    word_similarity($1, first_name || ' ' || last_name || ' ' || customer_id) AS "score!"
+                FROM customer
+                WHERE tenant_id = $3 AND $1 <% (first_name || ' ' || last_name || ' ' || customer_id)
+                ORDER BY "score!" DESC, customer_id
+                LIMIT $2"#,
+            text,
+            limit,
+            self.tenant_id,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(matches)
+    }
+}
+
+/// What support screens show about a customer, in one call.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomerSummary {
+    #[serde(flatten)]
+    pub profile: CustomerView,
+    pub active_rental: Option<ActiveRental>,
+    pub past_rentals: i64,
+    /// Not tracked by the read model yet, always null.
+    pub loyalty_balance: Option<i64>,
+    /// Not tracked by the read model yet, always null.
+    pub outstanding_balance: Option<i64>,
+    /// Not tracked by the read model yet, always null.
+    pub blacklisted: Option<bool>,
+    pub overdue: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveRental {
+    pub rent_id: i64,
+    pub vehicle_id: String,
+    pub vehicle_type: Option<VehicleType>,
+    pub start_date: DateTime<Utc>,
+}
+
+impl ReadModelRepository {
+    /// Assembles the summary of the customer in a single query, `None` when there is no such
+    /// customer.
+    pub async fn customer_summary(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<CustomerSummary>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"SELECT c.customer_id, c.first_name AS "first_name!", c.last_name AS "last_name!",
+                    a.rent_id AS "rent_id?", a.vehicle_id AS "vehicle_id?",
+                    v.vehicle_type AS "vehicle_type?: VehicleType", a.start_date AS "start_date?",
+                    coalesce(a.start_date < now() - make_interval(days => $2), false) AS "overdue!",
+                    (SELECT count(*) FROM rent p
+                        WHERE p.tenant_id = c.tenant_id AND p.customer_id = c.customer_id
+                            AND p.end_date IS NOT NULL) AS "past_rentals!"
+                FROM customer c
+                LEFT JOIN LATERAL (
+                    SELECT rent_id, vehicle_id, start_date FROM rent
+                    WHERE tenant_id = c.tenant_id AND customer_id = c.customer_id AND end_date IS NULL
+                    ORDER BY start_date DESC LIMIT 1
+                ) a ON true
+                LEFT JOIN vehicle v ON v.tenant_id = c.tenant_id AND v.vehicle_id = a.vehicle_id
+                WHERE c.tenant_id = $3 AND c.customer_id = $1"#,
+            customer_id,
+            MAX_RENTAL_DAYS,
+            self.tenant_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            let active_rental = match (row.rent_id, row.vehicle_id, row.start_date) {
+                (Some(rent_id), Some(vehicle_id), Some(start_date)) => Some(ActiveRental {
+                    rent_id,
+                    vehicle_id,
+                    vehicle_type: row.vehicle_type,
+                    start_date,
+                }),
+                _ => None,
+            };
+            CustomerSummary {
+                profile: CustomerView {
+                    customer_id: row.customer_id,
+                    first_name: row.first_name,
+                    last_name: row.last_name,
+                },
+                active_rental,
+                past_rentals: row.past_rentals,
+                loyalty_balance: None,
+                outstanding_balance: None,
+                blacklisted: None,
+                overdue: row.overdue,
+            }
+        }))
+    }
+}
+
+/// Whether the customer is renting a vehicle, for the desk to check before handing over the
+/// keys.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RentStatus {
+    pub active: bool,
+    pub vehicle_id: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    /// `MAX_RENTAL_DAYS` after `since`.
+    pub due_date: Option<DateTime<Utc>>,
+}
+
+/// Served by the customer primary key and `idx_vehicle_current_renter`: the renter of each
+/// vehicle is kept on it, so no rental is scanned.
+const RENT_STATUS: &str = r#"
+    SELECT v.vehicle_id, v.rented_since
+    FROM customer c
+    LEFT JOIN vehicle v ON v.tenant_id = c.tenant_id AND v.current_renter_email = c.customer_id
+    WHERE c.tenant_id = $1 AND c.customer_id = $2"#;
+
+impl ReadModelRepository {
+    /// The rental status of the customer, `None` when there is no such customer.
+    pub async fn rent_status(&self, customer_id: &str) -> Result<Option<RentStatus>, sqlx::Error> {
+        let row: Option<(Option<String>, Option<DateTime<Utc>>)> = sqlx::query_as(RENT_STATUS)
+            .bind(&self.tenant_id)
+            .bind(customer_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|(vehicle_id, since)| RentStatus {
+            active: vehicle_id.is_some(),
+            vehicle_id,
+            since,
+            due_date: since.map(|since| since + chrono::Duration::days(MAX_RENTAL_DAYS.into())),
+        }))
+    }
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct RentalView {
+    pub rent_id: i64,
+    pub customer_id: String,
+    pub vehicle_id: String,
+    pub start_date: DateTime<Utc>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub duration_minutes: Option<i64>,
+}
+
+impl Sortable for RentalView {
+    const SORT_FIELDS: &'static [(&'static str, &'static str)] = &[
+        ("rentId", "rent_id"),
+        ("startDate", "start_date"),
+        ("endDate", "end_date"),
+        ("durationMinutes", "duration_minutes"),
+        ("customerId", "customer_id"),
+        ("vehicleId", "vehicle_id"),
+    ];
+    const DEFAULT_SORT: (&'static str, SortDirection) = ("startDate", SortDirection::Desc);
+}
+
+impl ReadModelRepository {
+    /// Lists the rentals still open, the oldest first.
+    ///
+    /// A vehicle has at most one open rental, so the list is bounded by the fleet size.
+    pub async fn active_rentals(&self) -> Result<Vec<RentalView>, sqlx::Error> {
+        sqlx::query_as!(
+            RentalView,
+            r#"SELECT rent_id, customer_id, vehicle_id, start_date AS "start_date!", end_date, duration_minutes
+                FROM rent WHERE tenant_id = $1 AND end_date IS NULL ORDER BY start_date, rent_id"#,
+            self.tenant_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn find_rental(&self, rent_id: i64) -> Result<Option<RentalView>, sqlx::Error> {
+        sqlx::query_as!(
+            RentalView,
+            r#"SELECT rent_id, customer_id, vehicle_id, start_date AS "start_date!", end_date, duration_minutes
+                FROM rent WHERE tenant_id = $1 AND rent_id = $2"#,
+            self.tenant_id,
+            rent_id,
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn list_rentals(
+        &self,
+        filter: &RentalFilter,
+        sort: &SortParams<RentalView>,
+        page: PageParams,
+    ) -> Result<(Vec<RentalView>, i64), sqlx::Error> {
+        let mut select = QueryBuilder::new(
+            "SELECT rent_id, customer_id, vehicle_id, start_date, end_date, duration_minutes FROM rent",
+        );
+        push_rental_filter(&mut select, "", &self.tenant_id, filter);
+        select
+            .push(format!(" ORDER BY {}, rent_id LIMIT ", sort.order_by()))
+            .push_bind(page.limit)
+            .push(" OFFSET ")
+            .push_bind(page.offset);
+        let rentals = select.build_query_as().fetch_all(&self.pool).await?;
+        let total = self.count_rentals(filter).await?;
+        Ok((rentals, total))
+    }
+
+    pub async fn count_rentals(&self, filter: &RentalFilter) -> Result<i64, sqlx::Error> {
+        let mut count = QueryBuilder::new("SELECT count(*) FROM rent");
+        push_rental_filter(&mut count, "", &self.tenant_id, filter);
+        count.build_query_scalar().fetch_one(&self.pool).await
+    }
+}
+
+/// A rental along with its customer and vehicle, as exported to spreadsheets.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct RentalExportRow {
+    pub rent_id: i64,
+    pub customer_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub vehicle_id: String,
+    pub vehicle_type: Option<VehicleType>,
+    pub start_date: DateTime<Utc>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub duration_minutes: Option<i64>,
+}
+
+impl RentalExportRow {
+    pub const HEADER: &'static [&'static str] = &[
+        "rentId",
+        "customerId",
+        "firstName",
+        "lastName",
+        "vehicleId",
+        "vehicleType",
+        "startDate",
+        "endDate",
+        "durationMinutes",
+    ];
+}
+
+impl ReadModelRepository {
+    /// Streams the rentals matching the filter, the oldest first, fetching them as they are
+    /// consumed.
+    pub fn export_rentals(
+        &self,
+        filter: RentalFilter,
+    ) -> impl Stream<Item = Result<RentalExportRow, sqlx::Error>> + 'static {
+        let (pool, tenant_id) = (self.pool.clone(), self.tenant_id.clone());
+        try_stream! {
+            let mut select = QueryBuilder::new(
+                r#"SELECT r.rent_id, r.customer_id,
+                        coalesce(c.first_name, '') AS first_name,
+                        coalesce(c.last_name, '') AS last_name,
+                        r.vehicle_id, v.vehicle_type, r.start_date, r.end_date, r.duration_minutes
+                    FROM rent r
+                    LEFT JOIN customer c ON c.tenant_id = r.tenant_id AND c.customer_id = r.customer_id
+                    LEFT JOIN vehicle v ON v.tenant_id = r.tenant_id AND v.vehicle_id = r.vehicle_id"#,
+            );
+            push_rental_filter(&mut select, "r.", &tenant_id, &filter);
+            select.push(" ORDER BY r.start_date, r.rent_id");
+            let mut rows = select.build_query_as::<RentalExportRow>().fetch(&pool);
+            while let Some(row) = rows.try_next().await? {
+                yield row;
+            }
+        }
+    }
+}
+
+/// The tenant is qualified by `prefix`, the alias of `rent` when joined to the tables also
+/// holding one.
+fn push_rental_filter(
+    builder: &mut QueryBuilder<Postgres>,
+    prefix: &str,
+    tenant_id: &TenantId,
+    filter: &RentalFilter,
+) {
+    builder
+        .push(format!(" WHERE {prefix}tenant_id = "))
+        .push_bind(tenant_id.clone());
+    match filter.status {
+        Some(RentalStatus::Open) => {
+            builder.push(" AND end_date IS NULL");
+        }
+        Some(RentalStatus::Closed) => {
+            builder.push(" AND end_date IS NOT NULL");
+        }
+        Some(RentalStatus::Overdue) => {
+            builder
+                .push(" AND end_date IS NULL AND start_date < now() - make_interval(days => ")
+                .push_bind(MAX_RENTAL_DAYS)
+                .push(")");
+        }
+        None => {}
+    }
+    let date_column = if filter.status == Some(RentalStatus::Closed) {
+        "end_date"
+    } else {
+        "start_date"
+    };
+    if let Some(from) = filter.from {
+        builder
+            .push(format!(" AND {date_column} >= "))
+            .push_bind(from);
+    }
+    if let Some(to) = filter.to {
+        builder
+            .push(format!(" AND {date_column} <= "))
+            .push_bind(to);
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SearchHit {
+    Vehicle(VehicleView),
+    Customer(CustomerView),
+    Rental(RentalView),
+}
+
+impl ReadModelRepository {
+    /// Full-text search across vehicles, customers and rentals, returning at most
+    /// `limit` hits per type, grouped by type and ranked within each group.
+    pub async fn search(&self, text: &str, limit: i64) -> Result<Vec<SearchHit>, sqlx::Error> {
+        let query = prefix_tsquery(text);
+
+        let vehicles = sqlx::query_as!(
+            VehicleRow,
+            r#"SELECT vehicle_id, vehicle_type AS "vehicle_type: VehicleType", status, current_renter_email, rented_since, last_event_id, seats, transmission
+                FROM vehicle
+                WHERE tenant_id = $3 AND search @@ to_tsquery('simple', $1)
+                ORDER BY ts_rank(search, to_tsquery('simple', $1)) DESC, vehicle_id
+                LIMIT $2"#,
+            query,
+            limit,
+            self.tenant_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let customers = sqlx::query_as!(
+            CustomerView,
+            r#"SELECT customer_id, first_name AS "first_name!", last_name AS "last_name!" FROM customer
+                WHERE tenant_id = $3 AND search @@ to_tsquery('simple', $1)
+                ORDER BY ts_rank(search, to_tsquery('simple', $1)) DESC, customer_id
+                LIMIT $2"#,
+            query,
+            limit,
+            self.tenant_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let rentals = sqlx::query_as!(
+            RentalView,
+            r#"SELECT rent_id, customer_id, vehicle_id, start_date AS "start_date!", end_date, duration_minutes
+                FROM rent
+                WHERE tenant_id = $3 AND search @@ to_tsquery('simple', $1)
+                ORDER BY ts_rank(search, to_tsquery('simple', $1)) DESC, start_date DESC
+                LIMIT $2"#,
+            query,
+            limit,
+            self.tenant_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut hits = Vec::with_capacity(vehicles.len() + customers.len() + rentals.len());
+        for vehicle in vehicles {
+            hits.push(SearchHit::Vehicle(vehicle.try_into()?));
+        }
+        hits.extend(customers.into_iter().map(SearchHit::Customer));
+        hits.extend(rentals.into_iter().map(SearchHit::Rental));
+        Ok(hits)
+    }
+}
+
+/// Turns free text into a tsquery matching every word as a prefix.
+///
+/// Words are quoted so that no tsquery operator in the input is interpreted.
+fn prefix_tsquery(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("'{}':*", word.replace('\\', "\\\\").replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join(" & ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::{RentEvent, DEFAULT_TENANT},
+        read_model::{RentalProjection, VehicleProjection},
+        test_support,
+    };
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    #[test]
+    fn it_should_quote_every_word_of_a_search_as_a_prefix() {
+        assert_eq!(prefix_tsquery(" AA111 rossi "), "'AA111':* & 'rossi':*");
+        assert_eq!(
+            prefix_tsquery("o'neil | !x"),
+            "'o''neil':* & '|':* & '!x':*"
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_look_up_the_rent_status_in_the_indexes(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        // 10000 customers, the even ones renting a vehicle each.
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name)
+                SELECT 'customer' || i || '@example.com', 'Mario', 'Rossi'
+                FROM generate_series(1, 10000) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at, status,
+                    current_renter_email, rented_since)
+                SELECT 'V' || i, 'car', '2024-06-01T00:00:00Z',
+                    CASE WHEN i % 2 = 0 THEN 'rented' ELSE 'available' END,
+                    CASE WHEN i % 2 = 0 THEN 'customer' || i || '@example.com' END,
+                    CASE WHEN i % 2 = 0 THEN timestamptz '2024-07-01T00:00:00Z' END
+                FROM generate_series(1, 10000) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query("ANALYZE customer, vehicle")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let plan: Vec<String> = sqlx::query_scalar(&format!("EXPLAIN {RENT_STATUS}"))
+            .bind(DEFAULT_TENANT)
+            .bind("customer42@example.com")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let plan = plan.join("\n");
+        assert!(plan.contains("idx_vehicle_current_renter"), "{plan}");
+        assert!(!plan.contains("Seq Scan"), "{plan}");
+
+        let repository = ReadModelRepository::new(pool);
+        let since: DateTime<Utc> = "2024-07-01T00:00:00Z".parse().unwrap();
+        assert_eq!(
+            repository
+                .rent_status("customer42@example.com")
+                .await
+                .unwrap(),
+            Some(RentStatus {
+                active: true,
+                vehicle_id: Some("V42".to_string()),
+                since: Some(since),
+                due_date: Some("2024-07-31T00:00:00Z".parse().unwrap()),
+            })
+        );
+        assert_eq!(
+            repository
+                .rent_status("customer43@example.com")
+                .await
+                .unwrap(),
+            Some(RentStatus {
+                active: false,
+                vehicle_id: None,
+                since: None,
+                due_date: None,
+            })
+        );
+        assert_eq!(
+            repository.rent_status("nobody@example.com").await.unwrap(),
+            None
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_take_a_vehicle_off_every_day_its_rental_overlaps(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at) VALUES
+                ('AA111AA', 'car', '2024-06-01T00:00:00Z'),
+                ('BB222BB', 'car', '2024-06-01T00:00:00Z'),
+                ('CC333CC', 'van', '2024-06-01T00:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date) VALUES
+                (1, 'mario@example.com', 'AA111AA', '2024-07-02T18:00:00Z', '2024-07-04T09:00:00Z'),
+                (2, 'luigi@example.com', 'CC333CC', '2024-07-01T00:00:00Z', '2024-07-06T00:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let range =
+            CalendarRange::new(Some("car"), Some("2024-07-01"), Some("2024-07-06")).unwrap();
+        let calendar = ReadModelRepository::new(pool)
+            .availability_calendar(&range)
+            .await
+            .unwrap();
+        let available: Vec<(String, i64)> = calendar
+            .iter()
+            .map(|day| (day.day.to_string(), day.available))
+            .collect();
+        assert_eq!(
+            available,
+            [
+                ("2024-07-01".to_string(), 2),
+                ("2024-07-02".to_string(), 1),
+                ("2024-07-03".to_string(), 1),
+                ("2024-07-04".to_string(), 1),
+                ("2024-07-05".to_string(), 2),
+                ("2024-07-06".to_string(), 2),
+            ]
+        );
+        assert!(calendar.iter().all(|day| day.total == 2));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_shift_the_availability_counts_once_a_car_is_rented(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let vehicles = VehicleProjection::new(pool.clone());
+        let rentals = RentalProjection::new(pool.clone());
+        for (event_id, vehicle_id) in [(1, "AA111AA"), (2, "BB222BB"), (3, "CC333CC")] {
+            vehicles
+                .apply(
+                    event_id,
+                    RentEvent::VehicleAdded {
+                        tenant_id: default_tenant(),
+                        vehicle_id: vehicle_id.to_string(),
+                        vehicle_type: VehicleType::Car,
+                        seats: None,
+                        transmission: None,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE vehicle SET status = 'maintenance' WHERE vehicle_id = 'CC333CC'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let repository = ReadModelRepository::new(pool);
+        let counts = |summary: Vec<AvailabilitySummary>| {
+            summary
+                .into_iter()
+                .map(|row| (row.total, row.available, row.rented, row.maintenance))
+                .collect::<Vec<_>>()
+        };
+
+        let summary = repository
+            .availability_summary(Some(VehicleType::Car))
+            .await
+            .unwrap();
+        assert_eq!(counts(summary), [(3, 2, 0, 1)]);
+
+        let rented = RentEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: Utc::now(),
+        };
+        vehicles.apply(4, rented.clone()).await.unwrap();
+        rentals.apply(4, rented).await.unwrap();
+
+        let summary = repository.availability_summary(None).await.unwrap();
+        let car = summary
+            .iter()
+            .position(|row| row.vehicle_type == VehicleType::Car)
+            .unwrap();
+        assert_eq!(summary.len(), VehicleType::ALL.len());
+        assert_eq!(counts(summary)[car], (3, 1, 1, 1));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_rank_the_customers_by_the_similarity_of_their_name(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name) VALUES
+                ('anna@example.com', 'Anna', 'Bianchi'),
+                ('luca@example.com', 'Luca', 'Ross'),
+                ('mario@example.com', 'Mario', 'Rossi')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+
+        let matches = repository.search_customers("rossi", 10).await.unwrap();
+        let ranked: Vec<&str> = matches.iter().map(|m| m.last_name.as_str()).collect();
+        assert_eq!(ranked, ["Rossi", "Ross"]);
+        assert!(matches[0].score > matches[1].score);
+        // Misspelled names are found all the same.
+        let matches = repository.search_customers("bianki", 10).await.unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].customer_id, "anna@example.com");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_summarize_a_customer(_: PgPoolOptions, options: PgConnectOptions) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name) VALUES
+                ('mario@example.com', 'Mario', 'Rossi'),
+                ('luigi@example.com', 'Luigi', 'Verdi')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at)
+                VALUES ('AA111AA', 'van', '2024-06-01T00:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date) VALUES
+                (1, 'mario@example.com', 'AA111AA', '2024-06-02T08:00:00Z', '2024-06-03T08:00:00Z'),
+                (2, 'mario@example.com', 'AA111AA', '2024-06-05T08:00:00Z', '2024-06-06T08:00:00Z'),
+                (3, 'mario@example.com', 'AA111AA', '2024-07-01T08:00:00Z', NULL)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+
+        let summary = repository
+            .customer_summary("mario@example.com")
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(summary).unwrap(),
+            serde_json::json!({
+                "customerId": "mario@example.com",
+                "firstName": "Mario",
+                "lastName": "Rossi",
+                "activeRental": {
+                    "rentId": 3,
+                    "vehicleId": "AA111AA",
+                    "vehicleType": "Van",
+                    "startDate": "2024-07-01T08:00:00Z"
+                },
+                "pastRentals": 2,
+                "loyaltyBalance": null,
+                "outstandingBalance": null,
+                "blacklisted": null,
+                "overdue": true
+            })
+        );
+
+        let summary = repository
+            .customer_summary("luigi@example.com")
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(summary).unwrap(),
+            serde_json::json!({
+                "customerId": "luigi@example.com",
+                "firstName": "Luigi",
+                "lastName": "Verdi",
+                "activeRental": null,
+                "pastRentals": 0,
+                "loyaltyBalance": null,
+                "outstandingBalance": null,
+                "blacklisted": null,
+                "overdue": false
+            })
+        );
+        assert!(repository
+            .customer_summary("nobody@example.com")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_count_as_many_items_as_the_listings_total(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at, status)
+                SELECT 'V' || i, 'car', now(), CASE WHEN i % 3 = 0 THEN 'rented' ELSE 'available' END
+                FROM generate_series(1, 70) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name)
+                SELECT 'customer' || i || '@example.com', 'Mario', 'Rossi' FROM generate_series(1, 60) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date)
+                SELECT i, 'customer' || i % 60 || '@example.com', 'V' || i % 70 + 1,
+                    now() - i * interval '1 day',
+                    CASE WHEN i % 4 > 0 THEN now() - i * interval '1 day' + interval '2 hours' END
+                FROM generate_series(1, 90) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+        let page = PageParams::new(Some(10), None).unwrap();
+
+        for status in [
+            None,
+            Some(VehicleStatus::Rented),
+            Some(VehicleStatus::Available),
+        ] {
+            let filter = VehicleFilter {
+                status,
+                ..Default::default()
+            };
+            let (_, total) = repository
+                .list_vehicles(&filter, &SortParams::parse(None).unwrap(), page)
+                .await
+                .unwrap();
+            assert_eq!(repository.count_vehicles(&filter).await.unwrap(), total);
+        }
+
+        let (_, total) = repository.list_customers(page).await.unwrap();
+        assert_eq!(repository.count_customers().await.unwrap(), total);
+
+        for status in [None, Some("open"), Some("closed"), Some("overdue")] {
+            let filter = RentalFilter::new(None, None, status).unwrap();
+            let (_, total) = repository
+                .list_rentals(&filter, &SortParams::parse(None).unwrap(), page)
+                .await
+                .unwrap();
+            assert_eq!(repository.count_rentals(&filter).await.unwrap(), total);
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_return_an_empty_page_past_the_end_along_with_the_total(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at)
+                SELECT 'V' || i, 'car', now() FROM generate_series(1, 3) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name)
+                SELECT 'customer' || i || '@example.com', 'Mario', 'Rossi' FROM generate_series(1, 3) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date)
+                SELECT i, 'customer' || i || '@example.com', 'V' || i, now() FROM generate_series(1, 3) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+        let page = PageParams::new(Some(2), Some(3)).unwrap();
+
+        let (vehicles, total) = repository
+            .list_vehicles(
+                &VehicleFilter::default(),
+                &SortParams::parse(None).unwrap(),
+                page,
+            )
+            .await
+            .unwrap();
+        assert!(vehicles.is_empty());
+        assert_eq!(total, 3);
+        let (customers, total) = repository.list_customers(page).await.unwrap();
+        assert!(customers.is_empty());
+        assert_eq!(total, 3);
+        let (rentals, total) = repository
+            .list_rentals(
+                &RentalFilter::default(),
+                &SortParams::parse(None).unwrap(),
+                page,
+            )
+            .await
+            .unwrap();
+        assert!(rentals.is_empty());
+        assert_eq!(total, 3);
+
+        // The page before the end still holds the rest.
+        let page = PageParams::new(Some(2), Some(2)).unwrap();
+        let (vehicles, total) = repository
+            .list_vehicles(
+                &VehicleFilter::default(),
+                &SortParams::parse(None).unwrap(),
+                page,
+            )
+            .await
+            .unwrap();
+        assert_eq!(vehicles.len(), 1);
+        assert_eq!(total, 3);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_list_the_rentals_started_in_the_range_even_if_ending_after_it(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date) VALUES
+                (1, 'mario@example.com', 'AA111AA', '2024-06-28T10:00:00Z', '2024-07-02T10:00:00Z'),
+                (2, 'luigi@example.com', 'BB222BB', '2024-07-03T10:00:00Z', '2024-07-04T10:00:00Z'),
+                (3, 'anna@example.com', 'CC333CC', '2024-07-09T10:00:00Z', '2024-07-12T10:00:00Z'),
+                (4, 'luca@example.com', 'DD444DD', '2024-07-11T10:00:00Z', NULL)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+        let list = |filter: RentalFilter| {
+            let repository = repository.clone();
+            async move {
+                let sort = SortParams::parse(Some("rentId")).unwrap();
+                let (rentals, total) = repository
+                    .list_rentals(&filter, &sort, PageParams::default())
+                    .await
+                    .unwrap();
+                assert_eq!(total, rentals.len() as i64);
+                rentals
+                    .into_iter()
+                    .map(|rental| rental.rent_id)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Rental 3 starts inside the range and ends after it, rental 1 the other way round.
+        let filter = RentalFilter::new(
+            Some("2024-07-01T00:00:00Z"),
+            Some("2024-07-10T00:00:00Z"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(list(filter).await, [2, 3]);
+        // Closed rentals are listed by their end date instead.
+        let filter = RentalFilter::new(
+            Some("2024-07-01T00:00:00Z"),
+            Some("2024-07-10T00:00:00Z"),
+            Some("closed"),
+        )
+        .unwrap();
+        assert_eq!(list(filter).await, [1, 2]);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_list_the_vehicles_matching_every_filter(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        // Every fourth vehicle was registered before seats and transmission were recorded.
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at, status, seats, transmission)
+                SELECT 'V' || lpad(i::text, 2, '0'), CASE WHEN i % 2 = 0 THEN 'van' ELSE 'car' END::vehicle_type,
+                    now(), CASE WHEN i % 3 = 0 THEN 'rented' ELSE 'available' END,
+                    CASE WHEN i % 4 > 0 THEN 2 + i % 8 END,
+                    CASE WHEN i % 4 > 0 THEN CASE WHEN i % 5 = 0 THEN 'manual' ELSE 'automatic' END END
+                FROM generate_series(1, 40) i"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+        let list = |filter: VehicleFilter, sort: &'static str, limit| {
+            let repository = repository.clone();
+            async move {
+                let sort = SortParams::parse(Some(sort)).unwrap();
+                let page = PageParams::new(Some(limit), None).unwrap();
+                repository
+                    .list_vehicles(&filter, &sort, page)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let filter =
+            VehicleFilter::new(Some("van"), Some("available"), Some("6"), Some("automatic"))
+                .unwrap();
+        let (vehicles, total) = list(filter.clone(), "vehicleId:desc", 3).await;
+        // Vans are the even ones, available those that aren't a multiple of 3, with 6 seats or
+        // more from 4 on modulo 8, and automatic unless a multiple of 5; none of a multiple of 4.
+        let expected: Vec<String> = (1..=40)
+            .rev()
+            .filter(|i| i % 2 == 0 && i % 3 > 0 && i % 4 > 0 && 2 + i % 8 >= 6 && i % 5 > 0)
+            .map(|i| format!("V{i:02}"))
+            .collect();
+        assert_eq!(total, expected.len() as i64);
+        assert_eq!(
+            vehicles
+                .iter()
+                .map(|vehicle| vehicle.vehicle_id.clone())
+                .collect::<Vec<_>>(),
+            expected[..3]
+        );
+        assert!(vehicles
+            .iter()
+            .all(|vehicle| vehicle.vehicle_type == VehicleType::Van
+                && vehicle.status == VehicleStatus::Available
+                && vehicle.seats >= Some(6)
+                && vehicle.transmission == Some(Transmission::Automatic)));
+        assert_eq!(repository.count_vehicles(&filter).await.unwrap(), total);
+
+        let (vehicles, total) = list(VehicleFilter::default(), "vehicleId", 40).await;
+        assert_eq!(total, 40);
+        let legacy = vehicles.iter().filter(|vehicle| vehicle.seats.is_none());
+        assert!(legacy.clone().all(|vehicle| vehicle.transmission.is_none()));
+        assert_eq!(legacy.count(), 10);
+        for filter in [
+            VehicleFilter::new(None, None, Some("1"), None).unwrap(),
+            VehicleFilter::new(None, None, None, Some("manual")).unwrap(),
+            VehicleFilter::new(None, None, None, Some("automatic")).unwrap(),
+        ] {
+            let (vehicles, _) = list(filter, "vehicleId", 40).await;
+            assert!(vehicles.iter().all(|vehicle| vehicle.seats.is_some()));
+        }
+        let (_, total) = list(
+            VehicleFilter::new(None, None, Some("1"), None).unwrap(),
+            "vehicleId",
+            40,
+        )
+        .await;
+        assert_eq!(total, 30);
+    }
+}
@@ -0,0 +1,258 @@
+use std::{ops::Deref, sync::Arc};
+
+use actix_web::{
+    dev::Payload, error::ErrorInternalServerError, web::Data, FromRequest, HttpRequest,
+};
+use async_trait::async_trait;
+use futures_util::{
+    future::{ready, Ready},
+    stream::BoxStream,
+    StreamExt,
+};
+
+use super::queries::{
+    AvailabilitySummary, CalendarDay, CustomerMatch, CustomerSummary, CustomerView,
+    ReadModelRepository, RentStatus, RentalExportRow, RentalView, SearchHit, VehicleView,
+    Versioned,
+};
+use crate::{
+    domain::{TenantId, VehicleType},
+    filters::{CalendarRange, RentalFilter, VehicleFilter},
+    pagination::PageParams,
+    sorting::SortParams,
+    tenant::Tenant,
+    vehicle_stats::VehicleStats,
+};
+
+/// The reads of the read model the handlers of the API make, implemented by
+/// `ReadModelRepository` and, with `EVENT_STORE=memory`, by a `MemoryReadModel`.
+///
+/// Reads the rows of a single tenant, as `ReadModelRepository` does. The reports and the admin
+/// routes read Postgres alone, through `ReadModelRepository`.
+#[async_trait]
+pub trait ReadRepository: Send + Sync {
+    fn tenant_id(&self) -> &TenantId;
+
+    fn for_tenant(&self, tenant_id: TenantId) -> Arc<dyn ReadRepository>;
+
+    async fn availability_summary(
+        &self,
+        vehicle_type: Option<VehicleType>,
+    ) -> Result<Vec<AvailabilitySummary>, sqlx::Error>;
+
+    async fn availability_calendar(
+        &self,
+        range: &CalendarRange,
+    ) -> Result<Vec<CalendarDay>, sqlx::Error>;
+
+    async fn find_vehicle(
+        &self,
+        vehicle_id: &str,
+    ) -> Result<Option<Versioned<VehicleView>>, sqlx::Error>;
+
+    async fn list_vehicles(
+        &self,
+        filter: &VehicleFilter,
+        sort: &SortParams<VehicleView>,
+        page: PageParams,
+    ) -> Result<(Vec<VehicleView>, i64), sqlx::Error>;
+
+    async fn count_vehicles(&self, filter: &VehicleFilter) -> Result<i64, sqlx::Error>;
+
+    async fn vehicle_stats(&self, vehicle_id: &str) -> Result<Option<VehicleStats>, sqlx::Error>;
+
+    async fn customer_detail(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<Versioned<CustomerView>>, sqlx::Error>;
+
+    async fn list_customers(
+        &self,
+        page: PageParams,
+    ) -> Result<(Vec<CustomerView>, i64), sqlx::Error>;
+
+    async fn count_customers(&self) -> Result<i64, sqlx::Error>;
+
+    async fn search_customers(
+        &self,
+        text: &str,
+        limit: i64,
+    ) -> Result<Vec<CustomerMatch>, sqlx::Error>;
+
+    async fn customer_summary(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<CustomerSummary>, sqlx::Error>;
+
+    async fn rent_status(&self, customer_id: &str) -> Result<Option<RentStatus>, sqlx::Error>;
+
+    async fn active_rentals(&self) -> Result<Vec<RentalView>, sqlx::Error>;
+
+    async fn find_rental(&self, rent_id: i64) -> Result<Option<RentalView>, sqlx::Error>;
+
+    async fn list_rentals(
+        &self,
+        filter: &RentalFilter,
+        sort: &SortParams<RentalView>,
+        page: PageParams,
+    ) -> Result<(Vec<RentalView>, i64), sqlx::Error>;
+
+    async fn count_rentals(&self, filter: &RentalFilter) -> Result<i64, sqlx::Error>;
+
+    fn export_rentals(
+        &self,
+        filter: RentalFilter,
+    ) -> BoxStream<'static, Result<RentalExportRow, sqlx::Error>>;
+
+    async fn search(&self, text: &str, limit: i64) -> Result<Vec<SearchHit>, sqlx::Error>;
+}
+
+/// The read model as the handlers take it, `Data<dyn ReadRepository>`, reading the default
+/// tenant until the handlers scope it.
+pub fn read_repository(repository: impl ReadRepository + 'static) -> Data<dyn ReadRepository> {
+    Data::from(Arc::new(repository) as Arc<dyn ReadRepository>)
+}
+
+/// The read model scoped to the tenant of the caller, extracted from the
+/// `Data<dyn ReadRepository>` of the app.
+#[derive(Clone)]
+pub struct Reads(Arc<dyn ReadRepository>);
+
+impl Deref for Reads {
+    type Target = dyn ReadRepository;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl FromRequest for Reads {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let reads = req
+            .app_data::<Data<dyn ReadRepository>>()
+            .ok_or_else(|| ErrorInternalServerError("the read model is not configured"))
+            .map(|repository| Reads(repository.for_tenant(Tenant::of(req).0)));
+        ready(reads)
+    }
+}
+
+#[async_trait]
+impl ReadRepository for ReadModelRepository {
+    fn tenant_id(&self) -> &TenantId {
+        &self.tenant_id
+    }
+
+    fn for_tenant(&self, tenant_id: TenantId) -> Arc<dyn ReadRepository> {
+        Arc::new(ReadModelRepository::for_tenant(self, tenant_id))
+    }
+
+    async fn availability_summary(
+        &self,
+        vehicle_type: Option<VehicleType>,
+    ) -> Result<Vec<AvailabilitySummary>, sqlx::Error> {
+        ReadModelRepository::availability_summary(self, vehicle_type).await
+    }
+
+    async fn availability_calendar(
+        &self,
+        range: &CalendarRange,
+    ) -> Result<Vec<CalendarDay>, sqlx::Error> {
+        ReadModelRepository::availability_calendar(self, range).await
+    }
+
+    async fn find_vehicle(
+        &self,
+        vehicle_id: &str,
+    ) -> Result<Option<Versioned<VehicleView>>, sqlx::Error> {
+        ReadModelRepository::find_vehicle(self, vehicle_id).await
+    }
+
+    async fn list_vehicles(
+        &self,
+        filter: &VehicleFilter,
+        sort: &SortParams<VehicleView>,
+        page: PageParams,
+    ) -> Result<(Vec<VehicleView>, i64), sqlx::Error> {
+        ReadModelRepository::list_vehicles(self, filter, sort, page).await
+    }
+
+    async fn count_vehicles(&self, filter: &VehicleFilter) -> Result<i64, sqlx::Error> {
+        ReadModelRepository::count_vehicles(self, filter).await
+    }
+
+    async fn vehicle_stats(&self, vehicle_id: &str) -> Result<Option<VehicleStats>, sqlx::Error> {
+        ReadModelRepository::vehicle_stats(self, vehicle_id).await
+    }
+
+    async fn customer_detail(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<Versioned<CustomerView>>, sqlx::Error> {
+        ReadModelRepository::customer_detail(self, customer_id).await
+    }
+
+    async fn list_customers(
+        &self,
+        page: PageParams,
+    ) -> Result<(Vec<CustomerView>, i64), sqlx::Error> {
+        ReadModelRepository::list_customers(self, page).await
+    }
+
+    async fn count_customers(&self) -> Result<i64, sqlx::Error> {
+        ReadModelRepository::count_customers(self).await
+    }
+
+    async fn search_customers(
+        &self,
+        text: &str,
+        limit: i64,
+    ) -> Result<Vec<CustomerMatch>, sqlx::Error> {
+        ReadModelRepository::search_customers(self, text, limit).await
+    }
+
+    async fn customer_summary(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<CustomerSummary>, sqlx::Error> {
+        ReadModelRepository::customer_summary(self, customer_id).await
+    }
+
+    async fn rent_status(&self, customer_id: &str) -> Result<Option<RentStatus>, sqlx::Error> {
+        ReadModelRepository::rent_status(self, customer_id).await
+    }
+
+    async fn active_rentals(&self) -> Result<Vec<RentalView>, sqlx::Error> {
+        ReadModelRepository::active_rentals(self).await
+    }
+
+    async fn find_rental(&self, rent_id: i64) -> Result<Option<RentalView>, sqlx::Error> {
+        ReadModelRepository::find_rental(self, rent_id).await
+    }
+
+    async fn list_rentals(
+        &self,
+        filter: &RentalFilter,
+        sort: &SortParams<RentalView>,
+        page: PageParams,
+    ) -> Result<(Vec<RentalView>, i64), sqlx::Error> {
+        ReadModelRepository::list_rentals(self, filter, sort, page).await
+    }
+
+    async fn count_rentals(&self, filter: &RentalFilter) -> Result<i64, sqlx::Error> {
+        ReadModelRepository::count_rentals(self, filter).await
+    }
+
+    fn export_rentals(
+        &self,
+        filter: RentalFilter,
+    ) -> BoxStream<'static, Result<RentalExportRow, sqlx::Error>> {
+        ReadModelRepository::export_rentals(self, filter).boxed()
+    }
+
+    async fn search(&self, text: &str, limit: i64) -> Result<Vec<SearchHit>, sqlx::Error> {
+        ReadModelRepository::search(self, text, limit).await
+    }
+}
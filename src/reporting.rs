@@ -0,0 +1,121 @@
+use std::{future::Future, sync::Arc};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    web::Data,
+};
+
+use crate::{errors::CarRentalResponseError, request_id::RequestId};
+
+/// Where an unexpected error happened. It never holds the payloads, which may hold personal
+/// data, only what identifies them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub request_id: Option<String>,
+    pub command: Option<&'static str>,
+    pub listener_id: Option<String>,
+    pub event_id: Option<i64>,
+}
+
+impl ErrorContext {
+    /// The context of the request being handled, if any.
+    pub fn current() -> Self {
+        Self {
+            request_id: RequestId::current().map(|id| id.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Reports the unexpected errors, such as the store failing, to an error tracker; domain errors
+/// are never reported.
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, error: &dyn std::error::Error, context: &ErrorContext);
+}
+
+/// Reports nothing, the errors being logged all the same.
+pub struct NoopReporter;
+
+impl ErrorReporter for NoopReporter {
+    fn report(&self, _: &dyn std::error::Error, _: &ErrorContext) {}
+}
+
+pub fn noop() -> Arc<dyn ErrorReporter> {
+    Arc::new(NoopReporter)
+}
+
+/// Middleware reporting the errors the handlers answer with a 5xx, with the request id, the
+/// `ErrorReporter` of the app data being the reporter.
+///
+/// The errors of the commands are left to the application, which reports them with the
+/// command, and the 5xx answered on purpose, such as the probes', aren't errors.
+pub fn report_server_errors<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let reporter = req.app_data::<Data<dyn ErrorReporter>>().cloned();
+    let response = srv.call(req);
+    async move {
+        let response = response.await?;
+        let error = response
+            .response()
+            .error()
+            .filter(|error| error.as_error::<CarRentalResponseError>().is_none());
+        if let (Some(reporter), Some(error)) = (reporter, error) {
+            if response.status().is_server_error() {
+                reporter.report(error, &ErrorContext::current());
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(feature = "sentry")]
+pub use self::sentry::SentryReporter;
+
+#[cfg(feature = "sentry")]
+mod sentry {
+    use super::{ErrorContext, ErrorReporter};
+
+    /// Reports the errors to Sentry, along with the panics, once `SENTRY_DSN` is set.
+    pub struct SentryReporter {
+        // Sends the last reports when dropped.
+        _client: sentry::ClientInitGuard,
+    }
+
+    impl SentryReporter {
+        pub fn new(dsn: &str) -> Result<Self, String> {
+            let dsn = dsn.parse().map_err(|err| format!("SENTRY_DSN: {err}"))?;
+            let mut options = sentry::ClientOptions::default();
+            options.dsn = Some(dsn);
+            options.release = sentry::release_name!();
+            let client = sentry::init(options);
+            Ok(Self { _client: client })
+        }
+    }
+
+    impl ErrorReporter for SentryReporter {
+        fn report(&self, error: &dyn std::error::Error, context: &ErrorContext) {
+            sentry::with_scope(
+                |scope| {
+                    if let Some(request_id) = &context.request_id {
+                        scope.set_tag("request_id", request_id);
+                    }
+                    if let Some(command) = context.command {
+                        scope.set_tag("command", command);
+                    }
+                    if let Some(listener_id) = &context.listener_id {
+                        scope.set_tag("listener_id", listener_id);
+                    }
+                    if let Some(event_id) = context.event_id {
+                        scope.set_tag("event_id", event_id);
+                    }
+                },
+                || sentry::capture_error(error),
+            );
+        }
+    }
+}
@@ -0,0 +1,923 @@
+use std::error::Error;
+
+use actix_web::{
+    body::BoxBody,
+    error::{self, ResponseError},
+    http::{
+        header::{Accept, Header, HeaderValue, Quality, VARY},
+        StatusCode,
+    },
+    mime, HttpRequest, HttpResponse, Responder,
+};
+use async_stream::try_stream;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::{pin_mut, Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::VehicleType,
+    errors::{ErrorBody, ErrorCode},
+    filters::{CustomerRanking, RentalFilter, ReportPeriod, TopCustomersParams, MAX_RENTAL_DAYS},
+    read_model::queries::ReadModelRepository,
+};
+
+/// Representations a report can be rendered in, negotiated from the `Accept` header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl ReportFormat {
+    /// The representation the client prefers, JSON when it has no preference; `None` when it
+    /// accepts neither.
+    pub fn negotiate(req: &HttpRequest) -> Option<Self> {
+        let Ok(Accept(mut accepted)) = Accept::parse(req) else {
+            return Some(Self::default());
+        };
+        if accepted.is_empty() {
+            return Some(Self::default());
+        }
+        accepted.retain(|accepted| accepted.quality > Quality::ZERO);
+        Accept(accepted)
+            .ranked()
+            .iter()
+            .find_map(|mime| match (mime.type_(), mime.subtype()) {
+                (mime::STAR, _) | (mime::APPLICATION, mime::STAR | mime::JSON) => Some(Self::Json),
+                (mime::TEXT, mime::STAR | mime::CSV) => Some(Self::Csv),
+                _ => None,
+            })
+    }
+}
+
+/// A report that can be laid out as CSV rows.
+pub trait Tabular {
+    fn to_csv(&self) -> Result<String, csv::Error>;
+}
+
+impl<T: Serialize> Tabular for Vec<T> {
+    fn to_csv(&self) -> Result<String, csv::Error> {
+        to_csv(self)
+    }
+}
+
+impl Tabular for UtilizationReport {
+    fn to_csv(&self) -> Result<String, csv::Error> {
+        to_csv(&self.rows())
+    }
+}
+
+/// A report rendered in the representation negotiated by `ReportFormat`, both serializing the
+/// same values so that their numbers can't diverge.
+///
+/// The clients accepting neither get a `406 Not Acceptable`.
+pub struct Negotiated<T>(pub T);
+
+impl<T: Serialize + Tabular> Responder for Negotiated<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse {
+        let mut response = match ReportFormat::negotiate(req) {
+            Some(ReportFormat::Json) => HttpResponse::Ok().json(self.0),
+            Some(ReportFormat::Csv) => match self.0.to_csv() {
+                Ok(csv) => HttpResponse::Ok().content_type("text/csv").body(csv),
+                Err(err) => error::ErrorInternalServerError(err).error_response(),
+            },
+            None => NotAcceptable.error_response(),
+        };
+        response
+            .headers_mut()
+            .insert(VARY, HeaderValue::from_static("accept"));
+        response
+    }
+}
+
+/// `406 Not Acceptable`: the report can't be rendered in any of the types the client accepts.
+#[derive(Debug)]
+pub struct NotAcceptable;
+
+impl std::fmt::Display for NotAcceptable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("the report is only available as application/json or text/csv")
+    }
+}
+
+impl error::ResponseError for NotAcceptable {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::NOT_ACCEPTABLE
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .json(ErrorBody::new(ErrorCode::NotAcceptable, self.to_string()))
+    }
+}
+
+/// Renders report rows as CSV, with a header row named after the serialized fields.
+pub fn to_csv<T: Serialize>(rows: &[T]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    let csv = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(String::from_utf8(csv).expect("csv writer only emits utf-8 from strings"))
+}
+
+/// Renders rows as CSV while they are read, one chunk per row after the header, with the
+/// CRLF line endings of RFC 4180.
+pub fn stream_csv<T, E>(
+    header: &'static [&'static str],
+    rows: impl Stream<Item = Result<T, E>> + 'static,
+) -> impl Stream<Item = Result<Vec<u8>, Box<dyn Error>>> + 'static
+where
+    T: Serialize,
+    E: Error + 'static,
+{
+    try_stream! {
+        let mut builder = csv::WriterBuilder::new();
+        builder.has_headers(false).terminator(csv::Terminator::CRLF);
+        let mut writer = builder.from_writer(vec![]);
+        writer.write_record(header)?;
+        yield writer.into_inner().map_err(|e| e.into_error())?;
+        pin_mut!(rows);
+        while let Some(row) = rows.try_next().await? {
+            let mut writer = builder.from_writer(vec![]);
+            writer.serialize(row)?;
+            yield writer.into_inner().map_err(|e| e.into_error())?;
+        }
+    }
+}
+
+/// Names the rental export after its date range, from the first rental and up to `today`
+/// when left open.
+pub fn rental_export_filename(filter: &RentalFilter, today: NaiveDate) -> String {
+    let from = filter
+        .from
+        .map_or("start".to_string(), |from| from.date_naive().to_string());
+    let to = filter.to.map_or(today, |to| to.date_naive());
+    format!("rentals_{from}_{to}.csv")
+}
+
+/// An open rental kept past its due date, with what is needed to reach the customer.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverdueRental {
+    pub rent_id: i64,
+    pub customer_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub phone: Option<String>,
+    pub vehicle_id: String,
+    pub vehicle_type: VehicleType,
+    pub start_date: DateTime<Utc>,
+    pub due_date: DateTime<Utc>,
+    pub hours_overdue: i64,
+}
+
+#[derive(sqlx::FromRow)]
+struct OverdueRentalRow {
+    rent_id: i64,
+    customer_id: String,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    phone: Option<String>,
+    vehicle_id: String,
+    vehicle_type: VehicleType,
+    start_date: DateTime<Utc>,
+    due_date: DateTime<Utc>,
+    hours_overdue: i64,
+}
+
+/// Served by `idx_rent_open_start_date`: only open rentals are scanned, and only the ones
+/// started before the cutoff.
+const OVERDUE_RENTALS: &str = r#"
+    SELECT r.rent_id, r.customer_id, c.first_name, c.last_name, c.phone, r.vehicle_id,
+        v.vehicle_type,
+        r.start_date,
+        r.start_date + make_interval(days => $1) AS due_date,
+        floor(extract(epoch FROM now() - r.start_date - make_interval(days => $1)) / 3600)::bigint
+            AS hours_overdue
+    FROM rent r
+    JOIN vehicle v ON v.tenant_id = r.tenant_id AND v.vehicle_id = r.vehicle_id
+    LEFT JOIN customer c ON c.tenant_id = r.tenant_id AND c.customer_id = r.customer_id
+    WHERE r.end_date IS NULL
+    AND r.start_date <= now() - make_interval(days => $1, hours => $2)
+    AND r.tenant_id = $3
+    ORDER BY r.start_date, r.rent_id"#;
+
+impl ReadModelRepository {
+    /// Lists the open rentals that have been overdue for at least `min_hours_overdue` hours,
+    /// the most overdue first.
+    ///
+    /// Rentals are due `MAX_RENTAL_DAYS` after their start date.
+    pub async fn overdue_rentals(
+        &self,
+        min_hours_overdue: i32,
+    ) -> Result<Vec<OverdueRental>, sqlx::Error> {
+        let rows: Vec<OverdueRentalRow> = sqlx::query_as(OVERDUE_RENTALS)
+            .bind(MAX_RENTAL_DAYS)
+            .bind(min_hours_overdue)
+            .bind(&self.tenant_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| OverdueRental {
+                rent_id: row.rent_id,
+                customer_id: row.customer_id,
+                first_name: row.first_name.unwrap_or_default(),
+                last_name: row.last_name.unwrap_or_default(),
+                phone: row.phone,
+                vehicle_id: row.vehicle_id,
+                vehicle_type: row.vehicle_type,
+                start_date: row.start_date,
+                due_date: row.due_date,
+                hours_overdue: row.hours_overdue,
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct TopCustomer {
+    pub customer_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub rentals: i64,
+    pub total_days: f64,
+}
+
+impl ReadModelRepository {
+    /// Ranks the customers by the rentals they started during the period, or by the days they
+    /// spent renting, ties being broken by email.
+    ///
+    /// Rentals still open count up to now.
+    pub async fn top_customers(
+        &self,
+        period: ReportPeriod,
+        params: TopCustomersParams,
+    ) -> Result<Vec<TopCustomer>, sqlx::Error> {
+        let order_by = match params.by {
+            CustomerRanking::Rentals => "rentals DESC, total_days DESC",
+            CustomerRanking::Days => "total_days DESC, rentals DESC",
+        };
+        sqlx::query_as(&format!(
+            r#"SELECT r.customer_id,
+                    coalesce(c.first_name, '') AS first_name,
+                    coalesce(c.last_name, '') AS last_name,
+                    count(*) AS rentals,
+                    round(sum(extract(epoch FROM coalesce(r.end_date, now()) - r.start_date))::numeric / 86400, 2)::float8
+                        AS total_days
+                FROM rent r
+                LEFT JOIN customer c ON c.tenant_id = r.tenant_id AND c.customer_id = r.customer_id
+                WHERE r.tenant_id = $4 AND r.start_date >= $1 AND r.start_date < $2
+                GROUP BY r.customer_id, c.first_name, c.last_name
+                ORDER BY {order_by}, r.customer_id
+                LIMIT $3"#
+        ))
+        .bind(period.from)
+        .bind(period.to)
+        .bind(params.limit)
+        .bind(&self.tenant_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DurationGroup {
+    VehicleType,
+}
+
+/// Distribution of the duration of the rentals of a vehicle type, in minutes.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DurationStats {
+    pub vehicle_type: VehicleType,
+    pub rentals: i64,
+    pub average_minutes: f64,
+    pub median_minutes: f64,
+    pub p90_minutes: f64,
+}
+
+impl ReadModelRepository {
+    /// Computes the average, median and 90th percentile duration of the rentals that ended
+    /// during the period, for each vehicle type.
+    ///
+    /// Open rentals are left out, and a period without closed rentals yields no rows.
+    pub async fn rental_durations(
+        &self,
+        period: ReportPeriod,
+        group: DurationGroup,
+    ) -> Result<Vec<DurationStats>, sqlx::Error> {
+        let bucket = match group {
+            DurationGroup::VehicleType => "v.vehicle_type",
+        };
+        let rows: Vec<(VehicleType, i64, f64, f64, f64)> = sqlx::query_as(&format!(
+            r#"SELECT {bucket},
+                    count(*),
+                    avg(r.duration_minutes)::float8,
+                    percentile_cont(0.5) WITHIN GROUP (ORDER BY r.duration_minutes),
+                    percentile_cont(0.9) WITHIN GROUP (ORDER BY r.duration_minutes)
+                FROM rent r
+                JOIN vehicle v ON v.tenant_id = r.tenant_id AND v.vehicle_id = r.vehicle_id
+                WHERE r.tenant_id = $3 AND r.end_date >= $1 AND r.end_date < $2
+                    AND r.duration_minutes IS NOT NULL
+                GROUP BY {bucket}
+                ORDER BY {bucket}"#
+        ))
+        .bind(period.from)
+        .bind(period.to)
+        .bind(&self.tenant_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(
+                |(vehicle_type, rentals, average, median, p90)| DurationStats {
+                    vehicle_type,
+                    rentals,
+                    average_minutes: average,
+                    median_minutes: median,
+                    p90_minutes: p90,
+                },
+            )
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum UtilizationGroup {
+    VehicleType,
+    Vehicle,
+}
+
+/// Share of the period a vehicle, or all the vehicles of a type, spent rented.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UtilizationBucket {
+    pub bucket: String,
+    pub vehicles: i64,
+    pub available_hours: f64,
+    pub rented_hours: f64,
+    pub utilization: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UtilizationReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub buckets: Vec<UtilizationBucket>,
+    pub fleet: UtilizationBucket,
+}
+
+impl UtilizationReport {
+    /// Builds the report out of `(bucket, vehicles, available seconds, rented seconds)` rows,
+    /// the fleet average being weighted by the time each vehicle was available.
+    fn new(period: ReportPeriod, rows: Vec<(String, i64, f64, f64)>) -> Self {
+        let (vehicles, available, rented) = rows.iter().fold(
+            (0, 0.0, 0.0),
+            |(vehicles, available, rented),
+             (_, bucket_vehicles, bucket_available, bucket_rented)| {
+                (
+                    vehicles + bucket_vehicles,
+                    available + bucket_available,
+                    rented + bucket_rented,
+                )
+            },
+        );
+        let fleet = UtilizationBucket::new("fleet".to_string(), vehicles, available, rented);
+        let buckets = rows
+            .into_iter()
+            .map(|(bucket, vehicles, available, rented)| {
+                UtilizationBucket::new(bucket, vehicles, available, rented)
+            })
+            .collect();
+        Self {
+            from: period.from,
+            to: period.to,
+            buckets,
+            fleet,
+        }
+    }
+
+    /// Buckets followed by the fleet totals, as rendered in CSV.
+    pub fn rows(&self) -> Vec<UtilizationBucket> {
+        let mut rows = self.buckets.clone();
+        rows.push(self.fleet.clone());
+        rows
+    }
+}
+
+impl UtilizationBucket {
+    fn new(bucket: String, vehicles: i64, available_seconds: f64, rented_seconds: f64) -> Self {
+        let utilization = if available_seconds > 0.0 {
+            (rented_seconds / available_seconds * 10000.0).round() / 100.0
+        } else {
+            0.0
+        };
+        Self {
+            bucket,
+            vehicles,
+            available_hours: available_seconds / 3600.0,
+            rented_hours: rented_seconds / 3600.0,
+            utilization,
+        }
+    }
+}
+
+impl ReadModelRepository {
+    /// Computes the percentage of the period each vehicle, or vehicle type, was rented.
+    ///
+    /// Rentals are clipped to the period and still open ones count up to its end. Vehicles
+    /// registered during the period are only accounted for from their registration onwards.
+    pub async fn utilization(
+        &self,
+        period: ReportPeriod,
+        group: UtilizationGroup,
+    ) -> Result<UtilizationReport, sqlx::Error> {
+        let bucket = match group {
+            UtilizationGroup::VehicleType => "v.vehicle_type::text",
+            UtilizationGroup::Vehicle => "v.vehicle_id",
+        };
+        let rows: Vec<(String, i64, f64, f64)> = sqlx::query_as(&format!(
+            r#"WITH rented AS (
+                    SELECT vehicle_id,
+                        sum(extract(epoch FROM least(coalesce(end_date, $2), $2) - greatest(start_date, $1)))
+                            AS seconds
+                    FROM rent
+                    -- Written as a disjunction rather than with coalesce, so that the closed and the
+                    -- open rentals are looked up in their own partial indexes.
+                    WHERE tenant_id = $3 AND start_date < $2 AND (end_date > $1 OR end_date IS NULL)
+                    GROUP BY vehicle_id
+                )
+                SELECT {bucket},
+                    count(*),
+                    sum(extract(epoch FROM $2 - greatest(v.registered_at, $1)))::float8,
+                    coalesce(sum(r.seconds), 0)::float8
+                FROM vehicle v
+                LEFT JOIN rented r ON r.vehicle_id = v.vehicle_id
+                WHERE v.tenant_id = $3 AND v.registered_at < $2
+                GROUP BY {bucket}
+                ORDER BY {bucket}"#
+        ))
+        .bind(period.from)
+        .bind(period.to)
+        .bind(&self.tenant_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(UtilizationReport::new(period, rows))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{domain::DEFAULT_TENANT, read_model::queries::RentalExportRow, test_support};
+    use sqlx::{
+        postgres::{PgConnectOptions, PgPoolOptions},
+        PgPool,
+    };
+
+    /// Fills the rent table with 20000 rentals, 1% of them still open, and refreshes the
+    /// planner statistics.
+    async fn seed_rentals(pool: &PgPool) {
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at)
+                SELECT 'V' || i, 'car', now() - interval '3 years' FROM generate_series(1, 200) i"#,
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date)
+                SELECT i, 'customer' || i % 500 || '@example.com', 'V' || i % 200 + 1,
+                    now() - i * interval '1 hour',
+                    CASE WHEN i > 200 THEN now() - i * interval '1 hour' + interval '30 minutes' END
+                FROM generate_series(1, 20000) i"#,
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query("ANALYZE rent, vehicle")
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_look_up_open_rentals_in_the_partial_index(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        seed_rentals(&pool).await;
+
+        let plan: Vec<String> = sqlx::query_scalar(&format!("EXPLAIN {OVERDUE_RENTALS}"))
+            .bind(MAX_RENTAL_DAYS)
+            .bind(0)
+            .bind(DEFAULT_TENANT)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let plan = plan.join("\n");
+        assert!(plan.contains("idx_rent_open_start_date"), "{plan}");
+        assert!(!plan.contains("Seq Scan on rent"), "{plan}");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_report_the_overdue_rentals_with_the_contact_of_their_customer(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name, phone)
+                VALUES ('mario@example.com', 'Mario', 'Rossi', '+39 333 1234567')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at) VALUES
+                ('AA111AA', 'car', now() - interval '1 year'),
+                ('BB222BB', 'van', now() - interval '1 year')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date) VALUES
+                (1, 'mario@example.com', 'AA111AA', now() - make_interval(days => $1 + 2)),
+                (2, 'luigi@example.com', 'BB222BB', now() - interval '1 hour')"#,
+        )
+        .bind(MAX_RENTAL_DAYS)
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+
+        let overdue = repository.overdue_rentals(0).await.unwrap();
+        assert_eq!(overdue.len(), 1, "{overdue:?}");
+        let rental = &overdue[0];
+        assert_eq!(rental.rent_id, 1);
+        assert_eq!(
+            (rental.first_name.as_str(), rental.phone.as_deref()),
+            ("Mario", Some("+39 333 1234567"))
+        );
+        assert_eq!(rental.vehicle_id, "AA111AA");
+        assert_eq!(rental.vehicle_type, VehicleType::Car);
+        assert_eq!(rental.hours_overdue, 48);
+        assert!(repository.overdue_rentals(49).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_look_up_the_rentals_of_a_customer_in_their_index(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        seed_rentals(&pool).await;
+
+        let plan: Vec<String> = sqlx::query_scalar(
+            "EXPLAIN SELECT rent_id FROM rent WHERE tenant_id = $1 AND customer_id = $2 ORDER BY start_date DESC LIMIT 10",
+        )
+        .bind(DEFAULT_TENANT)
+        .bind("customer42@example.com")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let plan = plan.join("\n");
+        assert!(plan.contains("idx_rent_customer_start_date"), "{plan}");
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_compute_the_duration_percentiles_of_the_closed_rentals(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at) VALUES
+                ('AA111AA', 'car', '2024-01-01T00:00:00Z'),
+                ('BB222BB', 'van', '2024-01-01T00:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        // Zero-length rentals count, the open one doesn't.
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date, duration_minutes)
+                SELECT i, 'mario@example.com', vehicle_id, end_date - duration_minutes * interval '1 minute',
+                    end_date, duration_minutes
+                FROM (VALUES
+                    (1, 'AA111AA', 0),
+                    (2, 'AA111AA', 10),
+                    (3, 'AA111AA', 20),
+                    (4, 'AA111AA', 30),
+                    (5, 'AA111AA', 100),
+                    (6, 'BB222BB', 60)
+                ) AS r(i, vehicle_id, duration_minutes),
+                LATERAL (SELECT '2024-07-01T12:00:00Z'::timestamptz + i * interval '1 hour' AS end_date) d"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date)
+                VALUES (7, 'luigi@example.com', 'BB222BB', '2024-07-02T00:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+        let period = |from: &str, to: &str| ReportPeriod {
+            from: from.parse().unwrap(),
+            to: to.parse().unwrap(),
+        };
+
+        let durations = repository
+            .rental_durations(
+                period("2024-07-01T00:00:00Z", "2024-07-08T00:00:00Z"),
+                DurationGroup::VehicleType,
+            )
+            .await
+            .unwrap();
+        let durations: Vec<_> = durations
+            .iter()
+            .map(|stats| {
+                (
+                    stats.vehicle_type.clone(),
+                    stats.rentals,
+                    stats.average_minutes,
+                    stats.median_minutes,
+                    stats.p90_minutes,
+                )
+            })
+            .collect();
+        // The 90th percentile of the cars is interpolated: 30 + 0.6 * (100 - 30).
+        assert_eq!(
+            durations,
+            [
+                (VehicleType::Car, 5, 32.0, 20.0, 72.0),
+                (VehicleType::Van, 1, 60.0, 60.0, 60.0),
+            ]
+        );
+        assert!(repository
+            .rental_durations(
+                period("2024-08-01T00:00:00Z", "2024-08-08T00:00:00Z"),
+                DurationGroup::VehicleType,
+            )
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_clip_the_rentals_and_the_registrations_to_the_period(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at) VALUES
+                ('AA111AA', 'car', '2024-06-01T00:00:00Z'),
+                ('BB222BB', 'car', '2024-06-01T00:00:00Z'),
+                ('CC333CC', 'van', '2024-07-06T00:00:00Z'),
+                ('DD444DD', 'van', '2024-07-20T00:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        // A rental across each edge of the period, one still open of a vehicle registered
+        // halfway through it, and one ended before it.
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date) VALUES
+                (1, 'mario@example.com', 'AA111AA', '2024-06-29T00:00:00Z', '2024-07-03T00:00:00Z'),
+                (2, 'luigi@example.com', 'AA111AA', '2024-07-09T00:00:00Z', '2024-07-15T00:00:00Z'),
+                (3, 'anna@example.com', 'CC333CC', '2024-07-08T00:00:00Z', NULL),
+                (4, 'anna@example.com', 'BB222BB', '2024-06-20T00:00:00Z', '2024-06-25T00:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+        let period = ReportPeriod {
+            from: "2024-07-01T00:00:00Z".parse().unwrap(),
+            to: "2024-07-11T00:00:00Z".parse().unwrap(),
+        };
+        let utilization = |report: &UtilizationReport| {
+            report
+                .rows()
+                .into_iter()
+                .map(|bucket| {
+                    (
+                        bucket.bucket,
+                        bucket.vehicles,
+                        bucket.available_hours / 24.0,
+                        bucket.rented_hours / 24.0,
+                        bucket.utilization,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // AA111AA is rented the first 2 days and the last 2, CC333CC 3 of its 5 days, DD444DD
+        // is registered after the period.
+        let report = repository
+            .utilization(period, UtilizationGroup::Vehicle)
+            .await
+            .unwrap();
+        assert_eq!(
+            utilization(&report),
+            [
+                ("AA111AA".to_string(), 1, 10.0, 4.0, 40.0),
+                ("BB222BB".to_string(), 1, 10.0, 0.0, 0.0),
+                ("CC333CC".to_string(), 1, 5.0, 3.0, 60.0),
+                ("fleet".to_string(), 3, 25.0, 7.0, 28.0),
+            ]
+        );
+        let report = repository
+            .utilization(period, UtilizationGroup::VehicleType)
+            .await
+            .unwrap();
+        assert_eq!(
+            utilization(&report),
+            [
+                ("car".to_string(), 2, 20.0, 4.0, 20.0),
+                ("van".to_string(), 1, 5.0, 3.0, 60.0),
+                ("fleet".to_string(), 3, 25.0, 7.0, 28.0),
+            ]
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_rank_the_customers_differently_by_rentals_and_by_days(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name) VALUES
+                ('mario@example.com', 'Mario', 'Rossi'),
+                ('luigi@example.com', 'Luigi', 'Verdi'),
+                ('anna@example.com', 'Anna', 'Bianchi')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        // Mario often and briefly, Luigi once and long, Anna in between; Luigi's rental before
+        // the period doesn't count.
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date, end_date) VALUES
+                (1, 'mario@example.com', 'AA111AA', '2024-07-01T00:00:00Z', '2024-07-02T00:00:00Z'),
+                (2, 'mario@example.com', 'AA111AA', '2024-07-03T00:00:00Z', '2024-07-04T00:00:00Z'),
+                (3, 'mario@example.com', 'AA111AA', '2024-07-05T00:00:00Z', '2024-07-06T00:00:00Z'),
+                (4, 'luigi@example.com', 'BB222BB', '2024-07-01T00:00:00Z', '2024-07-11T00:00:00Z'),
+                (5, 'luigi@example.com', 'BB222BB', '2024-06-01T00:00:00Z', '2024-06-02T00:00:00Z'),
+                (6, 'anna@example.com', 'CC333CC', '2024-07-01T00:00:00Z', '2024-07-03T00:00:00Z'),
+                (7, 'anna@example.com', 'CC333CC', '2024-07-10T00:00:00Z', '2024-07-12T00:00:00Z')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = ReadModelRepository::new(pool);
+        let period = ReportPeriod {
+            from: "2024-07-01T00:00:00Z".parse().unwrap(),
+            to: "2024-08-01T00:00:00Z".parse().unwrap(),
+        };
+        let ranking = |by: &'static str, limit| {
+            let repository = repository.clone();
+            async move {
+                let params = TopCustomersParams::new(Some(by), Some(limit)).unwrap();
+                repository
+                    .top_customers(period, params)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|customer| (customer.first_name, customer.rentals, customer.total_days))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            ranking("rentals", 10).await,
+            [
+                ("Mario".to_string(), 3, 3.0),
+                ("Anna".to_string(), 2, 4.0),
+                ("Luigi".to_string(), 1, 10.0),
+            ]
+        );
+        assert_eq!(
+            ranking("days", 10).await,
+            [
+                ("Luigi".to_string(), 1, 10.0),
+                ("Anna".to_string(), 2, 4.0),
+                ("Mario".to_string(), 3, 3.0),
+            ]
+        );
+        assert_eq!(ranking("days", 1).await.len(), 1);
+    }
+
+    #[test]
+    fn it_should_weight_the_fleet_average_by_availability() {
+        let period = ReportPeriod {
+            from: "2024-07-01T00:00:00Z".parse().unwrap(),
+            to: "2024-07-11T00:00:00Z".parse().unwrap(),
+        };
+        let day = 86400.0;
+        // Two cars available for the whole 10 days, rented 5 days in total; a van
+        // registered on day 5 and rented ever since.
+        let report = UtilizationReport::new(
+            period,
+            vec![
+                ("Car".to_string(), 2, 20.0 * day, 5.0 * day),
+                ("Van".to_string(), 1, 5.0 * day, 5.0 * day),
+            ],
+        );
+        let utilization: Vec<_> = report.buckets.iter().map(|b| b.utilization).collect();
+        assert_eq!(utilization, [25.0, 100.0]);
+        assert_eq!(report.fleet.vehicles, 3);
+        assert_eq!(report.fleet.available_hours, 25.0 * 24.0);
+        assert_eq!(report.fleet.utilization, 40.0);
+    }
+
+    #[test]
+    fn it_should_report_no_utilization_without_available_vehicles() {
+        assert_eq!(
+            UtilizationBucket::new("Truck".to_string(), 0, 0.0, 0.0).utilization,
+            0.0
+        );
+    }
+
+    #[test]
+    fn it_should_render_rows_as_csv_with_a_header() {
+        let rows = [UtilizationBucket::new(
+            "Pick, Up".to_string(),
+            1,
+            7200.0,
+            3600.0,
+        )];
+        assert_eq!(
+            to_csv(&rows).unwrap(),
+            "bucket,vehicles,availableHours,rentedHours,utilization\n\"Pick, Up\",1,2.0,1.0,50.0\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_stream_rentals_as_csv() {
+        let rental = |rent_id, last_name: &str| RentalExportRow {
+            rent_id,
+            customer_id: "c1".into(),
+            first_name: "Mario".to_string(),
+            last_name: last_name.to_string(),
+            vehicle_id: "v1".to_string(),
+            vehicle_type: Some(VehicleType::PickUp),
+            start_date: "2026-10-01T08:00:00Z".parse().unwrap(),
+            end_date: None,
+            duration_minutes: None,
+        };
+        let rows = futures_util::stream::iter([
+            Ok::<_, sqlx::Error>(rental(1, "Rossi")),
+            Ok(rental(2, "Rossi, Jr.")),
+        ]);
+        let chunks: Vec<Vec<u8>> = stream_csv(RentalExportRow::HEADER, rows)
+            .try_collect()
+            .await
+            .unwrap();
+        let csv = String::from_utf8(chunks.concat()).unwrap();
+        assert!(csv.ends_with("\r\n"));
+        assert!(!csv.replace("\r\n", "").contains('\n'));
+
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        assert_eq!(reader.headers().unwrap(), RentalExportRow::HEADER);
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[1][3], "Rossi, Jr.");
+        assert_eq!(&records[1][5], "PickUp");
+    }
+
+    #[test]
+    fn it_should_name_the_rental_export_after_its_date_range() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 14).unwrap();
+        let filter = RentalFilter {
+            from: Some("2026-10-01T00:00:00Z".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            rental_export_filename(&filter, today),
+            "rentals_2026-10-01_2026-10-14.csv"
+        );
+        assert_eq!(
+            rental_export_filename(&RentalFilter::default(), today),
+            "rentals_start_2026-10-14.csv"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::{fmt::Display, future::Future};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    HttpMessage,
+};
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+
+use crate::telemetry;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request id taken from a client, longer ones being replaced.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// Identifies a request across services: taken from `X-Request-Id` when the client sends a
+/// usable one, generated otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    fn of(headers: &HeaderMap) -> Self {
+        let provided = headers.get(REQUEST_ID).filter(|value| {
+            !value.is_empty() && value.len() <= MAX_LENGTH && value.to_str().is_ok()
+        });
+        match provided {
+            Some(value) => Self(value.clone()),
+            None => Self(HeaderValue::from_str(&uuid::Uuid::new_v4().to_string()).unwrap()),
+        }
+    }
+
+    /// The id of the request being handled, if any.
+    pub fn current() -> Option<RequestId> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ids made of visible ASCII characters are kept.
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Middleware giving every request an id: it is stored in the request extensions, available
+/// through `RequestId::current` while handling it and returned in the `X-Request-Id` header.
+///
+/// It wraps `TracingLogger`, so that the span of the request can record the id.
+pub fn propagate<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let request_id = RequestId::of(req.headers());
+    req.extensions_mut().insert(request_id.clone());
+    let response = CURRENT.sync_scope(request_id.clone(), || srv.call(req));
+    let header = request_id.0.clone();
+    CURRENT.scope(request_id, async move {
+        let mut response = response.await?;
+        response.headers_mut().insert(REQUEST_ID, header);
+        Ok(response)
+    })
+}
+
+/// Span of a request, `tracing_actix_web::root_span!` apart from the request id which is the
+/// one from `propagate` rather than one of its own.
+pub struct RequestSpan;
+
+impl RootSpanBuilder for RequestSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(ToString::to_string);
+        let route = request.match_pattern();
+        let span = tracing::info_span!(
+            "HTTP request",
+            http.method = %request.method(),
+            http.route = route.as_deref().unwrap_or_else(|| request.path()),
+            http.status_code = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+            exception.message = tracing::field::Empty,
+            exception.details = tracing::field::Empty,
+            request_id = request_id.as_deref(),
+            actor = tracing::field::Empty,
+        );
+        telemetry::continue_trace(&span, request.headers());
+        span
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        match outcome {
+            Ok(response) => {
+                tracing::info!(parent: &span, status = response.status().as_u16(), "request handled")
+            }
+            Err(err) => tracing::warn!(parent: &span, error = %err, "request failed"),
+        }
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{application::ApplicationError, domain, errors::CarRentalResponseError};
+    use actix_web::{test, web, App, HttpResponse};
+
+    async fn not_found() -> Result<HttpResponse, CarRentalResponseError> {
+        Err(ApplicationError::Domain(domain::Error::CustomerNotFound).into())
+    }
+
+    #[actix_web::test]
+    async fn it_should_generate_a_request_id() {
+        let service = test::init_service(
+            App::new()
+                .wrap_fn(propagate)
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let response = test::call_service(&service, test::TestRequest::get().to_request()).await;
+        let request_id = response
+            .headers()
+            .get(REQUEST_ID)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(uuid::Uuid::parse_str(request_id).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn it_should_reuse_the_provided_request_id() {
+        let service = test::init_service(
+            App::new()
+                .wrap_fn(propagate)
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .insert_header((REQUEST_ID, "checkout-42"))
+            .to_request();
+        let response = test::call_service(&service, request).await;
+        assert_eq!(response.headers().get(REQUEST_ID).unwrap(), "checkout-42");
+
+        let request = test::TestRequest::get()
+            .insert_header((REQUEST_ID, "x".repeat(MAX_LENGTH + 1)))
+            .to_request();
+        let response = test::call_service(&service, request).await;
+        assert_ne!(
+            response.headers().get(REQUEST_ID).unwrap(),
+            &"x".repeat(MAX_LENGTH + 1)
+        );
+    }
+
+    #[actix_web::test]
+    async fn it_should_quote_the_request_id_in_error_bodies() {
+        let service = test::init_service(
+            App::new()
+                .wrap_fn(propagate)
+                .route("/", web::get().to(not_found)),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .insert_header((REQUEST_ID, "checkout-42"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&service, request).await;
+        assert_eq!(body["requestId"], "checkout-42");
+    }
+}
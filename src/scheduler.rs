@@ -0,0 +1,318 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::time::MissedTickBehavior;
+
+use crate::{backoff, shutdown::Shutdown};
+
+/// What the scheduled jobs run against.
+#[derive(Clone)]
+pub struct AppContext {
+    pub read_model: PgPool,
+}
+
+/// A job run every `interval`, such as a sweep of the read model.
+#[async_trait]
+pub trait ScheduledJob: Send + Sync {
+    /// Names the job in the logs and in `/admin/jobs`.
+    fn name(&self) -> &'static str;
+
+    fn interval(&self) -> Duration;
+
+    async fn run(&self, context: &AppContext) -> anyhow::Result<()>;
+}
+
+/// A scheduled job and how its runs went, as listed by `GET /admin/jobs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub interval_ms: u64,
+    pub running: bool,
+    pub runs: u64,
+    /// Runs left out as the previous one was still going.
+    pub skipped: u64,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_duration_ms: Option<u64>,
+    /// The error of the last run, `None` once one succeeds.
+    pub last_error: Option<String>,
+}
+
+/// How a run asked for by `Scheduler::run_now` went.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    Ran(JobStatus),
+    /// The previous run is still going.
+    Running,
+}
+
+struct Entry {
+    job: Box<dyn ScheduledJob>,
+    status: Mutex<JobStatus>,
+}
+
+/// Runs the jobs registered every their interval once started, the first run of each at a
+/// random point of its first interval so that the instances starting together spread apart.
+///
+/// A run is skipped while the previous one of the same job is still going, and cancelled by the
+/// shutdown.
+#[derive(Clone)]
+pub struct Scheduler {
+    context: AppContext,
+    shutdown: Shutdown,
+    jobs: Vec<Arc<Entry>>,
+}
+
+impl Scheduler {
+    pub fn new(context: AppContext, shutdown: Shutdown) -> Self {
+        Self {
+            context,
+            shutdown,
+            jobs: Vec::new(),
+        }
+    }
+
+    pub fn with_job(mut self, job: impl ScheduledJob + 'static) -> Self {
+        let status = JobStatus {
+            name: job.name(),
+            interval_ms: job.interval().as_millis() as u64,
+            running: false,
+            runs: 0,
+            skipped: 0,
+            last_run_at: None,
+            last_duration_ms: None,
+            last_error: None,
+        };
+        self.jobs.push(Arc::new(Entry {
+            job: Box::new(job),
+            status: Mutex::new(status),
+        }));
+        self
+    }
+
+    /// Spawns a task running each job every its interval until the shutdown.
+    pub fn start(&self) {
+        for entry in &self.jobs {
+            tokio::spawn(self.clone().every(entry.clone()));
+        }
+    }
+
+    async fn every(self, entry: Arc<Entry>) {
+        let interval = entry.job.interval();
+        let first = tokio::time::Instant::now() + interval.mul_f64(backoff::random());
+        let mut ticks = tokio::time::interval_at(first, interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let shutdown = self.shutdown.requested();
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return,
+                _ = ticks.tick() => {}
+            }
+            // Spawned so that the next ticks come on time, and are skipped while it runs.
+            let scheduler = self.clone();
+            let entry = entry.clone();
+            tokio::spawn(async move { scheduler.run(&entry).await });
+        }
+    }
+
+    /// Runs `entry` unless it's running already, returning its status once done.
+    async fn run(&self, entry: &Entry) -> Option<JobStatus> {
+        let name = entry.job.name();
+        {
+            let mut status = entry.status.lock().unwrap();
+            if status.running {
+                status.skipped += 1;
+                tracing::debug!(
+                    job = name,
+                    "skipped the job, its previous run is still going"
+                );
+                return None;
+            }
+            status.running = true;
+        }
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let result = tokio::select! {
+            _ = self.shutdown.requested() => Err(anyhow::anyhow!("cancelled by the shutdown")),
+            result = entry.job.run(&self.context) => result,
+        };
+        let duration = started.elapsed();
+        if let Err(err) = &result {
+            tracing::warn!(job = name, error = format!("{err:#}"), "the job failed");
+        }
+        let mut status = entry.status.lock().unwrap();
+        status.running = false;
+        status.runs += 1;
+        status.last_run_at = Some(started_at);
+        status.last_duration_ms = Some(duration.as_millis() as u64);
+        status.last_error = result.err().map(|err| format!("{err:#}"));
+        Some(status.clone())
+    }
+
+    /// Runs the job `name` at once, `None` when there is none.
+    pub async fn run_now(&self, name: &str) -> Option<RunOutcome> {
+        let entry = self.jobs.iter().find(|entry| entry.job.name() == name)?;
+        Some(match self.run(entry).await {
+            Some(status) => RunOutcome::Ran(status),
+            None => RunOutcome::Running,
+        })
+    }
+
+    /// The status of every job, in the order they were registered.
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        self.jobs
+            .iter()
+            .map(|entry| entry.status.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        sms::{OverdueNotifier, Sms, SmsSender},
+        test_support,
+    };
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use tokio::sync::Notify;
+
+    /// Waits on `release` every run, failing once released.
+    struct Blocking {
+        release: Arc<Notify>,
+    }
+
+    #[async_trait]
+    impl ScheduledJob for Blocking {
+        fn name(&self) -> &'static str {
+            "blocking"
+        }
+
+        fn interval(&self) -> Duration {
+            Duration::from_secs(3600)
+        }
+
+        async fn run(&self, _: &AppContext) -> anyhow::Result<()> {
+            self.release.notified().await;
+            anyhow::bail!("released")
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_skip_the_runs_while_the_previous_one_is_going() {
+        let options = PgConnectOptions::new().host("127.0.0.1").port(1);
+        let context = AppContext {
+            read_model: PgPoolOptions::new().connect_lazy_with(options),
+        };
+        let release = Arc::new(Notify::new());
+        let shutdown = Shutdown::default();
+        let scheduler = Scheduler::new(context, shutdown.clone()).with_job(Blocking {
+            release: release.clone(),
+        });
+        assert_eq!(scheduler.run_now("unknown").await, None);
+
+        let running = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.run_now("blocking").await }
+        });
+        while !scheduler.statuses()[0].running {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            scheduler.run_now("blocking").await,
+            Some(RunOutcome::Running)
+        );
+        release.notify_one();
+        let Some(RunOutcome::Ran(status)) = running.await.unwrap() else {
+            panic!("the job should have run");
+        };
+        assert!(!status.running);
+        assert_eq!((status.runs, status.skipped), (1, 1));
+        assert_eq!(status.last_error.as_deref(), Some("released"));
+
+        let running = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.run_now("blocking").await }
+        });
+        while !scheduler.statuses()[0].running {
+            tokio::task::yield_now().await;
+        }
+        shutdown.trigger();
+        let Some(RunOutcome::Ran(status)) = running.await.unwrap() else {
+            panic!("the job should have been cancelled");
+        };
+        assert_eq!(status.runs, 2);
+        assert_eq!(
+            status.last_error.as_deref(),
+            Some("cancelled by the shutdown")
+        );
+    }
+
+    /// Records the SMS sent.
+    #[derive(Default)]
+    struct Recording(Mutex<Vec<Sms>>);
+
+    #[async_trait]
+    impl SmsSender for Recording {
+        async fn send(&self, sms: &Sms) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(sms.clone());
+            Ok(())
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_sweep_the_overdue_rentals_every_interval(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name, phone)
+                VALUES ('mario@example.com', 'Mario', 'Rossi', '+39021234567')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at)
+                VALUES ('AA111AA', 'van', now() - interval '1 year')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date)
+                VALUES (1, 'mario@example.com', 'AA111AA', now() - interval '40 days')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let sender = Arc::new(Recording::default());
+        let shutdown = Shutdown::default();
+        let notifier = OverdueNotifier::new(sender.clone(), Duration::from_millis(20));
+        let scheduler =
+            Scheduler::new(AppContext { read_model: pool }, shutdown.clone()).with_job(notifier);
+        scheduler.start();
+
+        for _ in 0..250 {
+            if scheduler.statuses()[0].runs >= 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        shutdown.trigger();
+        let status = &scheduler.statuses()[0];
+        assert_eq!(status.name, "overdue_rentals");
+        assert!(status.runs >= 3, "{status:?}");
+        assert_eq!(status.last_error, None);
+        // Texted once a day, however often swept.
+        assert_eq!(sender.0.lock().unwrap().len(), 1);
+    }
+}
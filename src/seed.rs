@@ -0,0 +1,160 @@
+use crate::{
+    application::{Application, ApplicationError, ApplicationResult},
+    domain::{default_tenant, EndRent, RegisterCustomer, RegisterVehicle, StartRent, VehicleType},
+};
+
+const FIRST_NAMES: [&str; 8] = [
+    "Mario",
+    "Luigi",
+    "Giulia",
+    "Francesca",
+    "Marco",
+    "Chiara",
+    "Paolo",
+    "Sara",
+];
+const LAST_NAMES: [&str; 8] = [
+    "Rossi", "Verdi", "Bianchi", "Russo", "Ferrari", "Esposito", "Romano", "Colombo",
+];
+
+/// Size of the demo dataset, set by `SEED_CUSTOMERS`, `SEED_VEHICLES` and `SEED_RENTALS`, and
+/// the `SEED_RNG` it's drawn from: the same config yields the same dataset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeedConfig {
+    pub customers: usize,
+    pub vehicles: usize,
+    /// Rentals started, about half of them ended right away.
+    pub rentals: usize,
+    pub rng: u64,
+}
+
+impl Default for SeedConfig {
+    fn default() -> Self {
+        Self {
+            customers: 20,
+            vehicles: 12,
+            rentals: 15,
+            rng: 42,
+        }
+    }
+}
+
+/// Commands the seeding sent, by outcome.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Seeded {
+    pub applied: usize,
+    /// Customers and vehicles already registered, or rentals the state didn't allow.
+    pub skipped: usize,
+}
+
+impl Seeded {
+    fn count<T>(&mut self, outcome: ApplicationResult<T>) -> ApplicationResult<()> {
+        match outcome {
+            Ok(_) => self.applied += 1,
+            Err(ApplicationError::Domain(err)) => {
+                tracing::debug!(%err, "skipping a seed command");
+                self.skipped += 1;
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+}
+
+/// SplitMix64, enough to draw a reproducible demo dataset.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Registers the customers and the vehicles, spread across every type, then starts and ends
+/// rentals of them, all through the decisions and within the default tenant.
+///
+/// The customers and vehicles are named after their index, so that seeding again skips those
+/// already registered rather than failing; the rentals are drawn again, though, and add up.
+pub async fn run(app: &Application, config: SeedConfig) -> ApplicationResult<Seeded> {
+    let mut rng = Rng(config.rng);
+    let mut seeded = Seeded::default();
+    let mut customers = Vec::with_capacity(config.customers);
+    for i in 0..config.customers {
+        let first_name = FIRST_NAMES[rng.below(FIRST_NAMES.len())];
+        let last_name = LAST_NAMES[rng.below(LAST_NAMES.len())];
+        let customer_id = format!(
+            "{}.{}{i}@example.com",
+            first_name.to_lowercase(),
+            last_name.to_lowercase()
+        );
+        let command = RegisterCustomer {
+            tenant_id: default_tenant(),
+            customer_id: customer_id.clone().into(),
+            first_name: first_name.to_string(),
+            last_name: last_name.to_string(),
+            phone: None,
+        };
+        seeded.count(app.register_customer(command).await)?;
+        customers.push(customer_id);
+    }
+    for i in 0..config.vehicles {
+        let command = RegisterVehicle {
+            tenant_id: default_tenant(),
+            vehicle_id: format!("SD{i:04}"),
+            vehicle_type: VehicleType::ALL[i % VehicleType::ALL.len()].clone(),
+            seats: None,
+            transmission: None,
+        };
+        seeded.count(app.register_vehicle(command).await)?;
+    }
+    if customers.is_empty() {
+        return Ok(seeded);
+    }
+    for _ in 0..config.rentals {
+        let customer_id = customers[rng.below(customers.len())].clone();
+        let vehicle_type = VehicleType::ALL[rng.below(VehicleType::ALL.len())].clone();
+        let ended = rng.below(2) == 0;
+        let started = app
+            .start_rent(StartRent {
+                tenant_id: default_tenant(),
+                customer_id: customer_id.clone().into(),
+                vehicle_type,
+            })
+            .await;
+        let rented = started.is_ok();
+        seeded.count(started)?;
+        if rented && ended {
+            seeded.count(
+                app.end_rent(EndRent {
+                    tenant_id: default_tenant(),
+                    customer_id: customer_id.into(),
+                })
+                .await,
+            )?;
+        }
+    }
+    Ok(seeded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_should_draw_the_same_numbers_from_the_same_seed() {
+        let draw = |seed| {
+            let mut rng = Rng(seed);
+            (0..5).map(|_| rng.below(100)).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+    }
+}
@@ -0,0 +1,191 @@
+use disintegrate::{query, stream_query::origin, EventStore as _};
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use sqlx::{postgres::PgConnectOptions, PgPool};
+
+use crate::{
+    domain::DomainEvent,
+    pii::{self, EncryptedJson},
+    read_model::ReadModelSchema,
+    EventStore,
+};
+
+/// The tables of `PgEventStore` and of its snapshots, in the `public` schema.
+const EVENT_STORE_TABLES: [&str; 3] = ["event", "event_sequence", "snapshot"];
+
+/// The checkpoints table of the listeners, the one `PgEventListener` creates when it starts.
+pub const CREATE_CHECKPOINTS: &str = r#"CREATE TABLE IF NOT EXISTS public.event_listener (
+    id TEXT PRIMARY KEY,
+    last_processed_event_id BIGINT,
+    updated_at TIMESTAMP DEFAULT now()
+)"#;
+
+/// What the startup found of the database, told by `/readyz`: the startup fails unless
+/// every part is there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SelfCheck {
+    pub database: bool,
+    pub event_store: bool,
+    pub migrations: bool,
+    pub checkpoints: bool,
+    /// No event holds the email of its customer in plaintext.
+    pub sealed: bool,
+}
+
+impl SelfCheck {
+    /// What `EVENT_STORE=memory` starts with, having nothing to set up.
+    pub fn in_memory() -> Self {
+        Self {
+            database: true,
+            event_store: true,
+            migrations: true,
+            checkpoints: true,
+            sealed: true,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.database && self.event_store && self.migrations && self.checkpoints && self.sealed
+    }
+}
+
+/// What the startup found missing, telling how to set it up.
+#[derive(Debug, thiserror::Error)]
+pub enum Missing {
+    #[error(
+        "the database {target} is unreachable: {source}; check DATABASE_URL, or the PG* variables"
+    )]
+    Database {
+        target: String,
+        #[source]
+        source: sqlx::Error,
+    },
+    #[error("the event store tables are missing: {0}; run `car-rental migrate`, or start with DATABASE_AUTO_MIGRATE=true")]
+    EventStore(String),
+    #[error("the event store can't be read: {0}")]
+    EventStoreUnreadable(#[source] disintegrate_postgres::Error),
+    #[error("the migrations of the read model {schema} are not applied: {pending}; run `car-rental migrate`, or start with DATABASE_AUTO_MIGRATE=true")]
+    Migrations {
+        schema: ReadModelSchema,
+        pending: String,
+    },
+    #[error("the listener checkpoints in public.event_listener can't be read: {0}; run `car-rental migrate`, or start with DATABASE_AUTO_MIGRATE=true")]
+    Checkpoints(#[source] sqlx::Error),
+    #[error("events of public.event were recorded in plaintext, before sealing; run `car-rental seal-events`, or start with DATABASE_AUTO_MIGRATE=true")]
+    Plaintext,
+    #[error("the events recorded in plaintext can't be sealed: {0}")]
+    Unsealed(#[source] sqlx::Error),
+}
+
+/// Names the database `options` connect to, e.g. `rentals on db:5432`, for the errors.
+pub fn target(options: &PgConnectOptions) -> String {
+    format!(
+        "{} on {}:{}",
+        options.get_database().unwrap_or("postgres"),
+        options.get_host(),
+        options.get_port()
+    )
+}
+
+/// Fails unless the tables of the event store are there, before setting it up.
+pub async fn event_store_tables(pool: &PgPool) -> Result<(), Missing> {
+    let missing: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM unnest($1::text[]) AS name WHERE to_regclass('public.' || name) IS NULL",
+    )
+    .bind(EVENT_STORE_TABLES)
+    .fetch_all(pool)
+    .await
+    .map_err(|source| Missing::Database {
+        target: target(&pool.connect_options()),
+        source,
+    })?;
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Missing::EventStore(missing.join(", ")))
+    }
+}
+
+/// Fails unless the event store answers a query, one matching no event.
+pub async fn event_store(event_store: &EventStore) -> Result<(), Missing> {
+    let nothing = query::<DomainEvent>(Some(origin(i64::MAX)));
+    event_store
+        .stream(&nothing)
+        .try_collect::<Vec<_>>()
+        .await
+        .map(drop)
+        .map_err(Missing::EventStoreUnreadable)
+}
+
+/// Fails unless every migration of the read model was applied to `read_model`, connected to
+/// `schema`.
+pub async fn migrations(read_model: &PgPool, schema: &ReadModelSchema) -> Result<(), Missing> {
+    let unreachable = |source| Missing::Database {
+        target: target(&read_model.connect_options()),
+        source,
+    };
+    let migrated: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(read_model)
+        .await
+        .map_err(unreachable)?;
+    let applied: Vec<i64> = if migrated {
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(read_model)
+            .await
+            .map_err(unreachable)?
+    } else {
+        Vec::new()
+    };
+    let pending: Vec<_> = sqlx::migrate!()
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .map(|migration| format!("{}_{}", migration.version, migration.description))
+        .collect();
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(Missing::Migrations {
+            schema: schema.clone(),
+            pending: pending.join(", "),
+        })
+    }
+}
+
+/// Fails unless the checkpoints of the listeners can be read, creating their table first
+/// when `create` is set.
+pub async fn checkpoints(pool: &PgPool, create: bool) -> Result<(), Missing> {
+    if create {
+        sqlx::query(CREATE_CHECKPOINTS)
+            .execute(pool)
+            .await
+            .map_err(Missing::Checkpoints)?;
+    }
+    sqlx::query("SELECT count(*) FROM public.event_listener")
+        .execute(pool)
+        .await
+        .map(drop)
+        .map_err(Missing::Checkpoints)
+}
+
+/// Fails when events still hold the email of their customer in plaintext, recorded before
+/// sealing, unless `seal` is set: they are then sealed as `seal-events` does.
+pub async fn sealed(pool: &PgPool, serde: &EncryptedJson, seal: bool) -> Result<(), Missing> {
+    // The emails are the only identifiers with an `@`.
+    let plaintext: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM public.event WHERE customer_id LIKE '%@%')",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Missing::Unsealed)?;
+    if !plaintext {
+        return Ok(());
+    }
+    if !seal {
+        return Err(Missing::Plaintext);
+    }
+    let sealed = pii::seal_events(pool, serde)
+        .await
+        .map_err(Missing::Unsealed)?;
+    tracing::info!(sealed, "sealed the events recorded in plaintext");
+    Ok(())
+}
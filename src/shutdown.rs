@@ -0,0 +1,256 @@
+use std::{future::Future, time::Duration};
+
+use actix_web::dev::Server;
+use anyhow::anyhow;
+use tokio::{sync::watch, task::JoinError};
+
+use crate::backoff::Backoff;
+
+/// How long the HTTP server and the event listener get to finish once asked to stop.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Shutdown signal shared by the HTTP server and the event listener.
+///
+/// It's triggered by ctrl-c or SIGTERM through `listen`, or by `trigger` in tests.
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Sender<bool>);
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self(watch::channel(false).0)
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the shutdown is triggered.
+    pub fn requested(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut triggered = self.0.subscribe();
+        async move {
+            // The sender lives as long as the signal, so it isn't dropped before triggering.
+            let _ = triggered.wait_for(|triggered| *triggered).await;
+        }
+    }
+
+    /// Triggers the shutdown on the first signal; a second one exits right away.
+    pub async fn listen(self) {
+        signal().await;
+        tracing::info!("shutting down, signal again to exit now");
+        self.trigger();
+        signal().await;
+        tracing::warn!("exiting without waiting for the shutdown");
+        std::process::exit(130);
+    }
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.expect("failed to listen for ctrl-c"),
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to listen for ctrl-c");
+}
+
+/// Restarts of the event listener after a failure: `LISTENER_MAX_RESTARTS` at most, waiting
+/// `LISTENER_RESTART_BACKOFF_MS` before the first one and twice as long before each next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Restarts {
+    pub max: u32,
+    pub backoff: Duration,
+}
+
+impl Default for Restarts {
+    fn default() -> Self {
+        Self {
+            max: 0,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Runs the listener started by `start`, starting it again after a failure as `restarts`
+/// allows.
+///
+/// Stopping before the shutdown is requested is a failure too: the listener of disintegrate
+/// returns once all of its projections failed.
+pub async fn supervise<F, L>(
+    mut start: F,
+    restarts: Restarts,
+    shutdown: Shutdown,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> L,
+    L: Future<Output = anyhow::Result<()>>,
+{
+    let mut attempt = 0;
+    loop {
+        let result = start().await;
+        if shutdown.is_requested() {
+            return result;
+        }
+        let err = result
+            .err()
+            .unwrap_or_else(|| anyhow!("the event listener stopped unexpectedly"));
+        if attempt == restarts.max {
+            return Err(err);
+        }
+        attempt += 1;
+        let backoff = Backoff::new(restarts.backoff).delay(attempt);
+        tracing::warn!(
+            error = format!("{err:#}"),
+            attempt,
+            ?backoff,
+            "restarting the event listener"
+        );
+        tokio::select! {
+            () = tokio::time::sleep(backoff) => {}
+            () = shutdown.requested() => return Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Task {
+    Server,
+    Listener,
+}
+
+fn failure<E>(task: &str, result: Result<Result<(), E>, JoinError>) -> anyhow::Error
+where
+    E: Into<anyhow::Error>,
+{
+    match result {
+        Ok(Ok(())) => anyhow!("{task} stopped unexpectedly"),
+        Ok(Err(err)) => err.into().context(format!("{task} failed")),
+        Err(err) => anyhow::Error::from(err).context(format!("{task} panicked")),
+    }
+}
+
+/// Runs the HTTP server and the event listener until either stops or the shutdown is
+/// requested, then lets the others finish within the grace period: the server stops accepting
+/// connections and completes the requests in flight.
+///
+/// Either task stopping on its own is a failure, returned once the other one stopped.
+pub async fn run<L>(
+    server: Server,
+    listener: L,
+    shutdown: Shutdown,
+    grace_period: Duration,
+) -> anyhow::Result<()>
+where
+    L: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let handle = server.handle();
+    let mut server = tokio::spawn(server);
+    let mut listener = tokio::spawn(listener);
+    let failed = tokio::select! {
+        // The listener stops as soon as the shutdown is requested, which mustn't pass for a
+        // failure.
+        biased;
+        () = shutdown.requested() => None,
+        result = &mut server => Some((Task::Server, failure("the HTTP server", result))),
+        result = &mut listener => Some((Task::Listener, failure("the event listener", result))),
+    };
+    if let Some((_, err)) = &failed {
+        tracing::error!(error = format!("{err:#}"), "shutting down after a failure");
+        shutdown.trigger();
+    }
+    let stopped = failed.as_ref().map(|(task, _)| *task);
+    let stop_server = async {
+        if stopped == Some(Task::Server) {
+            return Ok(());
+        }
+        handle.stop(true).await;
+        Ok(server.await??)
+    };
+    let stop_listener = async {
+        if stopped == Some(Task::Listener) {
+            return Ok(());
+        }
+        listener.await?
+    };
+    let stopping = async {
+        let (server, listener) = tokio::join!(stop_server, stop_listener);
+        server.and(listener)
+    };
+    let result = match tokio::time::timeout(grace_period, stopping).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("the shutdown took longer than {grace_period:?}")),
+    };
+    match failed {
+        Some((_, err)) => Err(err),
+        None => result,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn it_should_restart_the_listener_until_it_gives_up() {
+        let restarts = Restarts {
+            max: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let starts = AtomicU32::new(0);
+        let failing = || async {
+            starts.fetch_add(1, Ordering::SeqCst);
+            anyhow::bail!("projection failed")
+        };
+        let result = supervise(failing, restarts, Shutdown::default()).await;
+        assert_eq!(result.unwrap_err().to_string(), "projection failed");
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+
+        let starts = AtomicU32::new(0);
+        let recovering = || async {
+            match starts.fetch_add(1, Ordering::SeqCst) {
+                0 => anyhow::bail!("projection failed"),
+                _ => Ok(()),
+            }
+        };
+        let result = supervise(recovering, restarts, Shutdown::default()).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "the event listener stopped unexpectedly"
+        );
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn it_should_not_restart_the_listener_once_shutting_down() {
+        let shutdown = Shutdown::default();
+        let starts = AtomicU32::new(0);
+        let stopping = || async {
+            starts.fetch_add(1, Ordering::SeqCst);
+            shutdown.trigger();
+            Ok(())
+        };
+        let restarts = Restarts {
+            max: 2,
+            backoff: Duration::from_millis(1),
+        };
+        assert!(supervise(stopping, restarts, shutdown.clone())
+            .await
+            .is_ok());
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,385 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    config::SmsConfig,
+    domain::TenantId,
+    reports::OverdueRental,
+    scheduler::{AppContext, ScheduledJob},
+    ReadModelRepository,
+};
+
+/// An SMS to a customer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sms {
+    pub to: String,
+    pub body: String,
+}
+
+/// Sends the SMS, through the API of a provider or, in the tests, to a recording.
+#[async_trait]
+pub trait SmsSender: Send + Sync {
+    async fn send(&self, sms: &Sms) -> anyhow::Result<()>;
+}
+
+/// The sender of `config`, `None` unless the SMS are enabled.
+pub fn sender(config: &SmsConfig) -> Option<Arc<dyn SmsSender>> {
+    if !config.enabled {
+        return None;
+    }
+    let (Some(account_sid), Some(auth_token), Some(from)) =
+        (&config.account_sid, &config.auth_token, &config.from)
+    else {
+        unreachable!("the configuration requires the credentials of the SMS API");
+    };
+    Some(Arc::new(TwilioSender::new(
+        &config.api_url,
+        account_sid,
+        auth_token.expose(),
+        from,
+    )))
+}
+
+/// Sends the SMS through an API shaped like Twilio's: a form posted to the messages of the
+/// account, authenticated by its SID and token.
+pub struct TwilioSender {
+    client: reqwest::Client,
+    messages_url: String,
+    account_sid: String,
+    auth_token: String,
+    from: String,
+}
+
+impl TwilioSender {
+    pub fn new(api_url: &str, account_sid: &str, auth_token: &str, from: &str) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("the HTTP client has a valid configuration"),
+            messages_url: format!(
+                "{}/Accounts/{account_sid}/Messages.json",
+                api_url.trim_end_matches('/')
+            ),
+            account_sid: account_sid.to_string(),
+            auth_token: auth_token.to_string(),
+            from: from.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl SmsSender for TwilioSender {
+    async fn send(&self, sms: &Sms) -> anyhow::Result<()> {
+        let response = self
+            .client
+            .post(&self.messages_url)
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .form(&[
+                ("To", sms.to.as_str()),
+                ("From", &self.from),
+                ("Body", &sms.body),
+            ])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "the SMS API answered {}: {}",
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Texts the customers of the overdue rentals, as listed by the overdue report, at most once
+/// per rental and day, swept every `interval` as the `overdue_rentals` job.
+///
+/// Each SMS is logged in `sms_log` before being sent, the log of a failed one being removed so
+/// that the next sweep sends it again; the customers without a phone are left out.
+pub struct OverdueNotifier {
+    sender: Arc<dyn SmsSender>,
+    interval: Duration,
+}
+
+impl OverdueNotifier {
+    pub fn new(sender: Arc<dyn SmsSender>, interval: Duration) -> Self {
+        Self { sender, interval }
+    }
+
+    /// Texts the customers of the rentals overdue not texted yet on `day`, those of every
+    /// tenant, returning how many SMS were sent.
+    pub async fn notify(&self, pool: &PgPool, day: NaiveDate) -> Result<usize, sqlx::Error> {
+        let tenants: Vec<TenantId> =
+            sqlx::query_scalar("SELECT DISTINCT tenant_id FROM rent WHERE end_date IS NULL")
+                .fetch_all(pool)
+                .await?;
+        let repository = ReadModelRepository::new(pool.clone());
+        let mut sent = 0;
+        for tenant_id in tenants {
+            let rentals = repository.for_tenant(tenant_id).overdue_rentals(0).await?;
+            sent += self.text(pool, day, rentals).await?;
+        }
+        Ok(sent)
+    }
+
+    async fn text(
+        &self,
+        pool: &PgPool,
+        day: NaiveDate,
+        rentals: Vec<OverdueRental>,
+    ) -> Result<usize, sqlx::Error> {
+        let mut sent = 0;
+        for rental in rentals {
+            let Some(phone) = &rental.phone else {
+                tracing::warn!(
+                    rent_id = rental.rent_id,
+                    customer_id = rental.customer_id,
+                    "no phone to text the customer of the overdue rental"
+                );
+                continue;
+            };
+            let sms = overdue(&rental, phone);
+            let logged = sqlx::query(
+                r#"INSERT INTO sms_log (rent_id, day, recipient) VALUES($1, $2, $3)
+                    ON CONFLICT (rent_id, day) DO NOTHING"#,
+            )
+            .bind(rental.rent_id)
+            .bind(day)
+            .bind(&sms.to)
+            .execute(pool)
+            .await?
+            .rows_affected();
+            if logged == 0 {
+                continue;
+            }
+            if let Err(err) = self.sender.send(&sms).await {
+                tracing::warn!(
+                    rent_id = rental.rent_id,
+                    error = format!("{err:#}"),
+                    "failed to text the customer of the overdue rental"
+                );
+                sqlx::query("DELETE FROM sms_log WHERE rent_id = $1 AND day = $2")
+                    .bind(rental.rent_id)
+                    .bind(day)
+                    .execute(pool)
+                    .await?;
+                continue;
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+#[async_trait]
+impl ScheduledJob for OverdueNotifier {
+    fn name(&self) -> &'static str {
+        "overdue_rentals"
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn run(&self, context: &AppContext) -> anyhow::Result<()> {
+        let sent = self
+            .notify(&context.read_model, Utc::now().date_naive())
+            .await?;
+        if sent > 0 {
+            tracing::info!(sent, "texted the customers of the overdue rentals");
+        }
+        Ok(())
+    }
+}
+
+/// The SMS reminding the customer of `rental` to return the vehicle, to `phone` without the
+/// spaces and dashes it may be written with.
+fn overdue(rental: &OverdueRental, phone: &str) -> Sms {
+    Sms {
+        to: phone
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == '+')
+            .collect(),
+        body: format!(
+            "Hi {}, the rental of {} was due back on {}. Please return it as soon as possible. Drive Me Crazy",
+            rental.first_name,
+            rental.vehicle_id,
+            rental.due_date.format("%Y-%m-%d %H:%M UTC"),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support;
+    use actix_web::{web, App, HttpRequest, HttpResponse};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use std::{collections::HashMap, sync::Mutex};
+
+    struct Texted {
+        authorization: String,
+        form: HashMap<String, String>,
+    }
+
+    #[derive(Clone, Default)]
+    struct Received(Arc<Mutex<Vec<Texted>>>);
+
+    /// Answers `201` to the messages of account `AC123`, recording their authorization and
+    /// form.
+    fn sms_api() -> (actix_test::TestServer, Received) {
+        let received = Received::default();
+        let server = {
+            let received = received.clone();
+            actix_test::start(move || {
+                let received = received.clone();
+                App::new().route(
+                    "/2010-04-01/Accounts/AC123/Messages.json",
+                    web::post().to(
+                        move |req: HttpRequest, form: web::Form<HashMap<String, String>>| {
+                            let received = received.clone();
+                            async move {
+                                let authorization = req
+                                    .headers()
+                                    .get("authorization")
+                                    .map_or("", |value| value.to_str().unwrap())
+                                    .to_string();
+                                received.0.lock().unwrap().push(Texted {
+                                    authorization,
+                                    form: form.into_inner(),
+                                });
+                                HttpResponse::Created().finish()
+                            }
+                        },
+                    ),
+                )
+            })
+        };
+        (server, received)
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_text_each_overdue_rental_once_a_day(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name, phone) VALUES
+                ('mario@example.com', 'Mario', 'Rossi', '+39 02-1234567'),
+                ('luigi@example.com', 'Luigi', 'Verdi', NULL)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at)
+                VALUES ('AA111AA', 'van', now() - interval '1 year'),
+                    ('BB222BB', 'car', now() - interval '1 year')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date) VALUES
+                (1, 'mario@example.com', 'AA111AA', now() - interval '40 days'),
+                (2, 'luigi@example.com', 'BB222BB', now() - interval '35 days')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let (server, received) = sms_api();
+        let sender = TwilioSender::new(
+            &server.url("/2010-04-01/"),
+            "AC123",
+            "s3cr3t",
+            "+15005550006",
+        );
+        let notifier = OverdueNotifier::new(Arc::new(sender), Duration::from_secs(60));
+        let today = NaiveDate::from_ymd_opt(2026, 10, 14).unwrap();
+
+        assert_eq!(notifier.notify(&pool, today).await.unwrap(), 1);
+        assert_eq!(notifier.notify(&pool, today).await.unwrap(), 0);
+        {
+            let received = received.0.lock().unwrap();
+            assert_eq!(received.len(), 1);
+            let Texted {
+                authorization,
+                form,
+            } = &received[0];
+            assert_eq!(authorization, "Basic QUMxMjM6czNjcjN0");
+            assert_eq!(form["To"], "+39021234567");
+            assert_eq!(form["From"], "+15005550006");
+            let due = (Utc::now() - chrono::Duration::days(10)).format("%Y-%m-%d");
+            assert!(
+                form["Body"].starts_with(&format!(
+                    "Hi Mario, the rental of AA111AA was due back on {due} "
+                )),
+                "{}",
+                form["Body"]
+            );
+        }
+
+        let tomorrow = today.succ_opt().unwrap();
+        assert_eq!(notifier.notify(&pool, tomorrow).await.unwrap(), 1);
+        assert_eq!(received.0.lock().unwrap().len(), 2);
+    }
+
+    /// Fails to send any SMS, counting the attempts.
+    #[derive(Default)]
+    struct FailingSender(Mutex<usize>);
+
+    #[async_trait]
+    impl SmsSender for FailingSender {
+        async fn send(&self, _: &Sms) -> anyhow::Result<()> {
+            *self.0.lock().unwrap() += 1;
+            anyhow::bail!("the SMS API answered 503 Service Unavailable")
+        }
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_text_again_once_the_sending_failed(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        sqlx::query(
+            r#"INSERT INTO customer (customer_id, first_name, last_name, phone)
+                VALUES ('mario@example.com', 'Mario', 'Rossi', '+39021234567')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO vehicle (vehicle_id, vehicle_type, registered_at)
+                VALUES ('AA111AA', 'van', now() - interval '1 year')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO rent (rent_id, customer_id, vehicle_id, start_date)
+                VALUES (1, 'mario@example.com', 'AA111AA', now() - interval '40 days')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let sender = Arc::new(FailingSender::default());
+        let notifier = OverdueNotifier::new(sender.clone(), Duration::from_secs(60));
+        let today = Utc::now().date_naive();
+
+        assert_eq!(notifier.notify(&pool, today).await.unwrap(), 0);
+        assert_eq!(notifier.notify(&pool, today).await.unwrap(), 0);
+        assert_eq!(*sender.0.lock().unwrap(), 2);
+        let logged: i64 = sqlx::query_scalar("SELECT count(*) FROM sms_log")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logged, 0);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    fmt::{self, Display},
+    future::{ready, Ready},
+    marker::PhantomData,
+};
+
+use actix_web::{error, web::Query, FromRequest, HttpRequest};
+use serde::Deserialize;
+
+/// A listing that can be ordered through the `?sort=field:direction` query parameter.
+pub trait Sortable {
+    /// Sortable fields, as exposed in the API, mapped to their column name.
+    const SORT_FIELDS: &'static [(&'static str, &'static str)];
+    /// Field and direction applied when no sort is requested.
+    const DEFAULT_SORT: (&'static str, SortDirection);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl Display for SortDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortDirection::Asc => write!(f, "asc"),
+            SortDirection::Desc => write!(f, "desc"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SortParams<T> {
+    pub field: &'static str,
+    pub column: &'static str,
+    pub direction: SortDirection,
+    sortable: PhantomData<T>,
+}
+
+impl<T: Sortable> SortParams<T> {
+    pub fn parse(sort: Option<&str>) -> Result<Self, String> {
+        let Some(sort) = sort else {
+            let (field, direction) = T::DEFAULT_SORT;
+            return Self::new(field, direction);
+        };
+        let (field, direction) = sort.split_once(':').unwrap_or((sort, "asc"));
+        let direction = match direction {
+            "asc" => SortDirection::Asc,
+            "desc" => SortDirection::Desc,
+            _ => return Err("sort: direction must be one of asc, desc".to_string()),
+        };
+        Self::new(field, direction)
+    }
+
+    fn new(field: &str, direction: SortDirection) -> Result<Self, String> {
+        let Some((field, column)) = T::SORT_FIELDS.iter().find(|(name, _)| *name == field) else {
+            let allowed = T::SORT_FIELDS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(format!("sort: field must be one of {allowed}"));
+        };
+        Ok(Self {
+            field,
+            column,
+            direction,
+            sortable: PhantomData,
+        })
+    }
+
+    /// Returns the `ORDER BY` expression, built only from whitelisted column names.
+    pub fn order_by(&self) -> String {
+        format!("{} {}", self.column, self.direction)
+    }
+}
+
+impl<T> Display for SortParams<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.field, self.direction)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawSortParams {
+    sort: Option<String>,
+}
+
+impl<T: Sortable> FromRequest for SortParams<T> {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        let sort = Query::<RawSortParams>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|params| Self::parse(params.sort.as_deref()))
+            .map_err(error::ErrorBadRequest);
+        ready(sort)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Listing;
+
+    impl Sortable for Listing {
+        const SORT_FIELDS: &'static [(&'static str, &'static str)] =
+            &[("startDate", "start_date"), ("customerId", "customer_id")];
+        const DEFAULT_SORT: (&'static str, SortDirection) = ("startDate", SortDirection::Desc);
+    }
+
+    #[test]
+    fn it_should_fall_back_to_the_default_sort() {
+        let sort = SortParams::<Listing>::parse(None).unwrap();
+        assert_eq!(sort.to_string(), "startDate:desc");
+        assert_eq!(sort.order_by(), "start_date desc");
+    }
+
+    #[test]
+    fn it_should_map_fields_to_columns() {
+        let sort = SortParams::<Listing>::parse(Some("customerId:desc")).unwrap();
+        assert_eq!(sort.order_by(), "customer_id desc");
+        let sort = SortParams::<Listing>::parse(Some("customerId")).unwrap();
+        assert_eq!(sort.order_by(), "customer_id asc");
+    }
+
+    #[test]
+    fn it_should_list_the_allowed_values_on_invalid_input() {
+        assert_eq!(
+            SortParams::<Listing>::parse(Some("start_date; DROP TABLE rent")).unwrap_err(),
+            "sort: field must be one of startDate, customerId"
+        );
+        assert_eq!(
+            SortParams::<Listing>::parse(Some("startDate:up")).unwrap_err(),
+            "sort: direction must be one of asc, desc"
+        );
+    }
+}
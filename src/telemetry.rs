@@ -0,0 +1,87 @@
+use actix_web::http::header::HeaderMap;
+use opentelemetry::{propagation::Extractor, trace::TracerProvider as _};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+/// Default of `OTEL_SERVICE_NAME`.
+pub const DEFAULT_SERVICE_NAME: &str = "car-rental";
+
+/// The export of the spans, when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub struct Telemetry(Option<SdkTracerProvider>);
+
+impl Telemetry {
+    /// Logs the spans and events filtered by `RUST_LOG`, `info` by default, and exports the
+    /// spans over OTLP/HTTP to `endpoint`, e.g. `http://localhost:4318`, when given.
+    ///
+    /// The trace of a request that comes with a W3C `traceparent` goes on from its parent.
+    pub fn init(endpoint: Option<&str>, service_name: &str) -> anyhow::Result<Self> {
+        let provider = match endpoint {
+            Some(endpoint) => {
+                let exporter = SpanExporter::builder()
+                    .with_http()
+                    .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                    .build()?;
+                let provider = SdkTracerProvider::builder()
+                    .with_resource(
+                        Resource::builder()
+                            .with_service_name(service_name.to_string())
+                            .build(),
+                    )
+                    .with_batch_exporter(exporter)
+                    .build();
+                opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+                Some(provider)
+            }
+            None => None,
+        };
+        tracing_subscriber::registry()
+            .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+            .with(tracing_subscriber::fmt::layer())
+            .with(provider.as_ref().map(layer))
+            .init();
+        Ok(Self(provider))
+    }
+
+    /// Exports the spans not exported yet, waiting for the exporter.
+    pub fn shutdown(self) {
+        if let Some(provider) = self.0 {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("failed to export the last spans: {err}");
+            }
+        }
+    }
+}
+
+/// Layer exporting the spans through `provider`.
+pub fn layer<S>(provider: &SdkTracerProvider) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(DEFAULT_SERVICE_NAME))
+}
+
+/// Makes the span of a request a child of the remote span of its `traceparent`, if any.
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+    // Only fails when the spans aren't exported, leaving no trace to go on with.
+    let _ = span.set_parent(parent);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
@@ -0,0 +1,40 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+
+use crate::{
+    domain::{default_tenant, TenantId},
+    tokens::Caller,
+};
+
+/// The tenant of the caller: the one of its API key, stored in the request extensions by
+/// `ApiKeys`, or of its token, the default tenant otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub TenantId);
+
+impl Tenant {
+    pub fn of(req: &HttpRequest) -> Self {
+        Self::named(req).unwrap_or_else(|| Self(default_tenant()))
+    }
+
+    /// The tenant named by the key or the token of the caller, `None` for the callers of the
+    /// whole platform, whose keys and tokens name none.
+    pub fn named(req: &HttpRequest) -> Option<Self> {
+        if let Some(tenant) = req.extensions().get::<Tenant>() {
+            return Some(tenant.clone());
+        }
+        Caller::extract(req)
+            .into_inner()
+            .ok()
+            .and_then(|caller| caller.tenant().map(|tenant| Self(tenant.to_string())))
+    }
+}
+
+impl FromRequest for Tenant {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Tenant::of(req)))
+    }
+}
@@ -0,0 +1,232 @@
+use std::{net::SocketAddr, sync::Mutex};
+
+use actix_web::dev::Server;
+use async_trait::async_trait;
+use chrono::Utc;
+use disintegrate_postgres::{PgEventStore, PgSnapshotter};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool,
+};
+
+use rustls::pki_types::CertificateDer;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    admin::RebuildStatus,
+    application::{
+        self, Application, ApplicationError, ApplicationResult, CommandService, RentEnded,
+        RentStarted,
+    },
+    config::{self, AppConfig},
+    domain::{
+        Email, EndRent, PlateNumber, RegisterCustomer, RegisterVehicle, StartRent, TenantId,
+        VehicleType,
+    },
+    health::Readiness,
+    http::PgBackend,
+    http_config::{HttpConfig, TlsConfig},
+    live::LiveUpdates,
+    metrics::Metrics,
+    pii::{self, EncryptedJson},
+    read_model::{queries::ReadModelRepository, repository::read_repository, ReadModelSchema},
+    reporting::{self, ErrorContext, ErrorReporter},
+    scheduler::{AppContext, Scheduler},
+    shutdown::Shutdown,
+};
+
+/// Tests use a schema other than the default one, so that nothing can rely on its name.
+const SCHEMA: &str = "test_read_model";
+
+/// Sets up a test database the way the application does at startup and connects to its
+/// read model: the event store first, as migrations backfill from it.
+pub async fn read_model(options: PgConnectOptions) -> PgPool {
+    let pool = PgPool::connect_with(options.clone()).await.unwrap();
+    PgEventStore::new(pool, EncryptedJson::default())
+        .await
+        .unwrap();
+    let schema: ReadModelSchema = SCHEMA.parse().unwrap();
+    let pool = schema.connect(PgPoolOptions::new(), options).await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    pool
+}
+
+/// Writes a self-signed certificate for `localhost` and its key to temporary files.
+pub fn self_signed(name: &str) -> (TlsConfig, CertificateDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = std::env::temp_dir().join(format!("car-rental-{}-{name}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (cert_file, key_file) = (dir.join("cert.pem"), dir.join("key.pem"));
+    std::fs::write(&cert_file, certified.cert.pem()).unwrap();
+    std::fs::write(&key_file, certified.key_pair.serialize_pem()).unwrap();
+    let config = TlsConfig {
+        cert_file,
+        key_file,
+        port: 0,
+    };
+    (config, certified.cert.der().clone())
+}
+
+/// Makes the error a mocked command fails with.
+pub type Failure = fn() -> ApplicationError;
+
+/// Commands decided without a database, for the handler tests: they succeed, renting and
+/// returning `AA111AA`, unless `failing` tells the error they fail with.
+#[derive(Default)]
+pub struct MockCommandService {
+    pub failing: Option<Failure>,
+    /// Reported as the version of every customer.
+    pub customer_version: Option<i64>,
+    sent: Mutex<Vec<&'static str>>,
+}
+
+impl MockCommandService {
+    pub fn failing(error: Failure) -> Self {
+        Self {
+            failing: Some(error),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_customer_version(mut self, version: i64) -> Self {
+        self.customer_version = Some(version);
+        self
+    }
+
+    /// The commands sent so far, by name.
+    pub fn sent(&self) -> Vec<&'static str> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    fn decide<T>(&self, command: &'static str, outcome: T) -> ApplicationResult<T> {
+        self.sent.lock().unwrap().push(command);
+        match self.failing {
+            Some(error) => Err(error()),
+            None => Ok(outcome),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandService for MockCommandService {
+    async fn register_vehicle(&self, command: RegisterVehicle) -> ApplicationResult<PlateNumber> {
+        self.decide("RegisterVehicle", command.vehicle_id)
+    }
+
+    async fn register_customer(&self, command: RegisterCustomer) -> ApplicationResult<Email> {
+        self.decide("RegisterCustomer", command.customer_id)
+    }
+
+    async fn start_rent(&self, command: StartRent) -> ApplicationResult<RentStarted> {
+        let started = RentStarted {
+            rent_id: 7,
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: command.vehicle_type,
+            start_date: Utc::now(),
+        };
+        self.decide("StartRent", started)
+    }
+
+    async fn end_rent(&self, _: EndRent) -> ApplicationResult<RentEnded> {
+        let ended = RentEnded {
+            vehicle_id: "AA111AA".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: None,
+            returned_date: Utc::now(),
+            duration_minutes: None,
+            already_returned: false,
+            event_id: 8,
+        };
+        self.decide("EndRent", ended)
+    }
+
+    async fn customer_version(&self, _: &TenantId, _: &Email) -> ApplicationResult<Option<i64>> {
+        Ok(self.customer_version)
+    }
+}
+
+/// Keeps the errors reported, with their context.
+#[derive(Default)]
+pub struct RecordingReporter(Mutex<Vec<(String, ErrorContext)>>);
+
+impl RecordingReporter {
+    pub fn reported(&self) -> Vec<(String, ErrorContext)> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl ErrorReporter for RecordingReporter {
+    fn report(&self, error: &dyn std::error::Error, context: &ErrorContext) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((error.to_string(), context.clone()));
+    }
+}
+
+pub async fn application(options: PgConnectOptions) -> Application {
+    read_model(options.clone()).await;
+    let pool = PgPool::connect_with(options).await.unwrap();
+    let event_store = PgEventStore::new(pool.clone(), pii::EncryptedJson::default())
+        .await
+        .unwrap();
+    let snapshotter = PgSnapshotter::new(pool, config::DEFAULT_SNAPSHOT_EVERY)
+        .await
+        .unwrap();
+    Application::new(
+        application::DecisionMaker::snapshotted(event_store.clone(), snapshotter),
+        event_store,
+    )
+}
+
+/// Binds the server, returning it along with the addresses it's bound to.
+pub async fn bind(options: PgConnectOptions, config: &HttpConfig) -> (Server, Vec<SocketAddr>) {
+    let pool = read_model(options.clone()).await;
+    let public_pool = PgPool::connect_with(options.clone()).await.unwrap();
+    let event_store = PgEventStore::new(public_pool.clone(), Default::default())
+        .await
+        .unwrap();
+    let snapshotter = PgSnapshotter::new(public_pool, config::DEFAULT_SNAPSHOT_EVERY)
+        .await
+        .unwrap();
+    let metrics = Metrics::default();
+    let scheduler = Scheduler::new(
+        AppContext {
+            read_model: pool.clone(),
+        },
+        Shutdown::default(),
+    );
+    let config = AppConfig {
+        http: config.clone(),
+        ..AppConfig::default()
+    };
+    crate::http::http_server(
+        &config,
+        application(options).await.with_metrics(metrics.clone()),
+        read_repository(ReadModelRepository::new(pool.clone())),
+        Some(PgBackend {
+            event_store,
+            snapshotter,
+            pool,
+            scheduler,
+        }),
+        RebuildStatus::default(),
+        Readiness::default(),
+        LiveUpdates::new(Shutdown::default()),
+        metrics,
+        reporting::noop(),
+    )
+    .unwrap()
+}
+
+/// Sends a `GET /healthz`, returning the raw response.
+pub async fn probe<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> String {
+    stream
+        .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    // Servers may close TLS connections without a close_notify.
+    let _ = stream.read_to_end(&mut response).await;
+    String::from_utf8(response).unwrap()
+}
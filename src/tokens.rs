@@ -0,0 +1,309 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    dev::Payload,
+    error,
+    http::{
+        header::{HeaderMap, AUTHORIZATION, WWW_AUTHENTICATE},
+        StatusCode,
+    },
+    web::Data,
+    FromRequest, HttpRequest, HttpResponse,
+};
+use jsonwebtoken::{errors::ErrorKind, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::{Email, TenantId},
+    errors::{ErrorBody, ErrorCode},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Customer,
+    Admin,
+}
+
+/// Claims of the bearer tokens; the subject of a customer token is the customer id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub role: Role,
+    pub exp: u64,
+    /// The tenant the caller acts within, the default one when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+}
+
+/// Validates the HS256 tokens signed with `JWT_SECRET`.
+///
+/// Without it, requests aren't told apart by token: bearer values are left to the API keys and
+/// the admin routes to `ADMIN_API_KEYS`.
+#[derive(Clone)]
+pub struct TokenKeys {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl std::fmt::Debug for TokenKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TokenKeys").finish_non_exhaustive()
+    }
+}
+
+impl TokenKeys {
+    pub fn new(secret: &str) -> Result<Self, String> {
+        if secret.is_empty() {
+            return Err("JWT_SECRET: must not be empty".to_string());
+        }
+        Ok(Self {
+            key: DecodingKey::from_secret(secret.as_bytes()),
+            validation: Validation::new(Algorithm::HS256),
+        })
+    }
+
+    pub fn decode(&self, token: &str) -> Result<Claims, AuthError> {
+        jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|err| match err.kind() {
+                ErrorKind::ExpiredSignature => AuthError::unauthenticated("the token expired"),
+                _ => AuthError::unauthenticated("the token is not valid"),
+            })
+    }
+}
+
+/// Bearer values shaped like a JWT, three dot separated parts, as opposed to API keys.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| token.split('.').count() == 3)
+}
+
+/// `401 Unauthorized` or `403 Forbidden`, rendered as the other errors.
+#[derive(Debug)]
+pub struct AuthError {
+    status: StatusCode,
+    message: &'static str,
+}
+
+impl AuthError {
+    pub fn unauthenticated(message: &'static str) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            message,
+        }
+    }
+
+    pub fn forbidden(message: &'static str) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+impl error::ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        let code = match self.status {
+            StatusCode::UNAUTHORIZED => {
+                response.insert_header((WWW_AUTHENTICATE, "Bearer"));
+                ErrorCode::Unauthenticated
+            }
+            _ => ErrorCode::Forbidden,
+        };
+        response.json(ErrorBody::new(code, self.message.to_string()))
+    }
+}
+
+/// Who is calling, as far as the bearer token tells.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    /// Whether tokens are validated at all.
+    tokens: bool,
+    claims: Option<Claims>,
+}
+
+impl Caller {
+    /// Customers can only act for themselves; admins and callers without a token, left to the
+    /// API keys, for anyone.
+    pub fn act_for(&self, customer_id: &Email) -> Result<(), AuthError> {
+        match &self.claims {
+            Some(Claims {
+                role: Role::Customer,
+                sub,
+                ..
+            }) if sub != customer_id => Err(AuthError::forbidden(
+                "customers can only act for themselves",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Customers can't register vehicles nor customers; admins and callers without a token,
+    /// left to the API keys, can.
+    pub fn register(&self) -> Result<(), AuthError> {
+        match &self.claims {
+            Some(Claims {
+                role: Role::Customer,
+                ..
+            }) => Err(AuthError::forbidden(
+                "customers can't register vehicles nor customers",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The subject of the token, if valid.
+    pub fn subject(&self) -> Option<&str> {
+        self.claims.as_ref().map(|claims| claims.sub.as_str())
+    }
+
+    /// The tenant of the token, if valid and given one.
+    pub fn tenant(&self) -> Option<&str> {
+        self.claims.as_ref()?.tenant.as_deref()
+    }
+
+    /// Admins, required by the admin routes; without tokens validated, no caller is one.
+    pub fn admin(&self) -> Result<(), AuthError> {
+        match &self.claims {
+            _ if !self.tokens => Err(AuthError::forbidden(
+                "the admin routes need ADMIN_API_KEYS or JWT_SECRET to be set",
+            )),
+            None => Err(AuthError::unauthenticated("an admin token is required")),
+            Some(claims) if claims.role == Role::Admin => Ok(()),
+            Some(_) => Err(AuthError::forbidden("an admin token is required")),
+        }
+    }
+
+    fn from_request(req: &HttpRequest) -> Result<Self, AuthError> {
+        let Some(keys) = req.app_data::<Data<TokenKeys>>() else {
+            return Ok(Self {
+                tokens: false,
+                claims: None,
+            });
+        };
+        let claims = bearer_token(req.headers())
+            .map(|token| keys.decode(token))
+            .transpose()?;
+        Ok(Self {
+            tokens: true,
+            claims,
+        })
+    }
+}
+
+impl FromRequest for Caller {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Caller::from_request(req))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    pub const SECRET: &str = "test-secret";
+
+    /// Mints a token signed with `SECRET`, expiring `expires_in` seconds from now.
+    pub fn token(sub: &str, role: Role, expires_in: i64) -> String {
+        tenant_token(sub, role, expires_in, None)
+    }
+
+    /// Mints a token of `tenant`, as `token` does.
+    pub fn tenant_token(sub: &str, role: Role, expires_in: i64, tenant: Option<&str>) -> String {
+        let claims = Claims {
+            sub: sub.to_string(),
+            role,
+            exp: (chrono::Utc::now().timestamp() + expires_in) as u64,
+            tenant: tenant.map(str::to_string),
+        };
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn caller(token: Option<&str>) -> Result<Caller, AuthError> {
+        let request = TestRequest::default().app_data(Data::new(TokenKeys::new(SECRET).unwrap()));
+        let request = match token {
+            Some(token) => request.insert_header((AUTHORIZATION, format!("Bearer {token}"))),
+            None => request,
+        };
+        Caller::from_request(&request.to_http_request())
+    }
+
+    #[std::prelude::v1::test]
+    fn it_should_only_let_customers_act_for_themselves() {
+        let mario = caller(Some(&token("mario@example.com", Role::Customer, 60))).unwrap();
+        assert!(mario.act_for(&"mario@example.com".into()).is_ok());
+        let err = mario.act_for(&"luigi@example.com".into()).unwrap_err();
+        assert_eq!(err.status, StatusCode::FORBIDDEN);
+        assert_eq!(mario.admin().unwrap_err().status, StatusCode::FORBIDDEN);
+        assert_eq!(mario.register().unwrap_err().status, StatusCode::FORBIDDEN);
+
+        let admin = caller(Some(&token("ops", Role::Admin, 60))).unwrap();
+        assert!(admin.act_for(&"luigi@example.com".into()).is_ok());
+        assert!(admin.admin().is_ok());
+        assert!(admin.register().is_ok());
+
+        let tenant = tenant_token("ops", Role::Admin, 60, Some("rentals-north"));
+        assert_eq!(
+            caller(Some(&tenant)).unwrap().tenant(),
+            Some("rentals-north")
+        );
+        assert_eq!(admin.tenant(), None);
+
+        let anonymous = caller(None).unwrap();
+        assert!(anonymous.act_for(&"luigi@example.com".into()).is_ok());
+        assert!(anonymous.register().is_ok());
+        assert_eq!(
+            anonymous.admin().unwrap_err().status,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[std::prelude::v1::test]
+    fn it_should_reject_expired_and_forged_tokens() {
+        let err = caller(Some(&token("mario@example.com", Role::Customer, -3600))).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(err.message, "the token expired");
+
+        let forged = jsonwebtoken::encode(
+            &Header::default(),
+            &Claims {
+                sub: "ops".to_string(),
+                role: Role::Admin,
+                exp: (chrono::Utc::now().timestamp() + 60) as u64,
+                tenant: None,
+            },
+            &EncodingKey::from_secret(b"another-secret"),
+        )
+        .unwrap();
+        let err = caller(Some(&forged)).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(err.message, "the token is not valid");
+    }
+}
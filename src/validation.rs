@@ -0,0 +1,528 @@
+use actix_web::{
+    dev::Payload,
+    error::{self, JsonPayloadError},
+    http::StatusCode,
+    web::{Json, JsonConfig},
+    FromRequest, HttpRequest, HttpResponse,
+};
+use chrono::{Datelike, Utc};
+use disintegrate::Event;
+use futures_util::future::LocalBoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    domain::{DomainEvent, EndRent, RegisterCustomer, RegisterVehicle, StartRent, VehicleType},
+    errors::{ErrorBody, ErrorCode},
+    import::{CustomerRow, VehicleRow},
+    request_id::RequestId,
+    webhooks::NewWebhook,
+};
+
+/// Default of `JSON_BODY_LIMIT`, in bytes; commands are far smaller.
+pub const DEFAULT_BODY_LIMIT: usize = 64 * 1024;
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_PLATE_NUMBER_LENGTH: usize = 10;
+const MAX_SEATS: u8 = 60;
+
+/// Checks the shape of a command before it reaches the decision maker.
+///
+/// Only the format of the fields is checked here; the invariants depending on the state are
+/// left to the decisions.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: &'static str,
+}
+
+/// Every field error found in a request, rendered as `422 Unprocessable Entity`.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    fn check(&mut self, field: &'static str, value: &str, rule: fn(&str) -> Option<&'static str>) {
+        if let Some(message) = rule(value) {
+            self.errors.push(FieldError { field, message });
+        }
+    }
+
+    fn into_result(self) -> Result<(), Self> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ValidationBody<'a> {
+    errors: &'a [FieldError],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let fields: Vec<_> = self.errors.iter().map(|error| error.field).collect();
+        write!(f, "invalid fields: {}", fields.join(", "))
+    }
+}
+
+impl error::ResponseError for ValidationErrors {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ValidationBody {
+            errors: &self.errors,
+            request_id: RequestId::current().map(|id| id.to_string()),
+        })
+    }
+}
+
+fn email(value: &str) -> Option<&'static str> {
+    let valid = match value.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.split('.').count() > 1
+                && domain.split('.').all(|label| !label.is_empty())
+                && !value.chars().any(char::is_whitespace)
+        }
+        None => false,
+    };
+    (!valid).then_some("must be a valid email")
+}
+
+fn name(value: &str) -> Option<&'static str> {
+    if value.trim().is_empty() {
+        Some("must not be blank")
+    } else if value.chars().count() > MAX_NAME_LENGTH {
+        Some("must be at most 100 characters")
+    } else {
+        None
+    }
+}
+
+fn phone_number(value: &str) -> Option<&'static str> {
+    let digits = value.chars().filter(char::is_ascii_digit).count();
+    let allowed = |c: char| c.is_ascii_digit() || " +-().".contains(c);
+    if !(6..=15).contains(&digits) || !value.chars().all(allowed) {
+        Some("must be a phone number of 6 to 15 digits")
+    } else {
+        None
+    }
+}
+
+fn model_year(value: &str) -> Option<&'static str> {
+    match value.parse::<i32>() {
+        Ok(year) if (1900..=Utc::now().year() + 1).contains(&year) => None,
+        _ => Some("must be a year from 1900 to the next one"),
+    }
+}
+
+fn plate_number(value: &str) -> Option<&'static str> {
+    if value.is_empty() || value.len() > MAX_PLATE_NUMBER_LENGTH {
+        Some("must be between 1 and 10 characters")
+    } else if !value.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some("must only contain letters and digits")
+    } else {
+        None
+    }
+}
+
+impl Validate for RegisterVehicle {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.check("vehicleId", &self.vehicle_id, plate_number);
+        if let Some(seats) = self.seats {
+            if !(1..=MAX_SEATS).contains(&seats) {
+                errors.errors.push(FieldError {
+                    field: "seats",
+                    message: "must be between 1 and 60",
+                });
+            }
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for VehicleRow {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.check("plate", &self.plate, plate_number);
+        errors.check("type", &self.vehicle_type, |value| {
+            let known = value.to_ascii_lowercase().parse::<VehicleType>().is_ok();
+            (!known).then_some("must be one of car, pick_up, van, truck")
+        });
+        for (field, value) in [("make", &self.make), ("model", &self.model)] {
+            if let Some(value) = value {
+                errors.check(field, value, name);
+            }
+        }
+        if let Some(year) = &self.year {
+            errors.check("year", year, model_year);
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for CustomerRow {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.check("email", &self.email, email);
+        errors.check("first_name", &self.first_name, name);
+        errors.check("last_name", &self.last_name, name);
+        if let Some(phone) = &self.phone {
+            errors.check("phone", phone, phone_number);
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for RegisterCustomer {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.check("customerId", &self.customer_id, email);
+        errors.check("firstName", &self.first_name, name);
+        errors.check("lastName", &self.last_name, name);
+        if let Some(phone) = &self.phone {
+            errors.check("phone", phone, phone_number);
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for StartRent {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.check("customerId", &self.customer_id, email);
+        errors.into_result()
+    }
+}
+
+impl Validate for EndRent {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.check("customerId", &self.customer_id, email);
+        errors.into_result()
+    }
+}
+
+impl Validate for NewWebhook {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.check("url", &self.url, webhook_url);
+        errors.check("secret", &self.secret, |secret| {
+            secret.is_empty().then_some("must not be empty")
+        });
+        let known = |event_type: &String| DomainEvent::SCHEMA.types.contains(&event_type.as_str());
+        if self.event_types.is_empty() || !self.event_types.iter().all(known) {
+            errors.errors.push(FieldError {
+                field: "eventTypes",
+                message: "must list domain event types",
+            });
+        }
+        errors.into_result()
+    }
+}
+
+fn webhook_url(value: &str) -> Option<&'static str> {
+    match reqwest::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => None,
+        _ => Some("must be an http or https URL"),
+    }
+}
+
+/// Reading of the JSON bodies: at most `limit` bytes, sent as `application/json`.
+pub fn json_config(limit: usize) -> JsonConfig {
+    JsonConfig::default()
+        .limit(limit)
+        .error_handler(|err, _| BodyError::from(err).into())
+}
+
+/// A body that can't be read as the command, rendered as the other errors.
+///
+/// When serde tells which field is wrong, it's given as `field` in the details, with the path
+/// serde gives, e.g. `vehicleType`.
+#[derive(Debug)]
+pub struct BodyError {
+    code: ErrorCode,
+    message: String,
+    details: serde_json::Map<String, serde_json::Value>,
+    /// Bytes a body is at most, when too large.
+    limit: Option<usize>,
+}
+
+impl BodyError {
+    fn new(code: ErrorCode, message: String) -> Self {
+        Self {
+            code,
+            message,
+            details: Default::default(),
+            limit: None,
+        }
+    }
+
+    fn mismatched(err: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        let mut error = Self::new(ErrorCode::MalformedBody, err.inner().to_string());
+        let field = err.path().to_string();
+        if field != "." {
+            error.details.insert("field".to_string(), field.into());
+        }
+        error
+    }
+}
+
+impl From<JsonPayloadError> for BodyError {
+    fn from(err: JsonPayloadError) -> Self {
+        match err {
+            JsonPayloadError::OverflowKnownLength { limit, .. }
+            | JsonPayloadError::Overflow { limit } => Self {
+                limit: Some(limit),
+                ..Self::new(
+                    ErrorCode::PayloadTooLarge,
+                    format!("the body must be at most {limit} bytes"),
+                )
+            },
+            JsonPayloadError::ContentType => Self::new(
+                ErrorCode::UnsupportedMediaType,
+                "the body must be sent as application/json".to_string(),
+            ),
+            JsonPayloadError::Deserialize(err) => {
+                let mut error = Self::new(ErrorCode::MalformedBody, err.to_string());
+                error.details.insert("line".to_string(), err.line().into());
+                error
+                    .details
+                    .insert("column".to_string(), err.column().into());
+                error
+            }
+            err => Self::new(ErrorCode::MalformedBody, err.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::ResponseError for BodyError {
+    fn status_code(&self) -> StatusCode {
+        match self.code {
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = match &self.limit {
+            Some(limit) => {
+                ErrorBody::localized(self.code, self.message.clone(), &[("limit", limit)])
+            }
+            None => ErrorBody::new(self.code, self.message.clone()),
+        };
+        body.details = self.details.clone();
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+/// JSON body that passed validation.
+///
+/// Bodies that can't be deserialized are rejected with a `BodyError` beforehand.
+#[derive(Debug)]
+pub struct Valid<T>(pub T);
+
+impl<T> Valid<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Validate + DeserializeOwned + 'static> FromRequest for Valid<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = Json::<serde_json::Value>::from_request(req, payload);
+        Box::pin(async move {
+            let Json(value) = json.await?;
+            Ok(Valid(parse(value)?))
+        })
+    }
+}
+
+/// Reads a command out of JSON, rejecting it as `Valid` does.
+pub fn parse<T: Validate + DeserializeOwned>(value: serde_json::Value) -> actix_web::Result<T> {
+    let command: T = serde_path_to_error::deserialize(value).map_err(BodyError::mismatched)?;
+    command.validate()?;
+    Ok(command)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{body::to_bytes, http::header::CONTENT_TYPE, ResponseError};
+
+    fn parse<T: DeserializeOwned>(json: serde_json::Value) -> T {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn it_should_list_every_invalid_field_of_a_customer() {
+        let command: RegisterCustomer = parse(serde_json::json!({
+            "customerId": "mario.example.com",
+            "firstName": "  ",
+            "lastName": "R".repeat(101),
+        }));
+        assert_eq!(
+            command.validate(),
+            Err(ValidationErrors {
+                errors: vec![
+                    FieldError {
+                        field: "customerId",
+                        message: "must be a valid email"
+                    },
+                    FieldError {
+                        field: "firstName",
+                        message: "must not be blank"
+                    },
+                    FieldError {
+                        field: "lastName",
+                        message: "must be at most 100 characters"
+                    },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn it_should_accept_a_well_formed_customer() {
+        let command: RegisterCustomer = parse(serde_json::json!({
+            "customerId": "mario@example.com",
+            "firstName": "Mario",
+            "lastName": "Rossi",
+        }));
+        assert_eq!(command.validate(), Ok(()));
+    }
+
+    #[test]
+    fn it_should_reject_malformed_emails_when_starting_a_rent() {
+        for customer_id in [
+            "",
+            "@example.com",
+            "mario@",
+            "mario@example",
+            "mario@example.",
+            "ma rio@example.com",
+            "mario@ex@ample.com",
+        ] {
+            let command: StartRent = parse(serde_json::json!({
+                "customerId": customer_id,
+                "vehicleType": "Car",
+            }));
+            assert_eq!(
+                command.validate(),
+                Err(ValidationErrors {
+                    errors: vec![FieldError {
+                        field: "customerId",
+                        message: "must be a valid email"
+                    }]
+                }),
+                "{customer_id}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_render_the_field_errors() {
+        let command: StartRent = parse(serde_json::json!({
+            "customerId": "",
+            "vehicleType": "Car",
+        }));
+        let response = command.validate().unwrap_err().error_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            r#"{"errors":[{"field":"customerId","message":"must be a valid email"}]}"#
+        );
+    }
+
+    async fn register_vehicle(command: Valid<RegisterVehicle>) -> HttpResponse {
+        HttpResponse::Created().json(command.0.vehicle_id)
+    }
+
+    #[actix_web::test]
+    async fn it_should_reject_unreadable_bodies_with_an_error_body() {
+        let service = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(json_config(64))
+                .route("/", actix_web::web::post().to(register_vehicle)),
+        )
+        .await;
+        let post = |content_type: &str, body: &str| {
+            actix_web::test::TestRequest::post()
+                .insert_header((CONTENT_TYPE, content_type))
+                .set_payload(body.to_string())
+                .to_request()
+        };
+        let send = |request| async {
+            let response = actix_web::test::call_service(&service, request).await;
+            let status = response.status();
+            let body: serde_json::Value = actix_web::test::read_body_json(response).await;
+            (status, body)
+        };
+
+        let (status, body) = send(post(
+            "application/json",
+            r#"{"vehicleId":"AA111AA","vehicleType":"Van"}"#,
+        ))
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        let (status, body) = send(post("application/json", r#"{"vehicleId":"AA1"#)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "MALFORMED_BODY");
+        assert_eq!(body["details"]["line"], 1);
+
+        let (status, body) = send(post(
+            "application/json",
+            r#"{"vehicleId":"AA111AA","vehicleType":"Boat"}"#,
+        ))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "MALFORMED_BODY");
+        assert_eq!(body["details"]["field"], "vehicleType");
+
+        let (status, body) = send(post(
+            "application/json",
+            &format!(
+                r#"{{"vehicleId":"{}","vehicleType":"Van"}}"#,
+                "A".repeat(64)
+            ),
+        ))
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["code"], "PAYLOAD_TOO_LARGE");
+
+        let (status, body) = send(post(
+            "text/plain",
+            r#"{"vehicleId":"AA111AA","vehicleType":"Van"}"#,
+        ))
+        .await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["code"], "UNSUPPORTED_MEDIA_TYPE");
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    dead_letter,
+    domain::{PlateNumber, RentEvent},
+    read_model::{queries::ReadModelRepository, rental_duration_minutes},
+    reporting::{self, ErrorReporter},
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use disintegrate::{query, Event, EventListener, PersistedEvent, StreamQuery};
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Keeps the rental counters of every vehicle up to date, apart from the read model of the
+/// vehicles and with a checkpoint of its own.
+///
+/// Counters are incremented rather than recomputed, so each vehicle records the last event
+/// applied to it, making sure a redelivered one is not counted twice.
+pub struct VehicleStatsProjection {
+    query: StreamQuery<RentEvent>,
+    pool: PgPool,
+    reporter: Arc<dyn ErrorReporter>,
+}
+
+impl VehicleStatsProjection {
+    pub const ID: &'static str = "drive_me_crazy_vehicle_stats";
+    pub const TABLES: &'static [&'static str] = &["vehicle_stats"];
+
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            query: query(None),
+            pool,
+            reporter: reporting::noop(),
+        }
+    }
+
+    /// Reports the events set aside as dead letters to `reporter`.
+    pub fn with_error_reporter(mut self, reporter: Arc<dyn ErrorReporter>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    async fn apply(&self, event_id: i64, event: RentEvent) -> Result<(), sqlx::Error> {
+        let (tenant_id, vehicle_id) = match &event {
+            RentEvent::VehicleAdded {
+                tenant_id,
+                vehicle_id,
+                ..
+            }
+            | RentEvent::VehicleRented {
+                tenant_id,
+                vehicle_id,
+                ..
+            }
+            | RentEvent::VehicleReturned {
+                tenant_id,
+                vehicle_id,
+                ..
+            } => (tenant_id.clone(), vehicle_id.clone()),
+        };
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO vehicle_stats (tenant_id, vehicle_id) VALUES($1, $2) ON CONFLICT (tenant_id, vehicle_id) DO NOTHING",
+        )
+        .bind(&tenant_id)
+        .bind(&vehicle_id)
+        .execute(&mut *tx)
+        .await?;
+        let (last_event_id, rented_since): (i64, Option<DateTime<Utc>>) = sqlx::query_as(
+            "SELECT last_event_id, rented_since FROM vehicle_stats WHERE tenant_id = $1 AND vehicle_id = $2 FOR UPDATE",
+        )
+        .bind(&tenant_id)
+        .bind(&vehicle_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if last_event_id >= event_id {
+            return Ok(());
+        }
+        match event {
+            RentEvent::VehicleAdded { .. } => {
+                sqlx::query(
+                    "UPDATE vehicle_stats SET last_event_id = $3 WHERE tenant_id = $1 AND vehicle_id = $2",
+                )
+                .bind(&tenant_id)
+                .bind(&vehicle_id)
+                .bind(event_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            RentEvent::VehicleRented { start_date, .. } => {
+                sqlx::query(
+                    r#"UPDATE vehicle_stats SET rentals = rentals + 1, last_rented_at = $3,
+                        rented_since = $3, last_event_id = $4 WHERE tenant_id = $1 AND vehicle_id = $2"#,
+                )
+                .bind(&tenant_id)
+                .bind(&vehicle_id)
+                .bind(start_date)
+                .bind(event_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            RentEvent::VehicleReturned {
+                start_date,
+                returned_date,
+                ..
+            } => {
+                let minutes = start_date
+                    .or(rented_since)
+                    .and_then(|start_date| rental_duration_minutes(start_date, returned_date));
+                sqlx::query(
+                    r#"UPDATE vehicle_stats SET rented_minutes = rented_minutes + $3,
+                        rented_since = NULL, last_event_id = $4 WHERE tenant_id = $1 AND vehicle_id = $2"#,
+                )
+                .bind(&tenant_id)
+                .bind(&vehicle_id)
+                .bind(minutes.unwrap_or(0))
+                .bind(event_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+        };
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl EventListener<RentEvent> for VehicleStatsProjection {
+    type Error = sqlx::Error;
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn query(&self) -> &StreamQuery<RentEvent> {
+        &self.query
+    }
+
+    #[tracing::instrument(skip_all, fields(listener_id = self.id(), event_id = event.id(), event_type = event.name()))]
+    async fn handle(&self, event: PersistedEvent<RentEvent>) -> Result<(), Self::Error> {
+        let (event_id, event_type) = (event.id(), event.name());
+        let result = self.apply(event_id, event.into_inner()).await;
+        dead_letter::settle(
+            &self.pool,
+            &*self.reporter,
+            self.id(),
+            event_id,
+            event_type,
+            result,
+        )
+        .await
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleStats {
+    pub vehicle_id: PlateNumber,
+    pub rentals: i64,
+    pub rented_minutes: i64,
+    pub last_rented_at: Option<DateTime<Utc>>,
+}
+
+impl ReadModelRepository {
+    /// The rental counters of the vehicle, `None` until the projection has seen it.
+    pub async fn vehicle_stats(
+        &self,
+        vehicle_id: &str,
+    ) -> Result<Option<VehicleStats>, sqlx::Error> {
+        sqlx::query_as(
+            r#"SELECT vehicle_id, rentals, rented_minutes, last_rented_at FROM vehicle_stats
+                WHERE tenant_id = $1 AND vehicle_id = $2"#,
+        )
+        .bind(&self.tenant_id)
+        .bind(vehicle_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::{default_tenant, VehicleType},
+        test_support,
+    };
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_count_each_event_once_when_replayed(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let projection = VehicleStatsProjection::new(pool.clone());
+        let date = |date: &str| date.parse::<DateTime<Utc>>().unwrap();
+        let rented = |start_date: &str| RentEvent::VehicleRented {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA123BB".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: date(start_date),
+        };
+        let returned = |start_date: Option<&str>, returned_date: &str| RentEvent::VehicleReturned {
+            tenant_id: default_tenant(),
+            customer_id: "mario@example.com".into(),
+            vehicle_id: "AA123BB".to_string(),
+            vehicle_type: VehicleType::Car,
+            start_date: start_date.map(date),
+            returned_date: date(returned_date),
+        };
+        let events = [
+            RentEvent::VehicleAdded {
+                tenant_id: default_tenant(),
+                vehicle_id: "AA123BB".to_string(),
+                vehicle_type: VehicleType::Car,
+                seats: None,
+                transmission: None,
+            },
+            rented("2024-07-01T09:00:00Z"),
+            returned(Some("2024-07-01T09:00:00Z"), "2024-07-01T10:30:00Z"),
+            rented("2024-07-02T09:00:00Z"),
+            // Recorded before the returns kept the start of the rental.
+            returned(None, "2024-07-02T09:45:00Z"),
+        ];
+        let expected = VehicleStats {
+            vehicle_id: "AA123BB".to_string(),
+            rentals: 2,
+            rented_minutes: 90 + 45,
+            last_rented_at: Some(date("2024-07-02T09:00:00Z")),
+        };
+        let repository = ReadModelRepository::new(pool);
+        for (event_id, event) in (1..).zip(events.clone()) {
+            projection.apply(event_id, event).await.unwrap();
+        }
+        assert_eq!(
+            repository.vehicle_stats("AA123BB").await.unwrap(),
+            Some(expected)
+        );
+
+        // Delivered again, from the start and from the middle of the stream.
+        for (event_id, event) in (1..)
+            .zip(events.clone())
+            .chain((3..).zip(events[2..].to_vec()))
+        {
+            projection.apply(event_id, event).await.unwrap();
+        }
+        let stats = repository.vehicle_stats("AA123BB").await.unwrap().unwrap();
+        assert_eq!((stats.rentals, stats.rented_minutes), (2, 135));
+        assert_eq!(repository.vehicle_stats("ZZ999ZZ").await.unwrap(), None);
+    }
+}
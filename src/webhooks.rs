@@ -0,0 +1,488 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use disintegrate::{query, Event, EventListener, PersistedEvent, StreamQuery};
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{types::Json, PgPool};
+
+use crate::{
+    backoff::Backoff,
+    domain::{DomainEvent, TenantId},
+};
+
+/// Header of the notifications carrying `sha256=` and the hex HMAC-SHA256 of the body, keyed
+/// by the secret of the webhook.
+pub const SIGNATURE: &str = "x-signature-256";
+pub const EVENT_TYPE: &str = "x-webhook-event";
+/// Header of the notifications carrying the id of the event, the same on every attempt.
+pub const DELIVERY: &str = "x-webhook-delivery";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewWebhook {
+    pub url: String,
+    pub secret: String,
+    /// Names of the domain events to notify, e.g. `VehicleRented`.
+    pub event_types: Vec<String>,
+}
+
+/// A subscription, its secret left out.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub webhook_id: i64,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A notification given up on after `DeliveryRetries::max_attempts` failed attempts.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDeadLetter {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event_id: i64,
+    pub event_type: String,
+    pub payload: Json<serde_json::Value>,
+    pub attempts: i32,
+    pub error: String,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// Registers the webhook of the tenant, notified of its events recorded from now on.
+pub async fn register(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+    webhook: &NewWebhook,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"INSERT INTO webhook (url, secret, event_types, after_event_id, tenant_id)
+            VALUES($1, $2, $3, (SELECT coalesce(max(event_id), 0) FROM event), $4)
+            RETURNING webhook_id"#,
+    )
+    .bind(&webhook.url)
+    .bind(&webhook.secret)
+    .bind(&webhook.event_types)
+    .bind(tenant_id)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn list(pool: &PgPool, tenant_id: &TenantId) -> Result<Vec<Webhook>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT webhook_id, url, event_types, created_at FROM webhook
+            WHERE tenant_id = $1 ORDER BY webhook_id"#,
+    )
+    .bind(tenant_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Removes the webhook of the tenant along with its deliveries, returning whether it existed.
+pub async fn remove(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+    webhook_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM webhook WHERE webhook_id = $1 AND tenant_id = $2")
+        .bind(webhook_id)
+        .bind(tenant_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The notifications given up on of the webhooks of the tenant.
+pub async fn dead_letters(
+    pool: &PgPool,
+    tenant_id: &TenantId,
+) -> Result<Vec<WebhookDeadLetter>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT d.id, d.webhook_id, d.event_id, d.event_type, d.payload, d.attempts, d.error,
+                d.recorded_at
+            FROM webhook_dead_letter d JOIN webhook w USING (webhook_id)
+            WHERE w.tenant_id = $1 ORDER BY d.id"#,
+    )
+    .bind(tenant_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Attempts at delivering a notification: `WEBHOOK_MAX_ATTEMPTS` at most, waiting
+/// `WEBHOOK_RETRY_BACKOFF_MS` after the first failure and twice as long after each next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryRetries {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for DeliveryRetries {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Notification<'a> {
+    event_id: i64,
+    event_type: &'a str,
+    data: serde_json::Value,
+}
+
+#[derive(sqlx::FromRow)]
+struct Subscriber {
+    webhook_id: i64,
+    url: String,
+    secret: String,
+}
+
+/// Notifies the webhooks subscribed to the events, every attempt being recorded.
+///
+/// The subscribers of an event are notified concurrently, their retries holding back the
+/// events that follow, so that each one is notified in order.
+pub struct WebhookDispatcher {
+    query: StreamQuery<DomainEvent>,
+    pool: PgPool,
+    client: reqwest::Client,
+    retries: DeliveryRetries,
+}
+
+impl WebhookDispatcher {
+    pub const ID: &'static str = "drive_me_crazy_webhooks";
+
+    pub fn new(pool: PgPool, retries: DeliveryRetries) -> Self {
+        Self {
+            query: query(None),
+            pool,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("the HTTP client has a valid configuration"),
+            retries,
+        }
+    }
+
+    /// The webhooks of the tenant to notify of the event, leaving out those already notified
+    /// or given up on, as it's delivered again after a restart.
+    async fn subscribers(
+        &self,
+        tenant_id: &TenantId,
+        event_id: i64,
+        event_type: &str,
+    ) -> Result<Vec<Subscriber>, sqlx::Error> {
+        sqlx::query_as(
+            r#"SELECT w.webhook_id, w.url, w.secret FROM webhook w
+                WHERE $2 = ANY(w.event_types) AND w.after_event_id < $1 AND w.tenant_id = $3
+                AND NOT EXISTS (SELECT 1 FROM webhook_delivery_attempt a
+                    WHERE a.webhook_id = w.webhook_id AND a.event_id = $1 AND a.delivered)
+                AND NOT EXISTS (SELECT 1 FROM webhook_dead_letter d
+                    WHERE d.webhook_id = w.webhook_id AND d.event_id = $1)"#,
+        )
+        .bind(event_id)
+        .bind(event_type)
+        .bind(tenant_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn deliver(
+        &self,
+        subscriber: &Subscriber,
+        event_id: i64,
+        event_type: &str,
+        body: &[u8],
+    ) -> Result<(), sqlx::Error> {
+        let signature = sign(&subscriber.secret, body);
+        let mut attempt = 1;
+        let error = loop {
+            let response = self
+                .client
+                .post(&subscriber.url)
+                .header(CONTENT_TYPE, "application/json")
+                .header(SIGNATURE, &signature)
+                .header(EVENT_TYPE, event_type)
+                .header(DELIVERY, event_id)
+                .body(body.to_vec())
+                .send()
+                .await;
+            let (status_code, error) = match response {
+                Ok(response) if response.status().is_success() => {
+                    (Some(response.status().as_u16()), None)
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16()),
+                    Some(format!("the webhook answered {}", response.status())),
+                ),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            sqlx::query(
+                r#"INSERT INTO webhook_delivery_attempt (webhook_id, event_id, attempt, status_code, error, delivered)
+                    VALUES($1, $2, $3, $4, $5, $6)"#,
+            )
+            .bind(subscriber.webhook_id)
+            .bind(event_id)
+            .bind(attempt as i32)
+            .bind(status_code.map(i32::from))
+            .bind(&error)
+            .bind(error.is_none())
+            .execute(&self.pool)
+            .await?;
+            let Some(error) = error else {
+                return Ok(());
+            };
+            if attempt >= self.retries.max_attempts {
+                break error;
+            }
+            let backoff = Backoff::new(self.retries.backoff).delay(attempt);
+            tracing::warn!(webhook_id = subscriber.webhook_id, event_id, attempt, ?backoff, %error, "failed to notify the webhook");
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        };
+        sqlx::query(
+            r#"INSERT INTO webhook_dead_letter (webhook_id, event_id, event_type, payload, attempts, error)
+                VALUES($1, $2, $3, convert_from($4, 'UTF8')::jsonb, $5, $6)
+                ON CONFLICT (webhook_id, event_id) DO NOTHING"#,
+        )
+        .bind(subscriber.webhook_id)
+        .bind(event_id)
+        .bind(event_type)
+        .bind(body)
+        .bind(attempt as i32)
+        .bind(&error)
+        .execute(&self.pool)
+        .await?;
+        tracing::error!(webhook_id = subscriber.webhook_id, event_id, attempts = attempt, %error, "notification set aside as a dead letter");
+        Ok(())
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of the body.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256={hex}")
+}
+
+#[async_trait]
+impl EventListener<DomainEvent> for WebhookDispatcher {
+    type Error = sqlx::Error;
+    fn id(&self) -> &'static str {
+        Self::ID
+    }
+
+    fn query(&self) -> &StreamQuery<DomainEvent> {
+        &self.query
+    }
+
+    #[tracing::instrument(skip_all, fields(listener_id = self.id(), event_id = event.id(), event_type = event.name()))]
+    async fn handle(&self, event: PersistedEvent<DomainEvent>) -> Result<(), Self::Error> {
+        let (event_id, event_type) = (event.id(), event.name());
+        let subscribers = self
+            .subscribers(event.tenant_id(), event_id, event_type)
+            .await?;
+        if subscribers.is_empty() {
+            return Ok(());
+        }
+        // Events serialize as `{"<type>": {<fields>}}`, the fields only are sent, named as
+        // in the event store.
+        let data = match serde_json::to_value(event.into_inner()) {
+            Ok(serde_json::Value::Object(mut event)) => event.remove(event_type),
+            _ => None,
+        };
+        let body = serde_json::to_vec(&Notification {
+            event_id,
+            event_type,
+            data: data.unwrap_or_default(),
+        })
+        .expect("events serialize to JSON");
+        futures_util::future::try_join_all(
+            subscribers
+                .iter()
+                .map(|subscriber| self.deliver(subscriber, event_id, event_type, &body)),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        domain::{default_tenant, VehicleType},
+        test_support,
+    };
+    use actix_web::{web, App, HttpRequest, HttpResponse};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct Notified {
+        signature: String,
+        delivery: String,
+        body: Vec<u8>,
+    }
+
+    #[derive(Clone, Default)]
+    struct Received(Arc<Mutex<Vec<Notified>>>);
+
+    /// Answers `503` to the first `failures` notifications, recording all of them.
+    fn subscriber(failures: usize) -> (actix_test::TestServer, Received) {
+        let received = Received::default();
+        let server = {
+            let received = received.clone();
+            actix_test::start(move || {
+                let received = received.clone();
+                App::new().route(
+                    "/hook",
+                    web::post().to(move |req: HttpRequest, body: web::Bytes| {
+                        let received = received.clone();
+                        async move {
+                            let header = |name| {
+                                req.headers()
+                                    .get(name)
+                                    .map_or("", |value| value.to_str().unwrap())
+                                    .to_string()
+                            };
+                            let mut received = received.0.lock().unwrap();
+                            received.push(Notified {
+                                signature: header(SIGNATURE),
+                                delivery: header(DELIVERY),
+                                body: body.to_vec(),
+                            });
+                            if received.len() <= failures {
+                                HttpResponse::ServiceUnavailable().finish()
+                            } else {
+                                HttpResponse::Ok().finish()
+                            }
+                        }
+                    }),
+                )
+            })
+        };
+        (server, received)
+    }
+
+    fn rented(id: i64) -> PersistedEvent<DomainEvent> {
+        PersistedEvent::new(
+            id,
+            DomainEvent::VehicleRented {
+                tenant_id: default_tenant(),
+                customer_id: "mario@example.com".into(),
+                vehicle_id: "AA111AA".to_string(),
+                vehicle_type: VehicleType::Van,
+                start_date: Utc::now(),
+            },
+        )
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_sign_the_notifications_and_retry_them(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let (server, received) = subscriber(2);
+        let webhook = NewWebhook {
+            url: server.url("/hook"),
+            secret: "partner-secret".to_string(),
+            event_types: vec!["VehicleRented".to_string()],
+        };
+        let webhook_id = register(&pool, &default_tenant(), &webhook).await.unwrap();
+        // Not notified of the events of the other tenants.
+        register(&pool, &"south".to_string(), &webhook)
+            .await
+            .unwrap();
+        let retries = DeliveryRetries {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+        };
+        let dispatcher = WebhookDispatcher::new(pool.clone(), retries);
+
+        dispatcher.handle(rented(1)).await.unwrap();
+        let notifications = received.0.lock().unwrap().clone();
+        assert_eq!(notifications.len(), 3);
+        for notified in &notifications {
+            assert_eq!(notified.signature, sign("partner-secret", &notified.body));
+            assert_eq!(notified.delivery, "1");
+        }
+        let body: serde_json::Value = serde_json::from_slice(&notifications[0].body).unwrap();
+        assert_eq!(body["eventType"], "VehicleRented");
+        assert_eq!(body["data"]["vehicle_id"], "AA111AA");
+        let attempts: Vec<(i32, Option<i32>, bool)> = sqlx::query_as(
+            "SELECT attempt, status_code, delivered FROM webhook_delivery_attempt WHERE webhook_id = $1 ORDER BY attempt",
+        )
+        .bind(webhook_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            attempts,
+            [
+                (1, Some(503), false),
+                (2, Some(503), false),
+                (3, Some(200), true)
+            ]
+        );
+
+        // Delivered again after a restart, the event isn't notified twice.
+        dispatcher.handle(rented(1)).await.unwrap();
+        assert_eq!(received.0.lock().unwrap().len(), 3);
+        assert!(dead_letters(&pool, &default_tenant())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn it_should_set_aside_the_notifications_failing_every_attempt(
+        _: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = test_support::read_model(options).await;
+        let (server, received) = subscriber(usize::MAX);
+        let webhook = NewWebhook {
+            url: server.url("/hook"),
+            secret: "partner-secret".to_string(),
+            event_types: vec!["VehicleRented".to_string(), "VehicleReturned".to_string()],
+        };
+        let webhook_id = register(&pool, &default_tenant(), &webhook).await.unwrap();
+        let retries = DeliveryRetries {
+            max_attempts: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let dispatcher = WebhookDispatcher::new(pool.clone(), retries);
+
+        dispatcher.handle(rented(1)).await.unwrap();
+        dispatcher.handle(rented(1)).await.unwrap();
+        assert_eq!(received.0.lock().unwrap().len(), 2);
+        let letters = dead_letters(&pool, &default_tenant()).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].webhook_id, webhook_id);
+        assert_eq!(letters[0].attempts, 2);
+        assert_eq!(
+            letters[0].error,
+            "the webhook answered 503 Service Unavailable"
+        );
+        assert_eq!(
+            letters[0].payload.0["data"]["customer_id"],
+            "mario@example.com"
+        );
+    }
+}